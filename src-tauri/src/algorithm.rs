@@ -2,7 +2,9 @@ use std::collections::HashMap;
 
 use tauri::{async_runtime::block_on, Event, Listener, Manager, Runtime};
 
-use crate::{history::History, utils::now};
+use crate::{history::{History, StoredDueEvent}, profile::active_dir, utils::now};
+
+pub const HISTORY_PATH: &str = "/history2.db"; // CHANGE FOR RELEASE VERSIONS
 
 static mut HISTORY: Option<History> = None;
 
@@ -96,14 +98,15 @@ pub async fn record_complete_event(
 
 #[tauri::command]
 pub async fn init_algo<R: Runtime>(app: tauri::AppHandle<R>) -> Result<(), String> {
-    let path = app
+    // The active profile's database
+    let path = active_dir().unwrap_or_else(|| app
         .path()
         .app_data_dir()
         .unwrap()
         .to_str()
         .expect("AppData failed to resolve")
         .to_owned()
-        + "/history2.db"; // CHANGE FOR RELEASE VERSIONS
+    ) + HISTORY_PATH;
     unsafe {
         init_history();
         let hist = HISTORY.as_mut().unwrap();
@@ -115,6 +118,49 @@ pub async fn init_algo<R: Runtime>(app: tauri::AppHandle<R>) -> Result<(), Strin
     Ok(())
 }
 
+/// Closes the history database, e.g. so its file can be replaced.
+pub async fn close_history_db() {
+    unsafe {
+        if let Some(history) = HISTORY.as_mut() {
+            history.close().await;
+        }
+    }
+}
+
+/// Closes the open history database (if any) and opens the one at `path`.
+pub async fn switch_history_db(path: &str) -> Result<(), sqlx::Error> {
+    unsafe {
+        init_history();
+        HISTORY.as_mut().unwrap().close().await;
+        HISTORY.as_mut().unwrap().load(path).await
+    }
+}
+
+/// Every due event in the open history database, as stored.
+pub async fn get_stored_due_events() -> Result<Vec<StoredDueEvent>, sqlx::Error> {
+    unsafe {
+        if HISTORY.is_none() { return Ok(vec![]); }
+        HISTORY.as_mut().unwrap().stored_due_events().await
+    }
+}
+
+/// Adds `events` to the open history database, after deleting every event
+/// already there if `replace` is set.
+pub async fn add_stored_due_events(events: &[StoredDueEvent], replace: bool) -> Result<(), String> {
+    unsafe {
+        if HISTORY.is_none() { return Err("History isn't loaded yet.".to_string()); }
+        let history = HISTORY.as_mut().unwrap();
+        if replace {
+            history.clear_due_events(Vec::new()).await?;
+        }
+        for event in events {
+            history.insert_stored_due_event(event).await
+                .or_else(|e| Err(format!("History Error: {}", e)))?;
+        }
+    }
+    Ok(())
+}
+
 struct CreateCompletePair {
     pub created: Option<i64>,
     pub completed: Option<i64>,
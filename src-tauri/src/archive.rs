@@ -0,0 +1,240 @@
+// Whole-database JSON archives, for moving between machines without sync.
+// An archive holds every list and task, the due date history and the
+// settings store, and is checked against archive.schema.json before
+// anything is imported.
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, from_value, to_string_pretty, Value};
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::{algorithm::{add_stored_due_events, get_stored_due_events}, history::StoredDueEvent, task::{get_saved_lists, get_saved_tasks, remove_synced_list, remove_synced_task, save_list_entry, save_task_entry, tasks_loaded, ListEntry, TaskEntry}, utils::now};
+
+pub const ARCHIVE_FORMAT: &str = "taskmgr-archive";
+pub const ARCHIVE_VERSION: u64 = 1;
+const SCHEMA: &str = include_str!("archive.schema.json");
+/// Schema errors past this many are only counted
+const MAX_REPORTED_ERRORS: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Archive {
+    pub format: String,
+    pub version: u64,
+    pub exported: i64,
+    pub lists: Vec<ListEntry>,
+    /// Tasks by list id
+    pub tasks: BTreeMap<String, Vec<TaskEntry>>,
+    pub due_events: Vec<StoredDueEvent>,
+    #[serde(default)]
+    pub settings: Option<Value>
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Keep local items; archived ones are added, or replace local ones
+    /// edited earlier
+    Merge,
+    /// Make the database match the archive
+    Replace
+}
+
+/// How many items an import added, updated, kept or removed.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ItemCounts {
+    pub added: usize,
+    pub updated: usize,
+    /// Local copy was edited later (or at the same time)
+    pub kept: usize,
+    pub removed: usize
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub mode: Option<ImportMode>,
+    pub lists: ItemCounts,
+    pub tasks: ItemCounts,
+    pub due_events: ItemCounts,
+    /// The archived settings store, for the app to apply
+    pub settings: Option<Value>,
+    /// Items that were skipped, and why
+    pub skipped: Vec<String>
+}
+
+/// Everything in the open databases, with `settings` as given.
+pub async fn build_archive(settings: Option<Value>) -> Result<Archive, String> {
+    let lists = get_saved_lists().await
+        .or_else(|e| Err(format!("Export Error: {}", e)))?;
+    let mut tasks = BTreeMap::new();
+    for list in &lists {
+        let saved = get_saved_tasks(&list.uuid).await
+            .or_else(|e| Err(format!("Export Error: {}", e)))?;
+        tasks.insert(list.uuid.clone(), saved);
+    }
+    let due_events = get_stored_due_events().await
+        .or_else(|e| Err(format!("Export Error: {}", e)))?;
+    Ok(Archive {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        exported: now(),
+        lists,
+        tasks,
+        due_events,
+        settings
+    })
+}
+
+/// Everything wrong with `value` as an archive, as `<path>: <problem>`.
+/// Empty if it's a valid archive this version can read.
+pub fn validate_archive(value: &Value) -> Vec<String> {
+    if let Some(version) = value.get("version").and_then(|v| v.as_u64()) {
+        if version > ARCHIVE_VERSION {
+            return vec![format!("This archive is from a newer version of Task Manager (format v{}).", version)];
+        }
+    }
+    let schema: Value = from_str(SCHEMA).expect("Invalid archive schema");
+    let validator = jsonschema::validator_for(&schema).expect("Invalid archive schema");
+    let mut errors: Vec<String> = validator.iter_errors(value)
+        .map(|e| {
+            let path = e.instance_path.to_string();
+            format!("{}: {}", if path.is_empty() { "/" } else { &path }, e)
+        })
+        .collect();
+    if errors.len() > MAX_REPORTED_ERRORS {
+        let more = errors.len() - MAX_REPORTED_ERRORS;
+        errors.truncate(MAX_REPORTED_ERRORS);
+        errors.push(format!("...and {} more.", more));
+    }
+    errors
+}
+
+/// Reads and validates archive text.
+pub fn parse_archive(text: &str) -> Result<Archive, String> {
+    let value: Value = from_str(text)
+        .or_else(|e| Err(format!("Not a JSON file: {}", e)))?;
+    let errors = validate_archive(&value);
+    if !errors.is_empty() {
+        return Err(format!("Invalid archive:\n{}", errors.join("\n")));
+    }
+    from_value(value).or_else(|e| Err(format!("Invalid archive: {}", e)))
+}
+
+/// Whether an archived item should replace the local one.
+fn archived_is_newer(local: Option<i64>, archived: Option<i64>) -> bool {
+    archived.unwrap_or(-1) > local.unwrap_or(-1)
+}
+
+/// Writes `archive` into the open databases. Nothing is changed if the
+/// archive refers to tasks in lists it doesn't have and that don't exist
+/// locally either; those are skipped and reported instead.
+pub async fn apply_archive(archive: &Archive, mode: ImportMode) -> Result<ImportReport, String> {
+    let err = |e: sqlx::Error| format!("Import Error: {}", e);
+    let mut report = ImportReport { mode: Some(mode), settings: archive.settings.clone(), ..Default::default() };
+    let local_lists: HashMap<String, ListEntry> = get_saved_lists().await.map_err(err)?
+        .into_iter().map(|l| (l.uuid.clone(), l)).collect();
+    let archived_ids: HashSet<&str> = archive.lists.iter().map(|l| l.uuid.as_str()).collect();
+
+    if mode == ImportMode::Replace {
+        for (id, _) in local_lists.iter().filter(|(id, _)| !archived_ids.contains(id.as_str())) {
+            for task in get_saved_tasks(id).await.map_err(err)? {
+                remove_synced_task(id, &task.id).await.map_err(err)?;
+                report.tasks.removed += 1;
+            }
+            remove_synced_list(id).await.map_err(err)?;
+            report.lists.removed += 1;
+        }
+    }
+    for list in &archive.lists {
+        match local_lists.get(&list.uuid) {
+            None => report.lists.added += 1,
+            Some(local) if mode == ImportMode::Replace || archived_is_newer(local.last_edited, list.last_edited) => report.lists.updated += 1,
+            Some(_) => {
+                report.lists.kept += 1;
+                continue;
+            }
+        }
+        save_list_entry(list).await.map_err(err)?;
+    }
+    for (list, tasks) in &archive.tasks {
+        if !archived_ids.contains(list.as_str()) && !local_lists.contains_key(list) {
+            report.skipped.push(format!("{} tasks in list {}, which isn't in the archive.", tasks.len(), list));
+            continue;
+        }
+        let local_tasks: HashMap<String, TaskEntry> = get_saved_tasks(list).await.map_err(err)?
+            .into_iter().map(|t| (t.id.clone(), t)).collect();
+        if mode == ImportMode::Replace {
+            let keep: HashSet<&str> = tasks.iter().map(|t| t.id.as_str()).collect();
+            for id in local_tasks.keys().filter(|id| !keep.contains(id.as_str())) {
+                remove_synced_task(list, id).await.map_err(err)?;
+                report.tasks.removed += 1;
+            }
+        }
+        for task in tasks {
+            match local_tasks.get(&task.id) {
+                None => report.tasks.added += 1,
+                Some(local) if mode == ImportMode::Replace || archived_is_newer(local.last_edited, task.last_edited) => report.tasks.updated += 1,
+                Some(_) => {
+                    report.tasks.kept += 1;
+                    continue;
+                }
+            }
+            save_task_entry(list, task).await.map_err(err)?;
+        }
+    }
+    if mode == ImportMode::Replace {
+        // Archived lists with no tasks entry have no tasks
+        for list in archive.lists.iter().filter(|l| !archive.tasks.contains_key(&l.uuid)) {
+            for task in get_saved_tasks(&list.uuid).await.map_err(err)? {
+                remove_synced_task(&list.uuid, &task.id).await.map_err(err)?;
+                report.tasks.removed += 1;
+            }
+        }
+    }
+
+    let local_events = get_stored_due_events().await.map_err(err)?;
+    let new_events: Vec<StoredDueEvent> = match mode {
+        ImportMode::Replace => {
+            report.due_events.removed = local_events.len();
+            archive.due_events.clone()
+        },
+        ImportMode::Merge => archive.due_events.iter()
+            .filter(|e| !local_events.contains(e))
+            .cloned()
+            .collect()
+    };
+    report.due_events.kept = archive.due_events.len() - new_events.len();
+    report.due_events.added = new_events.len();
+    add_stored_due_events(&new_events, mode == ImportMode::Replace).await?;
+    Ok(report)
+}
+
+/// Writes every list, task and due event, plus `settings` (the app's
+/// settings store), to a JSON archive at `path`.
+#[tauri::command]
+pub async fn export_all(path: String, settings: Option<Value>) -> Result<(), String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let archive = build_archive(settings).await?;
+    fs::write(&path, to_string_pretty(&archive).unwrap())
+        .or_else(|e| Err(format!("Export Error: {}", e)))
+}
+
+/// Imports a JSON archive. The whole file is validated first, so a bad
+/// archive changes nothing.
+#[tauri::command]
+pub async fn import_all<R: Runtime>(app: AppHandle<R>, path: String, mode: ImportMode) -> Result<ImportReport, String> {
+    let report = import_archive_file(&path, mode).await?;
+    let _ = app.emit("tasks-imported", report.tasks.added + report.tasks.updated);
+    Ok(report)
+}
+
+pub async fn import_archive_file(path: &str, mode: ImportMode) -> Result<ImportReport, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let text = fs::read_to_string(path)
+        .or_else(|e| Err(format!("Import Error: {}", e)))?;
+    let archive = parse_archive(&text)?;
+    apply_archive(&archive, mode).await
+}
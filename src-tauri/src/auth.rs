@@ -0,0 +1,441 @@
+use std::{fmt, time::Duration};
+
+use reqwest::{header, Method, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, json, Value};
+
+use crate::{http::{api_root, login_request, request_to, start_session, SessionCookie}, utils::now};
+
+/// Refresh this long before the access token runs out, so a request isn't
+/// sent with a token that expires on the way.
+const REFRESH_MARGIN_MS: i64 = 30 * 1000;
+/// OAuth client id the app identifies itself with.
+pub const OAUTH_CLIENT_ID: &str = "dev.pgil.forkbomb.taskmgr";
+pub const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Poll interval when the server doesn't give one (RFC 8628 §3.2)
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 5;
+/// Added to the interval each time the server says `slow_down`
+const SLOW_DOWN_SECS: u64 = 5;
+/// Never polls faster than this, even if the server asks for no wait
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+static mut DEVICE_LOGIN: Option<PendingDeviceLogin> = None;
+
+/// How the app is signed in to a server.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Session {
+    /// Servers without `/auth/token` (cookie from `GET /login`)
+    Cookie(SessionCookie),
+    Bearer(TokenSet)
+}
+
+impl Session {
+    /// Whether the session can no longer be used or refreshed.
+    pub fn is_expired(&self, now: i64) -> bool {
+        match self {
+            Session::Cookie(cookie) => cookie.is_expired(now),
+            Session::Bearer(tokens) => tokens.refresh_expires.is_some_and(|e| e <= now)
+        }
+    }
+}
+
+/// Access and refresh tokens from `POST /auth/token`. Expiry times are
+/// milliseconds since the epoch.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TokenSet {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub access_expires: i64,
+    pub refresh_expires: Option<i64>
+}
+
+impl TokenSet {
+    pub fn needs_refresh(&self, now: i64) -> bool {
+        self.access_expires - REFRESH_MARGIN_MS <= now
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: String,
+    expires_in: i64,
+    /// OAuth servers may leave this out, in which case the session ends
+    /// with the access token
+    #[serde(default)]
+    refresh_token: String,
+    refresh_expires_in: Option<i64>
+}
+
+impl TokenResponse {
+    fn into_tokens(self, now: i64) -> Result<TokenSet, AuthError> {
+        if !self.token_type.eq_ignore_ascii_case("bearer") {
+            return Err(AuthError::Rejected(format!("Unsupported token type {}.", self.token_type)));
+        }
+        let access_expires = now + self.expires_in * 1000;
+        let refresh_expires = if self.refresh_token.is_empty() {
+            Some(access_expires)
+        } else {
+            self.refresh_expires_in.map(|s| now + s * 1000)
+        };
+        Ok(TokenSet {
+            access_token: self.access_token,
+            refresh_token: self.refresh_token,
+            access_expires,
+            refresh_expires
+        })
+    }
+}
+
+/// Why a request to the sync server failed.
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// Wrong username, password or reset code
+    InvalidCredentials,
+    /// 401: the session is gone and the user has to sign in again
+    SessionExpired,
+    /// 403 on anything but a sign-in
+    Forbidden,
+    /// 429, with the server's `Retry-After` in seconds
+    RateLimited(Option<u64>),
+    /// 5xx
+    Server(u16),
+    /// Any other unsuccessful status
+    Unexpected(u16),
+    /// The server refused the request and said why (e.g. a taken username)
+    Rejected(String),
+    Network(String)
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => write!(f, "Invalid credentials."),
+            AuthError::SessionExpired => write!(f, "Your session has expired. Please sign in again."),
+            AuthError::Forbidden => write!(f, "The server refused access to this account."),
+            AuthError::RateLimited(Some(secs)) => write!(f, "Too many attempts. Try again in {} seconds.", secs),
+            AuthError::RateLimited(None) => write!(f, "Too many attempts. Try again later."),
+            AuthError::Server(status) => write!(f, "The sync server had a problem (HTTP {}). Try again later.", status),
+            AuthError::Unexpected(status) => write!(f, "HTTP Error: server responded {}", status),
+            AuthError::Rejected(msg) => write!(f, "{}", msg),
+            AuthError::Network(e) => write!(f, "HTTP Error: {}", e)
+        }
+    }
+}
+
+impl AuthError {
+    /// The error for an unsuccessful status, or `None` for a 2xx.
+    pub fn from_status(status: StatusCode, retry_after: Option<u64>) -> Option<AuthError> {
+        if status.is_success() {
+            return None;
+        }
+        Some(match status {
+            StatusCode::UNAUTHORIZED => AuthError::SessionExpired,
+            StatusCode::FORBIDDEN => AuthError::Forbidden,
+            StatusCode::TOO_MANY_REQUESTS => AuthError::RateLimited(retry_after),
+            s if s.is_server_error() => AuthError::Server(s.as_u16()),
+            s => AuthError::Unexpected(s.as_u16())
+        })
+    }
+
+    pub fn from_response(response: &Response) -> Option<AuthError> {
+        let retry_after = response.headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok());
+        AuthError::from_status(response.status(), retry_after)
+    }
+
+    /// Like `from_response`, but uses the body as the message for 400/409,
+    /// where the reference server explains what was wrong.
+    async fn from_response_body(response: Response) -> AuthError {
+        let error = AuthError::from_response(&response).unwrap_or(AuthError::Unexpected(response.status().as_u16()));
+        if response.status() != StatusCode::BAD_REQUEST && response.status() != StatusCode::CONFLICT {
+            return error;
+        }
+        match response.text().await {
+            Ok(msg) if !msg.trim().is_empty() => AuthError::Rejected(msg.trim().to_string()),
+            _ => error
+        }
+    }
+}
+
+fn network(e: reqwest::Error) -> AuthError {
+    AuthError::Network(format!("{}", e))
+}
+
+/// The `error` code of an OAuth error response (RFC 6749 §5.2).
+fn oauth_error(body: &str) -> Option<String> {
+    from_str::<Value>(body).ok()
+        .and_then(|v| v.get("error").and_then(|e| e.as_str()).map(|e| e.to_string()))
+}
+
+/// The error for a 400 from `/auth/token`. OAuth servers answer a refused
+/// password or refresh token with `invalid_grant`.
+async fn token_error(response: Response, invalid_grant: AuthError) -> AuthError {
+    let body = response.text().await.unwrap_or_default();
+    match oauth_error(&body).as_deref() {
+        Some("invalid_grant") => invalid_grant,
+        Some(e) => AuthError::Rejected(format!("Sign-in failed ({}).", e)),
+        None => AuthError::Unexpected(StatusCode::BAD_REQUEST.as_u16())
+    }
+}
+
+/// Signs in with a password. Uses bearer tokens, falling back to the
+/// cookie from `GET /login` on servers that don't have `/auth/token`.
+pub async fn password_login(root: &str, username: &str, password: &str) -> Result<Session, AuthError> {
+    let response = request_to(root, "/auth/token", Method::POST)
+        .form(&[("grant_type", "password"), ("username", username), ("password", password)])
+        .send().await.map_err(network)?;
+    match response.status() {
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => cookie_login(root, username, password).await,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(AuthError::InvalidCredentials),
+        StatusCode::BAD_REQUEST => Err(token_error(response, AuthError::InvalidCredentials).await),
+        s if s.is_success() => {
+            let body: TokenResponse = response.json().await.map_err(|e| AuthError::Rejected(format!("JSON Error: {}", e)))?;
+            Ok(Session::Bearer(body.into_tokens(now())?))
+        },
+        _ => Err(AuthError::from_response(&response).unwrap())
+    }
+}
+
+async fn cookie_login(root: &str, username: &str, password: &str) -> Result<Session, AuthError> {
+    let response = request_to(root, "/login", Method::GET)
+        .basic_auth(username, Some(password))
+        .send().await.map_err(network)?;
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => return Err(AuthError::InvalidCredentials),
+        s if !s.is_success() => return Err(AuthError::from_response(&response).unwrap()),
+        _ => {}
+    }
+    response.headers().get_all(header::SET_COOKIE).iter()
+        .filter_map(|v| v.to_str().ok().and_then(|v| SessionCookie::parse(v, now())))
+        .find(|c| !c.is_expired(now()))
+        .map(Session::Cookie)
+        .ok_or(AuthError::Rejected("The server didn't start a session.".to_string()))
+}
+
+/// Trades a refresh token for new tokens. `SessionExpired` means the
+/// refresh token is no longer valid.
+pub async fn refresh(root: &str, tokens: &TokenSet) -> Result<TokenSet, AuthError> {
+    let response = request_to(root, "/auth/token", Method::POST)
+        .form(&[("grant_type", "refresh_token"), ("refresh_token", tokens.refresh_token.as_str())])
+        .send().await.map_err(network)?;
+    if response.status() == StatusCode::BAD_REQUEST {
+        return Err(token_error(response, AuthError::SessionExpired).await);
+    }
+    if let Some(e) = AuthError::from_response(&response) {
+        return Err(match e {
+            AuthError::Forbidden => AuthError::SessionExpired,
+            e => e
+        });
+    }
+    let body: TokenResponse = response.json().await.map_err(|e| AuthError::Rejected(format!("JSON Error: {}", e)))?;
+    body.into_tokens(now())
+}
+
+/// Creates an account on the current server and signs in to it.
+#[tauri::command]
+pub async fn register_account(username: String, password: String) -> Result<bool, String> {
+    let response = request_to(&api_root(), "/auth/register", Method::POST)
+        .json(&json!({ "username": username, "password": password }))
+        .send().await.map_err(|e| network(e).to_string())?;
+    match response.status() {
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => {
+            return Err("This sync server doesn't allow creating accounts.".to_string());
+        },
+        s if !s.is_success() => return Err(AuthError::from_response_body(response).await.to_string()),
+        _ => {}
+    }
+    login_request(&username, &password).await?;
+    Ok(true)
+}
+
+/// Asks the server to send a password reset code. Succeeds whether or not
+/// the account exists.
+#[tauri::command]
+pub async fn request_password_reset(username: String) -> Result<(), String> {
+    let response = request_to(&api_root(), "/auth/reset", Method::POST)
+        .json(&json!({ "username": username }))
+        .send().await.map_err(|e| network(e).to_string())?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err("This sync server doesn't support password resets.".to_string());
+    }
+    if !response.status().is_success() {
+        return Err(AuthError::from_response_body(response).await.to_string());
+    }
+    Ok(())
+}
+
+/// Sets a new password with a reset code. Signs out every session of the
+/// account, this one included.
+#[tauri::command]
+pub async fn reset_password(username: String, code: String, new_password: String) -> Result<(), String> {
+    let response = request_to(&api_root(), "/auth/reset/confirm", Method::POST)
+        .json(&json!({ "username": username, "code": code, "new_password": new_password }))
+        .send().await.map_err(|e| network(e).to_string())?;
+    match response.status() {
+        StatusCode::FORBIDDEN => Err("Invalid or expired reset code.".to_string()),
+        s if !s.is_success() => Err(AuthError::from_response_body(response).await.to_string()),
+        _ => Ok(())
+    }
+}
+
+/// What to show the user while a device sign-in is waiting for approval.
+#[derive(Serialize, Clone, Debug)]
+pub struct DeviceLogin {
+    pub user_code: String,
+    pub verification_uri: String,
+    /// The URI with the code filled in, for a link or QR code
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: i64,
+    pub interval: u64
+}
+
+#[derive(Deserialize)]
+struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    verification_uri_complete: Option<String>,
+    expires_in: i64,
+    interval: Option<u64>
+}
+
+#[derive(Clone)]
+struct PendingDeviceLogin {
+    root: String,
+    device_code: String,
+    interval: u64,
+    /// Milliseconds since the epoch
+    expires: i64
+}
+
+/// One answer from the token endpoint while polling for a device code.
+#[derive(Debug, PartialEq)]
+pub enum DevicePoll {
+    Pending,
+    SlowDown,
+    Approved(TokenSet),
+    Denied,
+    Expired
+}
+
+impl DevicePoll {
+    /// Reads a token endpoint response as described in RFC 8628 §3.5.
+    pub fn parse(status: StatusCode, body: &str, now: i64) -> Result<DevicePoll, AuthError> {
+        if status.is_success() {
+            let body: TokenResponse = from_str(body).map_err(|e| AuthError::Rejected(format!("JSON Error: {}", e)))?;
+            return Ok(DevicePoll::Approved(body.into_tokens(now)?));
+        }
+        match oauth_error(body).as_deref() {
+            Some("authorization_pending") => Ok(DevicePoll::Pending),
+            Some("slow_down") => Ok(DevicePoll::SlowDown),
+            Some("access_denied") => Ok(DevicePoll::Denied),
+            Some("expired_token") => Ok(DevicePoll::Expired),
+            Some(e) => Err(AuthError::Rejected(format!("Sign-in failed ({}).", e))),
+            None => Err(AuthError::from_status(status, None).unwrap_or(AuthError::Unexpected(status.as_u16())))
+        }
+    }
+}
+
+/// Starts an OAuth 2.0 device authorization (RFC 8628) with the current
+/// server. Show the code and URI to the user, then call
+/// `poll_device_login`.
+#[tauri::command]
+pub async fn start_device_login() -> Result<DeviceLogin, String> {
+    let root = api_root();
+    let response = request_to(&root, "/auth/device", Method::POST)
+        .form(&[("client_id", OAUTH_CLIENT_ID)])
+        .send().await.map_err(|e| network(e).to_string())?;
+    if response.status() == StatusCode::NOT_FOUND || response.status() == StatusCode::METHOD_NOT_ALLOWED {
+        return Err("This sync server doesn't support signing in with a code.".to_string());
+    }
+    if let Some(e) = AuthError::from_response(&response) {
+        return Err(e.to_string());
+    }
+    let body: DeviceAuthorization = response.json().await.map_err(|e| format!("JSON Error: {}", e))?;
+    let interval = body.interval.unwrap_or(DEFAULT_POLL_INTERVAL_SECS);
+    unsafe {
+        DEVICE_LOGIN = Some(PendingDeviceLogin {
+            root,
+            device_code: body.device_code,
+            interval,
+            expires: now() + body.expires_in * 1000
+        });
+    }
+    Ok(DeviceLogin {
+        user_code: body.user_code,
+        verification_uri: body.verification_uri,
+        verification_uri_complete: body.verification_uri_complete,
+        expires_in: body.expires_in,
+        interval
+    })
+}
+
+/// Waits for the user to approve the device sign-in started by
+/// `start_device_login`, then signs in. Fails if it's denied, expires or
+/// is cancelled.
+#[tauri::command]
+pub async fn poll_device_login() -> Result<bool, String> {
+    poll_device_login_with(MIN_POLL_INTERVAL).await
+}
+
+/// `poll_device_login`, waiting at least `min_interval` between polls.
+pub(crate) async fn poll_device_login_with(min_interval: Duration) -> Result<bool, String> {
+    loop {
+        let pending = unsafe { DEVICE_LOGIN.clone() };
+        let pending = match pending {
+            Some(p) if p.root == api_root() => p,
+            _ => return Err("Sign-in was cancelled.".to_string())
+        };
+        if now() >= pending.expires {
+            cancel_device_login();
+            return Err("The sign-in code expired. Please start again.".to_string());
+        }
+        tokio::time::sleep(Duration::from_secs(pending.interval).max(min_interval)).await;
+        let response = request_to(&pending.root, "/auth/token", Method::POST)
+            .form(&[
+                ("grant_type", DEVICE_CODE_GRANT),
+                ("device_code", &pending.device_code),
+                ("client_id", OAUTH_CLIENT_ID)
+            ])
+            .send().await.map_err(|e| network(e).to_string())?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        // Cancelled (or restarted) while waiting
+        if unsafe { DEVICE_LOGIN.as_ref().map(|p| &p.device_code) } != Some(&pending.device_code) {
+            return Err("Sign-in was cancelled.".to_string());
+        }
+        match DevicePoll::parse(status, &body, now()).map_err(|e| e.to_string())? {
+            DevicePoll::Pending => {},
+            DevicePoll::SlowDown => unsafe {
+                if let Some(p) = DEVICE_LOGIN.as_mut() {
+                    p.interval += SLOW_DOWN_SECS;
+                }
+            },
+            DevicePoll::Approved(tokens) => {
+                cancel_device_login();
+                start_session(Session::Bearer(tokens));
+                return Ok(true);
+            },
+            DevicePoll::Denied => {
+                cancel_device_login();
+                return Err("Sign-in was denied.".to_string());
+            },
+            DevicePoll::Expired => {
+                cancel_device_login();
+                return Err("The sign-in code expired. Please start again.".to_string());
+            }
+        }
+    }
+}
+
+/// Stops a device sign-in; a running `poll_device_login` gives up.
+#[tauri::command]
+pub fn cancel_device_login() {
+    unsafe {
+        DEVICE_LOGIN = None;
+    }
+}
@@ -0,0 +1,118 @@
+// Sync transports. A backend pulls pages of changes after a cursor and
+// accepts pushed changes; the sync run itself (paging, resumable pushes,
+// merging) is the same for all of them.
+use serde::Serialize;
+
+use crate::{http::{check_timestamp, SyncData, SyncOptions}, storage::SyncState, task::{apply_remote_changes, chunk_changes, collect_local_changes, load_sync_state, merge_remote, preview_changes, save_sync_state, SyncPreview}, utils::now};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum AuthStatus {
+    SignedIn,
+    SignedOut
+}
+
+/// Somewhere tasks are synced with.
+pub trait SyncBackend {
+    /// Sync progress with this backend is saved under this key.
+    fn id(&self) -> String;
+
+    fn auth_status(&self) -> AuthStatus;
+
+    /// One page of changes after `cursor` (from the start if `None`). The
+    /// page's `cursor` is where the next one starts; backends that can't
+    /// page return everything with no cursor, and the changes are merged
+    /// in one go instead.
+    async fn pull(&self, cursor: Option<&str>, limit: u32) -> Result<SyncData, String>;
+
+    async fn push(&self, data: &SyncData) -> Result<(), String>;
+}
+
+async fn save_state(state: &SyncState) -> Result<(), String> {
+    save_sync_state(state).await
+        .map(|_| ())
+        .or_else(|e| Err(format!("Sync State Error: {}", e)))
+}
+
+/// Syncs with `backend`. Paged backends are pulled page by page and pushed
+/// to in chunks, with progress saved after each step so an interrupted sync
+/// resumes instead of starting over. Returns `false` if it stopped early
+/// because of `max_pages`.
+pub async fn sync_with<B: SyncBackend>(backend: &B, options: &SyncOptions) -> Result<bool, String> {
+    // Check connection is active
+    if backend.auth_status() != AuthStatus::SignedIn {
+        return Err("Not logged in.".to_string());
+    }
+    let mut state = load_sync_state(&backend.id()).await
+        .or_else(|e| Err(format!("Sync State Error: {}", e)))?;
+    let started = now();
+    let data = backend.pull(state.cursor.as_deref(), options.page_size).await?;
+    if data.cursor.is_none() {
+        return legacy_sync(backend, &data).await.map(|_| true);
+    }
+    // Pull
+    let mut page = data;
+    let mut pages = 1;
+    loop {
+        apply_remote_changes(&page, state.pushed_until).await
+            .or_else(|e| Err(format!("Compare and Save Error: {}", e)))?;
+        state.cursor = page.cursor.clone();
+        save_state(&state).await?;
+        if !page.has_more.unwrap_or(false) { break; }
+        if options.max_pages.is_some_and(|max| pages >= max) { return Ok(false); }
+        page = backend.pull(state.cursor.as_deref(), options.page_size).await?;
+        pages += 1;
+    }
+    // Push
+    if state.push_started.is_none() {
+        state.push_started = Some(started);
+        state.push_position = None;
+        save_state(&state).await?;
+    }
+    let until = state.push_started.unwrap();
+    let changes = collect_local_changes(state.pushed_until, until).await
+        .or_else(|e| Err(format!("Compare and Save Error: {}", e)))?;
+    for (chunk, last_key) in chunk_changes(changes, state.push_position.as_deref(), options.push_chunk) {
+        backend.push(&chunk).await?;
+        state.push_position = Some(last_key);
+        save_state(&state).await?;
+    }
+    state.pushed_until = until;
+    state.push_started = None;
+    state.push_position = None;
+    save_state(&state).await?;
+    Ok(true)
+}
+
+/// Single-pull sync for backends that can't page.
+async fn legacy_sync<B: SyncBackend>(backend: &B, data: &SyncData) -> Result<(), String> {
+    let to_post = merge_remote(data).await
+        .or_else(|e| Err(format!("Compare and Save Error: {}", e)))?;
+    if let Some(to_post) = to_post {
+        backend.push(&to_post).await?;
+    }
+    Ok(())
+}
+
+/// Dry run of `sync_with`: pulls everything the backend would send and
+/// reports what a sync would create, update or overwrite on each side.
+/// Nothing is saved locally (not even the cursor) and nothing is pushed.
+pub async fn preview_with<B: SyncBackend>(backend: &B, options: &SyncOptions) -> Result<SyncPreview, String> {
+    if backend.auth_status() != AuthStatus::SignedIn {
+        return Err("Not logged in.".to_string());
+    }
+    let state = load_sync_state(&backend.id()).await
+        .or_else(|e| Err(format!("Sync State Error: {}", e)))?;
+    let mut data = backend.pull(state.cursor.as_deref(), options.page_size).await?;
+    let paged = data.cursor.is_some();
+    let since = if paged { state.pushed_until } else { check_timestamp(data.last_sync) };
+    let mut page = data.clone();
+    while paged && page.has_more.unwrap_or(false) {
+        page = backend.pull(page.cursor.as_deref(), options.page_size).await?;
+        data.lists.extend(page.lists.iter().cloned());
+        for (list, tasks) in &page.tasks {
+            data.tasks.entry(list.clone()).or_default().extend(tasks.iter().cloned());
+        }
+    }
+    preview_changes(&data, since).await
+        .or_else(|e| Err(format!("Compare and Save Error: {}", e)))
+}
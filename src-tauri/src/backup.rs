@@ -0,0 +1,162 @@
+// Local backups of the task and history databases in `<app data>/backups`.
+//
+// Each backup is a folder named for when it was made, holding both
+// databases copied with SQLite's online backup API, so the copies are
+// consistent while the app keeps writing. Every copy has to pass
+// `PRAGMA integrity_check` or the backup is thrown away. One is made when
+// the app starts and then once a day, and older ones are thinned out the
+// same way as WebDAV snapshots.
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde::Serialize;
+
+use crate::{algorithm::HISTORY_PATH, ics::{format_utc, parse_datetime}, restore::{data_dir, replace_databases, snapshots_to_keep, BackupResult, RESTORE_SUFFIX}, storage::{snapshot_db, verify_db}, task::TASKS_PATH, utils::{now, DAY_MS}};
+
+const BACKUPS_PATH: &str = "/backups";
+/// Backups kept for the most recent days and weeks
+const KEEP_DAILY: usize = 7;
+const KEEP_WEEKLY: usize = 4;
+const BACKUP_INTERVAL_MS: i64 = DAY_MS;
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+/// Bumped to stop the running backup schedule.
+static SCHEDULE: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LocalBackup {
+    pub name: String,
+    pub created: i64,
+    /// Bytes taken by both databases
+    pub size: u64
+}
+
+/// The databases a backup holds, and a table each must have.
+fn databases() -> [(&'static str, &'static str); 2] {
+    [(TASKS_PATH, "Lists"), (HISTORY_PATH, "DueEvents")]
+}
+
+/// When the backup called `name` was made, if that's a backup name.
+fn backup_time(name: &str) -> Option<i64> {
+    parse_datetime(name).filter(|time| format_utc(*time) == name)
+}
+
+/// Backups in `dir`, newest first.
+pub fn backups_in(dir: &str) -> Result<Vec<LocalBackup>, String> {
+    let folder = dir.to_string() + BACKUPS_PATH;
+    if !Path::new(&folder).exists() {
+        return Ok(vec![]);
+    }
+    let entries = fs::read_dir(&folder).or_else(|e| Err(format!("Couldn't read {}: {}", folder, e)))?;
+    let mut backups = vec![];
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(created) = backup_time(&name).filter(|_| entry.path().is_dir()) else {
+            continue;
+        };
+        let size = databases().iter()
+            .filter_map(|(db, _)| fs::metadata(entry.path().to_string_lossy().to_string() + db).ok())
+            .map(|m| m.len())
+            .sum();
+        backups.push(LocalBackup { name, created, size });
+    }
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created));
+    Ok(backups)
+}
+
+/// Copies both databases in `dir` into a new backup and checks the copies,
+/// then removes the backups retention no longer keeps.
+pub async fn backup_in(dir: &str, daily: usize, weekly: usize) -> Result<BackupResult, String> {
+    if !Path::new(&(dir.to_string() + TASKS_PATH)).exists() {
+        return Err("There's nothing to back up yet.".to_string());
+    }
+    let created = now();
+    let name = format_utc(created);
+    let folder = dir.to_string() + BACKUPS_PATH + "/" + &name;
+    if Path::new(&folder).exists() {
+        return Err("A backup was just made.".to_string());
+    }
+    fs::create_dir_all(&folder).or_else(|e| Err(format!("Couldn't create {}: {}", folder, e)))?;
+    for (db, table) in databases() {
+        let path = dir.to_string() + db;
+        let copy = folder.clone() + db;
+        let copied = match snapshot_db(&path, &copy).await {
+            Ok(_) => verify_db(&copy, table).await.or_else(|e| Err(format!("The copy of {} failed its check ({}).", db.trim_start_matches('/'), e))),
+            Err(e) => Err(format!("Couldn't copy {}: {}", path, e))
+        };
+        if let Err(e) = copied {
+            let _ = fs::remove_dir_all(&folder);
+            return Err(e);
+        }
+    }
+
+    let backups = backups_in(dir)?;
+    let times: Vec<i64> = backups.iter().map(|b| b.created).collect();
+    let keep = snapshots_to_keep(&times, daily.max(1), weekly);
+    let mut removed = 0;
+    for backup in backups.iter().filter(|b| !keep.contains(&b.created) && b.name != name) {
+        fs::remove_dir_all(dir.to_string() + BACKUPS_PATH + "/" + &backup.name)
+            .or_else(|e| Err(format!("Couldn't remove backup {}: {}", backup.name, e)))?;
+        removed += 1;
+    }
+    Ok(BackupResult { name, removed })
+}
+
+/// Checks both databases in a backup, and only then replaces the open
+/// databases in `dir` with them; the old files are kept with a
+/// `.pre-restore` suffix.
+pub async fn restore_in(dir: &str, name: &str) -> Result<(), String> {
+    let folder = dir.to_string() + BACKUPS_PATH + "/" + name;
+    if backup_time(name).is_none() || !Path::new(&folder).is_dir() {
+        return Err(format!("There's no backup called {}.", name));
+    }
+    for (db, _) in databases() {
+        let restored = dir.to_string() + db + RESTORE_SUFFIX;
+        if let Err(e) = fs::copy(folder.clone() + db, &restored) {
+            for (db, _) in databases() {
+                let _ = fs::remove_file(dir.to_string() + db + RESTORE_SUFFIX);
+            }
+            return Err(format!("Couldn't copy {} from the backup: {}", db.trim_start_matches('/'), e));
+        }
+    }
+    replace_databases(dir, "backup").await
+}
+
+#[tauri::command]
+pub async fn backup_now() -> Result<BackupResult, String> {
+    backup_in(&data_dir()?, KEEP_DAILY, KEEP_WEEKLY).await
+}
+
+#[tauri::command]
+pub fn list_backups() -> Result<Vec<LocalBackup>, String> {
+    backups_in(&data_dir()?)
+}
+
+#[tauri::command]
+pub async fn restore_backup(name: String) -> Result<(), String> {
+    restore_in(&data_dir()?, &name).await
+}
+
+/// Backs up now, as the app starts, and then once a day while it runs.
+#[tauri::command]
+pub fn start_local_backups() {
+    let generation = SCHEDULE.fetch_add(1, Ordering::SeqCst) + 1;
+    tauri::async_runtime::spawn(async move {
+        let mut starting = true;
+        while SCHEDULE.load(Ordering::SeqCst) == generation {
+            let due = starting || list_backups().is_ok_and(|b| b.first().is_none_or(|b| now() - b.created >= BACKUP_INTERVAL_MS));
+            if due {
+                if let Err(e) = backup_now().await {
+                    println!("Local backup failed: {}", e);
+                }
+            }
+            starting = false;
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+/// Stops the backup schedule, e.g. when switching profile.
+pub fn stop_backups() {
+    SCHEDULE.fetch_add(1, Ordering::SeqCst);
+}
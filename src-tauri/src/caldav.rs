@@ -0,0 +1,654 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, read_to_string};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use quick_xml::{escape::escape, events::Event, Reader};
+use reqwest::{header, Client, Method, RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+
+use crate::{http::{self, SyncData}, ics::{self, Component}, task::{apply_remote_changes, collect_local_changes, get_saved_list, get_saved_tasks, is_valid_list_id, remove_synced_list, remove_synced_task, tasks_loaded, DEFAULT_COLOR, ListEntry, PendingChange}, utils::now};
+
+const CALDAV_CONF_PATH: &str = "/caldav.json";
+const CALDAV_STATE_PATH: &str = "/caldav_state.json";
+/// Credential store key prefix for the account password
+const PASSWORD_PREFIX: &str = "caldav:";
+
+/// Set while a sync runs; task events can ask for several at once.
+static SYNCING: AtomicBool = AtomicBool::new(false);
+
+const PROPFIND_HOME: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:" xmlns:cs="http://calendarserver.org/ns/" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:prop><d:resourcetype/><d:displayname/><cs:getctag/><c:supported-calendar-component-set/></d:prop>
+</d:propfind>"#;
+const PROPFIND_ETAGS: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getetag/></d:prop></d:propfind>"#;
+
+/// A calendar collection on the server. Its last path segment is the id of
+/// the list it maps to.
+#[derive(Debug, Clone)]
+struct RemoteCalendar {
+    id: String,
+    href: String,
+    name: String,
+    ctag: Option<String>
+}
+
+/// One `<response>` of a multistatus, with the properties that came back 200.
+#[derive(Debug, Default)]
+pub struct DavResponse {
+    pub href: String,
+    pub props: HashMap<String, String>,
+    pub collection: bool,
+    pub calendar: bool,
+    /// Components from `supported-calendar-component-set`, if given
+    pub components: Option<Vec<String>>
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CalDavState {
+    /// Local edits up to this time have been pushed
+    pushed_until: i64,
+    /// By list id
+    calendars: HashMap<String, CalendarState>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CalendarState {
+    href: String,
+    name: String,
+    ctag: Option<String>,
+    /// By href
+    resources: HashMap<String, Resource>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Resource {
+    uid: String,
+    etag: String
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct CalDavSyncResult {
+    pub pulled: usize,
+    pub pushed: usize,
+    pub deleted: usize
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CalDavAccount {
+    pub url: String,
+    pub username: String
+}
+
+/// Two-way sync of lists (calendars) and tasks (VTODOs) with a CalDAV
+/// calendar home, e.g. `https://dav.example.com/alice/`.
+pub struct CalDavClient {
+    home: Url,
+    username: String,
+    password: String,
+    state_path: String,
+    client: Client
+}
+
+fn db_error(e: sqlx::Error) -> String {
+    format!("Task DB Error: {}", e)
+}
+
+/// Whether an item was edited locally after `since`.
+fn edited_since(last_edited: Option<i64>, since: i64) -> bool {
+    last_edited.is_some_and(|t| t > since)
+}
+
+fn change_key(change: &PendingChange) -> (String, Option<i64>) {
+    let edited = match change {
+        PendingChange::List(l) => l.last_edited,
+        PendingChange::Task(_, t) => t.last_edited
+    };
+    (change.sort_key(), edited)
+}
+
+/// Percent-encodes everything but unreserved characters, for ids in paths.
+fn encode_segment(segment: &str) -> String {
+    segment.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b)
+    }).collect()
+}
+
+/// Parses a WebDAV multistatus. Namespace prefixes are ignored; the names
+/// asked for don't clash.
+pub fn parse_multistatus(xml: &str) -> Result<Vec<DavResponse>, String> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    let mut stack: Vec<String> = vec![];
+    let mut responses = vec![];
+    let mut current = DavResponse::default();
+    let mut props: HashMap<String, String> = HashMap::new();
+    let mut status = String::new();
+    let (mut collection, mut calendar, mut components) = (false, false, None::<Vec<String>>);
+    loop {
+        let event = reader.read_event().or_else(|e| Err(format!("Bad WebDAV response ({}).", e)))?;
+        let (start, empty) = match &event {
+            Event::Start(e) => (Some(e.clone()), false),
+            Event::Empty(e) => (Some(e.clone()), true),
+            _ => (None, false)
+        };
+        if let Some(e) = start {
+            let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+            let parent = stack.last().map(|s| s.as_str()).unwrap_or("");
+            match (parent, name.as_str()) {
+                (_, "response") => current = DavResponse::default(),
+                (_, "propstat") => {
+                    props.clear();
+                    status.clear();
+                    (collection, calendar, components) = (false, false, None);
+                },
+                ("resourcetype", "collection") => collection = true,
+                ("resourcetype", "calendar") => calendar = true,
+                ("supported-calendar-component-set", "comp") => {
+                    if let Ok(Some(attr)) = e.try_get_attribute("name") {
+                        components.get_or_insert_with(Vec::new)
+                            .push(attr.unescape_value().unwrap_or_default().to_ascii_uppercase());
+                    }
+                },
+                ("prop", _) => { props.entry(name.clone()).or_default(); },
+                _ => {}
+            }
+            if name == "supported-calendar-component-set" {
+                components.get_or_insert_with(Vec::new);
+            }
+            if !empty {
+                stack.push(name);
+            }
+            continue;
+        }
+        match event {
+            Event::Text(t) => {
+                let text = t.unescape().unwrap_or_default().to_string();
+                append_text(&stack, &text, &mut current, &mut props, &mut status);
+            },
+            Event::CData(t) => {
+                let text = String::from_utf8_lossy(&t.into_inner()).to_string();
+                append_text(&stack, &text, &mut current, &mut props, &mut status);
+            },
+            Event::End(_) => {
+                match stack.pop().as_deref() {
+                    Some("propstat") if status.contains(" 200") => {
+                        current.props.extend(props.drain());
+                        current.collection |= collection;
+                        current.calendar |= calendar;
+                        if components.is_some() {
+                            current.components = components.take();
+                        }
+                    },
+                    Some("response") => responses.push(std::mem::take(&mut current)),
+                    _ => {}
+                }
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(responses)
+}
+
+fn append_text(stack: &[String], text: &str, current: &mut DavResponse, props: &mut HashMap<String, String>, status: &mut String) {
+    let n = stack.len();
+    match (stack.get(n.wrapping_sub(2)).map(|s| s.as_str()), stack.last().map(|s| s.as_str())) {
+        (Some("response"), Some("href")) => current.href.push_str(text.trim()),
+        (Some("propstat"), Some("status")) => status.push_str(text),
+        (Some("prop"), Some(name)) => props.entry(name.to_string()).or_default().push_str(text),
+        _ => {}
+    }
+}
+
+impl CalDavClient {
+    pub fn new(url: &str, username: &str, password: &str, state_dir: &str) -> Result<CalDavClient, String> {
+        Ok(CalDavClient {
+            home: http::validate_folder_url(url)?,
+            username: username.to_string(),
+            password: password.to_string(),
+            state_path: state_dir.to_string() + CALDAV_STATE_PATH,
+            client: Client::new()
+        })
+    }
+
+    fn request(&self, method: &str, url: &Url) -> RequestBuilder {
+        self.client.request(Method::from_bytes(method.as_bytes()).unwrap(), url.clone())
+            .basic_auth(&self.username, Some(&self.password))
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, String> {
+        let response = request.send().await.or_else(|e| Err(format!("Couldn't reach the CalDAV server: {}", e)))?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED => Err("The CalDAV server rejected the username or password.".to_string()),
+            StatusCode::FORBIDDEN => Err("The CalDAV account can't access that calendar.".to_string()),
+            _ => Ok(response)
+        }
+    }
+
+    async fn multistatus(&self, request: RequestBuilder) -> Result<Vec<DavResponse>, String> {
+        let response = self.send(request).await?;
+        if response.status() != StatusCode::MULTI_STATUS {
+            return Err(format!("CalDAV Error: the server returned {}.", response.status()));
+        }
+        let body = response.text().await.or_else(|e| Err(format!("CalDAV Error: {}", e)))?;
+        parse_multistatus(&body)
+    }
+
+    fn resolve(&self, href: &str) -> Result<Url, String> {
+        self.home.join(href).or_else(|e| Err(format!("CalDAV Error: bad href {} ({}).", href, e)))
+    }
+
+    /// Calendars in the home that can hold tasks.
+    async fn calendars(&self) -> Result<Vec<RemoteCalendar>, String> {
+        let request = self.request("PROPFIND", &self.home)
+            .header("Depth", "1")
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(PROPFIND_HOME);
+        let mut calendars = vec![];
+        for r in self.multistatus(request).await? {
+            if !r.calendar || r.components.as_ref().is_some_and(|c| !c.iter().any(|c| c == "VTODO")) {
+                continue;
+            }
+            let url = self.resolve(&r.href)?;
+            let id = url.path_segments().and_then(|mut s| s.rfind(|s| !s.is_empty())).unwrap_or_default().to_string();
+            if !is_valid_list_id(&id) {
+                continue;
+            }
+            calendars.push(RemoteCalendar {
+                name: r.props.get("displayname").filter(|n| !n.is_empty()).cloned().unwrap_or(id.clone()),
+                ctag: r.props.get("getctag").filter(|c| !c.is_empty()).cloned(),
+                href: url.path().to_string(),
+                id
+            });
+        }
+        Ok(calendars)
+    }
+
+    /// Href → etag of every resource in a calendar.
+    async fn etags(&self, calendar: &str) -> Result<HashMap<String, String>, String> {
+        let request = self.request("PROPFIND", &self.resolve(calendar)?)
+            .header("Depth", "1")
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(PROPFIND_ETAGS);
+        Ok(self.multistatus(request).await?.into_iter()
+            .filter(|r| !r.collection)
+            .filter_map(|r| Some((self.resolve(&r.href).ok()?.path().to_string(), r.props.get("getetag")?.clone())))
+            .collect())
+    }
+
+    /// Href → (etag, iCalendar data) of the given resources.
+    async fn multiget(&self, calendar: &str, hrefs: &[String]) -> Result<Vec<(String, String, String)>, String> {
+        let mut body = String::from(r#"<?xml version="1.0" encoding="utf-8"?>
+<c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:prop><d:getetag/><c:calendar-data/></d:prop>"#);
+        for href in hrefs {
+            body.push_str(&format!("<d:href>{}</d:href>", escape(href.as_str())));
+        }
+        body.push_str("</c:calendar-multiget>");
+        let request = self.request("REPORT", &self.resolve(calendar)?)
+            .header("Depth", "1")
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(body);
+        Ok(self.multistatus(request).await?.into_iter()
+            .filter_map(|r| Some((
+                self.resolve(&r.href).ok()?.path().to_string(),
+                r.props.get("getetag").cloned().unwrap_or_default(),
+                r.props.get("calendar-data")?.clone()
+            )))
+            .collect())
+    }
+
+    async fn make_calendar(&self, list: &ListEntry) -> Result<String, String> {
+        let url = self.home.join(&format!("{}/", encode_segment(&list.uuid))).or_else(|e| Err(format!("CalDAV Error: {}", e)))?;
+        let body = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+<c:mkcalendar xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:set><d:prop><d:displayname>{}</d:displayname><c:supported-calendar-component-set><c:comp name="VTODO"/></c:supported-calendar-component-set></d:prop></d:set></c:mkcalendar>"#,
+            escape(list.name.as_str()));
+        let response = self.send(self.request("MKCALENDAR", &url)
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(body)).await?;
+        // 405: it's there already
+        if !response.status().is_success() && response.status() != StatusCode::METHOD_NOT_ALLOWED {
+            return Err(format!("CalDAV Error: couldn't create a calendar for {} ({}).", list.name, response.status()));
+        }
+        Ok(url.path().to_string())
+    }
+
+    async fn rename_calendar(&self, href: &str, name: &str) -> Result<(), String> {
+        let body = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propertyupdate xmlns:d="DAV:"><d:set><d:prop><d:displayname>{}</d:displayname></d:prop></d:set></d:propertyupdate>"#,
+            escape(name));
+        let response = self.send(self.request("PROPPATCH", &self.resolve(href)?)
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(body)).await?;
+        if !response.status().is_success() {
+            return Err(format!("CalDAV Error: couldn't rename {} ({}).", name, response.status()));
+        }
+        Ok(())
+    }
+
+    /// DELETE, only if unchanged since `etag` when one is known.
+    async fn delete(&self, href: &str, etag: Option<&str>) -> Result<bool, String> {
+        let mut request = self.request("DELETE", &self.resolve(href)?);
+        if let Some(etag) = etag.filter(|e| !e.is_empty()) {
+            request = request.header(header::IF_MATCH, etag);
+        }
+        let status = self.send(request).await?.status();
+        Ok(status.is_success() || status == StatusCode::NOT_FOUND)
+    }
+
+    fn load_state(&self) -> CalDavState {
+        read_to_string(&self.state_path).ok().and_then(|s| from_str(&s).ok()).unwrap_or_default()
+    }
+
+    fn save_state(&self, state: &CalDavState) -> Result<(), String> {
+        fs::write(&self.state_path, to_string(state).unwrap()).or_else(|e| Err(format!("{}", e)))
+    }
+
+    /// Checks the URL and credentials.
+    pub async fn check(&self) -> Result<usize, String> {
+        Ok(self.calendars().await?.len())
+    }
+
+    /// Pulls calendars whose ctag changed (fetching only resources whose etag
+    /// changed), then pushes local edits and deletions. Later edits win.
+    pub async fn sync(&self) -> Result<CalDavSyncResult, String> {
+        if !tasks_loaded() {
+            // Everything would look deleted
+            return Err("Tasks aren't loaded yet.".to_string());
+        }
+        let mut state = self.load_state();
+        let mut result = CalDavSyncResult::default();
+        let since = state.pushed_until;
+        let until = now();
+        let mut pulled = HashSet::new();
+
+        let remote = self.calendars().await?;
+        let gone: Vec<String> = state.calendars.keys()
+            .filter(|id| !remote.iter().any(|c| &&c.id == id))
+            .cloned()
+            .collect();
+        for id in gone {
+            state.calendars.remove(&id);
+            let list = get_saved_list(&id).await.or_else(|e| Err(db_error(e)))?;
+            // Edited here since, so it goes back up instead
+            if list.is_some_and(|l| !edited_since(l.last_edited, since)) {
+                remove_synced_list(&id).await.or_else(|e| Err(db_error(e)))?;
+                result.deleted += 1;
+            }
+        }
+        for calendar in &remote {
+            self.pull_calendar(calendar, &mut state, since, until, &mut pulled, &mut result).await?;
+        }
+        state.pushed_until = self.push(&mut state, since, until, &pulled, &mut result).await?;
+        self.save_state(&state)?;
+        Ok(result)
+    }
+
+    async fn pull_calendar(
+        &self,
+        calendar: &RemoteCalendar,
+        state: &mut CalDavState,
+        since: i64,
+        until: i64,
+        pulled: &mut HashSet<(String, Option<i64>)>,
+        result: &mut CalDavSyncResult
+    ) -> Result<(), String> {
+        let known = state.calendars.get(&calendar.id).cloned();
+        let local = get_saved_list(&calendar.id).await.or_else(|e| Err(db_error(e)))?;
+        if local.is_none() && known.is_some() {
+            // Deleted here; the push removes it from the server
+            return Ok(());
+        }
+        // Created or renamed by another client. Stamped with this sync's
+        // time so it isn't pushed straight back.
+        let renamed = known.as_ref().is_some_and(|k| k.name != calendar.name);
+        let list = match &local {
+            None => Some(ListEntry {
+                name: calendar.name.clone(),
+                uuid: calendar.id.clone(),
+                color: DEFAULT_COLOR,
+                last_edited: Some(until),
+                created: Some(until),
+                sealed: None,
+                extra: Default::default()
+            }),
+            Some(l) if renamed && l.name != calendar.name && !edited_since(l.last_edited, since) => Some(ListEntry {
+                name: calendar.name.clone(),
+                last_edited: Some(until),
+                ..l.clone()
+            }),
+            _ => None
+        };
+        if let Some(list) = list {
+            pulled.insert(change_key(&PendingChange::List(list.clone())));
+            let mut data = SyncData::new();
+            data.lists.push(list);
+            result.pulled += apply_remote_changes(&data, since).await.or_else(|e| Err(db_error(e)))?;
+        }
+        let mut cal_state = known.unwrap_or(CalendarState {
+            href: calendar.href.clone(),
+            name: calendar.name.clone(),
+            ctag: None,
+            resources: HashMap::new()
+        });
+        cal_state.name = calendar.name.clone();
+        if cal_state.ctag.is_some() && cal_state.ctag == calendar.ctag {
+            state.calendars.insert(calendar.id.clone(), cal_state);
+            return Ok(());
+        }
+
+        let etags = self.etags(&calendar.href).await?;
+        let changed: Vec<String> = etags.iter()
+            .filter(|(href, etag)| cal_state.resources.get(*href).map(|r| &r.etag) != Some(*etag))
+            .map(|(href, _)| href.clone())
+            .collect();
+        if !changed.is_empty() {
+            let mut data = SyncData::new();
+            for (href, etag, ics_data) in self.multiget(&calendar.href, &changed).await? {
+                let components = match ics::parse(&ics_data) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        println!("Skipping {}: {}", href, e);
+                        continue;
+                    }
+                };
+                // Recurring tasks repeat the UID; the first is the master
+                let task = components.iter()
+                    .flat_map(|c| c.find_all("VTODO"))
+                    .find_map(ics::vtodo_to_task);
+                let task = match task {
+                    Some(task) => task,
+                    None => continue
+                };
+                cal_state.resources.insert(href, Resource { uid: task.id.clone(), etag });
+                pulled.insert(change_key(&PendingChange::Task(calendar.id.clone(), task.clone())));
+                data.tasks.entry(calendar.id.clone()).or_default().push(task);
+            }
+            result.pulled += apply_remote_changes(&data, since).await.or_else(|e| Err(db_error(e)))?;
+        }
+        // Deleted by another client
+        let removed: Vec<String> = cal_state.resources.keys().filter(|href| !etags.contains_key(*href)).cloned().collect();
+        if !removed.is_empty() {
+            let tasks = get_saved_tasks(&calendar.id).await.or_else(|e| Err(db_error(e)))?;
+            for href in removed {
+                let resource = cal_state.resources.remove(&href).unwrap();
+                if tasks.iter().any(|t| t.id == resource.uid && !edited_since(t.last_edited, since)) {
+                    remove_synced_task(&calendar.id, &resource.uid).await.or_else(|e| Err(db_error(e)))?;
+                    result.deleted += 1;
+                }
+            }
+        }
+        cal_state.ctag = calendar.ctag.clone();
+        state.calendars.insert(calendar.id.clone(), cal_state);
+        Ok(())
+    }
+
+    /// Href of the calendar for `list`, creating it if needed.
+    async fn ensure_calendar(&self, state: &mut CalDavState, list: &ListEntry) -> Result<String, String> {
+        if let Some(c) = state.calendars.get(&list.uuid) {
+            return Ok(c.href.clone());
+        }
+        let href = self.make_calendar(list).await?;
+        state.calendars.insert(list.uuid.clone(), CalendarState {
+            href: href.clone(),
+            name: list.name.clone(),
+            ctag: None,
+            resources: HashMap::new()
+        });
+        Ok(href)
+    }
+
+    /// Pushes local edits in `(since, until]` and deletions. Returns the
+    /// time edits are pushed up to.
+    async fn push(
+        &self,
+        state: &mut CalDavState,
+        since: i64,
+        until: i64,
+        pulled: &HashSet<(String, Option<i64>)>,
+        result: &mut CalDavSyncResult
+    ) -> Result<i64, String> {
+        let changes = collect_local_changes(since, until).await.or_else(|e| Err(db_error(e)))?;
+        // How far local edits are pushed; held back for tasks that didn't go up
+        let mut pushed_until = until;
+        for change in changes {
+            if pulled.contains(&change_key(&change)) { continue; }
+            match change {
+                PendingChange::List(list) => {
+                    let existed = state.calendars.contains_key(&list.uuid);
+                    let href = self.ensure_calendar(state, &list).await?;
+                    let cal_state = state.calendars.get_mut(&list.uuid).unwrap();
+                    if existed && cal_state.name != list.name {
+                        self.rename_calendar(&href, &list.name).await?;
+                        cal_state.name = list.name.clone();
+                    }
+                    result.pushed += 1;
+                },
+                PendingChange::Task(list_id, task) => {
+                    let list = match get_saved_list(&list_id).await.or_else(|e| Err(db_error(e)))? {
+                        Some(list) => list,
+                        None => continue
+                    };
+                    let href = self.ensure_calendar(state, &list).await?;
+                    let cal_state = state.calendars.get_mut(&list_id).unwrap();
+                    let existing = cal_state.resources.iter().find(|(_, r)| r.uid == task.id).map(|(h, r)| (h.clone(), r.etag.clone()));
+                    let mut request = match &existing {
+                        Some((href, etag)) if !etag.is_empty() => self.request("PUT", &self.resolve(href)?).header(header::IF_MATCH, etag),
+                        Some((href, _)) => self.request("PUT", &self.resolve(href)?),
+                        None => self.request("PUT", &self.resolve(&format!("{}{}.ics", href, encode_segment(&task.id)))?)
+                            .header(header::IF_NONE_MATCH, "*")
+                    };
+                    let url = request.try_clone().unwrap().build().or_else(|e| Err(format!("CalDAV Error: {}", e)))?.url().path().to_string();
+                    request = request
+                        .header(header::CONTENT_TYPE, "text/calendar; charset=utf-8")
+                        .body(Component::calendar(vec![ics::task_to_vtodo(&task)]).to_ics());
+                    let response = self.send(request).await?;
+                    if response.status() == StatusCode::PRECONDITION_FAILED {
+                        // Changed on the server meanwhile. Still an edit here
+                        // next time, so the next pull decides and it's retried.
+                        pushed_until = pushed_until.min(task.last_edited.map_or(since, |t| t - 1));
+                        continue;
+                    }
+                    if !response.status().is_success() {
+                        return Err(format!("CalDAV Error: couldn't save {} ({}).", task.name, response.status()));
+                    }
+                    // Without an etag the next pull fetches it again
+                    let etag = response.headers().get(header::ETAG).and_then(|e| e.to_str().ok()).unwrap_or_default().to_string();
+                    cal_state.resources.insert(url, Resource { uid: task.id.clone(), etag });
+                    result.pushed += 1;
+                }
+            }
+        }
+
+        // Deleted here
+        let ids: Vec<String> = state.calendars.keys().cloned().collect();
+        for id in ids {
+            let cal_state = state.calendars.get(&id).unwrap().clone();
+            if get_saved_list(&id).await.or_else(|e| Err(db_error(e)))?.is_none() {
+                if self.delete(&cal_state.href, None).await? {
+                    state.calendars.remove(&id);
+                    result.deleted += 1;
+                }
+                continue;
+            }
+            let tasks: HashSet<String> = get_saved_tasks(&id).await.or_else(|e| Err(db_error(e)))?
+                .into_iter().map(|t| t.id).collect();
+            for (href, resource) in &cal_state.resources {
+                if tasks.contains(&resource.uid) { continue; }
+                if self.delete(href, Some(&resource.etag)).await? {
+                    state.calendars.get_mut(&id).unwrap().resources.remove(href);
+                    result.deleted += 1;
+                }
+            }
+        }
+        Ok(pushed_until)
+    }
+}
+
+fn load_account() -> Option<CalDavAccount> {
+    let dir = http::app_conf_dir()?;
+    from_str(&read_to_string(dir + CALDAV_CONF_PATH).ok()?).ok()
+}
+
+fn client() -> Result<Option<CalDavClient>, String> {
+    let account = match load_account() {
+        Some(account) => account,
+        None => return Ok(None)
+    };
+    let dir = http::app_conf_dir().ok_or("No app data directory.".to_string())?;
+    let password = http::credential_store()
+        .and_then(|s| s.load(&(PASSWORD_PREFIX.to_string() + &account.url)))
+        .ok_or("The CalDAV password is missing. Sign in again.".to_string())?;
+    Ok(Some(CalDavClient::new(&account.url, &account.username, &password, &dir)?))
+}
+
+#[tauri::command]
+pub fn get_caldav_account() -> Option<CalDavAccount> {
+    load_account()
+}
+
+/// Checks the account can see its calendars, then saves it. Starts over with
+/// a full sync.
+#[tauri::command]
+pub async fn set_caldav_account(url: String, username: String, password: String) -> Result<CalDavAccount, String> {
+    let dir = http::app_conf_dir().ok_or("No app data directory.".to_string())?;
+    let client = CalDavClient::new(&url, username.trim(), &password, &dir)?;
+    client.check().await?;
+    remove_caldav_account();
+    let account = CalDavAccount { url: client.home.to_string(), username: client.username.clone() };
+    http::credential_store()
+        .ok_or("No credential store.".to_string())?
+        .save(&(PASSWORD_PREFIX.to_string() + &account.url), &password)?;
+    fs::write(dir + CALDAV_CONF_PATH, to_string(&account).unwrap()).or_else(|e| Err(format!("{}", e)))?;
+    Ok(account)
+}
+
+#[tauri::command]
+pub fn remove_caldav_account() {
+    let dir = match http::app_conf_dir() {
+        Some(dir) => dir,
+        None => return
+    };
+    if let (Some(account), Some(store)) = (load_account(), http::credential_store()) {
+        store.remove(&(PASSWORD_PREFIX.to_string() + &account.url));
+    }
+    let _ = fs::remove_file(dir.clone() + CALDAV_CONF_PATH);
+    let _ = fs::remove_file(dir + CALDAV_STATE_PATH);
+}
+
+/// Syncs with the CalDAV account, if one is set up.
+#[tauri::command]
+pub async fn caldav_sync() -> Result<CalDavSyncResult, String> {
+    let client = match client()? {
+        Some(client) => client,
+        None => return Ok(CalDavSyncResult::default())
+    };
+    if SYNCING.swap(true, Ordering::SeqCst) {
+        return Ok(CalDavSyncResult::default());
+    }
+    let result = client.sync().await;
+    SYNCING.store(false, Ordering::SeqCst);
+    result
+}
@@ -0,0 +1,198 @@
+// Exporting lists to .ics files and importing tasks from them (see ics.rs
+// for the format itself).
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs, path::Path};
+
+use serde::Serialize;
+use serde_json::Map;
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::{ics::{self, Component}, task::{add_imported_list, fix_parents, get_saved_list, is_valid_list_id, lists_to_export, load_records, tasks_loaded, DEFAULT_COLOR, ListEntry, TaskEntry, TaskRecord}};
+
+/// Which list a task was exported from. Params carry the list's name and
+/// color so the list can be made again on import.
+const LIST_PROP: &str = "X-TASKMGR-LIST";
+/// Calendar name most clients use
+const CALNAME_PROP: &str = "X-WR-CALNAME";
+/// Properties an imported VTODO or VEVENT can have without anything being
+/// lost. Others are reported.
+const SUPPORTED: &[&str] = &[
+    "UID", "DTSTAMP", "CREATED", "LAST-MODIFIED", "SUMMARY", "DUE", "DTSTART", "DTEND", "DURATION",
+    "PRIORITY", "STATUS", "COMPLETED", "PERCENT-COMPLETE", "RELATED-TO", "SEQUENCE", "CLASS", "TRANSP",
+    "X-TASKMGR-SIZE", LIST_PROP
+];
+/// Top-level components that aren't tasks but don't hold any either
+const IGNORED: &[&str] = &["VTIMEZONE"];
+
+/// What an import made, and what in the file had no place to go.
+#[derive(Serialize, Debug, Default)]
+pub struct IcsImport {
+    pub lists: usize,
+    pub tasks: usize,
+    /// Property or component name (`VTODO DESCRIPTION`, `VJOURNAL`) → how
+    /// many were dropped
+    pub unsupported: BTreeMap<String, usize>
+}
+
+/// A list read from a file, before it's saved.
+pub struct ImportedList {
+    pub list: ListEntry,
+    pub tasks: Vec<TaskEntry>
+}
+
+/// Tasks in tree order, each parent before its subtasks.
+fn tree_order(tasks: &[TaskEntry]) -> Vec<&TaskEntry> {
+    fn walk(records: &[TaskRecord], ids: &mut Vec<String>) {
+        for r in records {
+            ids.push(r.id.clone());
+            walk(&r.subtasks, ids);
+        }
+    }
+    let mut ids = vec![];
+    walk(&load_records(&tasks.to_vec()), &mut ids);
+    let by_id: HashMap<&str, &TaskEntry> = tasks.iter().map(|t| (t.id.as_str(), t)).collect();
+    ids.iter().filter_map(|id| by_id.get(id.as_str()).copied()).collect()
+}
+
+/// A VCALENDAR with every task in `lists` as a VTODO. Subtasks point at
+/// their parent with RELATED-TO.
+pub fn write_calendar(lists: &[(ListEntry, Vec<TaskEntry>)]) -> Component {
+    let mut todos = vec![];
+    for (list, tasks) in lists {
+        for task in tree_order(tasks) {
+            let mut todo = ics::task_to_vtodo(task);
+            todo.add_with(LIST_PROP, &[("X-NAME", &list.name), ("X-COLOR", &list.color.to_string())], &list.uuid);
+            todos.push(todo);
+        }
+    }
+    let mut cal = Component::calendar(todos);
+    if let [(list, _)] = lists {
+        cal.add(CALNAME_PROP, &ics::escape_text(&list.name));
+    }
+    cal
+}
+
+fn count(unsupported: &mut BTreeMap<String, usize>, name: String) {
+    *unsupported.entry(name).or_default() += 1;
+}
+
+/// Reads the VTODOs and VEVENTs in `text` into lists. Tasks we exported go
+/// back into the lists they came from; anything else goes into a list named
+/// after its calendar, or `fallback_name`. Lists get new ids here, except
+/// ones from our own exports. Subtasks whose parent isn't in the same list
+/// become top-level tasks.
+pub fn read_calendar(text: &str, fallback_name: &str, unsupported: &mut BTreeMap<String, usize>) -> Result<Vec<ImportedList>, String> {
+    let mut lists: Vec<ImportedList> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for top in ics::parse(text)? {
+        if !top.name.eq_ignore_ascii_case("VCALENDAR") {
+            count(unsupported, top.name.clone());
+            continue;
+        }
+        let calname = top.text(CALNAME_PROP).filter(|n| !n.trim().is_empty());
+        for c in &top.components {
+            let is_event = c.name == "VEVENT";
+            if c.name != "VTODO" && !is_event {
+                if !IGNORED.contains(&c.name.as_str()) {
+                    count(unsupported, c.name.clone());
+                }
+                continue;
+            }
+            for p in &c.properties {
+                if !SUPPORTED.contains(&p.name.as_str()) {
+                    count(unsupported, format!("{} {}", c.name, p.name));
+                }
+            }
+            for sub in &c.components {
+                count(unsupported, format!("{} {}", c.name, sub.name));
+            }
+            let mut c = c.clone();
+            if c.text("UID").is_none_or(|id| id.is_empty()) {
+                c.properties.retain(|p| p.name != "UID");
+                c.add("UID", &uuid::Uuid::new_v4().to_string());
+            }
+            let mut task = match ics::vtodo_to_task(&c) {
+                Some(task) => task,
+                None => continue
+            };
+            if is_event {
+                // Events are done when they've happened, not when marked
+                task.completed = false;
+            }
+            let prop = c.get(LIST_PROP).filter(|p| is_valid_list_id(&p.value));
+            let key = match prop {
+                Some(p) => format!("list:{}", p.value),
+                None => format!("calendar:{}", calname.as_deref().unwrap_or(fallback_name))
+            };
+            let i = *index.entry(key).or_insert_with(|| {
+                let param = |name: &str| prop.and_then(|p| p.params.iter().find(|(k, _)| k == name)).map(|(_, v)| v.clone());
+                lists.push(ImportedList {
+                    list: ListEntry {
+                        name: param("X-NAME").or(calname.clone()).unwrap_or(fallback_name.to_string()),
+                        uuid: prop.map_or_else(|| uuid::Uuid::new_v4().to_string(), |p| p.value.clone()),
+                        color: param("X-COLOR").and_then(|c| c.parse().ok()).unwrap_or(DEFAULT_COLOR),
+                        last_edited: None,
+                        created: None,
+                        sealed: None,
+                        extra: Map::new()
+                    },
+                    tasks: vec![]
+                });
+                lists.len() - 1
+            });
+            lists[i].tasks.push(task);
+        }
+    }
+    for imported in &mut lists {
+        let mut seen = HashSet::new();
+        imported.tasks.retain(|t| seen.insert(t.id.clone()));
+        fix_parents(&mut imported.tasks);
+    }
+    Ok(lists)
+}
+
+/// Writes `list_ids` (every list if empty) to an .ics file at `path`.
+/// Returns how many tasks were written.
+#[tauri::command]
+pub async fn export_ics(list_ids: Vec<String>, path: String) -> Result<usize, String> {
+    let with_tasks = lists_to_export(&list_ids).await?;
+    let total = with_tasks.iter().map(|(_, tasks)| tasks.len()).sum();
+    fs::write(&path, write_calendar(&with_tasks).to_ics())
+        .or_else(|e| Err(format!("Export Error: {}", e)))?;
+    Ok(total)
+}
+
+/// Makes lists and tasks from the .ics file at `path`, then has the task
+/// list reload.
+#[tauri::command]
+pub async fn import_ics<R: Runtime>(app: AppHandle<R>, path: String) -> Result<IcsImport, String> {
+    let result = import_ics_file(&path).await?;
+    let _ = app.emit("tasks-imported", result.tasks);
+    Ok(result)
+}
+
+/// Makes lists and tasks from the .ics file at `path`. Lists that would
+/// overwrite an existing one are imported as copies.
+pub async fn import_ics_file(path: &str) -> Result<IcsImport, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let text = fs::read_to_string(path)
+        .or_else(|e| Err(format!("Import Error: {}", e)))?;
+    let name = Path::new(path).file_stem().map_or("Imported".to_string(), |s| s.to_string_lossy().to_string());
+    let mut result = IcsImport::default();
+    for mut imported in read_calendar(&text, &name, &mut result.unsupported)? {
+        let taken = get_saved_list(&imported.list.uuid).await
+            .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        if taken.is_some() {
+            imported.list.uuid = uuid::Uuid::new_v4().to_string();
+        }
+        let added = add_imported_list(&imported.list, &imported.tasks).await
+            .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        if !added {
+            return Err(format!("Import Error: couldn't add list {}.", imported.list.name));
+        }
+        result.lists += 1;
+        result.tasks += imported.tasks.len();
+    }
+    Ok(result)
+}
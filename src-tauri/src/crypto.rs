@@ -0,0 +1,538 @@
+// End-to-end encryption of sync payloads.
+//
+// List and task content is sealed with XChaCha20-Poly1305 before it leaves
+// the device. Ids, parents and timestamps stay readable so the server can
+// still merge. Data keys live in a keyring that is itself sealed twice: once
+// with a key derived from the user's passphrase (Argon2id) and once with a
+// random recovery key the user writes down. The sealed keyring is stored on
+// the sync server (and cached locally) so other devices can unlock it.
+use std::{collections::BTreeMap, fs::{self, read_to_string, write}};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use chacha20poly1305::{aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload}, Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_slice, from_str, to_string, to_vec};
+use sha2::{Digest, Sha256};
+
+use crate::{http::{api_root, app_conf_dir, cookie_file_name, get_keyring, put_keyring, SyncData}, task::{load_sync_state, save_sync_state, ListEntry, TaskEntry}};
+
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+const SEALED_VERSION: &str = "v1";
+const KEYRING_AAD: &[u8] = b"taskmgr-keyring-v1";
+const KEYRING_DIR: &str = "/keyrings";
+/// Crockford base32, which avoids I, L, O and U
+const PHRASE_ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const PHRASE_GROUP: usize = 5;
+
+static mut KEYRING: Option<Keyring> = None;
+
+/// Argon2id settings used to turn the passphrase into a key. Stored with the
+/// keyring so they can be raised later without breaking old keyrings.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KdfParams {
+    pub salt: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32
+}
+
+impl KdfParams {
+    /// Fresh salt with Argon2's recommended costs.
+    pub fn generate() -> KdfParams {
+        KdfParams::with_costs(Params::DEFAULT_M_COST, Params::DEFAULT_T_COST, Params::DEFAULT_P_COST)
+    }
+
+    pub fn with_costs(m_cost: u32, t_cost: u32, p_cost: u32) -> KdfParams {
+        KdfParams { salt: B64.encode(random_bytes::<SALT_LEN>()), m_cost, t_cost, p_cost }
+    }
+
+    pub fn derive(&self, passphrase: &str) -> Result<[u8; KEY_LEN], String> {
+        let salt = B64.decode(&self.salt).or_else(|e| Err(format!("Keyring Error: {}", e)))?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_LEN))
+            .or_else(|e| Err(format!("Keyring Error: {}", e)))?;
+        let mut key = [0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .or_else(|e| Err(format!("Keyring Error: {}", e)))?;
+        Ok(key)
+    }
+}
+
+/// The keyring as stored on disk and on the server. Holds no plaintext keys.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyringBlob {
+    pub version: u32,
+    /// Key id used for new data
+    pub current: u32,
+    pub kdf: KdfParams,
+    /// Secrets sealed with the passphrase key
+    pub by_passphrase: String,
+    /// Secrets sealed with the recovery key
+    pub by_recovery: String
+}
+
+impl KeyringBlob {
+    pub fn unlock(&self, passphrase: &str) -> Result<Keyring, String> {
+        let key = self.kdf.derive(passphrase)?;
+        let secrets = open_with(&key, KEYRING_AAD, &self.by_passphrase)
+            .or(Err("Wrong passphrase.".to_string()))?;
+        Keyring::from_secrets(&secrets)
+    }
+
+    pub fn recover(&self, phrase: &str) -> Result<Keyring, String> {
+        let key = parse_recovery_phrase(phrase)?;
+        let secrets = open_with(&key, KEYRING_AAD, &self.by_recovery)
+            .or(Err("That recovery phrase doesn't match this account's keys.".to_string()))?;
+        Keyring::from_secrets(&secrets)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyringSecrets {
+    current: u32,
+    keys: BTreeMap<u32, String>,
+    recovery: String
+}
+
+/// Unlocked data keys. Old keys are kept after rotation so data sealed with
+/// them can still be opened.
+pub struct Keyring {
+    current: u32,
+    keys: BTreeMap<u32, [u8; KEY_LEN]>,
+    recovery: [u8; KEY_LEN]
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedList {
+    name: String,
+    color: i32
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedTask {
+    name: String,
+    size: i32,
+    importance: i32,
+    due: i64,
+    completed: bool
+}
+
+impl Keyring {
+    pub fn generate() -> Keyring {
+        let mut keys = BTreeMap::new();
+        keys.insert(1, random_bytes::<KEY_LEN>());
+        Keyring { current: 1, keys, recovery: random_bytes::<KEY_LEN>() }
+    }
+
+    pub fn current_key_id(&self) -> u32 {
+        self.current
+    }
+
+    /// Adds a new data key and makes it current. Returns its id.
+    pub fn rotate(&mut self) -> u32 {
+        self.current = self.keys.keys().max().unwrap_or(&0) + 1;
+        self.keys.insert(self.current, random_bytes::<KEY_LEN>());
+        self.current
+    }
+
+    /// The recovery key as groups of base32 characters, with a checksum.
+    pub fn recovery_phrase(&self) -> String {
+        let mut bytes = self.recovery.to_vec();
+        bytes.extend_from_slice(&Sha256::digest(self.recovery)[..2]);
+        base32_encode(&bytes)
+            .as_bytes()
+            .chunks(PHRASE_GROUP)
+            .map(|c| String::from_utf8_lossy(c).to_string())
+            .collect::<Vec<String>>()
+            .join("-")
+    }
+
+    /// Seals the keyring with `passphrase` (and the recovery key).
+    pub fn lock(&self, passphrase: &str, kdf: KdfParams) -> Result<KeyringBlob, String> {
+        let secrets = to_vec(&KeyringSecrets {
+            current: self.current,
+            keys: self.keys.iter().map(|(id, k)| (*id, B64.encode(k))).collect(),
+            recovery: B64.encode(self.recovery)
+        }).unwrap();
+        let key = kdf.derive(passphrase)?;
+        Ok(KeyringBlob {
+            version: 1,
+            current: self.current,
+            by_passphrase: seal_with(&key, KEYRING_AAD, &secrets),
+            by_recovery: seal_with(&self.recovery, KEYRING_AAD, &secrets),
+            kdf
+        })
+    }
+
+    fn from_secrets(secrets: &[u8]) -> Result<Keyring, String> {
+        let secrets: KeyringSecrets = from_slice(secrets).or_else(|e| Err(format!("Keyring Error: {}", e)))?;
+        let mut keys = BTreeMap::new();
+        for (id, key) in secrets.keys {
+            keys.insert(id, decode_key(&key)?);
+        }
+        if !keys.contains_key(&secrets.current) {
+            return Err("Keyring Error: current key is missing.".to_string());
+        }
+        Ok(Keyring { current: secrets.current, keys, recovery: decode_key(&secrets.recovery)? })
+    }
+
+    /// Seals `plaintext` with the current key as `v1.<key id>.<base64>`.
+    /// `aad` ties the ciphertext to the item it belongs to, so it can't be
+    /// moved to another item by the server.
+    pub fn seal(&self, aad: &str, plaintext: &[u8]) -> String {
+        let key = self.keys.get(&self.current).unwrap();
+        format!("{}.{}.{}", SEALED_VERSION, self.current, seal_with(key, aad.as_bytes(), plaintext))
+    }
+
+    pub fn open(&self, aad: &str, sealed: &str) -> Result<Vec<u8>, String> {
+        let mut parts = sealed.splitn(3, '.');
+        let (version, id, body) = (parts.next(), parts.next(), parts.next());
+        if version != Some(SEALED_VERSION) || body.is_none() {
+            return Err("Unsupported encrypted data. Please update the app.".to_string());
+        }
+        let id: u32 = id.unwrap().parse().or(Err("Invalid encrypted data.".to_string()))?;
+        let key = self.keys.get(&id).ok_or(format!("Encryption key {} is missing. Unlock again to fetch the latest keys.", id))?;
+        open_with(key, aad.as_bytes(), body.unwrap())
+    }
+
+    pub fn seal_list(&self, list: &mut ListEntry) {
+        let content = to_vec(&SealedList { name: list.name.clone(), color: list.color }).unwrap();
+        list.sealed = Some(self.seal(&list_aad(&list.uuid), &content));
+        list.name = String::new();
+        list.color = 0;
+    }
+
+    pub fn open_list(&self, list: &mut ListEntry) -> Result<(), String> {
+        if list.sealed.is_none() { return Ok(()); }
+        let content = self.open(&list_aad(&list.uuid), list.sealed.as_ref().unwrap())?;
+        let content: SealedList = from_slice(&content).or_else(|e| Err(format!("Invalid encrypted data: {}", e)))?;
+        list.name = content.name;
+        list.color = content.color;
+        list.sealed = None;
+        Ok(())
+    }
+
+    pub fn seal_task(&self, list: &str, task: &mut TaskEntry) {
+        let content = to_vec(&SealedTask {
+            name: task.name.clone(),
+            size: task.size,
+            importance: task.importance,
+            due: task.due,
+            completed: task.completed
+        }).unwrap();
+        task.sealed = Some(self.seal(&task_aad(list, &task.id), &content));
+        task.name = String::new();
+        task.size = 0;
+        task.importance = 0;
+        task.due = 0;
+        task.completed = false;
+    }
+
+    pub fn open_task(&self, list: &str, task: &mut TaskEntry) -> Result<(), String> {
+        if task.sealed.is_none() { return Ok(()); }
+        let content = self.open(&task_aad(list, &task.id), task.sealed.as_ref().unwrap())?;
+        let content: SealedTask = from_slice(&content).or_else(|e| Err(format!("Invalid encrypted data: {}", e)))?;
+        task.name = content.name;
+        task.size = content.size;
+        task.importance = content.importance;
+        task.due = content.due;
+        task.completed = content.completed;
+        task.sealed = None;
+        Ok(())
+    }
+
+    pub fn seal_sync_data(&self, data: &mut SyncData) {
+        for list in data.lists.iter_mut() {
+            self.seal_list(list);
+        }
+        for (list, tasks) in data.tasks.iter_mut() {
+            for task in tasks.iter_mut() {
+                self.seal_task(list, task);
+            }
+        }
+    }
+
+    pub fn open_sync_data(&self, data: &mut SyncData) -> Result<(), String> {
+        for list in data.lists.iter_mut() {
+            self.open_list(list)?;
+        }
+        for (list, tasks) in data.tasks.iter_mut() {
+            for task in tasks.iter_mut() {
+                self.open_task(list, task)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn list_aad(uuid: &str) -> String {
+    format!("list:{}", uuid)
+}
+
+fn task_aad(list: &str, id: &str) -> String {
+    format!("task:{}:{}", list, id)
+}
+
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+pub fn encode_key(key: &[u8; KEY_LEN]) -> String {
+    B64.encode(key)
+}
+
+pub fn decode_key(key: &str) -> Result<[u8; KEY_LEN], String> {
+    B64.decode(key)
+        .ok()
+        .and_then(|k| k.try_into().ok())
+        .ok_or("Keyring Error: invalid key.".to_string())
+}
+
+/// base64 of nonce || ciphertext
+pub fn seal_with(key: &[u8; KEY_LEN], aad: &[u8], plaintext: &[u8]) -> String {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut out = nonce.to_vec();
+    out.extend(cipher.encrypt(&nonce, Payload { msg: plaintext, aad }).expect("Encryption failed"));
+    B64.encode(out)
+}
+
+pub fn open_with(key: &[u8; KEY_LEN], aad: &[u8], sealed: &str) -> Result<Vec<u8>, String> {
+    let bytes = B64.decode(sealed).or(Err("Invalid encrypted data.".to_string()))?;
+    if bytes.len() < NONCE_LEN {
+        return Err("Invalid encrypted data.".to_string());
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .or(Err("Couldn't decrypt data. It may have been tampered with.".to_string()))
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for b in bytes {
+        buffer = (buffer << 8) | *b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(PHRASE_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(PHRASE_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+/// Reads a recovery phrase back into the recovery key. Case, dashes and
+/// spaces don't matter, and O/I/L are read as 0/1/1.
+pub fn parse_recovery_phrase(phrase: &str) -> Result<[u8; KEY_LEN], String> {
+    let invalid = || "That doesn't look like a recovery phrase.".to_string();
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in phrase.chars().filter(|c| *c != '-' && !c.is_whitespace()) {
+        let c = match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c
+        };
+        let value = PHRASE_ALPHABET.iter().position(|a| *a as char == c).ok_or_else(invalid)?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    if bytes.len() != KEY_LEN + 2 {
+        return Err(invalid());
+    }
+    let (key, checksum) = bytes.split_at(KEY_LEN);
+    if checksum != &Sha256::digest(key)[..2] {
+        return Err("The recovery phrase has a typo in it.".to_string());
+    }
+    Ok(key.try_into().unwrap())
+}
+
+// Local copy of the server's keyring, one per server like cookies
+fn keyring_path() -> Option<String> {
+    app_conf_dir().map(|dir| format!("{}{}/{}.json", dir, KEYRING_DIR, cookie_file_name(&api_root())))
+}
+
+fn read_local_blob() -> Option<KeyringBlob> {
+    let res = read_to_string(keyring_path()?);
+    if res.is_err() { return None; }
+    from_str(&res.unwrap()).ok()
+}
+
+fn write_local_blob(blob: &KeyringBlob) -> Result<(), String> {
+    let path = keyring_path();
+    if path.is_none() { return Ok(()); }
+    let _ = app_conf_dir().map(|dir| fs::create_dir_all(dir + KEYRING_DIR));
+    write(path.unwrap(), to_string(blob).unwrap()).or_else(|e| Err(format!("{}", e)))
+}
+
+/// Whether encrypted sync is turned on for the current server.
+pub fn is_enabled() -> bool {
+    unsafe {
+        KEYRING.is_some() || read_local_blob().is_some()
+    }
+}
+
+/// Fails if the account (per the server) or this device uses encrypted
+/// sync but the keyring isn't unlocked, so nothing goes out in plaintext.
+pub fn check_unlocked(server_encrypted: bool) -> Result<(), String> {
+    unsafe {
+        if KEYRING.is_none() && (server_encrypted || is_enabled()) {
+            return Err("Encrypted sync is locked. Enter your passphrase to sync.".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Seals outgoing sync data if the keyring is unlocked.
+pub fn seal_outgoing(data: &mut SyncData) {
+    unsafe {
+        if KEYRING.is_some() {
+            KEYRING.as_ref().unwrap().seal_sync_data(data);
+        }
+    }
+}
+
+/// Opens sealed items in incoming sync data.
+pub fn open_incoming(data: &mut SyncData) -> Result<(), String> {
+    let sealed = data.lists.iter().any(|l| l.sealed.is_some())
+        || data.tasks.values().flatten().any(|t| t.sealed.is_some());
+    if !sealed { return Ok(()); }
+    unsafe {
+        if KEYRING.is_none() {
+            return Err("This account's data is encrypted. Enter your passphrase to sync.".to_string());
+        }
+        KEYRING.as_ref().unwrap().open_sync_data(data)
+    }
+}
+
+/// Makes the next sync push every local item again, so it's sealed with
+/// the current key.
+async fn repush_all() -> Result<(), String> {
+    let mut state = load_sync_state(&api_root()).await.or_else(|e| Err(format!("Sync State Error: {}", e)))?;
+    state.pushed_until = 0;
+    state.push_started = None;
+    state.push_position = None;
+    save_sync_state(&state).await.or_else(|e| Err(format!("Sync State Error: {}", e)))?;
+    Ok(())
+}
+
+/// The server's keyring, falling back to the local copy when offline.
+async fn current_blob() -> Result<KeyringBlob, String> {
+    match get_keyring().await {
+        Ok(Some(blob)) => {
+            write_local_blob(&blob)?;
+            Ok(blob)
+        },
+        Ok(None) => Err("Encrypted sync isn't set up for this account.".to_string()),
+        Err(e) => read_local_blob().ok_or(e)
+    }
+}
+
+async fn store_blob(blob: &KeyringBlob) -> Result<(), String> {
+    put_keyring(blob).await?;
+    write_local_blob(blob)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    pub unlocked: bool,
+    pub key_id: Option<u32>
+}
+
+#[tauri::command]
+pub fn get_encryption_status() -> EncryptionStatus {
+    unsafe {
+        let blob = read_local_blob();
+        EncryptionStatus {
+            enabled: KEYRING.is_some() || blob.is_some(),
+            unlocked: KEYRING.is_some(),
+            key_id: KEYRING.as_ref().map(|k| k.current_key_id()).or(blob.map(|b| b.current))
+        }
+    }
+}
+
+/// Turns on encrypted sync for this account. Returns the recovery phrase,
+/// which is the only way back in if the passphrase is forgotten.
+#[tauri::command]
+pub async fn enable_encryption(passphrase: String) -> Result<String, String> {
+    if passphrase.len() < 8 {
+        return Err("Use a passphrase of at least 8 characters.".to_string());
+    }
+    if get_keyring().await?.is_some() {
+        return Err("Encrypted sync is already set up for this account. Unlock it instead.".to_string());
+    }
+    let keyring = Keyring::generate();
+    store_blob(&keyring.lock(&passphrase, KdfParams::generate())?).await?;
+    let phrase = keyring.recovery_phrase();
+    unsafe {
+        KEYRING = Some(keyring);
+    }
+    repush_all().await?;
+    Ok(phrase)
+}
+
+#[tauri::command]
+pub async fn unlock_encryption(passphrase: String) -> Result<(), String> {
+    let keyring = current_blob().await?.unlock(&passphrase)?;
+    unsafe {
+        KEYRING = Some(keyring);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn lock_encryption() {
+    unsafe {
+        KEYRING = None;
+    }
+}
+
+/// Starts sealing new data with a fresh key and re-uploads everything under
+/// it. Old keys stay in the keyring for data other devices haven't re-sent.
+#[tauri::command]
+pub async fn rotate_encryption_key(passphrase: String) -> Result<u32, String> {
+    let blob = current_blob().await?;
+    let mut keyring = blob.unlock(&passphrase)?;
+    let id = keyring.rotate();
+    store_blob(&keyring.lock(&passphrase, KdfParams::generate())?).await?;
+    unsafe {
+        KEYRING = Some(keyring);
+    }
+    repush_all().await?;
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn export_recovery_phrase(passphrase: String) -> Result<String, String> {
+    Ok(current_blob().await?.unlock(&passphrase)?.recovery_phrase())
+}
+
+/// Unlocks the keyring with the recovery phrase and sets a new passphrase.
+#[tauri::command]
+pub async fn recover_encryption(phrase: String, new_passphrase: String) -> Result<(), String> {
+    if new_passphrase.len() < 8 {
+        return Err("Use a passphrase of at least 8 characters.".to_string());
+    }
+    let keyring = current_blob().await?.recover(&phrase)?;
+    store_blob(&keyring.lock(&new_passphrase, KdfParams::generate())?).await?;
+    unsafe {
+        KEYRING = Some(keyring);
+    }
+    Ok(())
+}
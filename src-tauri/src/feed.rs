@@ -0,0 +1,313 @@
+// A calendar feed of due dates that phones and calendar apps can subscribe
+// to. It's served over plain HTTP from this device, so the feed URL holds a
+// random token and anything without it gets a 404.
+use std::{fs::read_to_string, net::{SocketAddr, UdpSocket}, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, task::JoinHandle, time::timeout};
+
+use crate::{crypto, http::{self, write_private}, ics::{self, Component}, task::{get_saved_lists, get_saved_tasks, ListEntry, TaskEntry}, utils::{now, DAY_MS}};
+
+const FEED_CONF_PATH: &str = "/feed.json";
+const TOKEN_LEN: usize = 24;
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Timed events are short blocks starting at the deadline
+const EVENT_LENGTH: &str = "PT15M";
+const FEED_NAME: &str = "Task Manager";
+
+static mut FEED: Option<FeedService> = None;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct FeedConf {
+    enabled: bool,
+    /// Reachable from other devices on the network, not just this one
+    lan: bool,
+    /// Kept so subscriptions still work after a restart
+    port: u16,
+    token: String,
+    /// Minutes east of UTC, for the dates of all-day events
+    utc_offset: i32
+}
+
+/// Where the feed is, for the settings page.
+#[derive(Serialize, Debug, Clone)]
+pub struct FeedInfo {
+    /// Without filters; add `?list=`, `?tag=`, `allday=1` or `alarm=` to it
+    pub url: String,
+    pub lan: bool
+}
+
+struct FeedService {
+    listener: JoinHandle<()>,
+    info: FeedInfo
+}
+
+/// What a subscription asked for, from the query string.
+#[derive(Debug, Default, PartialEq)]
+pub struct FeedFilter {
+    /// List ids; every list if empty
+    pub lists: Vec<String>,
+    /// Tasks need one of these tags; any task if empty
+    pub tags: Vec<String>,
+    pub all_day: bool,
+    /// Minutes before the event to remind
+    pub alarm: Option<u32>
+}
+
+impl FeedFilter {
+    pub fn from_query(url: &Url) -> FeedFilter {
+        let mut filter = FeedFilter::default();
+        for (k, v) in url.query_pairs() {
+            match k.as_ref() {
+                "list" => filter.lists.push(v.to_string()),
+                "tag" => filter.tags.push(v.trim_start_matches('#').to_lowercase()),
+                "allday" => filter.all_day = v == "1" || v == "true",
+                "alarm" => filter.alarm = v.parse().ok(),
+                _ => {}
+            }
+        }
+        filter
+    }
+
+    fn matches(&self, list: &ListEntry, task: &TaskEntry) -> bool {
+        !task.completed
+            && (self.lists.is_empty() || self.lists.contains(&list.uuid))
+            && (self.tags.is_empty() || task.tags().iter().any(|t| self.tags.contains(t)))
+    }
+}
+
+/// A VEVENT for each uncompleted task in `lists` that `filter` lets through.
+/// `utc_offset` (minutes east of UTC) decides which day all-day events fall on.
+pub fn feed_calendar(lists: &[(ListEntry, Vec<TaskEntry>)], filter: &FeedFilter, utc_offset: i32) -> Component {
+    let stamp = ics::format_utc(now());
+    let mut events = vec![];
+    for (list, tasks) in lists {
+        for task in tasks.iter().filter(|t| filter.matches(list, t)) {
+            let mut event = Component::new("VEVENT");
+            event.add("UID", &ics::escape_text(&format!("{}@{}", task.id, list.uuid)));
+            event.add("DTSTAMP", &stamp);
+            event.add("SUMMARY", &ics::escape_text(&task.name));
+            event.add("DESCRIPTION", &ics::escape_text(&list.name));
+            if filter.all_day {
+                let local = task.due + utc_offset as i64 * 60 * 1000;
+                event.add_with("DTSTART", &[("VALUE", "DATE")], &ics::format_date(local));
+                event.add_with("DTEND", &[("VALUE", "DATE")], &ics::format_date(local + DAY_MS));
+            } else {
+                event.add("DTSTART", &ics::format_utc(task.due));
+                event.add("DURATION", EVENT_LENGTH);
+            }
+            event.add("TRANSP", "TRANSPARENT");
+            if let Some(minutes) = filter.alarm {
+                let mut alarm = Component::new("VALARM");
+                alarm.add("ACTION", "DISPLAY");
+                alarm.add("DESCRIPTION", &ics::escape_text(&task.name));
+                alarm.add("TRIGGER", &format!("-PT{}M", minutes));
+                event.components.push(alarm);
+            }
+            events.push(event);
+        }
+    }
+    let mut cal = Component::calendar(events);
+    cal.add("X-WR-CALNAME", FEED_NAME);
+    cal.add_with("REFRESH-INTERVAL", &[("VALUE", "DURATION")], "PT1H");
+    cal.add("X-PUBLISHED-TTL", "PT1H");
+    cal
+}
+
+/// Compares without stopping at the first difference, so response times
+/// don't give the token away.
+fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body
+    )
+}
+
+/// Answers one request head.
+async fn respond(head: &str, token: &str, utc_offset: i32) -> String {
+    let not_found = response("404 Not Found", "text/plain", "Not found.");
+    let mut parts = head.lines().next().unwrap_or_default().split(' ');
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let url = match Url::parse(&format!("http://localhost{}", target)) {
+        Ok(url) if target.starts_with('/') => url,
+        _ => return response("400 Bad Request", "text/plain", "Bad request.")
+    };
+    let given = url.path().strip_prefix("/feed/").and_then(|p| p.strip_suffix(".ics")).unwrap_or_default();
+    if !token_eq(given, token) {
+        return not_found;
+    }
+    if method != "GET" && method != "HEAD" {
+        return response("405 Method Not Allowed", "text/plain", "Method not allowed.");
+    }
+    let filter = FeedFilter::from_query(&url);
+    let mut lists = vec![];
+    let saved = match get_saved_lists().await {
+        Ok(saved) => saved,
+        Err(_) => return response("500 Internal Server Error", "text/plain", "Couldn't read tasks.")
+    };
+    for list in saved {
+        if !filter.lists.is_empty() && !filter.lists.contains(&list.uuid) { continue; }
+        match get_saved_tasks(&list.uuid).await {
+            Ok(tasks) => lists.push((list, tasks)),
+            Err(_) => return response("500 Internal Server Error", "text/plain", "Couldn't read tasks.")
+        }
+    }
+    let body = feed_calendar(&lists, &filter, utc_offset).to_ics();
+    let mut res = response("200 OK", "text/calendar; charset=utf-8", &body);
+    if method == "HEAD" {
+        res.truncate(res.len() - body.len());
+    }
+    res
+}
+
+async fn handle(mut stream: TcpStream, token: Arc<String>, utc_offset: i32) {
+    let mut head = vec![];
+    let mut buf = [0u8; 1024];
+    let read = timeout(IO_TIMEOUT, async {
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await.ok()?;
+            if n == 0 || head.len() + n > MAX_REQUEST_LEN { return None; }
+            head.extend_from_slice(&buf[..n]);
+        }
+        Some(())
+    }).await;
+    if !matches!(read, Ok(Some(()))) {
+        return;
+    }
+    let res = respond(&String::from_utf8_lossy(&head), &token, utc_offset).await;
+    let _ = timeout(IO_TIMEOUT, stream.write_all(res.as_bytes())).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Serves the feed on `addr` until the returned handle is aborted.
+pub async fn listen(addr: &str, token: &str, utc_offset: i32) -> Result<(JoinHandle<()>, SocketAddr), String> {
+    let listener = TcpListener::bind(addr).await
+        .or_else(|e| Err(format!("Feed Error: {}", e)))?;
+    let local = listener.local_addr()
+        .or_else(|e| Err(format!("Feed Error: {}", e)))?;
+    let token = Arc::new(token.to_string());
+    let handle = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle(stream, token.clone(), utc_offset));
+        }
+    });
+    Ok((handle, local))
+}
+
+/// This device's address on the network, if it has one.
+fn lan_address() -> Option<String> {
+    // Nothing is sent; connecting just picks the outgoing interface
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    Some(socket.local_addr().ok()?.ip().to_string())
+}
+
+fn new_token() -> String {
+    URL_SAFE_NO_PAD.encode(crypto::random_bytes::<TOKEN_LEN>())
+}
+
+fn load_conf() -> Option<FeedConf> {
+    let path = http::app_conf_dir()? + FEED_CONF_PATH;
+    from_str(&read_to_string(path).ok()?).ok()
+}
+
+fn save_conf(conf: &FeedConf) -> Result<(), String> {
+    let dir = http::app_conf_dir().ok_or("No app data directory.".to_string())?;
+    write_private(&(dir + FEED_CONF_PATH), &to_string(conf).unwrap())
+}
+
+async fn start(conf: &mut FeedConf) -> Result<FeedInfo, String> {
+    stop();
+    let host = if conf.lan { "0.0.0.0" } else { "127.0.0.1" };
+    // The saved port may have been taken since
+    let (listener, addr) = match listen(&format!("{}:{}", host, conf.port), &conf.token, conf.utc_offset).await {
+        Ok(bound) => bound,
+        Err(_) if conf.port != 0 => listen(&format!("{}:0", host), &conf.token, conf.utc_offset).await?,
+        Err(e) => return Err(e)
+    };
+    conf.port = addr.port();
+    let address = if conf.lan { lan_address().unwrap_or("127.0.0.1".to_string()) } else { "127.0.0.1".to_string() };
+    let info = FeedInfo { url: format!("http://{}:{}/feed/{}.ics", address, conf.port, conf.token), lan: conf.lan };
+    unsafe {
+        FEED = Some(FeedService { listener, info: info.clone() });
+    }
+    Ok(info)
+}
+
+/// Stops serving without turning the feed off, e.g. when switching profiles.
+pub fn stop() {
+    unsafe {
+        if let Some(service) = FEED.take() {
+            service.listener.abort();
+        }
+    }
+}
+
+/// The running feed, if any.
+#[tauri::command]
+pub fn get_ics_feed() -> Option<FeedInfo> {
+    unsafe {
+        FEED.as_ref().map(|f| f.info.clone())
+    }
+}
+
+/// Turns the feed on, keeping its token and port from last time.
+/// `utc_offset` is in minutes east of UTC.
+#[tauri::command]
+pub async fn start_ics_feed(lan: bool, utc_offset: i32) -> Result<FeedInfo, String> {
+    let mut conf = load_conf().unwrap_or(FeedConf { enabled: true, lan, port: 0, token: new_token(), utc_offset });
+    conf.enabled = true;
+    conf.lan = lan;
+    conf.utc_offset = utc_offset;
+    let info = start(&mut conf).await?;
+    save_conf(&conf)?;
+    Ok(info)
+}
+
+/// Starts the feed if it was on last time the app ran.
+#[tauri::command]
+pub async fn resume_ics_feed() -> Result<Option<FeedInfo>, String> {
+    match load_conf() {
+        Some(mut conf) if conf.enabled => {
+            let info = start(&mut conf).await?;
+            save_conf(&conf)?;
+            Ok(Some(info))
+        },
+        _ => Ok(None)
+    }
+}
+
+#[tauri::command]
+pub fn stop_ics_feed() -> Result<(), String> {
+    stop();
+    if let Some(mut conf) = load_conf() {
+        conf.enabled = false;
+        save_conf(&conf)?;
+    }
+    Ok(())
+}
+
+/// New token, so every existing subscription stops working.
+#[tauri::command]
+pub async fn reset_ics_feed_token() -> Result<Option<FeedInfo>, String> {
+    let mut conf = match load_conf() {
+        Some(conf) => conf,
+        None => return Ok(None)
+    };
+    conf.token = new_token();
+    save_conf(&conf)?;
+    if !conf.enabled {
+        return Ok(None);
+    }
+    let info = start(&mut conf).await?;
+    save_conf(&conf)?;
+    Ok(Some(info))
+}
@@ -0,0 +1,287 @@
+use std::{collections::HashSet, fs::{self, read_to_string, OpenOptions}, io::{Read, Seek, SeekFrom, Write}, path::Path, sync::atomic::{AtomicBool, Ordering}, time::Duration};
+
+use notify::{recommended_watcher, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::{http::{self, SyncData}, task::{apply_remote_changes, collect_local_changes, load_sync_state, save_sync_state, ListEntry, PendingChange, TaskEntry}, utils::now};
+
+const FOLDER_CONF_PATH: &str = "/folder.json";
+/// Logs live in a subfolder so the shared folder can hold other things
+const LOG_DIR: &str = "/taskmgr-sync";
+const LOG_EXT: &str = "jsonl";
+/// Sync state keys: `folder:<folder>` for what this device has written,
+/// `folder:<log path>` for how far each other log has been read
+const STATE_PREFIX: &str = "folder:";
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+static mut WATCHER: Option<RecommendedWatcher> = None;
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize, Deserialize, Clone)]
+struct FolderConf {
+    path: Option<String>,
+    device_id: String
+}
+
+/// One list or task as it was saved on the device that wrote the log.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Mutation {
+    List { list: ListEntry },
+    Task { list: String, task: TaskEntry }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LogLine {
+    device: String,
+    time: i64,
+    #[serde(flatten)]
+    mutation: Mutation
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct FolderSyncResult {
+    /// Items saved from other devices' logs
+    pub imported: usize,
+    /// Items appended to this device's log
+    pub exported: usize
+}
+
+fn load_conf() -> Option<FolderConf> {
+    let dir = http::app_conf_dir()?;
+    let conf = read_to_string(dir.clone() + FOLDER_CONF_PATH).ok().and_then(|s| from_str(&s).ok());
+    Some(conf.unwrap_or_else(|| FolderConf { path: None, device_id: uuid::Uuid::new_v4().to_string() }))
+}
+
+fn write_conf(conf: &FolderConf) -> Result<(), String> {
+    let dir = http::app_conf_dir().ok_or("No app data directory.".to_string())?;
+    fs::write(dir + FOLDER_CONF_PATH, to_string(conf).unwrap()).or_else(|e| Err(format!("{}", e)))
+}
+
+fn log_path(folder: &str, device_id: &str) -> String {
+    format!("{}{}/{}.{}", folder, LOG_DIR, device_id, LOG_EXT)
+}
+
+/// Reads the complete lines of `path` after byte `offset`. A line still
+/// being written (or synced) is left for next time. Returns the lines and
+/// the offset to resume from.
+fn read_new_lines(path: &Path, offset: u64) -> Result<(Vec<String>, u64), String> {
+    let mut file = fs::File::open(path).or_else(|e| Err(format!("{}", e)))?;
+    let len = file.metadata().or_else(|e| Err(format!("{}", e)))?.len();
+    // Shorter than what was read: the file was replaced, so start over.
+    // Re-applying lines is harmless since older edits never win.
+    let offset = if len < offset { 0 } else { offset };
+    file.seek(SeekFrom::Start(offset)).or_else(|e| Err(format!("{}", e)))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).or_else(|e| Err(format!("{}", e)))?;
+    let complete = match bytes.iter().rposition(|b| *b == b'\n') {
+        Some(i) => i + 1,
+        None => return Ok((vec![], offset))
+    };
+    let lines = String::from_utf8_lossy(&bytes[..complete])
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.to_string())
+        .collect();
+    Ok((lines, offset + complete as u64))
+}
+
+/// Appends lines to this device's log. If the last write was cut short, the
+/// partial line is ended first so it can't run into the new ones.
+fn append_lines(path: &str, lines: &[String]) -> Result<(), String> {
+    let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)
+        .or_else(|e| Err(format!("{}", e)))?;
+    let mut out = String::new();
+    let len = file.metadata().or_else(|e| Err(format!("{}", e)))?.len();
+    if len > 0 {
+        let mut last = [0u8; 1];
+        file.seek(SeekFrom::Start(len - 1)).or_else(|e| Err(format!("{}", e)))?;
+        file.read_exact(&mut last).or_else(|e| Err(format!("{}", e)))?;
+        if last[0] != b'\n' {
+            out.push('\n');
+        }
+    }
+    for line in lines {
+        out.push_str(line);
+        out.push('\n');
+    }
+    file.write_all(out.as_bytes()).or_else(|e| Err(format!("{}", e)))?;
+    file.sync_data().or_else(|e| Err(format!("{}", e)))
+}
+
+fn change_key(change: &PendingChange) -> (String, Option<i64>) {
+    let edited = match change {
+        PendingChange::List(l) => l.last_edited,
+        PendingChange::Task(_, t) => t.last_edited
+    };
+    (change.sort_key(), edited)
+}
+
+/// Merges every other device's log in `folder` into the open task database,
+/// then appends this device's changes to its own log.
+pub async fn sync_folder_with(folder: &str, device_id: &str) -> Result<FolderSyncResult, String> {
+    let dir = folder.to_string() + LOG_DIR;
+    fs::create_dir_all(&dir).or_else(|e| Err(format!("Couldn't open the sync folder: {}", e)))?;
+    let own = log_path(folder, device_id);
+    let mut result = FolderSyncResult::default();
+    let mut imported = HashSet::new();
+
+    let entries = fs::read_dir(&dir).or_else(|e| Err(format!("Couldn't open the sync folder: {}", e)))?;
+    let mut logs: Vec<_> = entries.filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == LOG_EXT) && p.to_str() != Some(own.as_str()))
+        .collect();
+    logs.sort();
+    for log in logs {
+        let key = STATE_PREFIX.to_string() + &log.to_string_lossy();
+        let mut state = load_sync_state(&key).await.or_else(|e| Err(format!("Task DB Error: {}", e)))?;
+        let offset = state.cursor.as_deref().and_then(|c| c.parse().ok()).unwrap_or(0);
+        let (lines, offset) = match read_new_lines(&log, offset) {
+            Ok(read) => read,
+            Err(e) => {
+                println!("Skipping sync log {}: {}", log.display(), e);
+                continue;
+            }
+        };
+        let mut data = SyncData::new();
+        for line in lines {
+            let line = match from_str::<LogLine>(&line) {
+                Ok(line) => line,
+                // Cut short by a crash and ended by the next append
+                Err(_) => continue
+            };
+            // A copy of this device's own log, e.g. a sync conflict
+            if line.device == device_id { continue; }
+            let change = match line.mutation {
+                Mutation::List { list } => PendingChange::List(list),
+                Mutation::Task { list, task } => PendingChange::Task(list, task)
+            };
+            // Already seen in another log this time round
+            if !imported.insert(change_key(&change)) { continue; }
+            match change {
+                PendingChange::List(l) => data.lists.push(l),
+                PendingChange::Task(list, t) => data.tasks.entry(list).or_default().push(t)
+            }
+        }
+        // Against every local item, so lines read again never undo later edits
+        result.imported += apply_remote_changes(&data, 0).await.or_else(|e| Err(format!("Task DB Error: {}", e)))?;
+        state.cursor = Some(offset.to_string());
+        save_sync_state(&state).await.or_else(|e| Err(format!("Task DB Error: {}", e)))?;
+    }
+
+    let key = STATE_PREFIX.to_string() + folder;
+    let mut state = load_sync_state(&key).await.or_else(|e| Err(format!("Task DB Error: {}", e)))?;
+    let until = now();
+    let changes = collect_local_changes(state.pushed_until, until).await
+        .or_else(|e| Err(format!("Task DB Error: {}", e)))?;
+    let mut lines = Vec::new();
+    for change in changes {
+        // Just read from another log, so it doesn't need writing back
+        if imported.contains(&change_key(&change)) { continue; }
+        let mutation = match change {
+            PendingChange::List(list) => Mutation::List { list },
+            PendingChange::Task(list, task) => Mutation::Task { list, task }
+        };
+        lines.push(to_string(&LogLine { device: device_id.to_string(), time: until, mutation }).unwrap());
+    }
+    if !lines.is_empty() {
+        append_lines(&own, &lines).or_else(|e| Err(format!("Couldn't write the sync log: {}", e)))?;
+    }
+    result.exported = lines.len();
+    state.pushed_until = until;
+    save_sync_state(&state).await.or_else(|e| Err(format!("Task DB Error: {}", e)))?;
+    Ok(result)
+}
+
+/// Watches the shared folder and merges whenever another device's log
+/// changes, telling the windows to reload (`folder-synced`).
+fn watch<R: Runtime>(app: AppHandle<R>, folder: &str, device_id: &str) -> Result<(), String> {
+    stop_watching();
+    let own = log_path(folder, device_id);
+    let dir = folder.to_string() + LOG_DIR;
+    fs::create_dir_all(&dir).or_else(|e| Err(format!("Couldn't open the sync folder: {}", e)))?;
+    let (folder, device_id) = (folder.to_string(), device_id.to_string());
+    let mut watcher = recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let event = match res {
+            Ok(event) => event,
+            Err(_) => return
+        };
+        if event.paths.iter().all(|p| p.to_str() == Some(own.as_str())) {
+            return;
+        }
+        // Sync tools write in bursts; merge once they settle
+        if PENDING.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let (app, folder, device_id) = (app.clone(), folder.clone(), device_id.clone());
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(DEBOUNCE).await;
+            PENDING.store(false, Ordering::SeqCst);
+            match sync_folder_with(&folder, &device_id).await {
+                Ok(res) if res.imported > 0 => { let _ = app.emit("folder-synced", res); },
+                Ok(_) => {},
+                Err(e) => println!("Folder sync error: {}", e)
+            }
+        });
+    }).or_else(|e| Err(format!("Couldn't watch the sync folder: {}", e)))?;
+    watcher.watch(Path::new(&dir), RecursiveMode::NonRecursive)
+        .or_else(|e| Err(format!("Couldn't watch the sync folder: {}", e)))?;
+    unsafe {
+        WATCHER = Some(watcher);
+    }
+    Ok(())
+}
+
+pub fn stop_watching() {
+    unsafe {
+        WATCHER = None;
+    }
+}
+
+#[tauri::command]
+pub fn get_sync_folder() -> Option<String> {
+    load_conf()?.path
+}
+
+/// Sets (or with `None`, stops using) the shared folder. Merges right away.
+#[tauri::command]
+pub async fn set_sync_folder<R: Runtime>(app: AppHandle<R>, path: Option<String>) -> Result<FolderSyncResult, String> {
+    let mut conf = load_conf().ok_or("No app data directory.".to_string())?;
+    let path = path.map(|p| p.trim().trim_end_matches(['/', '\\']).to_string()).filter(|p| !p.is_empty());
+    if let Some(path) = &path {
+        if !Path::new(path).is_dir() {
+            return Err(format!("{} isn't a folder.", path));
+        }
+    }
+    conf.path = path;
+    write_conf(&conf)?;
+    stop_watching();
+    start_folder_sync(app).await
+}
+
+/// Merges the shared folder, if one is set, and watches it for changes.
+/// Called once the task database is open.
+#[tauri::command]
+pub async fn start_folder_sync<R: Runtime>(app: AppHandle<R>) -> Result<FolderSyncResult, String> {
+    let conf = match load_conf() {
+        Some(FolderConf { path: Some(path), device_id }) => FolderConf { path: Some(path), device_id },
+        _ => return Ok(FolderSyncResult::default())
+    };
+    // Keeps the device id stable from the first sync on
+    write_conf(&conf)?;
+    let folder = conf.path.unwrap();
+    let res = sync_folder_with(&folder, &conf.device_id).await?;
+    watch(app, &folder, &conf.device_id)?;
+    Ok(res)
+}
+
+/// Writes this device's latest changes to the shared folder.
+#[tauri::command]
+pub async fn sync_folder() -> Result<FolderSyncResult, String> {
+    match load_conf() {
+        Some(FolderConf { path: Some(path), device_id }) => sync_folder_with(&path, &device_id).await,
+        _ => Ok(FolderSyncResult::default())
+    }
+}
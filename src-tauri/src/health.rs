@@ -0,0 +1,199 @@
+// Finding and fixing damage in the task database: subtasks whose parent is
+// gone (left by partial syncs and the old `delete_task`, which kept
+// children), loops of parents, task ids used in more than one list, task
+// tables without a list and timestamps that are missing or out of range.
+// Checking and repairing are one pass, so a check lists exactly what a
+// repair would do. Repaired items get a new `last_edited` so the fixes sync.
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::Error;
+
+use crate::{ics::format_utc, storage::TaskDb, task::{is_valid_list_id, parent_problems, ListEntry}, utils::{now, DAY_MS}};
+
+/// Times below this are taken to be in seconds (it's 1973 in ms)
+const MIN_TIME: i64 = 100_000_000_000;
+/// How far ahead of the clock an edit time may be
+const MAX_SKEW_MS: i64 = DAY_MS;
+const RECOVERED_LIST: &str = "Recovered tasks";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    OrphanedSubtask,
+    ParentCycle,
+    DuplicateId,
+    /// A task table without a row in Lists
+    MissingList,
+    /// A row in Lists without a task table
+    MissingTable,
+    InvalidTimestamp
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DbIssue {
+    pub kind: IssueKind,
+    pub list: String,
+    /// The task, unless it's about the list itself
+    pub task: Option<String>,
+    pub problem: String,
+    /// What a repair does about it
+    pub fix: String
+}
+
+impl DbIssue {
+    fn new(kind: IssueKind, list: &str, task: Option<&str>, problem: String, fix: String) -> DbIssue {
+        DbIssue { kind, list: list.to_string(), task: task.map(|t| t.to_string()), problem, fix }
+    }
+}
+
+/// What's wrong with `time` and what it should be, unless it's a time in ms
+/// up to `latest`. Times in seconds are scaled; others become `fallback`.
+fn check_time(time: Option<i64>, fallback: i64, latest: i64) -> Option<(String, i64)> {
+    match time {
+        None => Some(("is missing".to_string(), fallback)),
+        Some(t) if (MIN_TIME..=latest).contains(&t) => None,
+        Some(t) if t > 0 && t < MIN_TIME && t * 1000 <= latest => Some((format!("{} is in seconds", t), t * 1000)),
+        Some(t) if t > latest => Some((format!("{} is in the future", t), fallback)),
+        Some(t) => Some((format!("{} is out of range", t), fallback))
+    }
+}
+
+/// Fixes `created` and `last_edited` in place, returning what was wrong with
+/// each and what it was set to.
+fn fix_times(created: &mut Option<i64>, last_edited: &mut Option<i64>, now: i64) -> Vec<(String, String)> {
+    let mut fixes = vec![];
+    let latest = now + MAX_SKEW_MS;
+    // Fixed items are stamped with now anyway
+    if let Some((problem, _)) = check_time(*last_edited, now, latest) {
+        fixes.push((format!("last_edited {}", problem), "Set last_edited to now".to_string()));
+        *last_edited = Some(now);
+    }
+    if let Some((problem, time)) = check_time(*created, last_edited.unwrap_or(now), latest) {
+        fixes.push((format!("created {}", problem), format!("Set created to {}", format_utc(time))));
+        *created = Some(time);
+    }
+    fixes
+}
+
+/// Finds the problems in `db` and, if `repair` is set, fixes them. Either
+/// way, returns each problem with what the repair does about it.
+pub async fn check_in(db: &mut TaskDb, repair: bool) -> Result<Vec<DbIssue>, Error> {
+    let now = now();
+    let mut issues = vec![];
+    let mut lists = db.get_lists().await?.unwrap_or_default();
+    lists.retain(|l| is_valid_list_id(&l.uuid));
+    let tables: Vec<String> = db.task_tables().await?.unwrap_or_default().into_iter().filter(|t| is_valid_list_id(t)).collect();
+
+    // Tables without a list: empty ones go, others get a list so their
+    // tasks show up
+    for table in tables.iter().filter(|t| !lists.iter().any(|l| &&l.uuid == t)).cloned().collect::<Vec<_>>() {
+        let tasks = db.get_tasks(table.clone()).await?.unwrap_or_default();
+        if tasks.is_empty() {
+            issues.push(DbIssue::new(IssueKind::MissingList, &table, None, "The task table has no list".to_string(), "Remove the empty table".to_string()));
+            if repair {
+                db.drop_task_table(&table).await?;
+            }
+            continue;
+        }
+        let problem = format!("{} tasks have no list", tasks.len());
+        issues.push(DbIssue::new(IssueKind::MissingList, &table, None, problem, format!("Add a list called \"{}\"", RECOVERED_LIST)));
+        let created = tasks.iter().filter_map(|t| t.created).filter(|t| (MIN_TIME..=now).contains(t)).min();
+        let list = ListEntry {
+            name: RECOVERED_LIST.to_string(),
+            uuid: table,
+            color: 0,
+            created: created.or(Some(now)),
+            last_edited: Some(now),
+            sealed: None,
+            extra: Default::default()
+        };
+        if repair {
+            db.save_synced_list(&list).await?;
+        }
+        lists.push(list);
+    }
+
+    for list in lists.iter_mut() {
+        let mut changed = false;
+        if !tables.contains(&list.uuid) {
+            issues.push(DbIssue::new(IssueKind::MissingTable, &list.uuid, None, "The list has no task table".to_string(), "Create an empty one".to_string()));
+            changed = true;
+        }
+        for (problem, fix) in fix_times(&mut list.created, &mut list.last_edited, now) {
+            issues.push(DbIssue::new(IssueKind::InvalidTimestamp, &list.uuid, None, problem, fix));
+            changed = true;
+        }
+        if repair && changed {
+            list.last_edited = Some(now);
+            db.save_synced_list(list).await?;
+        }
+    }
+
+    // Task id → the list that keeps it
+    let mut seen: HashMap<String, String> = HashMap::new();
+    for list in &lists {
+        if !tables.contains(&list.uuid) {
+            continue;
+        }
+        let mut tasks = db.get_tasks(list.uuid.clone()).await?.unwrap_or_default();
+        let mut changed = vec![false; tasks.len()];
+        let mut removed = vec![];
+
+        // Copies of tasks in earlier lists get new ids, and their subtasks
+        // follow them
+        for i in 0..tasks.len() {
+            let id = tasks[i].id.clone();
+            let Some(first) = seen.get(&id) else {
+                seen.insert(id, list.uuid.clone());
+                continue;
+            };
+            let new_id = uuid::Uuid::new_v4().to_string();
+            let fix = if repair { format!("Give it the new id {}", new_id) } else { "Give it a new id".to_string() };
+            issues.push(DbIssue::new(IssueKind::DuplicateId, &list.uuid, Some(&id), format!("The id is also used in list {}", first), fix));
+            for (j, task) in tasks.iter_mut().enumerate() {
+                if task.parent.as_ref() == Some(&id) {
+                    task.parent = Some(new_id.clone());
+                    changed[j] = true;
+                }
+            }
+            tasks[i].id = new_id;
+            changed[i] = true;
+            removed.push(id);
+        }
+
+        let problems = parent_problems(&tasks);
+        for (i, task) in tasks.iter_mut().enumerate() {
+            let parent = task.parent.clone().unwrap_or_default();
+            if problems.orphans.contains(&task.id) {
+                issues.push(DbIssue::new(IssueKind::OrphanedSubtask, &list.uuid, Some(&task.id), format!("Its parent {} isn't in the list", parent), "Move it to the top level".to_string()));
+                task.parent = None;
+                changed[i] = true;
+            } else if problems.loops.contains(&task.id) {
+                issues.push(DbIssue::new(IssueKind::ParentCycle, &list.uuid, Some(&task.id), format!("Its parent {} is also one of its subtasks", parent), "Move it to the top level".to_string()));
+                task.parent = None;
+                changed[i] = true;
+            }
+            let mut fixes = fix_times(&mut task.created, &mut task.last_edited, now);
+            if let Some((problem, due)) = check_time(Some(task.due), now, i64::MAX) {
+                fixes.push((format!("due {}", problem), format!("Set due to {}", format_utc(due))));
+                task.due = due;
+            }
+            for (problem, fix) in fixes {
+                issues.push(DbIssue::new(IssueKind::InvalidTimestamp, &list.uuid, Some(&task.id), problem, fix));
+                changed[i] = true;
+            }
+        }
+
+        if repair {
+            for id in removed {
+                db.delete_task(list.uuid.clone(), id).await?;
+            }
+            for (task, _) in tasks.iter_mut().zip(&changed).filter(|(_, c)| **c) {
+                task.last_edited = Some(now);
+                db.save_synced_task(list.uuid.clone(), task).await?;
+            }
+        }
+    }
+    Ok(issues)
+}
@@ -1,8 +1,23 @@
 use crate::algorithm::DueEvent;
 use crate::storage::DatabaseManager;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::Error;
 
+/// A DueEvents row exactly as stored, `type` included, for archives.
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StoredDueEvent {
+    #[serde(rename = "type")]
+    #[sqlx(rename = "type")]
+    pub event_type: i32,
+    pub time: i64,
+    pub id: String,
+    pub list: String,
+    pub importance: i32,
+    pub size: i32,
+    pub due: i64
+}
+
 pub struct History {
     db_mgr: Option<DatabaseManager>,
     is_loaded: bool,
@@ -92,6 +107,31 @@ impl History {
         Ok(self.db_mgr.as_mut().unwrap().select_all::<DueEvent>(&query, Vec::new()).await?)
     }
 
+    pub async fn stored_due_events(&mut self) -> Result<Vec<StoredDueEvent>, Error> {
+        if self.db_mgr.is_none() { return Ok(vec![]); }
+        Ok(self.db_mgr.as_mut().unwrap().select_all::<StoredDueEvent>("SELECT * FROM DueEvents", Vec::new()).await?.unwrap_or_default())
+    }
+
+    pub async fn insert_stored_due_event(&mut self, event: &StoredDueEvent) -> Result<(), Error> {
+        if self.db_mgr.is_none() { return Ok(()); }
+        self.db_mgr.as_mut().unwrap().execute(
+            "INSERT INTO DueEvents \
+            (type, time, id, list, importance, size, due) \
+            VALUES \
+            ($1, $2, $3, $4, $5, $6, $7)",
+            vec![
+                json!(event.event_type),
+                json!(event.time),
+                json!(event.id),
+                json!(event.list),
+                json!(event.importance),
+                json!(event.size),
+                json!(event.due)
+            ]
+        ).await?;
+        Ok(())
+    }
+
     pub async fn clear_due_events(&mut self, conditions: Vec<String>) -> Result<(), String> {
         let mut query = "DELETE FROM DueEvents".to_string();
         if conditions.len() > 0 {
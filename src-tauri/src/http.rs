@@ -2,183 +2,619 @@ use std::{collections::HashMap, env::consts::OS, fs::{self, read_to_string, remo
 
 use reqwest::{header, Error, Method, StatusCode};
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_string};
-use tauri::{Event, Listener, Runtime, Url};
+use serde_json::{from_str, to_string, Map, Value};
+use cookie::Cookie;
+use tauri::Url;
 use reqwest::{Client, Response, RequestBuilder};
 
-use crate::{task::{compare_and_save, ListEntry, TaskEntry}, utils::now};
+use crate::{auth::{self, AuthError, Session}, backend::{preview_with, sync_with, AuthStatus, SyncBackend}, crypto::{self, KeyringBlob}, profile, task::{ListEntry, SyncPreview, TaskEntry}, utils::now};
 
-const API_ROOT: &str = "https://api.forkbomb2491.dev"; // Prod
-const COOKIE_PATH: &str = "/cookie"; // Prod
-// const API_ROOT: &str = "http://localhost:5000"; // Debug/testing
-// const COOKIE_PATH: &str = "/cookie2"; // Debug/testing
+#[cfg(not(debug_assertions))]
+const DEFAULT_API_ROOT: &str = "https://api.forkbomb2491.dev"; // Prod
+#[cfg(debug_assertions)]
+const DEFAULT_API_ROOT: &str = "http://localhost:5000"; // Debug/testing
+const PROD_API_ROOT: &str = "https://api.forkbomb2491.dev";
+const COOKIE_PATH: &str = "/cookie"; // Legacy (pre-endpoint) cookie for prod
+const COOKIE_DIR: &str = "/cookies"; // Plaintext per-server cookies (0.4.0-alpha.2)
+const SESSION_DIR: &str = "/sessions";
+const DEVICE_KEY_PATH: &str = "/device.key";
+#[cfg(feature = "os-keyring")]
+const KEYRING_SERVICE: &str = "dev.pgil.forkbomb.taskmgr";
+const SERVER_CONF_PATH: &str = "/server.json";
 
-static mut COOKIE: Option<SessionCooke> = None;
+static mut SESSION: Option<Session> = None;
 static mut APP_CONF_DIR: Option<String> = None;
+static mut API_ROOT: Option<String> = None;
 
-pub fn set_app_conf_dir(path: String) {
+/// Sets the config directory of the active profile and reloads its server
+/// and session. `None` runs without saved config (tests).
+pub fn set_app_conf_dir(path: Option<String>) {
     unsafe {
-        APP_CONF_DIR = Some(path);
-        println!("Dir set to {}", &APP_CONF_DIR.as_mut().unwrap())
+        APP_CONF_DIR = path;
+        if APP_CONF_DIR.is_some() {
+            println!("Dir set to {}", &APP_CONF_DIR.as_mut().unwrap())
+        }
+        API_ROOT = None;
+        SESSION = None;
     }
+    // Keys belong to the previous profile's account
+    crypto::lock_encryption();
+    load_server_conf();
 }
 
-struct SessionCooke {
-    cookie: Mutex<String>
+#[derive(Serialize, Deserialize)]
+struct ServerConf {
+    api_root: String
 }
 
-impl SessionCooke {
-    fn new() -> Self {
-        SessionCooke { cookie: Mutex::from("".to_string()) }
+/// App config directory, once it's been set at startup.
+pub fn app_conf_dir() -> Option<String> {
+    unsafe {
+        APP_CONF_DIR.clone()
     }
+}
 
-    fn get_cookie(&self) -> String {
-        let lock = self.cookie.lock().unwrap();
-        return lock.clone();
+fn load_server_conf() {
+    unsafe {
+        if APP_CONF_DIR.is_none() {
+            return;
+        }
+        API_ROOT = saved_endpoint(APP_CONF_DIR.as_ref().unwrap());
     }
+}
 
-    fn set_cookie(&mut self, cookie: &str) {
-        let cookie = cookie.to_string();
-        *self.cookie.lock().unwrap() = cookie;
+/// The server saved in a profile's config directory, if it set one.
+pub fn saved_endpoint(dir: &str) -> Option<String> {
+    let res = read_to_string(dir.to_string() + SERVER_CONF_PATH).ok()?;
+    let conf: ServerConf = match from_str(&res) {
+        Ok(conf) => conf,
+        Err(e) => {
+            println!("Server config error {}", e);
+            return None;
+        }
+    };
+    match validate_endpoint(&conf.api_root) {
+        Ok(root) => Some(root),
+        Err(e) => {
+            println!("Ignoring saved server: {}", e);
+            None
+        }
     }
 }
 
-fn read_cookie() -> Option<String> {
-    // Check if app data is set
-    unsafe { 
-        if APP_CONF_DIR.is_none() {
-            return None;
+/// Saves (or with `None`, forgets) the server for the profile in `dir`.
+pub fn write_server_conf(dir: &str, root: Option<&str>) -> Result<(), String> {
+    let path = dir.to_string() + SERVER_CONF_PATH;
+    if root.is_none() {
+        let _ = remove_file(path);
+        return Ok(());
+    }
+    let conf = ServerConf { api_root: root.unwrap().to_string() };
+    write(path, to_string(&conf).unwrap()).or_else(|e| Err(format!("{}", e)))
+}
+
+/// The server used when a profile hasn't picked one.
+pub fn default_api_root() -> String {
+    DEFAULT_API_ROOT.to_string()
+}
+
+/// Returns the sync server currently in use, without a trailing slash.
+pub fn api_root() -> String {
+    unsafe {
+        match API_ROOT.as_ref() {
+            Some(root) => root.clone(),
+            None => DEFAULT_API_ROOT.to_string()
         }
-        let base_dir = APP_CONF_DIR.as_mut().unwrap().clone();
-        let res = read_to_string(base_dir + COOKIE_PATH);
-        if res.is_ok() {
-            return Some(res.unwrap());
+    }
+}
+
+/// The scheme rule for every server URL: https, or http on the loopback
+/// interface.
+fn check_scheme(url: &Url, host: &str) -> Result<(), String> {
+    let is_loopback = host == "localhost"
+        || host.parse::<std::net::IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false)
+        || host.trim_matches(|c| c == '[' || c == ']').parse::<std::net::Ipv6Addr>().map(|ip| ip.is_loopback()).unwrap_or(false);
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if is_loopback => Ok(()),
+        "http" => Err("Server URL must use https:// (http:// is only allowed for localhost).".to_string()),
+        s => Err(format!("Unsupported scheme '{}'.", s))
+    }
+}
+
+/// Checks a user-supplied server URL and normalizes it (no trailing slash).
+/// Only HTTPS is accepted, except for servers on the loopback interface.
+pub fn validate_endpoint(endpoint: &str) -> Result<String, String> {
+    let endpoint = endpoint.trim();
+    if endpoint.is_empty() {
+        return Err("Server URL is empty.".to_string());
+    }
+    let url = Url::parse(endpoint).or_else(|e| Err(format!("Invalid server URL: {}", e)))?;
+    let host = match url.host_str() {
+        Some(h) => h.to_string(),
+        None => return Err("Server URL has no host.".to_string())
+    };
+    if url.username() != "" || url.password().is_some() {
+        return Err("Server URL must not contain credentials.".to_string());
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err("Server URL must not contain a query or fragment.".to_string());
+    }
+    check_scheme(&url, &host)?;
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+/// Checks a user-supplied folder URL (a CalDAV or WebDAV collection) the
+/// same way, and gives it a trailing slash so names join onto it.
+pub fn validate_folder_url(url: &str) -> Result<Url, String> {
+    let mut url = url.trim().to_string();
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    let parsed = Url::parse(&url).or(Err(format!("{} isn't a valid URL.", url)))?;
+    check_scheme(&parsed, parsed.host_str().ok_or("Server URL has no host.".to_string())?)?;
+    Ok(parsed)
+}
+
+/// File name (under `COOKIE_DIR`) holding the session cookie for a server,
+/// so signing in to one server never sends its cookie to another.
+pub fn cookie_file_name(endpoint: &str) -> String {
+    let mut name = String::new();
+    for c in endpoint.split("://").last().unwrap_or(endpoint).chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+            name.push(c.to_ascii_lowercase());
         } else {
-            return None;
+            name.push('_');
         }
     }
+    name
 }
 
-fn write_cookie(cookie: &str) {
-    // Check if app data is set
-    unsafe { 
-        if APP_CONF_DIR.is_none() {
-            return;
+/// The signed-in session for a server, parsed from its `Set-Cookie`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SessionCookie {
+    pub name: String,
+    pub value: String,
+    /// Milliseconds since the epoch, or `None` if the server set no expiry
+    pub expires: Option<i64>
+}
+
+impl SessionCookie {
+    /// Parses a `Set-Cookie` value. `Max-Age` wins over `Expires`, as it
+    /// does in browsers; other attributes are dropped.
+    pub fn parse(set_cookie: &str, now: i64) -> Option<SessionCookie> {
+        let cookie = Cookie::parse(set_cookie.trim()).ok()?;
+        let expires = match cookie.max_age() {
+            Some(age) => Some(now + age.whole_milliseconds() as i64),
+            None => cookie.expires_datetime().map(|t| t.unix_timestamp() * 1000)
+        };
+        Some(SessionCookie {
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+            expires
+        })
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.value.is_empty() || self.expires.is_some_and(|e| e <= now)
+    }
+
+    /// Value for the `Cookie` request header.
+    pub fn header_value(&self) -> String {
+        format!("{}={}", self.name, self.value)
+    }
+}
+
+/// Keeps session credentials between launches, one per server.
+pub trait CredentialStore {
+    fn load(&self, server: &str) -> Option<String>;
+    fn save(&self, server: &str, secret: &str) -> Result<(), String>;
+    fn remove(&self, server: &str);
+}
+
+/// Credentials in `<dir>/sessions`, encrypted with a random per-install key
+/// (`device.key`, readable only by the user on Unix). This keeps sessions out
+/// of plaintext backups and casual reads; the OS keyring backend
+/// (`os-keyring` feature) is stronger where it's available.
+pub struct EncryptedFileStore {
+    dir: String
+}
+
+impl EncryptedFileStore {
+    pub fn new(dir: &str) -> Self {
+        EncryptedFileStore { dir: dir.to_string() }
+    }
+
+    fn path(&self, server: &str) -> String {
+        format!("{}{}/{}", self.dir, SESSION_DIR, cookie_file_name(server))
+    }
+
+    fn device_key(&self) -> Result<[u8; crypto::KEY_LEN], String> {
+        let path = self.dir.clone() + DEVICE_KEY_PATH;
+        if let Ok(key) = read_to_string(&path) {
+            if let Ok(key) = crypto::decode_key(key.trim()) {
+                return Ok(key);
+            }
+        }
+        let key = crypto::random_bytes::<{ crypto::KEY_LEN }>();
+        write_private(&path, &crypto::encode_key(&key))?;
+        Ok(key)
+    }
+}
+
+impl CredentialStore for EncryptedFileStore {
+    fn load(&self, server: &str) -> Option<String> {
+        let sealed = read_to_string(self.path(server)).ok()?;
+        let secret = crypto::open_with(&self.device_key().ok()?, server.as_bytes(), sealed.trim()).ok()?;
+        String::from_utf8(secret).ok()
+    }
+
+    fn save(&self, server: &str, secret: &str) -> Result<(), String> {
+        let sealed = crypto::seal_with(&self.device_key()?, server.as_bytes(), secret.as_bytes());
+        let _ = fs::create_dir_all(self.dir.clone() + SESSION_DIR);
+        write_private(&self.path(server), &sealed)
+    }
+
+    fn remove(&self, server: &str) {
+        let _ = remove_file(self.path(server));
+    }
+}
+
+/// Writes a file only the current user can read.
+pub fn write_private(path: &str, contents: &str) -> Result<(), String> {
+    write(path, contents).or_else(|e| Err(format!("{}", e)))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = fs::set_permissions(path, fs::Permissions::from_mode(0o600));
+    }
+    Ok(())
+}
+
+/// Credentials in the OS keyring (Keychain, Credential Manager, Secret
+/// Service).
+#[cfg(feature = "os-keyring")]
+pub struct KeyringStore;
+
+#[cfg(feature = "os-keyring")]
+impl CredentialStore for KeyringStore {
+    fn load(&self, server: &str) -> Option<String> {
+        keyring::Entry::new(KEYRING_SERVICE, server).ok()?.get_password().ok()
+    }
+
+    fn save(&self, server: &str, secret: &str) -> Result<(), String> {
+        keyring::Entry::new(KEYRING_SERVICE, server)
+            .and_then(|e| e.set_password(secret))
+            .or_else(|e| Err(format!("Keyring Error: {}", e)))
+    }
+
+    fn remove(&self, server: &str) {
+        if let Ok(entry) = keyring::Entry::new(KEYRING_SERVICE, server) {
+            let _ = entry.delete_credential();
+        }
+    }
+}
+
+/// Name the current session is kept under. Profiles can use the same server
+/// with different accounts, so the key includes the profile.
+fn session_key() -> String {
+    profile::credential_key(&api_root())
+}
+
+#[cfg(feature = "os-keyring")]
+pub fn credential_store() -> Option<Box<dyn CredentialStore>> {
+    Some(Box::new(KeyringStore))
+}
+
+#[cfg(not(feature = "os-keyring"))]
+pub fn credential_store() -> Option<Box<dyn CredentialStore>> {
+    app_conf_dir().map(|dir| Box::new(EncryptedFileStore::new(&dir)) as Box<dyn CredentialStore>)
+}
+
+fn read_session() -> Option<Session> {
+    let store = credential_store()?;
+    let root = api_root();
+    if let Some(secret) = store.load(&session_key()) {
+        // Sessions saved before token auth are bare cookies
+        return from_str(&secret).ok()
+            .or_else(|| from_str(&secret).ok().map(Session::Cookie));
+    }
+    // Plaintext cookie files from older versions get moved into the store
+    let dir = app_conf_dir()?;
+    let mut legacy = vec![format!("{}{}/{}", dir, COOKIE_DIR, cookie_file_name(&root))];
+    if root == PROD_API_ROOT && profile::is_default() {
+        legacy.push(dir + COOKIE_PATH);
+    }
+    for path in legacy {
+        let raw = read_to_string(&path);
+        if raw.is_err() { continue; }
+        let _ = remove_file(&path);
+        let cookie = SessionCookie::parse(&raw.unwrap(), now());
+        if cookie.is_some() {
+            let session = Session::Cookie(cookie.unwrap());
+            write_session(&session);
+            return Some(session);
         }
-        let base_dir = APP_CONF_DIR.as_mut().unwrap().clone();
-        let res = write(base_dir + COOKIE_PATH, cookie);
+    }
+    None
+}
+
+fn write_session(session: &Session) {
+    if let Some(store) = credential_store() {
+        let res = store.save(&session_key(), &to_string(session).unwrap());
         if res.is_err() {
-            println!("Write cookie error {}", res.unwrap_err());
+            println!("Write session error {}", res.unwrap_err());
         }
     }
 }
 
-fn save_cookie(_e: Event) {
-    // Check if cookie is initialized
+fn remove_session() {
+    if let Some(store) = credential_store() {
+        store.remove(&session_key());
+    }
+}
+
+/// Deletes a saved session that isn't the current one (e.g. a removed
+/// profile's).
+pub fn remove_saved_session(key: &str) {
+    if let Some(store) = credential_store() {
+        store.remove(key);
+    }
+}
+
+/// The current server's session, unless it has expired (in which case it's
+/// forgotten).
+fn active_session() -> Option<Session> {
     unsafe {
-        if COOKIE.is_none() {
-            return;
+        if SESSION.as_ref().is_some_and(|s| s.is_expired(now())) {
+            end_session();
         }
-        write_cookie(&COOKIE.as_mut().unwrap().get_cookie());
+        SESSION.clone()
     }
 }
 
+fn end_session() {
+    unsafe {
+        SESSION = None;
+    }
+    remove_session();
+}
+
+/// Picks up a renewed (or ended) cookie session from the server's reply.
 fn set_cookie(response: &Response) {
-    if (*response).headers().contains_key(header::SET_COOKIE) {
-        let cookie = (*response)
-            .headers()
-            .get(header::SET_COOKIE)
-            .unwrap()
-            .to_str()
-            .unwrap();
+    for value in response.headers().get_all(header::SET_COOKIE) {
+        let cookie = value.to_str().ok().and_then(|v| SessionCookie::parse(v, now()));
+        if cookie.is_none() { continue; }
+        let cookie = cookie.unwrap();
         unsafe {
-            if COOKIE.is_none() {
-                COOKIE = Some(SessionCooke::new());
+            // Ignore unrelated cookies (e.g. from a load balancer)
+            let current = match SESSION.as_ref() {
+                Some(Session::Cookie(c)) => c,
+                _ => continue
+            };
+            if current.name != cookie.name { continue; }
+            if cookie.is_expired(now()) {
+                // The server ended the session
+                end_session();
+            } else {
+                let session = Session::Cookie(cookie);
+                write_session(&session);
+                SESSION = Some(session);
             }
-            COOKIE.as_mut().unwrap().set_cookie(cookie);
-            write_cookie(cookie);
         }
+        return;
+    }
+}
+
+/// Adds the current session's credentials, replacing any already there.
+fn authorize(headers: &mut header::HeaderMap) {
+    match active_session() {
+        Some(Session::Cookie(cookie)) => {
+            headers.insert(header::COOKIE, header::HeaderValue::from_str(&cookie.header_value()).unwrap());
+        },
+        Some(Session::Bearer(tokens)) => {
+            let value = format!("Bearer {}", tokens.access_token);
+            headers.insert(header::AUTHORIZATION, header::HeaderValue::from_str(&value).unwrap());
+        },
+        None => {}
     }
 }
 
+/// Request to the current server. Send it with `send` so the credentials
+/// are added and kept fresh.
 fn base_request(endpoint: &str, method: Method) -> RequestBuilder {
+    request_to(&api_root(), endpoint, method)
+}
+
+/// Sends a request with the session's credentials. Bearer tokens are
+/// refreshed when they are about to expire, and once more (with the request
+/// retried) if the server still answers 401.
+async fn send(request: RequestBuilder) -> Result<Response, Error> {
+    if active_session().is_some_and(|s| matches!(s, Session::Bearer(t) if t.needs_refresh(now()))) {
+        refresh_session().await;
+    }
+    let mut request = request.build()?;
+    authorize(request.headers_mut());
+    let retry = request.try_clone();
+    let response = Client::new().execute(request).await?;
+    if response.status() != StatusCode::UNAUTHORIZED || retry.is_none() {
+        return Ok(response);
+    }
+    if !refresh_session().await {
+        return Ok(response);
+    }
+    let mut retry = retry.unwrap();
+    authorize(retry.headers_mut());
+    Client::new().execute(retry).await
+}
+
+/// Swaps the refresh token for new tokens. Returns `false` if that wasn't
+/// possible; a rejected refresh token also ends the session.
+async fn refresh_session() -> bool {
+    let tokens = match active_session() {
+        Some(Session::Bearer(tokens)) => tokens,
+        _ => return false
+    };
+    match auth::refresh(&api_root(), &tokens).await {
+        Ok(tokens) => {
+            let session = Session::Bearer(tokens);
+            write_session(&session);
+            unsafe {
+                SESSION = Some(session);
+            }
+            true
+        },
+        Err(AuthError::SessionExpired) => {
+            end_session();
+            false
+        },
+        Err(e) => {
+            println!("Token refresh error {}", e);
+            false
+        }
+    }
+}
+
+/// The error for an unsuccessful response, as a message for the user.
+fn status_error(response: &Response) -> Option<String> {
+    AuthError::from_response(response).map(|e| e.to_string())
+}
+
+/// Request without the session's credentials, so it is safe to send to any
+/// server.
+pub fn request_to(root: &str, endpoint: &str, method: Method) -> RequestBuilder {
     let mut ret = Client::new()
         .request(method, Url::from_str(
-            &(API_ROOT.to_owned() + endpoint)
+            &(root.to_owned() + endpoint)
         ).unwrap());
     ret = ret.header(header::USER_AGENT, format!("Task-Manager/{} ({})", env!("CARGO_PKG_VERSION"), OS));
-    unsafe {
-        if COOKIE.is_some() {
-            ret = ret.header(header::COOKIE, COOKIE.as_mut().unwrap().get_cookie());
-        }
-    }
     ret = ret.timeout(Duration::from_secs(5));
     return ret;
 }
 
 #[tauri::command]
-pub fn is_logged_in<R: Runtime>(app: tauri::AppHandle<R>) -> bool {
+pub fn is_logged_in() -> bool {
     unsafe {
-        if COOKIE.is_none() {
-            let cookie = read_cookie();
-            if cookie.is_none() {
-                return false;
-            }
-            app.listen_any("exit-requested", save_cookie);
-            COOKIE = Some(SessionCooke::new());
-            COOKIE.as_mut().unwrap().set_cookie(&cookie.unwrap());
+        if SESSION.is_none() {
+            SESSION = read_session();
         }
-        let cookie = COOKIE.as_mut().unwrap().get_cookie();
-        return cookie != "";
     }
+    active_session().is_some()
 }
 
 #[tauri::command]
-pub async fn log_in<R: Runtime>(app: tauri::AppHandle<R>, username: &str, password: &str) -> Result<bool, String> {
-    let mut request = base_request("/login", Method::GET);
-    request = request.basic_auth(username, Some(password));
-    let response = request.send().await.or_else(|e| Err(format!("{}", e)))?;
-    if response.status() == StatusCode::FORBIDDEN {
-        return Err("Invalid credentials.".to_string());
-    }
-    unsafe { if COOKIE.is_none() {
-        app.listen_any("exit-requested", save_cookie);
-    }}
-    set_cookie(&response);
+pub async fn log_in(username: &str, password: &str) -> Result<bool, String> {
+    login_request(username, password).await?;
     Ok(true)
 }
 
+/// Signs in to the current server and stores the session.
+pub async fn login_request(username: &str, password: &str) -> Result<(), String> {
+    let session = auth::password_login(&api_root(), username, password).await
+        .or_else(|e| Err(e.to_string()))?;
+    start_session(session);
+    Ok(())
+}
+
+/// Makes `session` the current server's session and saves it.
+pub fn start_session(session: Session) {
+    write_session(&session);
+    unsafe {
+        SESSION = Some(session);
+    }
+}
+
+/// Revokes the session on the server (best effort) and forgets it locally.
 #[tauri::command]
 pub async fn log_out() -> Result<(),  ()> {
+    crypto::lock_encryption();
+    if active_session().is_some() {
+        let _ = send(base_request("/logout", Method::POST)).await;
+    }
+    end_session();
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_server_endpoint() -> String {
+    api_root()
+}
+
+/// Switches the sync server. The session for the previous server is kept on
+/// disk, and the one for the new server (if any) is loaded in its place.
+#[tauri::command]
+pub fn set_server_endpoint(endpoint: Option<String>) -> Result<String, String> {
+    let root = match endpoint {
+        Some(e) => Some(validate_endpoint(&e)?),
+        None => None
+    };
+    if let Some(dir) = app_conf_dir() {
+        write_server_conf(&dir, root.as_deref())?;
+    }
+    // Keys belong to an account on one server
+    crypto::lock_encryption();
     unsafe {
-        if COOKIE.is_none() { return Ok(()); }
-        COOKIE = None;
-        let base_dir = APP_CONF_DIR.as_mut().unwrap().clone();
-        let _ = remove_file(base_dir + COOKIE_PATH);
-        Ok(())
+        API_ROOT = root;
+        SESSION = read_session();
+    }
+    Ok(api_root())
+}
+
+#[derive(Serialize)]
+pub struct ConnectionStatus {
+    pub endpoint: String,
+    pub status: u16,
+    pub latency_ms: u64,
+    pub logged_in: bool
+}
+
+/// Checks that a sync server is reachable (defaults to the current one).
+/// Any HTTP response other than a 404 or 5xx counts as a working server.
+#[tauri::command]
+pub async fn test_connection(endpoint: Option<String>) -> Result<ConnectionStatus, String> {
+    let root = match endpoint {
+        Some(e) => validate_endpoint(&e)?,
+        None => api_root()
+    };
+    let started = std::time::Instant::now();
+    let response = if root == api_root() {
+        send(base_request("/sync", Method::GET)).await
+    } else {
+        request_to(&root, "/sync", Method::GET).send().await
+    };
+    let response = response.or_else(|e| Err(format!("Could not reach {}: {}", root, e)))?;
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND || status.is_server_error() {
+        return Err(format!("{} does not look like a Task Manager server (HTTP {}).", root, status.as_u16()));
     }
+    Ok(ConnectionStatus {
+        endpoint: root,
+        status: status.as_u16(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        logged_in: status.is_success()
+    })
 }
 
 async fn get(endpoint: &str) -> Result<Response, Error> {
     let request = base_request(endpoint, Method::GET);
-    request.send().await
+    send(request).await
 }
 
 async fn post<S: Serialize>(endpoint: &str, body: &S) -> Result<Response, Error> {
     let mut request = base_request(endpoint, Method::POST);
     request = request.json(body);
-    request.send().await
+    send(request).await
 }
 
 async fn patch<S: Serialize>(endpoint: &str, body: &S) -> Result<Response, Error> {
     let mut request = base_request(endpoint, Method::PATCH);
     request = request.json(body);
-    request.send().await
+    send(request).await
 }
 
 async fn delete(endpoint: &str) -> Result<Response, Error> {
     let request = base_request(endpoint, Method::DELETE);
-    request.send().await
+    send(request).await
 }
 
 // post list
@@ -213,31 +649,142 @@ async fn delete_list(list: String) -> Result<Response, Error> {
 // sync
 #[tauri::command]
 pub async fn do_sync() -> Result<(), String> {
-    // Check connection is active
-    unsafe {
-        if COOKIE.is_none() || (COOKIE.is_some() && COOKIE.as_mut().unwrap().get_cookie() == "") {
-            return Err("Not logged in.".to_string());
+    do_sync_with(&SyncOptions::default()).await.map(|_| ())
+}
+
+/// Page and chunk sizes for a sync run.
+pub struct SyncOptions {
+    /// Items requested per `GET /sync` page
+    pub page_size: u32,
+    /// Items sent per `POST /sync`
+    pub push_chunk: usize,
+    /// Stop after this many pages (the rest is fetched next time)
+    pub max_pages: Option<usize>
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions { page_size: 500, push_chunk: 200, max_pages: None }
+    }
+}
+
+/// Syncs with the current server. Servers with the `paged` capability are
+/// pulled page by page and pushed to in chunks, with progress saved after
+/// each step so an interrupted sync resumes instead of starting over.
+/// Returns `false` if it stopped early because of `max_pages`.
+pub async fn do_sync_with(options: &SyncOptions) -> Result<bool, String> {
+    sync_with(&HttpBackend::current(), options).await
+}
+
+/// Dry run of `do_sync`: pulls everything the server would send and
+/// reports what a sync would create, update or overwrite on each side.
+/// Nothing is saved locally (not even the cursor) and nothing is pushed.
+#[tauri::command]
+pub async fn preview_sync() -> Result<SyncPreview, String> {
+    preview_with(&HttpBackend::current(), &SyncOptions::default()).await
+}
+
+/// The sync server (see sync-server/README.md) with the current session.
+pub struct HttpBackend {
+    root: String,
+    /// Capabilities both sides support, from the last pull
+    common: Mutex<Vec<String>>
+}
+
+impl HttpBackend {
+    pub fn current() -> HttpBackend {
+        HttpBackend { root: api_root(), common: Mutex::new(vec![]) }
+    }
+}
+
+impl SyncBackend for HttpBackend {
+    fn id(&self) -> String {
+        self.root.clone()
+    }
+
+    fn auth_status(&self) -> AuthStatus {
+        match active_session() {
+            Some(_) => AuthStatus::SignedIn,
+            None => AuthStatus::SignedOut
+        }
+    }
+
+    /// Also checks the server speaks our protocol and can hold encrypted
+    /// data if this account uses it. Pages only come with a cursor if the
+    /// server has the `paged` capability.
+    async fn pull(&self, cursor: Option<&str>, limit: u32) -> Result<SyncData, String> {
+        let mut data = get_sync_page(cursor, limit).await?;
+        let common = negotiate(&data)?;
+        crypto::check_unlocked(data.encrypted.unwrap_or(false))?;
+        if crypto::is_enabled() && !common.iter().any(|c| c == CAP_SEALED) {
+            return Err("This sync server can't store encrypted data.".to_string());
         }
+        if !common.iter().any(|c| c == CAP_PAGED) {
+            data.cursor = None;
+        }
+        *self.common.lock().unwrap() = common;
+        Ok(data)
+    }
+
+    async fn push(&self, data: &SyncData) -> Result<(), String> {
+        let common = self.common.lock().unwrap().clone();
+        post_sync(data, &common).await
     }
-    // Get
-    let response = get("/sync").await;
+}
+
+/// GET /sync. Servers without paging ignore the query and send everything.
+async fn get_sync_page(cursor: Option<&str>, limit: u32) -> Result<SyncData, String> {
+    let request = base_request("/sync", Method::GET)
+        .query(&[("cursor", cursor.unwrap_or("0").to_string()), ("limit", limit.to_string())])
+        .header(PROTOCOL_HEADER, PROTOCOL_VERSION.to_string())
+        .header(CAPABILITIES_HEADER, CAPABILITIES.join(","));
+    let response = send(request).await;
     if response.is_err() { return Err(format!("HTTP Error: {}", response.unwrap_err())); }
     let response = response.unwrap();
-    // Compare and Save
+    if let Some(e) = status_error(&response) { return Err(e); }
     let body = response.text().await;
     if body.is_err() { return Err("Received no data from server.".to_string()); }
     let data: Result<SyncData, serde_json::Error> = from_str(&body.unwrap());
     if data.is_err() { return Err(format!("JSON Error: {}", data.unwrap_err())); }
-    let comp_save_res = compare_and_save(&data.unwrap()).await;
-    if comp_save_res.is_err() { return Err(format!("Compare and Save Error: {}", comp_save_res.unwrap_err())); }
-    // TODO: Implement POST sync
-    let to_post = comp_save_res.unwrap();
-    if to_post.is_some() {
-        let response = post("/sync", &to_string(&to_post.unwrap()).expect("Error converting POST data to String (sync)")).await;
-        if response.is_err() { return Err(format!("HTTP Error: {}", response.unwrap_err())); }
-        let response = response.unwrap();
-        set_cookie(&response);
+    let mut data = data.unwrap();
+    crypto::open_incoming(&mut data)?;
+    Ok(data)
+}
+
+async fn post_sync(data: &SyncData, common: &[String]) -> Result<(), String> {
+    let mut data = data.clone();
+    crypto::seal_outgoing(&mut data);
+    let response = if common.iter().any(|c| c == CAP_JSON_BODY) {
+        post("/sync", &data).await
+    } else {
+        // Legacy servers expect the JSON wrapped in a string
+        post("/sync", &to_string(&data).expect("Error converting POST data to String (sync)")).await
+    };
+    if response.is_err() { return Err(format!("HTTP Error: {}", response.unwrap_err())); }
+    let response = response.unwrap();
+    if let Some(e) = status_error(&response) { return Err(e); }
+    set_cookie(&response);
+    Ok(())
+}
+
+/// The account's sealed keyring, or `None` if encrypted sync isn't set up.
+pub async fn get_keyring() -> Result<Option<KeyringBlob>, String> {
+    let response = get("/keyring").await;
+    if response.is_err() { return Err(format!("HTTP Error: {}", response.unwrap_err())); }
+    let response = response.unwrap();
+    if response.status() == StatusCode::NOT_FOUND { return Ok(None); }
+    if let Some(e) = status_error(&response) { return Err(e); }
+    response.json().await.map(Some).or_else(|e| Err(format!("JSON Error: {}", e)))
+}
+
+pub async fn put_keyring(blob: &KeyringBlob) -> Result<(), String> {
+    let response = send(base_request("/keyring", Method::PUT).json(blob)).await;
+    if response.is_err() { return Err(format!("HTTP Error: {}", response.unwrap_err())); }
+    let response = response.unwrap();
+    if response.status() == StatusCode::NOT_FOUND || response.status() == StatusCode::METHOD_NOT_ALLOWED {
+        return Err("This sync server can't store encrypted data.".to_string());
     }
+    if let Some(e) = status_error(&response) { return Err(e); }
     Ok(())
 }
 
@@ -257,28 +804,90 @@ pub async fn send_telemetry(device_id: String, previous_version: String) -> Resu
     Ok(true)
 }
 
+/// Converts a timestamp in seconds (as older servers send) to milliseconds.
+/// Anything before 1970 is left alone rather than guessed at.
 pub fn check_timestamp(last_sync: i64) -> i64 {
-    let last_sync = last_sync + 1;
-    if last_sync.ilog10() < 10 {
+    let last_sync = last_sync.saturating_add(1);
+    if last_sync > 0 && last_sync.ilog10() < 10 {
         last_sync * 1000
     } else {
         last_sync
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Version of the `/sync` wire format this client speaks. Servers that
+/// predate versioning don't send one, which deserializes as 0.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Optional protocol features this client supports.
+pub const CAPABILITIES: &[&str] = &[CAP_JSON_BODY, CAP_PAGED, CAP_SEALED];
+/// POST /sync bodies are sent as a JSON object rather than a JSON string.
+pub const CAP_JSON_BODY: &str = "json-body";
+/// GET /sync takes `?cursor=&limit=` and returns one page of changes.
+pub const CAP_PAGED: &str = "paged";
+/// The server stores `sealed` on lists and tasks and has `/keyring`.
+pub const CAP_SEALED: &str = "sealed";
+const PROTOCOL_HEADER: &str = "X-Sync-Protocol";
+const CAPABILITIES_HEADER: &str = "X-Sync-Capabilities";
+
+/// Checks the server's protocol version against ours and returns the
+/// capabilities both sides support.
+pub fn negotiate(server: &SyncData) -> Result<Vec<String>, String> {
+    if server.min_protocol_version.unwrap_or(0) > PROTOCOL_VERSION {
+        return Err(format!(
+            "The sync server needs protocol v{} or newer (this version of Task Manager speaks v{}). Please update the app.",
+            server.min_protocol_version.unwrap(),
+            PROTOCOL_VERSION
+        ));
+    }
+    Ok(server.capabilities
+        .iter()
+        .filter(|c| CAPABILITIES.contains(&c.as_str()))
+        .cloned()
+        .collect())
+}
+
+/// Body of GET and POST `/sync`. See sync-server/README.md for the protocol.
+///
+/// New fields must be optional (`#[serde(default)]`) so older peers can still
+/// parse messages, and fields this version doesn't know about are kept in
+/// `extra` so they survive being passed along.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SyncData {
+    #[serde(default)]
+    pub protocol_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_protocol_version: Option<u32>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
     pub last_sync: i64,
     pub lists: Vec<ListEntry>,
-    pub tasks: HashMap<String, Vec<TaskEntry>>
+    pub tasks: HashMap<String, Vec<TaskEntry>>,
+    /// Position after this page (paged servers only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// Whether more pages follow `cursor`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_more: Option<bool>,
+    /// Set by the server when the account uses encrypted sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>
 }
 
 impl SyncData {
     pub fn new() -> Self {
         SyncData {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: None,
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
             last_sync: now(),
             lists: Vec::new(),
-            tasks: HashMap::new()
+            tasks: HashMap::new(),
+            cursor: None,
+            has_more: None,
+            encrypted: None,
+            extra: Map::new()
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,347 @@
+use serde_json::Map;
+
+use crate::{task::{DEFAULT_IMPORTANCE, DEFAULT_SIZE, TaskEntry}, utils::{now, DAY_MS}};
+
+const PRODID: &str = "-//Forkbomb//Task Manager//EN";
+/// Lines are folded at 75 octets (RFC 5545 3.1)
+const MAX_LINE_LEN: usize = 75;
+/// Task size has no iCalendar equivalent
+const SIZE_PROP: &str = "X-TASKMGR-SIZE";
+const MAX_IMPORTANCE: i32 = 4;
+
+/// An iCalendar component (VCALENDAR, VTODO, ...).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String
+}
+
+impl Component {
+    pub fn new(name: &str) -> Self {
+        Component { name: name.to_string(), properties: vec![], components: vec![] }
+    }
+
+    /// A VCALENDAR holding `components`.
+    pub fn calendar(components: Vec<Component>) -> Self {
+        let mut cal = Component::new("VCALENDAR");
+        cal.add("VERSION", "2.0");
+        cal.add("PRODID", PRODID);
+        cal.components = components;
+        cal
+    }
+
+    pub fn add(&mut self, name: &str, value: &str) {
+        self.properties.push(Property { name: name.to_string(), params: vec![], value: value.to_string() });
+    }
+
+    pub fn add_with(&mut self, name: &str, params: &[(&str, &str)], value: &str) {
+        self.properties.push(Property {
+            name: name.to_string(),
+            params: params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            value: value.to_string()
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.get(name).map(|p| p.value.as_str())
+    }
+
+    /// Text value with escapes undone.
+    pub fn text(&self, name: &str) -> Option<String> {
+        self.value(name).map(unescape_text)
+    }
+
+    /// All components named `name`, at any depth.
+    pub fn find_all(&self, name: &str) -> Vec<&Component> {
+        let mut found = vec![];
+        for c in &self.components {
+            if c.name.eq_ignore_ascii_case(name) {
+                found.push(c);
+            }
+            found.extend(c.find_all(name));
+        }
+        found
+    }
+
+    pub fn to_ics(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut String) {
+        out.push_str(&fold_line(&format!("BEGIN:{}", self.name)));
+        for p in &self.properties {
+            let mut line = p.name.clone();
+            for (k, v) in &p.params {
+                line.push_str(&format!(";{}={}", k, quote_param(v)));
+            }
+            line.push(':');
+            line.push_str(&p.value);
+            out.push_str(&fold_line(&line));
+        }
+        for c in &self.components {
+            c.write(out);
+        }
+        out.push_str(&fold_line(&format!("END:{}", self.name)));
+    }
+}
+
+fn quote_param(value: &str) -> String {
+    if value.contains([':', ';', ',']) {
+        format!("\"{}\"", value.replace('"', ""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Splits a content line into CRLF-terminated lines of at most 75 octets,
+/// without breaking UTF-8 sequences.
+pub fn fold_line(line: &str) -> String {
+    let mut out = String::new();
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > MAX_LINE_LEN {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+    out
+}
+
+pub fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+pub fn unescape_text(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => out.push('\\')
+        }
+    }
+    out
+}
+
+/// Parses iCalendar text into its top-level components.
+pub fn parse(text: &str) -> Result<Vec<Component>, String> {
+    // Unfold: a line starting with a space or tab continues the last one
+    let mut lines: Vec<String> = vec![];
+    for raw in text.split('\n') {
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        if (raw.starts_with(' ') || raw.starts_with('\t')) && !lines.is_empty() {
+            lines.last_mut().unwrap().push_str(&raw[1..]);
+        } else if !raw.is_empty() {
+            lines.push(raw.to_string());
+        }
+    }
+    let mut stack: Vec<Component> = vec![];
+    let mut top = vec![];
+    for line in lines {
+        let prop = parse_line(&line).ok_or(format!("ICS Error: bad line \"{}\".", line))?;
+        if prop.name.eq_ignore_ascii_case("BEGIN") {
+            stack.push(Component::new(&prop.value.to_ascii_uppercase()));
+        } else if prop.name.eq_ignore_ascii_case("END") {
+            let c = stack.pop().ok_or("ICS Error: unexpected END.".to_string())?;
+            if !c.name.eq_ignore_ascii_case(&prop.value) {
+                return Err(format!("ICS Error: {} ended by {}.", c.name, prop.value));
+            }
+            match stack.last_mut() {
+                Some(parent) => parent.components.push(c),
+                None => top.push(c)
+            }
+        } else if let Some(c) = stack.last_mut() {
+            c.properties.push(prop);
+        }
+    }
+    if let Some(c) = stack.last() {
+        return Err(format!("ICS Error: {} isn't ended.", c.name));
+    }
+    Ok(top)
+}
+
+fn parse_line(line: &str) -> Option<Property> {
+    // The value starts at the first colon outside a quoted parameter
+    let mut quoted = false;
+    let mut split = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => { split = Some(i); break; },
+            _ => {}
+        }
+    }
+    let split = split?;
+    let (head, value) = (&line[..split], &line[split + 1..]);
+    // Parameters are split the same way, so quoted values can hold `;`
+    let mut quoted = false;
+    let mut parts = head.split(|c| {
+        if c == '"' { quoted = !quoted; }
+        c == ';' && !quoted
+    });
+    let name = parts.next()?.trim().to_ascii_uppercase();
+    if name.is_empty() {
+        return None;
+    }
+    let params = parts.filter_map(|p| {
+        let (k, v) = p.split_once('=')?;
+        Some((k.to_ascii_uppercase(), v.trim_matches('"').to_string()))
+    }).collect();
+    Some(Property { name, params, value: value.to_string() })
+}
+
+/// Days since 1970-01-01 to (year, month, day), proleptic Gregorian.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+pub fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let m = m as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// A UTC DATE-TIME value (`20240131T093000Z`) for a timestamp in ms.
+pub fn format_utc(ms: i64) -> String {
+    let secs = ms.div_euclid(1000);
+    let (y, m, d) = civil_from_days(secs.div_euclid(86400));
+    let rem = secs.rem_euclid(86400);
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", y, m, d, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+/// A DATE value (`20240131`).
+pub fn format_date(ms: i64) -> String {
+    let (y, m, d) = civil_from_days(ms.div_euclid(DAY_MS));
+    format!("{:04}{:02}{:02}", y, m, d)
+}
+
+/// Timestamp in ms of a DATE or DATE-TIME value. Times with a TZID or no
+/// zone at all are read as UTC, since there's no time zone database here.
+pub fn parse_datetime(value: &str) -> Option<i64> {
+    let value = value.trim();
+    // Values come from servers and files, so anything odd is refused rather
+    // than sliced
+    if value.len() < 8 || !value.is_ascii() {
+        return None;
+    }
+    let num = |s: &str| s.parse::<i64>().ok();
+    let (y, m, d) = (num(&value[0..4])?, num(&value[4..6])? as u32, num(&value[6..8])? as u32);
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+    let mut ms = days_from_civil(y, m, d) * DAY_MS;
+    if value.len() > 8 {
+        let time = value.get(9..15)?;
+        if &value[8..9] != "T" {
+            return None;
+        }
+        let (h, min, s) = (num(&time[0..2])?, num(&time[2..4])?, num(&time[4..6])?);
+        ms += ((h * 60 + min) * 60 + s) * 1000;
+    }
+    Some(ms)
+}
+
+/// iCalendar PRIORITY runs 1 (highest) to 9 (lowest), 0 being undefined.
+pub fn importance_to_priority(importance: i32) -> i32 {
+    9 - 2 * importance.clamp(0, MAX_IMPORTANCE)
+}
+
+pub fn priority_to_importance(priority: i32) -> i32 {
+    if !(1..=9).contains(&priority) {
+        return DEFAULT_IMPORTANCE;
+    }
+    ((10 - priority) / 2).clamp(0, MAX_IMPORTANCE)
+}
+
+/// A task as a VTODO. The UID is the task id.
+pub fn task_to_vtodo(task: &TaskEntry) -> Component {
+    let mut todo = Component::new("VTODO");
+    let edited = task.last_edited.unwrap_or_else(now);
+    todo.add("UID", &escape_text(&task.id));
+    todo.add("DTSTAMP", &format_utc(edited));
+    if let Some(created) = task.created {
+        todo.add("CREATED", &format_utc(created));
+    }
+    todo.add("LAST-MODIFIED", &format_utc(edited));
+    todo.add("SUMMARY", &escape_text(&task.name));
+    todo.add("DUE", &format_utc(task.due));
+    todo.add("PRIORITY", &importance_to_priority(task.importance).to_string());
+    todo.add(SIZE_PROP, &task.size.to_string());
+    if task.completed {
+        todo.add("STATUS", "COMPLETED");
+        todo.add("COMPLETED", &format_utc(edited));
+        todo.add("PERCENT-COMPLETE", "100");
+    } else {
+        todo.add("STATUS", "NEEDS-ACTION");
+    }
+    if let Some(parent) = &task.parent {
+        todo.add_with("RELATED-TO", &[("RELTYPE", "PARENT")], &escape_text(parent));
+    }
+    todo
+}
+
+/// A task from a VTODO written by us or another client. Returns `None`
+/// without a UID. A VTODO with no due date falls back to its start, then to
+/// now.
+pub fn vtodo_to_task(todo: &Component) -> Option<TaskEntry> {
+    let id = todo.text("UID").filter(|id| !id.is_empty())?;
+    let date = |name: &str| todo.value(name).and_then(parse_datetime);
+    let completed = todo.value("STATUS").is_some_and(|s| s.eq_ignore_ascii_case("COMPLETED"))
+        || todo.get("COMPLETED").is_some();
+    let parent = todo.properties.iter()
+        .filter(|p| p.name == "RELATED-TO")
+        .find(|p| p.params.iter().all(|(k, v)| k != "RELTYPE" || v.eq_ignore_ascii_case("PARENT")))
+        .map(|p| unescape_text(&p.value));
+    Some(TaskEntry {
+        name: todo.text("SUMMARY").unwrap_or_default(),
+        size: todo.value(SIZE_PROP).and_then(|s| s.trim().parse().ok()).unwrap_or(DEFAULT_SIZE),
+        importance: todo.value("PRIORITY").and_then(|p| p.trim().parse().ok()).map(priority_to_importance).unwrap_or(DEFAULT_IMPORTANCE),
+        due: date("DUE").or(date("DTSTART")).unwrap_or_else(now),
+        completed,
+        id,
+        parent,
+        last_edited: date("LAST-MODIFIED").or(date("DTSTAMP")),
+        created: date("CREATED"),
+        sealed: None,
+        extra: Map::new()
+    })
+}
+
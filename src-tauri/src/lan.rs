@@ -0,0 +1,597 @@
+use std::{fs::read_to_string, net::SocketAddr, sync::{Arc, Mutex}, time::Duration};
+
+use argon2::Argon2;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use snow::{Builder, HandshakeState, TransportState};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, task::JoinHandle, time::timeout};
+
+use crate::{crypto, http::{self, write_private, SyncData}, storage::{SyncState, TaskDb}, task::{self, compare_and_save_in, changes_since_in}, utils::now};
+
+const SERVICE_TYPE: &str = "_taskmgr._tcp.local.";
+/// First pairing: both keys are learned, the PIN proves it's the right device
+const PAIRING_PATTERN: &str = "Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s";
+/// Paired devices: both keys must be in the other's peer list
+const SYNC_PATTERN: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+const PIN_SALT: &[u8] = b"taskmgr-lan-pairing-v1";
+const PIN_LEN: usize = 6;
+const PIN_TTL_MS: i64 = 2 * 60 * 1000;
+const MODE_PAIR: u8 = b'P';
+const MODE_SYNC: u8 = b'S';
+const NOISE_MAX_LEN: usize = 65535;
+const NOISE_TAG_LEN: usize = 16;
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+const IDENTITY_PATH: &str = "/lan.key";
+const PEERS_PATH: &str = "/lan_peers.json";
+/// Sync state is kept per peer next to the servers'
+const STATE_PREFIX: &str = "lan:";
+
+static mut LAN: Option<LanService> = None;
+
+/// This device's long-term Noise key.
+#[derive(Serialize, Deserialize, Clone)]
+struct Identity {
+    device_id: String,
+    private_key: String,
+    public_key: String
+}
+
+/// A device that was paired with this one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LanPeer {
+    pub id: String,
+    pub name: String,
+    pub public_key: String
+}
+
+/// A device seen on the network.
+#[derive(Serialize, Debug, Clone)]
+pub struct LanDevice {
+    pub id: String,
+    pub name: String,
+    pub address: String,
+    pub paired: bool
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum LanMessage {
+    Hello { device_id: String, name: String },
+    Changes(SyncData),
+    Error(String)
+}
+
+struct Pairing {
+    pin: String,
+    expires: i64
+}
+
+/// One end of LAN sync. The app has one for the active profile; tests can
+/// run several side by side, each with its own directory and task database.
+pub struct LanNode {
+    identity: Identity,
+    private_key: Vec<u8>,
+    name: String,
+    dir: String,
+    /// Task database to merge into, or `None` for the app's open one
+    db_path: Option<String>,
+    pairing: Mutex<Option<Pairing>>
+}
+
+struct LanService {
+    node: Arc<LanNode>,
+    listener: JoinHandle<()>,
+    mdns: Option<(ServiceDaemon, String)>
+}
+
+/// An encrypted, length-framed connection.
+struct Channel {
+    stream: TcpStream,
+    transport: TransportState,
+    remote_key: Vec<u8>
+}
+
+impl LanNode {
+    /// Loads the device key from `dir`, creating it on first use.
+    pub fn open(dir: &str, name: &str, db_path: Option<String>) -> Result<LanNode, String> {
+        let identity = match read_to_string(dir.to_string() + IDENTITY_PATH).ok().and_then(|s| from_str::<Identity>(&s).ok()) {
+            Some(identity) => identity,
+            None => {
+                let keys = Builder::new(SYNC_PATTERN.parse().unwrap()).generate_keypair()
+                    .or_else(|e| Err(format!("LAN Error: {}", e)))?;
+                let identity = Identity {
+                    device_id: uuid::Uuid::new_v4().to_string(),
+                    private_key: crypto::encode_key(&keys.private.clone().try_into().unwrap()),
+                    public_key: crypto::encode_key(&keys.public.clone().try_into().unwrap())
+                };
+                write_private(&(dir.to_string() + IDENTITY_PATH), &to_string(&identity).unwrap())?;
+                identity
+            }
+        };
+        Ok(LanNode {
+            private_key: crypto::decode_key(&identity.private_key)?.to_vec(),
+            identity,
+            name: name.to_string(),
+            dir: dir.to_string(),
+            db_path,
+            pairing: Mutex::new(None)
+        })
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.identity.device_id
+    }
+
+    pub fn peers(&self) -> Vec<LanPeer> {
+        read_to_string(self.dir.clone() + PEERS_PATH).ok()
+            .and_then(|s| from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_peer(&self, peer: LanPeer) -> Result<(), String> {
+        let mut peers = self.peers();
+        peers.retain(|p| p.id != peer.id && p.public_key != peer.public_key);
+        peers.push(peer);
+        write_private(&(self.dir.clone() + PEERS_PATH), &to_string(&peers).unwrap())
+    }
+
+    pub fn forget_peer(&self, id: &str) -> Result<(), String> {
+        let mut peers = self.peers();
+        peers.retain(|p| p.id != id);
+        write_private(&(self.dir.clone() + PEERS_PATH), &to_string(&peers).unwrap())
+    }
+
+    fn peer_with_key(&self, key: &[u8]) -> Option<LanPeer> {
+        let key = crypto::encode_key(key.try_into().ok()?);
+        self.peers().into_iter().find(|p| p.public_key == key)
+    }
+
+    /// Allows one pairing attempt in the next two minutes. Returns the PIN
+    /// to show on this device and type on the other.
+    pub fn start_pairing(&self) -> String {
+        let n = u32::from_be_bytes(crypto::random_bytes::<4>()) % 10u32.pow(PIN_LEN as u32);
+        let digits = format!("{:0width$}", n, width = PIN_LEN);
+        *self.pairing.lock().unwrap() = Some(Pairing { pin: digits.clone(), expires: now() + PIN_TTL_MS });
+        digits
+    }
+
+    /// The PIN of the pending pairing, which is used up either way.
+    fn take_pin(&self) -> Option<String> {
+        let pairing = self.pairing.lock().unwrap().take()?;
+        if pairing.expires < now() {
+            return None;
+        }
+        Some(pairing.pin)
+    }
+
+    fn hello(&self) -> LanMessage {
+        LanMessage::Hello { device_id: self.identity.device_id.clone(), name: self.name.clone() }
+    }
+
+    /// Accepts connections on `addr` until the returned task is aborted.
+    pub async fn listen(self: Arc<Self>, addr: &str) -> Result<(JoinHandle<()>, SocketAddr), String> {
+        let listener = TcpListener::bind(addr).await.or_else(|e| Err(format!("LAN Error: {}", e)))?;
+        let local = listener.local_addr().or_else(|e| Err(format!("LAN Error: {}", e)))?;
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let node = self.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = timeout(IO_TIMEOUT, node.serve(stream)).await.unwrap_or(Err("timed out".to_string())) {
+                        println!("LAN sync error: {}", e);
+                    }
+                });
+            }
+        });
+        Ok((handle, local))
+    }
+
+    async fn serve(&self, mut stream: TcpStream) -> Result<(), String> {
+        let mode = stream.read_u8().await.or_else(|e| Err(format!("{}", e)))?;
+        match mode {
+            MODE_PAIR => {
+                let pin = self.take_pin().ok_or("pairing isn't open".to_string())?;
+                let state = self.handshake(PAIRING_PATTERN, Some(&pin), false)?;
+                let mut channel = Channel::accept(stream, state).await?;
+                let peer = match channel.receive().await? {
+                    LanMessage::Hello { device_id, name } => LanPeer { id: device_id, name, public_key: crypto::encode_key(channel.remote_key()?) },
+                    _ => return Err("expected hello".to_string())
+                };
+                self.save_peer(peer)?;
+                channel.send(&self.hello()).await
+            },
+            MODE_SYNC => {
+                let state = self.handshake(SYNC_PATTERN, None, false)?;
+                let mut channel = Channel::accept(stream, state).await?;
+                let peer = match self.peer_with_key(&channel.remote_key) {
+                    Some(peer) => peer,
+                    None => {
+                        let _ = channel.send(&LanMessage::Error("This device isn't paired.".to_string())).await;
+                        return Err("unknown device".to_string());
+                    }
+                };
+                let mut data = match channel.receive().await? {
+                    LanMessage::Changes(data) => data,
+                    _ => return Err("expected changes".to_string())
+                };
+                let mut state = self.load_state(&peer.id).await?;
+                data.last_sync = state.pushed_until;
+                // Taken before reading local changes, so edits made during
+                // the exchange go next time (as do merged items, which are
+                // stamped when saved) instead of being skipped
+                let until = now();
+                let reply = match self.merge(&data).await {
+                    Ok(reply) => reply,
+                    Err(e) => {
+                        let _ = channel.send(&LanMessage::Error(e.clone())).await;
+                        return Err(e);
+                    }
+                };
+                state.pushed_until = until;
+                channel.send(&LanMessage::Changes(reply)).await?;
+                self.save_state(&state).await
+            },
+            _ => Err("unknown mode".to_string())
+        }
+    }
+
+    /// Pairs with the device listening at `address` using the PIN it shows.
+    pub async fn pair(&self, address: &str, pin: &str) -> Result<LanPeer, String> {
+        let pin = pin.trim();
+        if pin.len() != PIN_LEN || !pin.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("The PIN is {} digits.", PIN_LEN));
+        }
+        let state = self.handshake(PAIRING_PATTERN, Some(pin), true)?;
+        let result = timeout(IO_TIMEOUT, async {
+            let mut channel = Channel::connect(address, MODE_PAIR, state).await?;
+            channel.send(&self.hello()).await?;
+            match channel.receive().await {
+                Ok(LanMessage::Hello { device_id, name }) => Ok(LanPeer { id: device_id, name, public_key: crypto::encode_key(channel.remote_key()?) }),
+                _ => Err("Pairing failed. Check the PIN and try again.".to_string())
+            }
+        }).await.unwrap_or(Err("LAN Error: the other device didn't respond.".to_string()))?;
+        self.save_peer(result.clone())?;
+        Ok(result)
+    }
+
+    /// Syncs with the paired device listening at `address`. Returns the peer.
+    pub async fn sync_with(&self, address: &str) -> Result<LanPeer, String> {
+        let state = self.handshake(SYNC_PATTERN, None, true)?;
+        timeout(IO_TIMEOUT, async {
+            let mut channel = Channel::connect(address, MODE_SYNC, state).await?;
+            let peer = self.peer_with_key(&channel.remote_key)
+                .ok_or("That device isn't paired with this one.".to_string())?;
+            let mut state = self.load_state(&peer.id).await?;
+            let since = state.pushed_until;
+            // See `serve`: edits made from here on go next time
+            let until = now();
+            let mut data = self.changes_since(since).await?;
+            data.last_sync = since;
+            channel.send(&LanMessage::Changes(data)).await?;
+            let mut reply = match channel.receive().await {
+                Ok(LanMessage::Changes(reply)) => reply,
+                Ok(LanMessage::Error(e)) => return Err(format!("{} refused to sync: {}", peer.name, e)),
+                Ok(_) => return Err("LAN Error: unexpected reply.".to_string()),
+                Err(_) => return Err(format!("{} refused to sync. Try pairing again.", peer.name))
+            };
+            // Everything sent was either kept there or is in the reply
+            reply.last_sync = since;
+            self.merge(&reply).await?;
+            state.pushed_until = until;
+            self.save_state(&state).await?;
+            Ok(peer)
+        }).await.unwrap_or(Err("LAN Error: the other device didn't respond.".to_string()))
+    }
+
+    fn handshake(&self, pattern: &str, pin: Option<&str>, initiator: bool) -> Result<HandshakeState, String> {
+        let psk = pin.map(pin_key).transpose()?;
+        let mut builder = Builder::new(pattern.parse().unwrap()).local_private_key(&self.private_key);
+        if let Some(psk) = &psk {
+            builder = builder.psk(3, psk);
+        }
+        match initiator {
+            true => builder.build_initiator(),
+            false => builder.build_responder()
+        }.or_else(|e| Err(format!("LAN Error: {}", e)))
+    }
+
+    async fn open_db(path: &str) -> Result<TaskDb, String> {
+        let mut db = TaskDb::new();
+        db.load(path).await.or_else(|e| Err(format!("Task DB Error: {}", e)))?;
+        Ok(db)
+    }
+
+    async fn merge(&self, data: &SyncData) -> Result<SyncData, String> {
+        let res = match &self.db_path {
+            None => task::merge_remote(data).await
+                .and_then(|r| Ok(r.unwrap_or_else(SyncData::new))),
+            Some(path) => {
+                let mut db = Self::open_db(path).await?;
+                let res = compare_and_save_in(&mut db, data).await;
+                db.close().await;
+                res
+            }
+        };
+        res.or_else(|e| Err(format!("Task DB Error: {}", e)))
+    }
+
+    async fn changes_since(&self, since: i64) -> Result<SyncData, String> {
+        let res = match &self.db_path {
+            None => task::local_changes_since(since).await,
+            Some(path) => {
+                let mut db = Self::open_db(path).await?;
+                let res = changes_since_in(&mut db, since).await;
+                db.close().await;
+                res
+            }
+        };
+        res.or_else(|e| Err(format!("Task DB Error: {}", e)))
+    }
+
+    async fn load_state(&self, peer: &str) -> Result<SyncState, String> {
+        let server = STATE_PREFIX.to_string() + peer;
+        let res = match &self.db_path {
+            None => task::load_sync_state(&server).await,
+            Some(path) => {
+                let mut db = Self::open_db(path).await?;
+                let res = db.get_sync_state(&server).await.and_then(|s| Ok(s.unwrap_or(SyncState::new(&server))));
+                db.close().await;
+                res
+            }
+        };
+        res.or_else(|e| Err(format!("Task DB Error: {}", e)))
+    }
+
+    async fn save_state(&self, state: &SyncState) -> Result<(), String> {
+        let res = match &self.db_path {
+            None => task::save_sync_state(state).await,
+            Some(path) => {
+                let mut db = Self::open_db(path).await?;
+                let res = db.set_sync_state(state).await;
+                db.close().await;
+                res
+            }
+        };
+        res.and(Ok(())).or_else(|e| Err(format!("Task DB Error: {}", e)))
+    }
+}
+
+/// A PIN has few guesses' worth of entropy, so it's only ever good for one
+/// attempt; stretching it just makes a recorded handshake slower to try.
+fn pin_key(pin: &str) -> Result<[u8; 32], String> {
+    let mut key = [0u8; 32];
+    Argon2::default().hash_password_into(pin.as_bytes(), PIN_SALT, &mut key)
+        .or_else(|e| Err(format!("LAN Error: {}", e)))?;
+    Ok(key)
+}
+
+impl Channel {
+    async fn connect(address: &str, mode: u8, mut state: HandshakeState) -> Result<Channel, String> {
+        let mut stream = TcpStream::connect(address).await
+            .or_else(|e| Err(format!("Couldn't reach {}: {}", address, e)))?;
+        stream.write_u8(mode).await.or_else(|e| Err(format!("LAN Error: {}", e)))?;
+        let mut buf = vec![0u8; NOISE_MAX_LEN];
+        // -> e
+        let len = state.write_message(&[], &mut buf).or_else(|e| Err(format!("LAN Error: {}", e)))?;
+        write_frame(&mut stream, &buf[..len]).await?;
+        // <- e, ee, s, es
+        let frame = read_frame(&mut stream).await?;
+        state.read_message(&frame, &mut buf).or_else(|e| Err(format!("LAN Error: {}", e)))?;
+        // -> s, se (psk)
+        let len = state.write_message(&[], &mut buf).or_else(|e| Err(format!("LAN Error: {}", e)))?;
+        write_frame(&mut stream, &buf[..len]).await?;
+        Self::finish(stream, state)
+    }
+
+    async fn accept(mut stream: TcpStream, mut state: HandshakeState) -> Result<Channel, String> {
+        let mut buf = vec![0u8; NOISE_MAX_LEN];
+        let frame = read_frame(&mut stream).await?;
+        state.read_message(&frame, &mut buf).or_else(|e| Err(format!("{}", e)))?;
+        let len = state.write_message(&[], &mut buf).or_else(|e| Err(format!("{}", e)))?;
+        write_frame(&mut stream, &buf[..len]).await?;
+        let frame = read_frame(&mut stream).await?;
+        state.read_message(&frame, &mut buf).or_else(|e| Err(format!("handshake failed: {}", e)))?;
+        Self::finish(stream, state)
+    }
+
+    fn finish(stream: TcpStream, state: HandshakeState) -> Result<Channel, String> {
+        let remote_key = state.get_remote_static().ok_or("LAN Error: no remote key.".to_string())?.to_vec();
+        let transport = state.into_transport_mode().or_else(|e| Err(format!("LAN Error: {}", e)))?;
+        Ok(Channel { stream, transport, remote_key })
+    }
+
+    fn remote_key(&self) -> Result<&[u8; 32], String> {
+        self.remote_key.as_slice().try_into().or(Err("LAN Error: bad remote key.".to_string()))
+    }
+
+    /// Messages go as an encrypted length followed by as many encrypted
+    /// frames as it takes.
+    async fn send(&mut self, message: &LanMessage) -> Result<(), String> {
+        let body = to_string(message).unwrap().into_bytes();
+        self.send_frame(&(body.len() as u32).to_be_bytes()).await?;
+        for chunk in body.chunks(NOISE_MAX_LEN - NOISE_TAG_LEN) {
+            self.send_frame(chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_frame(&mut self, plain: &[u8]) -> Result<(), String> {
+        let mut buf = vec![0u8; plain.len() + NOISE_TAG_LEN];
+        let len = self.transport.write_message(plain, &mut buf).or_else(|e| Err(format!("LAN Error: {}", e)))?;
+        write_frame(&mut self.stream, &buf[..len]).await
+    }
+
+    async fn receive(&mut self) -> Result<LanMessage, String> {
+        let header = self.receive_frame().await?;
+        let len = u32::from_be_bytes(header.try_into().or(Err("LAN Error: bad header.".to_string()))?) as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err("LAN Error: message too large.".to_string());
+        }
+        let mut body = Vec::with_capacity(len);
+        while body.len() < len {
+            body.extend(self.receive_frame().await?);
+        }
+        from_str(&String::from_utf8_lossy(&body)).or_else(|e| Err(format!("LAN Error: {}", e)))
+    }
+
+    async fn receive_frame(&mut self) -> Result<Vec<u8>, String> {
+        let frame = read_frame(&mut self.stream).await?;
+        let mut buf = vec![0u8; frame.len()];
+        let len = self.transport.read_message(&frame, &mut buf).or_else(|e| Err(format!("LAN Error: {}", e)))?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+async fn write_frame(stream: &mut TcpStream, frame: &[u8]) -> Result<(), String> {
+    stream.write_u16(frame.len() as u16).await.or_else(|e| Err(format!("LAN Error: {}", e)))?;
+    stream.write_all(frame).await.or_else(|e| Err(format!("LAN Error: {}", e)))
+}
+
+async fn read_frame(stream: &mut TcpStream) -> Result<Vec<u8>, String> {
+    let len = stream.read_u16().await.or_else(|e| Err(format!("LAN Error: {}", e)))? as usize;
+    let mut frame = vec![0u8; len];
+    stream.read_exact(&mut frame).await.or_else(|e| Err(format!("LAN Error: {}", e)))?;
+    Ok(frame)
+}
+
+/// Announces this device as `<device id>._taskmgr._tcp.local.`.
+fn advertise(node: &LanNode, port: u16) -> Result<(ServiceDaemon, String), String> {
+    let mdns = ServiceDaemon::new().or_else(|e| Err(format!("mDNS Error: {}", e)))?;
+    let id = node.device_id();
+    let properties = [("id", id), ("name", node.name.as_str())];
+    let info = ServiceInfo::new(SERVICE_TYPE, id, &format!("{}.local.", id), "", port, &properties[..])
+        .or_else(|e| Err(format!("mDNS Error: {}", e)))?
+        .enable_addr_auto();
+    let fullname = info.get_fullname().to_string();
+    mdns.register(info).or_else(|e| Err(format!("mDNS Error: {}", e)))?;
+    Ok((mdns, fullname))
+}
+
+/// The running node and its mDNS daemon, cloned out so that nothing holds
+/// on to the service while `stop_lan_sync` drops it.
+fn running() -> Result<(Arc<LanNode>, Option<ServiceDaemon>), String> {
+    unsafe {
+        let service = LAN.as_ref().ok_or("Nearby sync is off.".to_string())?;
+        Ok((service.node.clone(), service.mdns.as_ref().map(|(mdns, _)| mdns.clone())))
+    }
+}
+
+fn device_name() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or("Task Manager".to_string())
+}
+
+/// Starts accepting nearby syncs and announces this device. Returns the port.
+#[tauri::command]
+pub async fn start_lan_sync(name: Option<String>) -> Result<u16, String> {
+    stop_lan_sync();
+    let dir = http::app_conf_dir().ok_or("No app data directory.".to_string())?;
+    let name = name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).unwrap_or_else(device_name);
+    let node = Arc::new(LanNode::open(&dir, &name, None)?);
+    let (listener, addr) = node.clone().listen("0.0.0.0:0").await?;
+    let mdns = match advertise(&node, addr.port()) {
+        Ok(mdns) => Some(mdns),
+        Err(e) => {
+            // Still reachable by address
+            println!("{}", e);
+            None
+        }
+    };
+    unsafe {
+        LAN = Some(LanService { node, listener, mdns });
+    }
+    Ok(addr.port())
+}
+
+#[tauri::command]
+pub fn stop_lan_sync() {
+    unsafe {
+        if let Some(service) = LAN.take() {
+            service.listener.abort();
+            if let Some((mdns, fullname)) = service.mdns {
+                let _ = mdns.unregister(&fullname);
+                let _ = mdns.shutdown();
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub fn start_lan_pairing() -> Result<String, String> {
+    Ok(running()?.0.start_pairing())
+}
+
+/// Devices announcing themselves on the network within a few seconds.
+#[tauri::command]
+pub async fn discover_lan_devices() -> Result<Vec<LanDevice>, String> {
+    let (node, advertising) = running()?;
+    // Without an advertising daemon, browse with one of our own
+    let own = advertising.is_none();
+    let mdns = match advertising {
+        Some(mdns) => mdns,
+        None => ServiceDaemon::new().or_else(|e| Err(format!("mDNS Error: {}", e)))?
+    };
+    let events = match mdns.browse(SERVICE_TYPE) {
+        Ok(events) => events,
+        Err(e) => {
+            if own {
+                let _ = mdns.shutdown();
+            }
+            return Err(format!("mDNS Error: {}", e));
+        }
+    };
+    let peers = node.peers();
+    let mut devices: Vec<LanDevice> = Vec::new();
+    let _ = timeout(Duration::from_secs(3), async {
+        while let Ok(event) = events.recv_async().await {
+            if let ServiceEvent::ServiceResolved(info) = event {
+                let id = info.get_property_val_str("id").unwrap_or_default().to_string();
+                let address = match info.get_addresses().iter().find(|a| a.is_ipv4()).or(info.get_addresses().iter().next()) {
+                    Some(ip) => SocketAddr::new(*ip, info.get_port()).to_string(),
+                    None => continue
+                };
+                if id.is_empty() || id == node.device_id() || devices.iter().any(|d| d.id == id) {
+                    continue;
+                }
+                devices.push(LanDevice {
+                    name: info.get_property_val_str("name").unwrap_or(&id).to_string(),
+                    paired: peers.iter().any(|p| p.id == id),
+                    id,
+                    address
+                });
+            }
+        }
+    }).await;
+    let _ = mdns.stop_browse(SERVICE_TYPE);
+    if own {
+        let _ = mdns.shutdown();
+    }
+    Ok(devices)
+}
+
+#[tauri::command]
+pub async fn pair_lan_device(address: String, pin: String) -> Result<LanPeer, String> {
+    let node = running()?.0;
+    node.pair(&address, &pin).await
+}
+
+#[tauri::command]
+pub async fn sync_lan_device(address: String) -> Result<LanPeer, String> {
+    let node = running()?.0;
+    node.sync_with(&address).await
+}
+
+#[tauri::command]
+pub fn list_lan_peers() -> Result<Vec<LanPeer>, String> {
+    let dir = http::app_conf_dir().ok_or("No app data directory.".to_string())?;
+    Ok(LanNode::open(&dir, "", None)?.peers())
+}
+
+#[tauri::command]
+pub fn forget_lan_peer(id: String) -> Result<(), String> {
+    let dir = http::app_conf_dir().ok_or("No app data directory.".to_string())?;
+    LanNode::open(&dir, "", None)?.forget_peer(&id)
+}
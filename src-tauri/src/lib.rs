@@ -10,6 +10,26 @@ mod task;
 mod storage;
 mod utils;
 mod http;
+mod auth;
+mod crypto;
+mod profile;
+mod lan;
+mod folder;
+mod ics;
+mod caldav;
+mod webdav;
+mod backend;
+mod calendar;
+mod feed;
+mod archive;
+mod spreadsheet;
+mod todotxt;
+mod markdown;
+mod migrate;
+mod taskwarrior;
+mod backup;
+mod restore;
+mod health;
 
 mod tests;
 
@@ -44,11 +64,82 @@ pub fn run() {
             http::is_logged_in,
             http::send_telemetry,
             http::do_sync,
+            http::preview_sync,
             http::log_out,
+            http::get_server_endpoint,
+            http::set_server_endpoint,
+            http::test_connection,
+            auth::register_account,
+            auth::request_password_reset,
+            auth::reset_password,
+            auth::start_device_login,
+            auth::poll_device_login,
+            auth::cancel_device_login,
+            crypto::get_encryption_status,
+            crypto::enable_encryption,
+            crypto::unlock_encryption,
+            crypto::lock_encryption,
+            crypto::rotate_encryption_key,
+            crypto::export_recovery_phrase,
+            crypto::recover_encryption,
+            profile::list_profiles,
+            profile::add_profile,
+            profile::rename_profile,
+            profile::switch_profile,
+            profile::remove_profile,
+            lan::start_lan_sync,
+            lan::stop_lan_sync,
+            lan::start_lan_pairing,
+            lan::discover_lan_devices,
+            lan::pair_lan_device,
+            lan::sync_lan_device,
+            lan::list_lan_peers,
+            lan::forget_lan_peer,
+            folder::get_sync_folder,
+            folder::set_sync_folder,
+            folder::start_folder_sync,
+            folder::sync_folder,
+            caldav::get_caldav_account,
+            caldav::set_caldav_account,
+            caldav::remove_caldav_account,
+            caldav::caldav_sync,
+            webdav::get_webdav_backup,
+            webdav::set_webdav_backup,
+            webdav::remove_webdav_backup,
+            webdav::backup_to_webdav,
+            webdav::list_webdav_snapshots,
+            webdav::restore_webdav_snapshot,
+            webdav::start_webdav_backups,
+            calendar::export_ics,
+            calendar::import_ics,
+            feed::get_ics_feed,
+            feed::start_ics_feed,
+            feed::resume_ics_feed,
+            feed::stop_ics_feed,
+            feed::reset_ics_feed_token,
+            archive::export_all,
+            archive::import_all,
+            spreadsheet::export_csv,
+            spreadsheet::preview_csv_import,
+            spreadsheet::import_csv,
+            todotxt::export_todotxt,
+            todotxt::import_todotxt,
+            markdown::export_markdown,
+            markdown::import_markdown,
+            migrate::import_todoist,
+            migrate::import_mstodo,
+            taskwarrior::export_taskwarrior,
+            taskwarrior::import_taskwarrior,
+            backup::backup_now,
+            backup::list_backups,
+            backup::restore_backup,
+            backup::start_local_backups,
+            task::check_database,
+            task::repair_database,
         ])
         .build(tauri::generate_context!())
         .expect("error while building tauri application");
-    http::set_app_conf_dir(app.path().app_data_dir().unwrap().to_str().unwrap().to_string());
+    profile::init_profiles(app.path().app_data_dir().unwrap().to_str().unwrap().to_string());
     app.run(|handle, event| match event {
         tauri::RunEvent::ExitRequested { .. } => {
             let _ = handle.emit("exit-requested", ());
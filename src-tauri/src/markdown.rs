@@ -0,0 +1,188 @@
+// GitHub-flavored Markdown checklists, for pasting lists into PR
+// descriptions and notes apps and back.
+use serde::Serialize;
+use serde_json::Map;
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::{calendar::ImportedList, ics::civil_from_days, spreadsheet::{parse_date, DateOrder}, task::{add_imported_list, fix_parents, get_saved_list, get_saved_tasks, load_records, tasks_loaded, DEFAULT_COLOR, DEFAULT_IMPORTANCE, DEFAULT_SIZE, ListEntry, TaskEntry, TaskRecord}, utils::now};
+
+/// Spaces per subtask level in exports
+const INDENT: &str = "  ";
+/// Columns a tab counts as when reading indents
+const TAB_WIDTH: usize = 4;
+
+#[derive(Serialize, Debug, Default)]
+pub struct MarkdownImport {
+    pub lists: usize,
+    pub tasks: usize
+}
+
+/// `ms` as a local date, with the time unless it's midnight.
+fn format_due(ms: i64, utc_offset: i32) -> String {
+    let secs = (ms + utc_offset as i64 * 60_000).div_euclid(1000);
+    let (y, m, d) = civil_from_days(secs.div_euclid(86400));
+    let rem = secs.rem_euclid(86400);
+    match rem {
+        0 => format!("{:04}-{:02}-{:02}", y, m, d),
+        _ => format!("{:04}-{:02}-{:02} {:02}:{:02}", y, m, d, rem / 3600, rem % 3600 / 60)
+    }
+}
+
+/// `list` as a heading and a nested checklist, with due dates local to
+/// `utc_offset`.
+pub fn write_markdown(list: &ListEntry, tasks: &[TaskEntry], utc_offset: i32) -> String {
+    fn walk(records: &[TaskRecord], depth: usize, utc_offset: i32, out: &mut String) {
+        for r in records {
+            let name = r.name.split_whitespace().collect::<Vec<_>>().join(" ");
+            out.push_str(&format!("{}- [{}] {} (due: {})\n", INDENT.repeat(depth), if r.completed { "x" } else { " " }, name, format_due(r.due, utc_offset)));
+            walk(&r.subtasks, depth + 1, utc_offset, out);
+        }
+    }
+    let mut out = format!("# {}\n\n", list.name);
+    walk(&load_records(&tasks.to_vec()), 0, utc_offset, &mut out);
+    out
+}
+
+/// The indent width, checkbox state and text of a list item line. Items
+/// without a checkbox are unchecked.
+fn parse_item(line: &str) -> Option<(usize, bool, &str)> {
+    let text = line.trim_start();
+    let width = line[..line.len() - text.len()].chars().map(|c| if c == '\t' { TAB_WIDTH } else { 1 }).sum();
+    let rest = if let Some(rest) = text.strip_prefix(['-', '*', '+']) {
+        rest
+    } else {
+        // Ordered lists: `1.` or `1)`
+        let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return None;
+        }
+        text[digits..].strip_prefix(['.', ')'])?
+    };
+    let rest = rest.strip_prefix([' ', '\t'])?.trim_start();
+    let (completed, rest) = match rest.get(..3) {
+        Some("[ ]") => (false, &rest[3..]),
+        Some("[x]" | "[X]") => (true, &rest[3..]),
+        _ => (false, rest)
+    };
+    Some((width, completed, rest.trim()))
+}
+
+/// A task's name and due date, from text ending in `(due: ...)`.
+fn split_due(text: &str, utc_offset: i32) -> (String, Option<i64>) {
+    if let Some(start) = text.rfind("(due:").filter(|_| text.ends_with(')')) {
+        let value = &text[start + 5..text.len() - 1];
+        if let Some(due) = parse_date(value, DateOrder::Mdy, utc_offset) {
+            return (text[..start].trim_end().to_string(), Some(due));
+        }
+    }
+    (text.to_string(), None)
+}
+
+/// Lists in a Markdown checklist. Each `#` heading starts a list; items
+/// before the first heading go in a list named `fallback_name`. Deeper
+/// indented items are subtasks of the item above.
+pub fn read_markdown(text: &str, fallback_name: &str, utc_offset: i32) -> Vec<ImportedList> {
+    let new_list = |name: &str| ImportedList {
+        list: ListEntry {
+            name: name.to_string(),
+            uuid: uuid::Uuid::new_v4().to_string(),
+            color: DEFAULT_COLOR,
+            last_edited: None,
+            created: None,
+            sealed: None,
+            extra: Map::new()
+        },
+        tasks: vec![]
+    };
+    let mut lists = vec![];
+    let mut current = new_list(fallback_name);
+    // Items above at each indent, innermost last
+    let mut stack: Vec<(usize, String)> = vec![];
+    for line in text.lines() {
+        let heading = line.trim_start().trim_start_matches('#');
+        if line.trim_start().starts_with('#') && heading.starts_with(' ') {
+            let name = heading.trim().trim_end_matches('#').trim();
+            if !current.tasks.is_empty() {
+                lists.push(std::mem::replace(&mut current, new_list(name)));
+            } else {
+                current.list.name = name.to_string();
+            }
+            stack.clear();
+            continue;
+        }
+        let Some((width, completed, text)) = parse_item(line) else { continue };
+        if text.is_empty() {
+            continue;
+        }
+        let (name, due) = split_due(text, utc_offset);
+        while stack.last().is_some_and(|(w, _)| *w >= width) {
+            stack.pop();
+        }
+        let task = TaskEntry {
+            name,
+            size: DEFAULT_SIZE,
+            importance: DEFAULT_IMPORTANCE,
+            due: due.unwrap_or_else(now),
+            completed,
+            id: uuid::Uuid::new_v4().to_string(),
+            parent: stack.last().map(|(_, id)| id.clone()),
+            last_edited: None,
+            created: None,
+            sealed: None,
+            extra: Map::new()
+        };
+        stack.push((width, task.id.clone()));
+        current.tasks.push(task);
+    }
+    if !current.tasks.is_empty() {
+        lists.push(current);
+    }
+    for list in &mut lists {
+        fix_parents(&mut list.tasks);
+    }
+    lists
+}
+
+/// The list `list_id` as a Markdown checklist.
+#[tauri::command]
+pub async fn export_markdown(list_id: String, utc_offset: i32) -> Result<String, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let list = get_saved_list(&list_id).await
+        .or_else(|e| Err(format!("Export Error: {}", e)))?
+        .ok_or(format!("Export Error: no list {}.", list_id))?;
+    let tasks = get_saved_tasks(&list_id).await
+        .or_else(|e| Err(format!("Export Error: {}", e)))?;
+    Ok(write_markdown(&list, &tasks, utc_offset))
+}
+
+/// Makes new lists from a Markdown checklist, then has the task list
+/// reload.
+#[tauri::command]
+pub async fn import_markdown<R: Runtime>(app: AppHandle<R>, text: String, name: String, utc_offset: i32) -> Result<MarkdownImport, String> {
+    let result = import_markdown_text(&text, &name, utc_offset).await?;
+    let _ = app.emit("tasks-imported", result.tasks);
+    Ok(result)
+}
+
+pub async fn import_markdown_text(text: &str, name: &str, utc_offset: i32) -> Result<MarkdownImport, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let lists = read_markdown(text, name, utc_offset);
+    if lists.is_empty() {
+        return Err("There are no list items to import.".to_string());
+    }
+    let mut result = MarkdownImport::default();
+    for imported in lists {
+        let added = add_imported_list(&imported.list, &imported.tasks).await
+            .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        if !added {
+            return Err(format!("Import Error: couldn't add list {}.", imported.list.name));
+        }
+        result.lists += 1;
+        result.tasks += imported.tasks.len();
+    }
+    Ok(result)
+}
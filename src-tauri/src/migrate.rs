@@ -0,0 +1,532 @@
+// Importers for other to-do apps: Todoist's CSV project exports and JSON
+// backups, and Microsoft To Do's JSON export. Anything without a Task
+// Manager equivalent is counted in the report instead of being kept.
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs, path::Path};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{from_str, Map, Value};
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::{calendar::ImportedList, spreadsheet::{parse_date, DateOrder}, task::{add_imported_list, fix_parents, tasks_loaded, DEFAULT_COLOR, DEFAULT_IMPORTANCE, DEFAULT_SIZE, ListEntry, TaskEntry}, utils::now};
+
+
+#[derive(Serialize, Debug, Default)]
+pub struct MigrationImport {
+    pub lists: usize,
+    pub tasks: usize,
+    /// What couldn't be brought over (`comments`, `reminders`) → how many
+    pub dropped: BTreeMap<String, usize>
+}
+
+fn count(dropped: &mut BTreeMap<String, usize>, what: &str) {
+    *dropped.entry(what.to_string()).or_default() += 1;
+}
+
+fn new_list(name: &str) -> ImportedList {
+    ImportedList {
+        list: ListEntry {
+            name: name.to_string(),
+            uuid: uuid::Uuid::new_v4().to_string(),
+            color: DEFAULT_COLOR,
+            last_edited: None,
+            created: None,
+            sealed: None,
+            extra: Map::new()
+        },
+        tasks: vec![]
+    }
+}
+
+fn new_task(name: String, importance: i32, due: i64, completed: bool, parent: Option<String>) -> TaskEntry {
+    TaskEntry {
+        name,
+        size: DEFAULT_SIZE,
+        importance,
+        due,
+        completed,
+        id: uuid::Uuid::new_v4().to_string(),
+        parent,
+        last_edited: None,
+        created: None,
+        sealed: None,
+        extra: Map::new()
+    }
+}
+
+/// `name` with a `#tag` for each label, spaces in labels made underscores.
+fn with_tags(name: &str, labels: &[String]) -> String {
+    let tags = labels.iter().map(|l| format!("#{}", l.split_whitespace().collect::<Vec<_>>().join("_")));
+    std::iter::once(name.trim().to_string()).chain(tags).collect::<Vec<_>>().join(" ")
+}
+
+/// Sections have no date of their own, so they're due with their earliest
+/// task, or today if they have none.
+fn date_sections(tasks: &mut [TaskEntry], sections: &HashSet<String>) {
+    let mut earliest: HashMap<String, i64> = HashMap::new();
+    for task in tasks.iter() {
+        if let Some(parent) = task.parent.as_ref().filter(|p| sections.contains(*p)) {
+            let due = earliest.entry(parent.clone()).or_insert(task.due);
+            *due = (*due).min(task.due);
+        }
+    }
+    let today = now();
+    for task in tasks.iter_mut().filter(|t| sections.contains(&t.id)) {
+        task.due = earliest.get(&task.id).copied().unwrap_or(today);
+    }
+}
+
+/// Todoist's p1 (most urgent) to p4 (no priority). p3 and p4 are both
+/// average, since p4 is what tasks get when no one picked a priority.
+fn todoist_importance(level: i64) -> i32 {
+    match level {
+        1 => 4,
+        2 => 3,
+        _ => DEFAULT_IMPORTANCE
+    }
+}
+
+/// The project name from a Todoist CSV file name, `Work [2203306141]`.
+fn todoist_project_name(stem: &str) -> &str {
+    match stem.rsplit_once(" [") {
+        Some((name, id)) if id.ends_with(']') && id[..id.len() - 1].chars().all(|c| c.is_ascii_digit()) => name,
+        _ => stem
+    }
+}
+
+/// A Todoist CSV project export. Sections become top-level tasks, INDENT
+/// makes subtasks, `@labels` become `#tags` and `note` rows are dropped,
+/// as are descriptions, assignees, durations and deadlines. Dates are
+/// read in `utc_offset`; recurring ones and relative ones like `tomorrow`
+/// are due today.
+pub fn read_todoist_csv(text: &str, name: &str, utc_offset: i32, dropped: &mut BTreeMap<String, usize>) -> Result<ImportedList, String> {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let headers: Vec<String> = reader.headers()
+        .or_else(|e| Err(format!("Import Error: {}", e)))?
+        .iter().map(|h| h.trim().to_uppercase()).collect();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let (Some(kind_col), Some(content_col)) = (column("TYPE"), column("CONTENT")) else {
+        return Err(format!("Import Error: {} isn't a Todoist CSV export.", name));
+    };
+    let columns = ["DESCRIPTION", "PRIORITY", "INDENT", "RESPONSIBLE", "DATE", "DURATION", "DEADLINE"].map(column);
+    let [description_col, priority_col, indent_col, responsible_col, date_col, duration_col, deadline_col] = columns;
+
+    let mut imported = new_list(todoist_project_name(name));
+    let mut sections = HashSet::new();
+    // Tasks above at each indent, innermost last; a section is indent 0
+    let mut stack: Vec<(i64, String)> = vec![];
+    for row in reader.records() {
+        let row = row.or_else(|e| Err(format!("Import Error: {}", e)))?;
+        let cell = |col: Option<usize>| col.and_then(|c| row.get(c)).unwrap_or_default().trim();
+        let content = cell(Some(content_col));
+        match cell(Some(kind_col)).to_lowercase().as_str() {
+            "section" => {
+                let section = new_task(content.to_string(), DEFAULT_IMPORTANCE, 0, false, None);
+                sections.insert(section.id.clone());
+                stack = vec![(0, section.id.clone())];
+                imported.tasks.push(section);
+            },
+            "task" if !content.is_empty() => {
+                for (col, what) in [(description_col, "descriptions"), (responsible_col, "assignees"), (duration_col, "durations"), (deadline_col, "deadlines")] {
+                    if !cell(col).is_empty() {
+                        count(dropped, what);
+                    }
+                }
+                let date = cell(date_col);
+                let due = if date.is_empty() {
+                    now()
+                } else if date.to_lowercase().starts_with("every") {
+                    count(dropped, "recurring due dates");
+                    now()
+                } else {
+                    parse_date(date, DateOrder::Mdy, utc_offset).unwrap_or_else(|| {
+                        count(dropped, "unreadable due dates");
+                        now()
+                    })
+                };
+                let indent: i64 = cell(indent_col).parse().unwrap_or(1).max(1);
+                while stack.last().is_some_and(|(i, _)| *i >= indent) {
+                    stack.pop();
+                }
+                let words: Vec<String> = content.split_whitespace()
+                    .map(|w| match w.strip_prefix('@') {
+                        Some(label) if !label.is_empty() => format!("#{}", label),
+                        _ => w.to_string()
+                    })
+                    .collect();
+                let priority = cell(priority_col).parse().unwrap_or(4);
+                let task = new_task(words.join(" "), todoist_importance(priority), due, false, stack.last().map(|(_, id)| id.clone()));
+                stack.push((indent, task.id.clone()));
+                imported.tasks.push(task);
+            },
+            "note" => count(dropped, "comments"),
+            _ => {}
+        }
+    }
+    date_sections(&mut imported.tasks, &sections);
+    fix_parents(&mut imported.tasks);
+    Ok(imported)
+}
+
+/// Todoist ids are strings now, but were numbers in older backups.
+fn todoist_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::String(s)) => Some(s),
+        Some(Value::Number(n)) => Some(n.to_string()),
+        _ => None
+    })
+}
+
+/// Flags are booleans now, but were 0 or 1 in older backups.
+fn todoist_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Bool(b) => b,
+        Value::Number(n) => n.as_i64() != Some(0),
+        _ => false
+    })
+}
+
+#[derive(Deserialize)]
+struct TodoistBackup {
+    #[serde(default)]
+    projects: Vec<TodoistProject>,
+    #[serde(default)]
+    sections: Vec<TodoistSection>,
+    #[serde(default, alias = "tasks")]
+    items: Vec<TodoistItem>,
+    #[serde(default)]
+    notes: Vec<Value>,
+    #[serde(default)]
+    project_notes: Vec<Value>,
+    #[serde(default)]
+    reminders: Vec<Value>,
+    #[serde(default)]
+    filters: Vec<Value>
+}
+
+#[derive(Deserialize)]
+struct TodoistProject {
+    #[serde(default, deserialize_with = "todoist_id")]
+    id: Option<String>,
+    name: String,
+    #[serde(default, deserialize_with = "todoist_flag")]
+    is_deleted: bool
+}
+
+#[derive(Deserialize)]
+struct TodoistSection {
+    #[serde(default, deserialize_with = "todoist_id")]
+    id: Option<String>,
+    #[serde(default, deserialize_with = "todoist_id")]
+    project_id: Option<String>,
+    name: String,
+    #[serde(default, deserialize_with = "todoist_flag")]
+    is_deleted: bool
+}
+
+#[derive(Deserialize)]
+struct TodoistItem {
+    #[serde(default, deserialize_with = "todoist_id")]
+    id: Option<String>,
+    #[serde(default, deserialize_with = "todoist_id")]
+    project_id: Option<String>,
+    #[serde(default, deserialize_with = "todoist_id")]
+    section_id: Option<String>,
+    #[serde(default, deserialize_with = "todoist_id")]
+    parent_id: Option<String>,
+    content: String,
+    #[serde(default)]
+    description: String,
+    /// 4 is p1 here, unlike in CSV exports
+    #[serde(default)]
+    priority: Option<i64>,
+    #[serde(default)]
+    due: Option<TodoistDue>,
+    #[serde(default, alias = "is_completed", deserialize_with = "todoist_flag")]
+    checked: bool,
+    #[serde(default, deserialize_with = "todoist_flag")]
+    is_deleted: bool,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    responsible_uid: Option<Value>,
+    #[serde(default)]
+    duration: Option<Value>,
+    #[serde(default)]
+    deadline: Option<Value>
+}
+
+#[derive(Deserialize)]
+struct TodoistDue {
+    #[serde(default)]
+    date: String,
+    #[serde(default)]
+    datetime: Option<String>,
+    #[serde(default)]
+    is_recurring: bool
+}
+
+/// A Todoist JSON backup, as from the sync API (`projects`, `sections`,
+/// `items`) or the REST API (`tasks`). Each project is a list, sections
+/// become top-level tasks, and labels become `#tags`. Recurring tasks
+/// keep their next due date.
+pub fn read_todoist_json(text: &str, utc_offset: i32, dropped: &mut BTreeMap<String, usize>) -> Result<Vec<ImportedList>, String> {
+    let backup: TodoistBackup = from_str(text)
+        .or_else(|e| Err(format!("Import Error: this isn't a Todoist backup ({}).", e)))?;
+    let mut lists: Vec<ImportedList> = vec![];
+    let mut list_of: HashMap<String, usize> = HashMap::new();
+    for project in backup.projects.iter().filter(|p| !p.is_deleted) {
+        if let Some(id) = &project.id {
+            list_of.insert(id.clone(), lists.len());
+        }
+        lists.push(new_list(&project.name));
+    }
+    let mut ids: HashMap<String, String> = HashMap::new();
+    let mut sections = HashSet::new();
+    for section in backup.sections.iter().filter(|s| !s.is_deleted) {
+        let Some(&list) = section.project_id.as_ref().and_then(|p| list_of.get(p)) else { continue };
+        let task = new_task(section.name.clone(), DEFAULT_IMPORTANCE, 0, false, None);
+        if let Some(id) = &section.id {
+            ids.insert(id.clone(), task.id.clone());
+        }
+        sections.insert(task.id.clone());
+        lists[list].tasks.push(task);
+    }
+    let items: Vec<&TodoistItem> = backup.items.iter().filter(|i| !i.is_deleted).collect();
+    for item in &items {
+        if let Some(id) = &item.id {
+            ids.insert(id.clone(), uuid::Uuid::new_v4().to_string());
+        }
+    }
+    for item in items {
+        let Some(&list) = item.project_id.as_ref().and_then(|p| list_of.get(p)) else {
+            count(dropped, "tasks in missing projects");
+            continue;
+        };
+        let missing = [
+            (item.description.trim().is_empty(), "descriptions"),
+            (item.responsible_uid.as_ref().is_none_or(Value::is_null), "assignees"),
+            (item.duration.as_ref().is_none_or(Value::is_null), "durations"),
+            (item.deadline.as_ref().is_none_or(Value::is_null), "deadlines")
+        ];
+        for (missing, what) in missing {
+            if !missing {
+                count(dropped, what);
+            }
+        }
+        let due = match &item.due {
+            Some(due) => {
+                if due.is_recurring {
+                    count(dropped, "recurrence");
+                }
+                parse_date(due.datetime.as_deref().unwrap_or(&due.date), DateOrder::Mdy, utc_offset).unwrap_or_else(|| {
+                    count(dropped, "unreadable due dates");
+                    now()
+                })
+            },
+            None => now()
+        };
+        let parent = item.parent_id.as_ref().or(item.section_id.as_ref()).and_then(|p| ids.get(p)).cloned();
+        let importance = todoist_importance(5 - item.priority.unwrap_or(1));
+        let mut task = new_task(with_tags(&item.content, &item.labels), importance, due, item.checked, parent);
+        if let Some(id) = item.id.as_ref().and_then(|i| ids.get(i)) {
+            task.id = id.clone();
+        }
+        lists[list].tasks.push(task);
+    }
+    for (items, what) in [(&backup.notes, "comments"), (&backup.project_notes, "project comments"), (&backup.reminders, "reminders"), (&backup.filters, "filters")] {
+        let live = items.iter().filter(|i| i.get("is_deleted") != Some(&Value::Bool(true))).count();
+        if live > 0 {
+            *dropped.entry(what.to_string()).or_default() += live;
+        }
+    }
+    for list in &mut lists {
+        date_sections(&mut list.tasks, &sections);
+        fix_parents(&mut list.tasks);
+    }
+    Ok(lists)
+}
+
+#[derive(Deserialize)]
+struct MsTodoExport {
+    #[serde(alias = "value")]
+    lists: Vec<MsTodoList>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MsTodoList {
+    display_name: String,
+    #[serde(default)]
+    tasks: Vec<MsTodoTask>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MsTodoTask {
+    title: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    importance: String,
+    #[serde(default)]
+    due_date_time: Option<MsTodoDateTime>,
+    #[serde(default)]
+    body: Option<MsTodoBody>,
+    #[serde(default)]
+    checklist_items: Vec<MsTodoChecklistItem>,
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    recurrence: Option<Value>,
+    #[serde(default)]
+    is_reminder_on: bool,
+    #[serde(default)]
+    has_attachments: bool,
+    #[serde(default)]
+    linked_resources: Vec<Value>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MsTodoDateTime {
+    date_time: String,
+    #[serde(default)]
+    time_zone: String
+}
+
+#[derive(Deserialize)]
+struct MsTodoBody {
+    #[serde(default)]
+    content: String
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MsTodoChecklistItem {
+    display_name: String,
+    #[serde(default)]
+    is_checked: bool
+}
+
+/// A Microsoft To Do export: Graph API lists with their tasks, under
+/// `lists` or `value`. Checklist steps become subtasks due with their
+/// task, and categories become `#tags`. Due times in UTC stay UTC; any
+/// other zone is read as `utc_offset`.
+pub fn read_mstodo_json(text: &str, utc_offset: i32, dropped: &mut BTreeMap<String, usize>) -> Result<Vec<ImportedList>, String> {
+    let export: MsTodoExport = from_str(text)
+        .or_else(|e| Err(format!("Import Error: this isn't a Microsoft To Do export ({}).", e)))?;
+    let mut lists = vec![];
+    for list in export.lists {
+        let mut imported = new_list(&list.display_name);
+        for task in list.tasks {
+            let missing = [
+                (task.body.as_ref().is_none_or(|b| b.content.trim().is_empty()), "notes"),
+                (!task.is_reminder_on, "reminders"),
+                (task.recurrence.as_ref().is_none_or(Value::is_null), "recurrence"),
+                (!task.has_attachments, "attachments"),
+                (task.linked_resources.is_empty(), "linked resources")
+            ];
+            for (missing, what) in missing {
+                if !missing {
+                    count(dropped, what);
+                }
+            }
+            let due = match &task.due_date_time {
+                Some(due) => {
+                    let zone = if due.time_zone.eq_ignore_ascii_case("utc") { " Z" } else { "" };
+                    parse_date(&format!("{}{}", due.date_time, zone), DateOrder::Mdy, utc_offset).unwrap_or_else(|| {
+                        count(dropped, "unreadable due dates");
+                        now()
+                    })
+                },
+                None => now()
+            };
+            let importance = match task.importance.to_lowercase().as_str() {
+                "high" => 3,
+                "low" => 1,
+                _ => DEFAULT_IMPORTANCE
+            };
+            let parent = new_task(with_tags(&task.title, &task.categories), importance, due, task.status.eq_ignore_ascii_case("completed"), None);
+            let steps: Vec<TaskEntry> = task.checklist_items.iter()
+                .map(|step| new_task(step.display_name.trim().to_string(), importance, due, step.is_checked, Some(parent.id.clone())))
+                .collect();
+            imported.tasks.push(parent);
+            imported.tasks.extend(steps);
+        }
+        lists.push(imported);
+    }
+    Ok(lists)
+}
+
+async fn add_lists(lists: Vec<ImportedList>, result: &mut MigrationImport) -> Result<(), String> {
+    for imported in lists {
+        let added = add_imported_list(&imported.list, &imported.tasks).await
+            .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        if !added {
+            return Err(format!("Import Error: couldn't add list {}.", imported.list.name));
+        }
+        result.lists += 1;
+        result.tasks += imported.tasks.len();
+    }
+    Ok(())
+}
+
+/// Makes new lists from Todoist CSV exports (one per project) or a JSON
+/// backup, then has the task list reload.
+#[tauri::command]
+pub async fn import_todoist<R: Runtime>(app: AppHandle<R>, paths: Vec<String>, utc_offset: i32) -> Result<MigrationImport, String> {
+    let result = import_todoist_files(&paths, utc_offset).await?;
+    let _ = app.emit("tasks-imported", result.tasks);
+    Ok(result)
+}
+
+/// Nothing is saved unless every file can be read.
+pub async fn import_todoist_files(paths: &[String], utc_offset: i32) -> Result<MigrationImport, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let mut result = MigrationImport::default();
+    let mut lists = vec![];
+    for path in paths {
+        let text = fs::read_to_string(path)
+            .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        if text.trim_start().starts_with('{') {
+            lists.extend(read_todoist_json(&text, utc_offset, &mut result.dropped)?);
+        } else {
+            let name = Path::new(path).file_stem().map_or("Todoist".to_string(), |s| s.to_string_lossy().to_string());
+            lists.push(read_todoist_csv(&text, &name, utc_offset, &mut result.dropped)?);
+        }
+    }
+    if lists.is_empty() {
+        return Err("There are no projects to import.".to_string());
+    }
+    add_lists(lists, &mut result).await?;
+    Ok(result)
+}
+
+/// Makes new lists from a Microsoft To Do JSON export, then has the task
+/// list reload.
+#[tauri::command]
+pub async fn import_mstodo<R: Runtime>(app: AppHandle<R>, path: String, utc_offset: i32) -> Result<MigrationImport, String> {
+    let result = import_mstodo_file(&path, utc_offset).await?;
+    let _ = app.emit("tasks-imported", result.tasks);
+    Ok(result)
+}
+
+pub async fn import_mstodo_file(path: &str, utc_offset: i32) -> Result<MigrationImport, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let text = fs::read_to_string(path)
+        .or_else(|e| Err(format!("Import Error: {}", e)))?;
+    let mut result = MigrationImport::default();
+    let lists = read_mstodo_json(&text, utc_offset, &mut result.dropped)?;
+    if lists.is_empty() {
+        return Err("There are no lists to import.".to_string());
+    }
+    add_lists(lists, &mut result).await?;
+    Ok(result)
+}
@@ -0,0 +1,260 @@
+use std::fs::{self, read_to_string, write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::{algorithm::{switch_history_db, HISTORY_PATH}, auth, backup, feed, folder, lan, webdav, http::{self, api_root, saved_endpoint, validate_endpoint, write_server_conf}, task::{switch_task_db, TASKS_PATH}};
+
+/// The profile that existed before profiles did. Its data stays directly
+/// in the app data directory, so older installs keep working unchanged.
+pub const DEFAULT_PROFILE: &str = "default";
+const PROFILES_PATH: &str = "/profiles.json";
+const PROFILES_DIR: &str = "/profiles";
+const MAX_NAME_LEN: usize = 64;
+
+static mut BASE_DIR: Option<String> = None;
+static mut ACTIVE: Option<String> = None;
+
+/// An account profile: its own task and history databases, sync server
+/// and session.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub id: String,
+    pub name: String
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProfileConf {
+    active: String,
+    profiles: Vec<Profile>
+}
+
+impl ProfileConf {
+    fn new() -> Self {
+        ProfileConf {
+            active: DEFAULT_PROFILE.to_string(),
+            profiles: vec![Profile { id: DEFAULT_PROFILE.to_string(), name: "Personal".to_string() }]
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProfileInfo {
+    pub id: String,
+    pub name: String,
+    pub endpoint: String,
+    pub active: bool
+}
+
+fn load_conf(base_dir: &str) -> ProfileConf {
+    let res = read_to_string(base_dir.to_string() + PROFILES_PATH);
+    if res.is_err() {
+        return ProfileConf::new();
+    }
+    match from_str::<ProfileConf>(&res.unwrap()) {
+        Ok(conf) if conf.profiles.iter().any(|p| p.id == conf.active) => conf,
+        Ok(_) => ProfileConf::new(),
+        Err(e) => {
+            println!("Profile config error {}", e);
+            ProfileConf::new()
+        }
+    }
+}
+
+fn write_conf(base_dir: &str, conf: &ProfileConf) -> Result<(), String> {
+    write(base_dir.to_string() + PROFILES_PATH, to_string(conf).unwrap()).or_else(|e| Err(format!("{}", e)))
+}
+
+fn base_dir() -> Result<String, String> {
+    unsafe { BASE_DIR.clone().ok_or("Profiles aren't loaded.".to_string()) }
+}
+
+/// Loads the profile list from the app data directory and makes the last
+/// active profile current. Call once at startup, before anything touches the
+/// task or history databases.
+pub fn init_profiles(base_dir: String) {
+    let conf = load_conf(&base_dir);
+    let dir = profile_dir(&base_dir, &conf.active);
+    let _ = fs::create_dir_all(&dir);
+    unsafe {
+        BASE_DIR = Some(base_dir);
+        ACTIVE = Some(conf.active);
+    }
+    http::set_app_conf_dir(Some(dir));
+}
+
+fn profile_dir(base_dir: &str, id: &str) -> String {
+    if id == DEFAULT_PROFILE {
+        base_dir.to_string()
+    } else {
+        format!("{}{}/{}", base_dir, PROFILES_DIR, id)
+    }
+}
+
+/// Data directory of the active profile, once profiles are loaded.
+pub fn active_dir() -> Option<String> {
+    unsafe {
+        let base = BASE_DIR.as_ref()?;
+        Some(profile_dir(base, ACTIVE.as_deref().unwrap_or(DEFAULT_PROFILE)))
+    }
+}
+
+pub fn is_default() -> bool {
+    unsafe { ACTIVE.as_deref().unwrap_or(DEFAULT_PROFILE) == DEFAULT_PROFILE }
+}
+
+/// Key the active profile's session for `server` is stored under.
+pub fn credential_key(server: &str) -> String {
+    unsafe { key_for(ACTIVE.as_deref().unwrap_or(DEFAULT_PROFILE), server) }
+}
+
+fn key_for(id: &str, server: &str) -> String {
+    if id == DEFAULT_PROFILE {
+        server.to_string()
+    } else {
+        format!("{}:{}", id, server)
+    }
+}
+
+fn check_name(conf: &ProfileConf, name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(format!("Profile names must be 1 to {} characters.", MAX_NAME_LEN));
+    }
+    if conf.profiles.iter().any(|p| p.name.eq_ignore_ascii_case(name)) {
+        return Err(format!("There is already a profile called {}.", name));
+    }
+    Ok(name.to_string())
+}
+
+#[tauri::command]
+pub fn list_profiles() -> Result<Vec<ProfileInfo>, String> {
+    let base = base_dir()?;
+    let conf = load_conf(&base);
+    Ok(conf.profiles.iter().map(|p| ProfileInfo {
+        id: p.id.clone(),
+        name: p.name.clone(),
+        endpoint: match p.id == conf.active {
+            true => api_root(),
+            false => saved_endpoint(&profile_dir(&base, &p.id)).unwrap_or_else(http::default_api_root)
+        },
+        active: p.id == conf.active
+    }).collect())
+}
+
+/// Creates a profile with an empty task database. It isn't switched to.
+#[tauri::command]
+pub fn add_profile(name: String, endpoint: Option<String>) -> Result<Profile, String> {
+    let base = base_dir()?;
+    let mut conf = load_conf(&base);
+    let name = check_name(&conf, &name)?;
+    let root = match endpoint {
+        Some(e) => Some(validate_endpoint(&e)?),
+        None => None
+    };
+    let profile = Profile { id: uuid::Uuid::new_v4().to_string(), name };
+    let dir = profile_dir(&base, &profile.id);
+    fs::create_dir_all(&dir).or_else(|e| Err(format!("{}", e)))?;
+    write_server_conf(&dir, root.as_deref())?;
+    conf.profiles.push(profile.clone());
+    write_conf(&base, &conf)?;
+    Ok(profile)
+}
+
+#[tauri::command]
+pub fn rename_profile(id: String, name: String) -> Result<(), String> {
+    let base = base_dir()?;
+    let mut conf = load_conf(&base);
+    let others = ProfileConf {
+        active: conf.active.clone(),
+        profiles: conf.profiles.iter().filter(|p| p.id != id).cloned().collect()
+    };
+    let name = check_name(&others, &name)?;
+    let profile = conf.profiles.iter_mut().find(|p| p.id == id).ok_or("No such profile.".to_string())?;
+    profile.name = name;
+    write_conf(&base, &conf)
+}
+
+/// Makes `id` the active profile: closes the current databases, opens the
+/// profile's, and loads its server and session.
+pub async fn activate_profile(id: &str) -> Result<(), String> {
+    let base = base_dir()?;
+    let mut conf = load_conf(&base);
+    if !conf.profiles.iter().any(|p| p.id == id) {
+        return Err("No such profile.".to_string());
+    }
+    let dir = profile_dir(&base, id);
+    fs::create_dir_all(&dir).or_else(|e| Err(format!("{}", e)))?;
+    auth::cancel_device_login();
+    lan::stop_lan_sync();
+    folder::stop_watching();
+    webdav::stop_backups();
+    backup::stop_backups();
+    feed::stop();
+    // The databases open first, so a failure leaves the old profile active
+    let old = profile_dir(&base, &conf.active);
+    conf.active = id.to_string();
+    let switched = match open_databases(&dir).await {
+        Ok(_) => write_conf(&base, &conf),
+        e => e
+    };
+    if let Err(e) = switched {
+        let _ = open_databases(&old).await;
+        return Err(e);
+    }
+    unsafe {
+        ACTIVE = Some(id.to_string());
+    }
+    http::set_app_conf_dir(Some(dir));
+    Ok(())
+}
+
+/// Opens the task and history databases in `dir` as the globals.
+pub async fn open_databases(dir: &str) -> Result<(), String> {
+    switch_task_db(&(dir.to_string() + TASKS_PATH)).await
+        .or_else(|e| Err(format!("Task DB Error: {}", e)))?;
+    switch_history_db(&(dir.to_string() + HISTORY_PATH)).await
+        .or_else(|e| Err(format!("History DB Error: {}", e)))
+}
+
+/// Switches profile and tells the windows to reload (`profile-switched`).
+#[tauri::command]
+pub async fn switch_profile<R: Runtime>(app: AppHandle<R>, id: String) -> Result<(), String> {
+    activate_profile(&id).await?;
+    let _ = app.emit("profile-switched", id);
+    Ok(())
+}
+
+/// Deletes a profile and all of its data. The default and the active
+/// profile can't be removed.
+#[tauri::command]
+pub fn remove_profile(id: String) -> Result<(), String> {
+    let base = base_dir()?;
+    let mut conf = load_conf(&base);
+    if id == DEFAULT_PROFILE {
+        return Err("The default profile can't be removed.".to_string());
+    }
+    if id == conf.active {
+        return Err("Switch to another profile before removing this one.".to_string());
+    }
+    if !conf.profiles.iter().any(|p| p.id == id) {
+        return Err("No such profile.".to_string());
+    }
+    let dir = profile_dir(&base, &id);
+    let server = saved_endpoint(&dir).unwrap_or_else(http::default_api_root);
+    http::remove_saved_session(&key_for(&id, &server));
+    conf.profiles.retain(|p| p.id != id);
+    write_conf(&base, &conf)?;
+    let _ = fs::remove_dir_all(dir);
+    Ok(())
+}
+
+/// Forgets the loaded profiles (tests).
+pub fn close_profiles() {
+    unsafe {
+        BASE_DIR = None;
+        ACTIVE = None;
+    }
+    http::set_app_conf_dir(None);
+}
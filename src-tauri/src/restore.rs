@@ -0,0 +1,116 @@
+// What local backups and WebDAV snapshots share: which of them retention
+// keeps, and swapping checked copies in for the open databases.
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{algorithm::{close_history_db, HISTORY_PATH}, http, profile::{active_dir, open_databases}, storage::verify_db, task::{close_task_db, TASKS_PATH}, utils::DAY_MS};
+
+/// Where the replaced databases are kept after a restore
+const PRE_RESTORE_SUFFIX: &str = ".pre-restore";
+/// Where copies wait to be checked before a restore
+pub const RESTORE_SUFFIX: &str = ".restore";
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BackupResult {
+    pub name: String,
+    /// Older backups removed by retention
+    pub removed: usize
+}
+
+/// Creation times of the snapshots to keep: the newest of each of the
+/// `daily` most recent days that have one, and likewise for the `weekly`
+/// most recent weeks. The newest snapshot is always kept.
+pub fn snapshots_to_keep(times: &[i64], daily: usize, weekly: usize) -> HashSet<i64> {
+    let mut times = times.to_vec();
+    times.sort_unstable_by(|a, b| b.cmp(a));
+    let mut keep = HashSet::new();
+    let (mut days, mut weeks) = (HashSet::new(), HashSet::new());
+    for (i, time) in times.iter().enumerate() {
+        let day = time.div_euclid(DAY_MS);
+        // Weeks start on Monday; day 0 was a Thursday
+        let week = (day + 3).div_euclid(7);
+        if i == 0 {
+            keep.insert(*time);
+        }
+        if days.len() < daily && days.insert(day) {
+            keep.insert(*time);
+        }
+        if weeks.len() < weekly && weeks.insert(week) {
+            keep.insert(*time);
+        }
+    }
+    keep
+}
+
+fn move_db(from: &str, to: &str) -> Result<(), String> {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = fs::remove_file(to.to_string() + suffix);
+        if Path::new(&(from.to_string() + suffix)).exists() {
+            fs::rename(from.to_string() + suffix, to.to_string() + suffix)
+                .or_else(|e| Err(format!("Couldn't replace {}: {}", to, e)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks the copies of both databases staged in `dir` with a `.restore`
+/// suffix. If they pass, the open databases are closed and replaced by
+/// them, keeping the old files with a `.pre-restore` suffix; if not, the
+/// copies are removed. If swapping or reopening fails, the old databases
+/// are put back and reopened. `source` is what they came from, for errors.
+pub async fn replace_databases(dir: &str, source: &str) -> Result<(), String> {
+    let staged = [(TASKS_PATH, "Lists"), (HISTORY_PATH, "DueEvents")];
+    for (db, table) in staged {
+        if let Err(e) = verify_db(&(dir.to_string() + db + RESTORE_SUFFIX), table).await {
+            for (db, _) in staged {
+                let _ = fs::remove_file(dir.to_string() + db + RESTORE_SUFFIX);
+            }
+            return Err(format!("The {}'s {} is damaged ({}).", source, db.trim_start_matches('/'), e));
+        }
+    }
+
+    close_task_db().await;
+    close_history_db().await;
+    // Databases moved out of the way, which have to come back on failure
+    let mut moved = vec![];
+    let mut replaced = Ok(());
+    for (db, _) in staged {
+        let path = dir.to_string() + db;
+        replaced = move_db(&path, &(path.clone() + PRE_RESTORE_SUFFIX));
+        if !Path::new(&path).exists() {
+            moved.push(db);
+        }
+        if replaced.is_ok() {
+            replaced = move_db(&(path.clone() + RESTORE_SUFFIX), &path);
+        }
+        if replaced.is_err() {
+            break;
+        }
+    }
+    if replaced.is_ok() {
+        replaced = open_databases(dir).await;
+    }
+    let Err(e) = replaced else {
+        return Ok(());
+    };
+
+    // Put the old databases back, so a failed restore leaves things as they were
+    close_task_db().await;
+    close_history_db().await;
+    for (db, _) in staged {
+        let path = dir.to_string() + db;
+        let _ = fs::remove_file(path.clone() + RESTORE_SUFFIX);
+        if moved.contains(&db) {
+            move_db(&(path.clone() + PRE_RESTORE_SUFFIX), &path).or_else(|r| Err(format!("{} Restoring the old databases failed too: {}", e, r)))?;
+        }
+    }
+    open_databases(dir).await.or_else(|r| Err(format!("{} Reopening the old databases failed too: {}", e, r)))?;
+    Err(e)
+}
+
+pub fn data_dir() -> Result<String, String> {
+    active_dir().or_else(http::app_conf_dir).ok_or("No app data directory.".to_string())
+}
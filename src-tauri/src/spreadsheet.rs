@@ -0,0 +1,567 @@
+// CSV export of tasks, and CSV import through a column mapping so sheets
+// laid out any way can be read. Imports are previewed before anything is
+// saved.
+use std::{collections::{BTreeMap, HashMap}, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::{calendar::ImportedList, ics::{civil_from_days, days_from_civil}, task::{add_imported_list, fix_parents, lists_to_export, load_records, tasks_loaded, DEFAULT_COLOR, DEFAULT_IMPORTANCE, DEFAULT_SIZE, ListEntry, TaskEntry, TaskRecord}, utils::{now, DAY_MS}};
+
+pub const EXPORT_HEADERS: [&str; 7] = ["list", "path", "name", "size", "importance", "due", "completed"];
+/// Between parent names in a path column
+pub const PATH_SEPARATOR: &str = " > ";
+const SIZES: [&str; 5] = ["tiny", "small", "medium", "big", "huge"];
+const IMPORTANCES: [&str; 5] = ["trivial", "unimportant", "average", "important", "vital"];
+/// Priority words other apps and people use, checked after the mapping's own
+const PRIORITY_WORDS: &[(&str, i32)] = &[
+    ("none", 0), ("lowest", 0), ("low", 1), ("minor", 1), ("normal", 2), ("medium", 2), ("moderate", 2),
+    ("high", 3), ("major", 3), ("urgent", 4), ("critical", 4), ("highest", 4), ("top", 4),
+    // Todoist-style, p1 being the most urgent
+    ("p1", 4), ("p2", 3), ("p3", 2), ("p4", 1)
+];
+const TRUE_WORDS: &[&str] = &["x", "y", "yes", "true", "1", "done", "complete", "completed", "finished", "✓", "✔"];
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+/// Header names each field is guessed from, lowercase
+const ALIASES: [(&str, &[&str]); 8] = [
+    ("name", &["name", "task", "title", "task name", "summary", "content"]),
+    ("list", &["list", "project", "course", "class", "category", "list name"]),
+    ("path", &["path", "parent", "parents", "parent path"]),
+    ("indent", &["indent", "level", "depth", "outline level"]),
+    ("size", &["size", "effort", "estimate"]),
+    ("importance", &["importance", "priority"]),
+    ("due", &["due", "due date", "deadline", "date", "due_date", "dueDate"]),
+    ("completed", &["completed", "done", "complete", "status", "finished"])
+];
+/// Tasks shown per list in a preview
+const PREVIEW_TASKS: usize = 50;
+
+/// Which column holds each field, by header name. A missing field is
+/// guessed from the headers; an empty one is left unmapped.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct ColumnMapping {
+    pub name: Option<String>,
+    pub list: Option<String>,
+    /// Parent names, outermost first, separated by `PATH_SEPARATOR`
+    pub path: Option<String>,
+    /// Leading whitespace or a depth number. A row indented deeper than the
+    /// one above it is its subtask. Can be the name column.
+    pub indent: Option<String>,
+    pub size: Option<String>,
+    pub importance: Option<String>,
+    pub due: Option<String>,
+    pub completed: Option<String>,
+    /// How to read dates like 03/04/2024
+    pub date_order: DateOrder,
+    /// Priority text → importance, checked before the built-in words
+    pub priorities: BTreeMap<String, i32>
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DateOrder {
+    /// Month, day, year
+    #[default]
+    Mdy,
+    /// Day, month, year
+    Dmy
+}
+
+/// A CSV file read through a mapping.
+pub struct CsvRead {
+    pub headers: Vec<String>,
+    /// The mapping with guessed columns filled in
+    pub mapping: ColumnMapping,
+    pub lists: Vec<ImportedList>,
+    /// `Row <n>: <problem>`, for rows read with defaults or skipped
+    pub problems: Vec<String>
+}
+
+#[derive(Serialize)]
+pub struct PreviewList {
+    pub name: String,
+    pub tasks: usize,
+    /// The first tasks, as a tree
+    pub sample: Vec<TaskRecord>
+}
+
+/// What an import would make, without saving anything.
+#[derive(Serialize)]
+pub struct CsvPreview {
+    pub headers: Vec<String>,
+    pub mapping: ColumnMapping,
+    pub lists: Vec<PreviewList>,
+    pub problems: Vec<String>
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct CsvImport {
+    pub lists: usize,
+    pub tasks: usize,
+    pub problems: Vec<String>
+}
+
+/// `ms` as ISO 8601 at `utc_offset` minutes from UTC, e.g.
+/// `2024-06-10T14:30:00+02:00`.
+pub fn format_iso(ms: i64, utc_offset: i32) -> String {
+    let secs = (ms + utc_offset as i64 * 60_000).div_euclid(1000);
+    let (y, m, d) = civil_from_days(secs.div_euclid(86400));
+    let rem = secs.rem_euclid(86400);
+    let zone = match utc_offset {
+        0 => "Z".to_string(),
+        o => format!("{}{:02}:{:02}", if o < 0 { '-' } else { '+' }, o.abs() / 60, o.abs() % 60)
+    };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}", y, m, d, rem / 3600, rem % 3600 / 60, rem % 60, zone)
+}
+
+/// `HH:MM[:SS[.fff]][am|pm][Z|±HH[:MM]]` as (seconds into the day, zone
+/// offset in minutes if given).
+fn parse_time(token: &str) -> Option<(i64, Option<i32>)> {
+    let mut time = token.to_ascii_lowercase();
+    let mut zone = None;
+    if let Some(t) = time.strip_suffix('z') {
+        time = t.to_string();
+        zone = Some(0);
+    } else if let Some(i) = time.rfind(['+', '-']) {
+        let offset = &time[i + 1..];
+        let (h, m) = match offset.split_once(':') {
+            Some((h, m)) => (h.parse::<i32>().ok()?, m.parse::<i32>().ok()?),
+            None if offset.len() == 4 => (offset.get(..2)?.parse().ok()?, offset.get(2..)?.parse().ok()?),
+            None => (offset.parse().ok()?, 0)
+        };
+        // Real zones are within ±14:00, and anything further could overflow
+        if !(0..=14).contains(&h) || !(0..60).contains(&m) {
+            return None;
+        }
+        zone = Some(if &time[i..i + 1] == "-" { -(h * 60 + m) } else { h * 60 + m });
+        time.truncate(i);
+    }
+    let pm = if let Some(t) = time.strip_suffix("pm") {
+        time = t.to_string();
+        Some(true)
+    } else if let Some(t) = time.strip_suffix("am") {
+        time = t.to_string();
+        Some(false)
+    } else {
+        None
+    };
+    let mut parts = time.split(':');
+    let mut h: i64 = parts.next()?.parse().ok()?;
+    let m: i64 = parts.next()?.parse().ok()?;
+    let s: f64 = parts.next().map_or(Some(0.0), |s| s.parse().ok())?;
+    match pm {
+        Some(true) if h < 12 => h += 12,
+        Some(false) if h == 12 => h = 0,
+        _ => {}
+    }
+    if h > 23 || m > 59 || !(0.0..60.0).contains(&s) {
+        return None;
+    }
+    Some((h * 3600 + m * 60 + s as i64, zone))
+}
+
+fn month_number(word: &str) -> Option<u32> {
+    let word = word.to_lowercase();
+    MONTHS.iter().position(|m| word.len() >= 3 && word.starts_with(m)).map(|m| m as u32 + 1)
+}
+
+/// Timestamp in ms of a spreadsheet date: ISO 8601, `20240610`, numeric
+/// dates in `order`, dates with month names, or a timestamp in ms. Times
+/// without a zone are read at `utc_offset` minutes from UTC.
+pub fn parse_date(value: &str, order: DateOrder, utc_offset: i32) -> Option<i64> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    if value.chars().all(|c| c.is_ascii_digit()) {
+        if value.len() >= 12 {
+            return value.parse().ok();
+        }
+        if value.len() != 8 {
+            return None;
+        }
+    }
+    // 2024-06-10T14:30 and 20240610T143000Z
+    let mut text = value.replace(',', " ");
+    let bytes = text.as_bytes();
+    if let Some(i) = text.find(['T', 't']).filter(|&i| i > 0 && bytes[i - 1].is_ascii_digit() && bytes.get(i + 1).is_some_and(|b| b.is_ascii_digit())) {
+        let time = &text[i + 1..];
+        if time.get(..6).is_some_and(|t| t.chars().all(|c| c.is_ascii_digit())) {
+            text = format!("{} {}:{}:{}", &text[..i], &time[..2], &time[2..4], &time[4..]);
+        } else {
+            text.replace_range(i..i + 1, " ");
+        }
+    }
+
+    let mut time = None;
+    let mut zone = None;
+    let mut pm = None;
+    let mut date_parts = vec![];
+    for token in text.split_whitespace() {
+        let lower = token.to_lowercase();
+        match lower.as_str() {
+            "am" | "a.m." => pm = Some(false),
+            "pm" | "p.m." => pm = Some(true),
+            "z" | "utc" | "gmt" => zone = Some(0),
+            _ if token.contains(':') => {
+                let (secs, z) = parse_time(token)?;
+                time = Some(secs);
+                zone = z.or(zone);
+            },
+            _ if token.len() == 8 && token.chars().all(|c| c.is_ascii_digit()) => {
+                date_parts.extend([&token[..4], &token[4..6], &token[6..]]);
+            },
+            _ => date_parts.extend(token.split(['-', '/', '.']).filter(|p| !p.is_empty()))
+        }
+    }
+    if let (Some(secs), Some(pm)) = (time, pm) {
+        let h = secs / 3600;
+        if pm && h < 12 {
+            time = Some(secs + 12 * 3600);
+        } else if !pm && h == 12 {
+            time = Some(secs - 12 * 3600);
+        }
+    }
+
+    let named = date_parts.iter().find_map(|p| month_number(p));
+    // Weekday names and the like
+    let numbers: Vec<&str> = date_parts.into_iter().filter(|p| p.chars().all(|c| c.is_ascii_digit())).collect();
+    let (y, m, d) = match (named, numbers.as_slice()) {
+        (Some(m), [a, b]) if a.len() == 4 => (a.parse().ok()?, m, b.parse().ok()?),
+        (Some(m), [a, b]) => (b.parse().ok()?, m, a.parse().ok()?),
+        (None, [a, b, c]) if a.len() == 4 => (a.parse().ok()?, b.parse().ok()?, c.parse().ok()?),
+        (None, [a, b, c]) => {
+            let (m, d) = match order {
+                DateOrder::Mdy => (a, b),
+                DateOrder::Dmy => (b, a)
+            };
+            (c.parse().ok()?, m.parse().ok()?, d.parse().ok()?)
+        },
+        _ => return None
+    };
+    let y: i64 = if y < 100 { y + 2000 } else { y };
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+    let local = days_from_civil(y, m, d) * DAY_MS + time.unwrap_or(0) * 1000;
+    Some(local - zone.unwrap_or(utc_offset) as i64 * 60_000)
+}
+
+/// A size or importance from a number or one of `names`.
+fn parse_level(value: &str, names: &[&str; 5]) -> Option<i32> {
+    let value = value.trim().to_lowercase();
+    if let Ok(n) = value.parse::<i32>() {
+        return (0..=4).contains(&n).then_some(n);
+    }
+    names.iter().position(|n| *n == value).map(|n| n as i32)
+}
+
+fn parse_importance(value: &str, priorities: &BTreeMap<String, i32>) -> Option<i32> {
+    let key = value.trim().to_lowercase();
+    if let Some((_, importance)) = priorities.iter().find(|(k, _)| k.to_lowercase() == key) {
+        return Some((*importance).clamp(0, 4));
+    }
+    parse_level(value, &IMPORTANCES)
+        .or_else(|| PRIORITY_WORDS.iter().find(|(w, _)| *w == key).map(|(_, i)| *i))
+}
+
+fn parse_completed(value: &str) -> bool {
+    TRUE_WORDS.contains(&value.trim().to_lowercase().as_str())
+}
+
+/// The delimiter used in the header line, out of comma, semicolon and tab.
+fn detect_delimiter(text: &str) -> u8 {
+    let header = text.lines().next().unwrap_or_default();
+    [b',', b';', b'\t'].into_iter()
+        .max_by_key(|d| (header.matches(*d as char).count(), *d == b','))
+        .unwrap()
+}
+
+impl ColumnMapping {
+    fn fields_mut(&mut self) -> [(&'static str, &mut Option<String>); 8] {
+        [
+            ("name", &mut self.name), ("list", &mut self.list), ("path", &mut self.path), ("indent", &mut self.indent),
+            ("size", &mut self.size), ("importance", &mut self.importance), ("due", &mut self.due), ("completed", &mut self.completed)
+        ]
+    }
+
+    /// This mapping, with fields it doesn't mention guessed from `headers`.
+    pub fn guessed(&self, headers: &[String]) -> ColumnMapping {
+        let mut mapping = self.clone();
+        for (field, column) in mapping.fields_mut() {
+            if column.is_some() {
+                continue;
+            }
+            let aliases = ALIASES.iter().find(|(f, _)| *f == field).unwrap().1;
+            *column = Some(headers.iter()
+                .find(|h| aliases.iter().any(|a| a.eq_ignore_ascii_case(h.trim())))
+                .cloned()
+                .unwrap_or_default());
+        }
+        mapping
+    }
+}
+
+struct Row {
+    line: usize,
+    list: String,
+    path: Option<Vec<String>>,
+    depth: Option<usize>,
+    task: TaskEntry
+}
+
+/// Reads `text` through `mapping`. Rows without a list column go into a
+/// list named `fallback_list`.
+pub fn read_csv(text: &str, mapping: &ColumnMapping, utc_offset: i32, fallback_list: &str) -> Result<CsvRead, String> {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(detect_delimiter(text))
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let headers: Vec<String> = reader.headers()
+        .or_else(|e| Err(format!("Not a CSV file: {}", e)))?
+        .iter().map(|h| h.to_string()).collect();
+    let mapping = mapping.guessed(&headers);
+    let column = |field: &Option<String>| -> Result<Option<usize>, String> {
+        match field.as_deref() {
+            None | Some("") => Ok(None),
+            Some(name) => headers.iter().position(|h| h.trim().eq_ignore_ascii_case(name.trim()))
+                .map(Some)
+                .ok_or(format!("There's no column named \"{}\".", name))
+        }
+    };
+    let name_col = column(&mapping.name)?.ok_or("Pick the column with task names.".to_string())?;
+    let list_col = column(&mapping.list)?;
+    let path_col = column(&mapping.path)?;
+    let indent_col = column(&mapping.indent)?;
+    let size_col = column(&mapping.size)?;
+    let importance_col = column(&mapping.importance)?;
+    let due_col = column(&mapping.due)?;
+    let completed_col = column(&mapping.completed)?;
+
+    let mut problems = vec![];
+    let mut rows = vec![];
+    for (i, record) in reader.records().enumerate() {
+        let line = i + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                problems.push(format!("Row {}: {}", line, e));
+                continue;
+            }
+        };
+        let cell = |col: Option<usize>| col.and_then(|c| record.get(c)).unwrap_or_default();
+        let name = cell(Some(name_col)).trim();
+        if name.is_empty() {
+            if record.iter().any(|c| !c.trim().is_empty()) {
+                problems.push(format!("Row {}: no task name, skipped.", line));
+            }
+            continue;
+        }
+        let size = match cell(size_col) {
+            "" => DEFAULT_SIZE,
+            s => parse_level(s, &SIZES).unwrap_or_else(|| {
+                problems.push(format!("Row {}: unknown size \"{}\", used medium.", line, s));
+                DEFAULT_SIZE
+            })
+        };
+        let importance = match cell(importance_col) {
+            "" => DEFAULT_IMPORTANCE,
+            s => parse_importance(s, &mapping.priorities).unwrap_or_else(|| {
+                problems.push(format!("Row {}: unknown priority \"{}\", used average.", line, s));
+                DEFAULT_IMPORTANCE
+            })
+        };
+        let due = match cell(due_col) {
+            "" => now(),
+            s => parse_date(s, mapping.date_order, utc_offset).unwrap_or_else(|| {
+                problems.push(format!("Row {}: couldn't read date \"{}\", used today.", line, s));
+                now()
+            })
+        };
+        let path = path_col.map(|_| cell(path_col).split(PATH_SEPARATOR.trim())
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect());
+        let depth = indent_col.map(|c| {
+            let raw = cell(Some(c));
+            raw.trim().parse().unwrap_or_else(|_| raw.len() - raw.trim_start().len())
+        });
+        let list = match cell(list_col).trim() {
+            "" => fallback_list,
+            l => l
+        };
+        rows.push(Row {
+            line,
+            list: list.to_string(),
+            path,
+            depth,
+            task: TaskEntry {
+                name: name.to_string(),
+                size,
+                importance,
+                due,
+                completed: parse_completed(cell(completed_col)),
+                id: uuid::Uuid::new_v4().to_string(),
+                parent: None,
+                last_edited: None,
+                created: None,
+                sealed: None,
+                extra: Map::new()
+            }
+        });
+    }
+
+    let mut order: Vec<String> = vec![];
+    let mut by_list: HashMap<String, Vec<Row>> = HashMap::new();
+    for row in rows {
+        if !by_list.contains_key(&row.list) {
+            order.push(row.list.clone());
+        }
+        by_list.entry(row.list.clone()).or_default().push(row);
+    }
+    let mut lists = vec![];
+    for name in order {
+        let mut rows = by_list.remove(&name).unwrap();
+        link_parents(&mut rows, &name, &mut problems);
+        let mut tasks: Vec<TaskEntry> = rows.into_iter().map(|r| r.task).collect();
+        fix_parents(&mut tasks);
+        lists.push(ImportedList {
+            list: ListEntry {
+                name,
+                uuid: uuid::Uuid::new_v4().to_string(),
+                color: DEFAULT_COLOR,
+                last_edited: None,
+                created: None,
+                sealed: None,
+                extra: Map::new()
+            },
+            tasks
+        });
+    }
+    Ok(CsvRead { headers, mapping, lists, problems })
+}
+
+/// Sets each row's parent from its path, or failing that its indent.
+fn link_parents(rows: &mut [Row], list: &str, problems: &mut Vec<String>) {
+    let mut by_path: HashMap<Vec<String>, String> = HashMap::new();
+    for row in rows.iter() {
+        if let Some(path) = &row.path {
+            let mut own = path.clone();
+            own.push(row.task.name.clone());
+            by_path.entry(own).or_insert(row.task.id.clone());
+        }
+    }
+    // Rows above at each depth, innermost last
+    let mut stack: Vec<(usize, String)> = vec![];
+    for row in rows.iter_mut() {
+        match (&row.path, row.depth) {
+            (Some(path), _) if !path.is_empty() => {
+                row.task.parent = by_path.get(path).cloned();
+                if row.task.parent.is_none() {
+                    problems.push(format!("Row {}: no task \"{}\" in {}, so it's not a subtask.", row.line, path.join(PATH_SEPARATOR), list));
+                }
+            },
+            (_, Some(depth)) => {
+                while stack.last().is_some_and(|(d, _)| *d >= depth) {
+                    stack.pop();
+                }
+                row.task.parent = stack.last().map(|(_, id)| id.clone());
+                stack.push((depth, row.task.id.clone()));
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Every task in `lists` as CSV with `EXPORT_HEADERS`, parents first.
+pub fn write_csv(lists: &[(ListEntry, Vec<TaskEntry>)], utc_offset: i32) -> Result<String, String> {
+    fn walk(records: &[TaskRecord], path: &mut Vec<String>, out: &mut Vec<(String, TaskRecord)>) {
+        for r in records {
+            out.push((path.join(PATH_SEPARATOR), r.clone()));
+            path.push(r.name.clone());
+            walk(&r.subtasks, path, out);
+            path.pop();
+        }
+    }
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let err = |e: csv::Error| format!("Export Error: {}", e);
+    writer.write_record(EXPORT_HEADERS).map_err(err)?;
+    for (list, tasks) in lists {
+        let mut rows = vec![];
+        walk(&load_records(tasks), &mut vec![], &mut rows);
+        for (path, r) in rows {
+            writer.write_record([
+                list.name.as_str(),
+                &path,
+                &r.name,
+                &r.size.to_string(),
+                &r.importance.to_string(),
+                &format_iso(r.due, utc_offset),
+                if r.completed { "true" } else { "false" }
+            ]).map_err(err)?;
+        }
+    }
+    let bytes = writer.into_inner().or_else(|e| Err(format!("Export Error: {}", e)))?;
+    Ok(String::from_utf8(bytes).unwrap())
+}
+
+/// Writes `list_ids` (every list if empty) to a CSV file at `path`, with
+/// due dates at `utc_offset` minutes from UTC. Returns how many tasks were
+/// written.
+#[tauri::command]
+pub async fn export_csv(list_ids: Vec<String>, path: String, utc_offset: i32) -> Result<usize, String> {
+    let with_tasks = lists_to_export(&list_ids).await?;
+    let total = with_tasks.iter().map(|(_, tasks)| tasks.len()).sum();
+    fs::write(&path, write_csv(&with_tasks, utc_offset)?)
+        .or_else(|e| Err(format!("Export Error: {}", e)))?;
+    Ok(total)
+}
+
+fn read_csv_file(path: &str, mapping: &ColumnMapping, utc_offset: i32) -> Result<CsvRead, String> {
+    let text = fs::read_to_string(path)
+        .or_else(|e| Err(format!("Import Error: {}", e)))?;
+    let name = Path::new(path).file_stem().map_or("Imported".to_string(), |s| s.to_string_lossy().to_string());
+    read_csv(&text, mapping, utc_offset, &name)
+}
+
+/// What importing the CSV file at `path` through `mapping` would make.
+/// Nothing is saved.
+#[tauri::command]
+pub async fn preview_csv_import(path: String, mapping: ColumnMapping, utc_offset: i32) -> Result<CsvPreview, String> {
+    let read = read_csv_file(&path, &mapping, utc_offset)?;
+    let lists = read.lists.iter().map(|l| {
+        let sample: Vec<TaskEntry> = l.tasks.iter().take(PREVIEW_TASKS).cloned().collect();
+        PreviewList { name: l.list.name.clone(), tasks: l.tasks.len(), sample: load_records(&sample) }
+    }).collect();
+    Ok(CsvPreview { headers: read.headers, mapping: read.mapping, lists, problems: read.problems })
+}
+
+/// Makes new lists from the CSV file at `path`, then has the task list
+/// reload.
+#[tauri::command]
+pub async fn import_csv<R: Runtime>(app: AppHandle<R>, path: String, mapping: ColumnMapping, utc_offset: i32) -> Result<CsvImport, String> {
+    let result = import_csv_file(&path, &mapping, utc_offset).await?;
+    let _ = app.emit("tasks-imported", result.tasks);
+    Ok(result)
+}
+
+pub async fn import_csv_file(path: &str, mapping: &ColumnMapping, utc_offset: i32) -> Result<CsvImport, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let read = read_csv_file(path, mapping, utc_offset)?;
+    let mut result = CsvImport { problems: read.problems, ..Default::default() };
+    for imported in read.lists {
+        let added = add_imported_list(&imported.list, &imported.tasks).await
+            .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        if !added {
+            return Err(format!("Import Error: couldn't add list {}.", imported.list.name));
+        }
+        result.lists += 1;
+        result.tasks += imported.tasks.len();
+    }
+    Ok(result)
+}
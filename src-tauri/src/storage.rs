@@ -1,10 +1,17 @@
 use serde_json::{json, Value as JsonValue};
-use sqlx::{migrate::MigrateDatabase, sqlite::SqliteRow, Error, FromRow, Pool};
+use std::path::Path;
+
+use libsqlite3_sys::{sqlite3, sqlite3_backup_finish, sqlite3_backup_init, sqlite3_backup_step, sqlite3_errcode, sqlite3_sleep, SQLITE_BUSY, SQLITE_DONE, SQLITE_LOCKED, SQLITE_OK};
+use sqlx::{migrate::MigrateDatabase, sqlite::{SqliteConnectOptions, SqliteConnection, SqliteRow}, Connection, Error, FromRow, Pool};
 
 use crate::{task::{ListEntry, TaskEntry}, utils::now};
 
 type Db = sqlx::sqlite::Sqlite;
 
+/// How often a backup waits for a writer to finish, and for how long
+const BACKUP_RETRIES: usize = 50;
+const BACKUP_RETRY_MS: i32 = 100;
+
 async fn connect(path: &str) -> Result<Pool<Db>, Error> {
     if !Db::database_exists(path).await.unwrap_or(false) {
         Db::create_database(&("sqlite:".to_string().to_owned() + path)).await?;
@@ -14,6 +21,79 @@ async fn connect(path: &str) -> Result<Pool<Db>, Error> {
     Ok(pool)
 }
 
+/// Writes a consistent copy of the database at `path` to `dest` (which
+/// mustn't exist) with SQLite's online backup API, even while the database
+/// is open elsewhere.
+pub async fn snapshot_db(path: &str, dest: &str) -> Result<(), Error> {
+    if Path::new(dest).exists() {
+        return Err(Error::Configuration(format!("{} already exists", dest).into()));
+    }
+    let mut source = SqliteConnection::connect_with(&SqliteConnectOptions::new().filename(path).read_only(true)).await?;
+    let mut copy = match SqliteConnection::connect_with(&SqliteConnectOptions::new().filename(dest).create_if_missing(true)).await {
+        Ok(copy) => copy,
+        Err(e) => {
+            let _ = source.close().await;
+            return Err(e);
+        }
+    };
+    let result = match (source.lock_handle().await, copy.lock_handle().await) {
+        (Ok(mut from), Ok(mut to)) => backup(from.as_raw_handle().as_ptr(), to.as_raw_handle().as_ptr()),
+        (Err(e), _) | (_, Err(e)) => Err(e)
+    };
+    let _ = source.close().await;
+    let _ = copy.close().await;
+    result
+}
+
+/// Copies every page of `from` into `to` in one step, waiting out writers
+/// that hold a lock.
+fn backup(from: *mut sqlite3, to: *mut sqlite3) -> Result<(), Error> {
+    let main = c"main".as_ptr();
+    unsafe {
+        let backup = sqlite3_backup_init(to, main, from, main);
+        if backup.is_null() {
+            return Err(Error::Protocol(format!("backup failed ({})", sqlite3_errcode(to))));
+        }
+        let mut rc = sqlite3_backup_step(backup, -1);
+        for _ in 0..BACKUP_RETRIES {
+            if rc != SQLITE_BUSY && rc != SQLITE_LOCKED {
+                break;
+            }
+            sqlite3_sleep(BACKUP_RETRY_MS);
+            rc = sqlite3_backup_step(backup, -1);
+        }
+        let finish = sqlite3_backup_finish(backup);
+        match (rc, finish) {
+            (SQLITE_DONE, SQLITE_OK) => Ok(()),
+            (SQLITE_DONE, code) | (code, _) => Err(Error::Protocol(format!("backup failed ({})", code)))
+        }
+    }
+}
+
+/// Runs SQLite's integrity check on the database at `path` and returns its
+/// table names if it passes.
+pub async fn check_db(path: &str) -> Result<Vec<String>, String> {
+    let url = format!("sqlite:{}?mode=ro", path);
+    let pool: Pool<Db> = Pool::connect(&url).await.or_else(|e| Err(format!("{}", e)))?;
+    let check: Result<Vec<(String,)>, Error> = sqlx::query_as("PRAGMA integrity_check").fetch_all(&pool).await;
+    let tables: Result<Vec<(String,)>, Error> = sqlx::query_as("SELECT name FROM sqlite_master WHERE type = 'table'").fetch_all(&pool).await;
+    pool.close().await;
+    let check = check.or_else(|e| Err(format!("{}", e)))?;
+    if check.len() != 1 || check[0].0 != "ok" {
+        return Err(check.into_iter().map(|c| c.0).collect::<Vec<_>>().join("; "));
+    }
+    Ok(tables.or_else(|e| Err(format!("{}", e)))?.into_iter().map(|t| t.0).collect())
+}
+
+/// Runs `check_db` on the database at `path` and makes sure it has `table`.
+pub async fn verify_db(path: &str, table: &str) -> Result<(), String> {
+    let tables = check_db(path).await?;
+    if !tables.iter().any(|t| t == table) {
+        return Err("missing tables".to_string());
+    }
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct DatabaseManager {
     pool: Option<Pool<Db>>,
@@ -171,9 +251,39 @@ impl TaskDb {
             Vec::new(),
         )
         .await;
+        let _ = self.db_mgr.as_mut().unwrap().execute(
+            "CREATE TABLE IF NOT EXISTS SyncState ( \
+            server TEXT, \
+            cursor TEXT, \
+            pushed_until BIGINT, \
+            push_started BIGINT, \
+            push_position TEXT, \
+            PRIMARY KEY(server) \
+        )",
+            Vec::new(),
+        )
+        .await;
         Ok(())
     }
 
+    async fn create_task_table(&mut self, list: &str) -> Result<Option<(u64, i64)>, Error> {
+        self.db_mgr.as_mut().unwrap().execute(
+            &format!("CREATE TABLE IF NOT EXISTS '{}' ( \
+            id TEXT, \
+            name TEXT, \
+            importance INTEGER, \
+            size INTEGER, \
+            due BIGINT, \
+            completed BOOLEAN, \
+            parent TEXT, \
+            created BIGINT, \
+            last_edited BIGINT, \
+            PRIMARY KEY(id) \
+            )", list),
+            Vec::new()
+        ).await
+    }
+
     pub async fn new_list(&mut self, list: &ListEntry) -> Result<bool, Error> {
         if !self.is_loaded { return Ok(false); }
         if self.get_list(list.uuid.clone()).await.unwrap().is_some() { return Ok(false); }
@@ -191,21 +301,7 @@ impl TaskDb {
             ]
         ).await?;
         if result.is_none() { return Ok(false); }
-        let result = self.db_mgr.as_mut().unwrap().execute(
-            &format!("CREATE TABLE '{}' ( \
-            id TEXT, \
-            name TEXT, \
-            importance INTEGER, \
-            size INTEGER, \
-            due BIGINT, \
-            completed BOOLEAN, \
-            parent TEXT, \
-            created BIGINT, \
-            last_edited BIGINT, \
-            PRIMARY KEY(id) \
-            )", list.uuid),
-            Vec::new()
-        ).await?;
+        let result = self.create_task_table(&list.uuid).await?;
         if result.is_none() { return Ok(false); }
         return Ok(true);
     }
@@ -258,6 +354,27 @@ impl TaskDb {
         Ok(true)
     }
 
+    /// Names of the tables that hold a list's tasks, whether or not the
+    /// list itself is there.
+    pub async fn task_tables(&mut self) -> Result<Option<Vec<String>>, Error> {
+        if !self.is_loaded { return Ok(None); }
+        let tables = self.db_mgr.as_mut().unwrap().select_all::<(String,)>(
+            "SELECT name FROM sqlite_master WHERE type = 'table' \
+            AND name NOT IN ('Lists', 'SyncState') AND name NOT LIKE 'sqlite_%' ORDER BY name",
+            Vec::new()
+        ).await?;
+        Ok(tables.map(|t| t.into_iter().map(|t| t.0).collect()))
+    }
+
+    pub async fn drop_task_table(&mut self, list: &str) -> Result<bool, Error> {
+        if !self.is_loaded { return Ok(false); }
+        let result = self.db_mgr.as_mut().unwrap().execute(
+            &format!("DROP TABLE IF EXISTS '{}'", list),
+            Vec::new()
+        ).await?;
+        Ok(result.is_some())
+    }
+
     pub async fn new_task(&mut self, list: String, task: &TaskEntry) -> Result<bool, Error> {
         if !self.is_loaded { return Ok(false); }
         let result = self.db_mgr.as_mut().unwrap().execute(
@@ -363,5 +480,121 @@ impl TaskDb {
         }
         Ok(self.db_mgr.as_mut().unwrap().select_all::<ListEntry>(&query, Vec::new()).await?)
     }
+
+    /// Lists edited in `(since, until]`.
+    pub async fn lists_edited_between(&mut self, since: i64, until: i64) -> Result<Option<Vec<ListEntry>>, Error> {
+        if !self.is_loaded { return Ok(None); }
+        self.db_mgr.as_mut().unwrap().select_all::<ListEntry>(
+            "SELECT * FROM Lists WHERE last_edited > ? AND last_edited <= ? ORDER BY uuid",
+            vec![json!(since), json!(until)]
+        ).await
+    }
+
+    /// Tasks in `list` edited in `(since, until]`.
+    pub async fn tasks_edited_between(&mut self, list: String, since: i64, until: i64) -> Result<Option<Vec<TaskEntry>>, Error> {
+        if !self.is_loaded { return Ok(None); }
+        self.db_mgr.as_mut().unwrap().select_all::<TaskEntry>(
+            &format!("SELECT * FROM '{}' WHERE last_edited > ? AND last_edited <= ? ORDER BY id", list),
+            vec![json!(since), json!(until)]
+        ).await
+    }
+
+    /// Stores a list received through sync, keeping its timestamps.
+    pub async fn save_synced_list(&mut self, list: &ListEntry) -> Result<bool, Error> {
+        if !self.is_loaded { return Ok(false); }
+        let result = self.db_mgr.as_mut().unwrap().execute(
+            "INSERT OR REPLACE INTO Lists \
+            (uuid, name, color, created, last_edited) \
+            VALUES \
+            (?, ?, ?, ?, ?)",
+            vec![
+                json!(list.uuid),
+                json!(list.name),
+                json!(list.color),
+                json!(list.created),
+                json!(list.last_edited)
+            ]
+        ).await?;
+        if result.is_none() { return Ok(false); }
+        Ok(self.create_task_table(&list.uuid).await?.is_some())
+    }
+
+    /// Stores a task received through sync, keeping its timestamps and
+    /// parent. The list's table is created if the list hasn't arrived yet.
+    pub async fn save_synced_task(&mut self, list: String, task: &TaskEntry) -> Result<bool, Error> {
+        if !self.is_loaded { return Ok(false); }
+        self.create_task_table(&list).await?;
+        let result = self.db_mgr.as_mut().unwrap().execute(
+            &format!("INSERT OR REPLACE INTO '{}' \
+                (id, name, importance, size, due, completed, parent, created, last_edited) \
+                VALUES \
+                (?, ?, ?, ?, ?, ?, ?, ?, ?) \
+            ", list),
+            vec![
+                json!(task.id),
+                json!(task.name),
+                json!(task.importance),
+                json!(task.size),
+                json!(task.due),
+                json!(task.completed),
+                json!(task.parent),
+                json!(task.created),
+                json!(task.last_edited)
+            ]
+        ).await?;
+        Ok(result.is_some())
+    }
+
+    pub async fn get_sync_state(&mut self, server: &str) -> Result<Option<SyncState>, Error> {
+        if !self.is_loaded { return Ok(None); }
+        self.db_mgr.as_mut().unwrap().select_one::<SyncState>(
+            "SELECT * FROM SyncState WHERE server=?",
+            vec![json!(server)]
+        ).await
+    }
+
+    pub async fn set_sync_state(&mut self, state: &SyncState) -> Result<bool, Error> {
+        if !self.is_loaded { return Ok(false); }
+        let result = self.db_mgr.as_mut().unwrap().execute(
+            "INSERT OR REPLACE INTO SyncState \
+            (server, cursor, pushed_until, push_started, push_position) \
+            VALUES \
+            (?, ?, ?, ?, ?)",
+            vec![
+                json!(state.server),
+                json!(state.cursor),
+                json!(state.pushed_until),
+                json!(state.push_started),
+                json!(state.push_position)
+            ]
+        ).await?;
+        Ok(result.is_some())
+    }
+}
+
+/// Progress of paged sync with one server, saved after every page and chunk
+/// so an interrupted sync picks up where it stopped.
+#[derive(sqlx::FromRow, Clone, Debug, PartialEq)]
+pub struct SyncState {
+    pub server: String,
+    /// Server cursor after the last page that was applied locally
+    pub cursor: Option<String>,
+    /// Local edits up to this time have been pushed
+    pub pushed_until: i64,
+    /// Upper bound of the push in progress, if any
+    pub push_started: Option<i64>,
+    /// Sort key of the last item the server acknowledged in that push
+    pub push_position: Option<String>,
 }
 
+impl SyncState {
+    pub fn new(server: &str) -> SyncState {
+        SyncState {
+            server: server.to_string(),
+            cursor: None,
+            pushed_until: 0,
+            push_started: None,
+            push_position: None
+        }
+    }
+}
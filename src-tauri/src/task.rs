@@ -1,13 +1,19 @@
 use std::{collections::HashMap};
 
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use tauri::{async_runtime::block_on, AppHandle, Event, Listener, Manager, Runtime};
 
-use crate::{http::{check_timestamp, SyncData}, storage::TaskDb, utils::{de_float_guard, now}};
+use crate::{health::{self, DbIssue}, http::{check_timestamp, SyncData}, profile::active_dir, storage::{SyncState, TaskDb}, utils::{de_float_guard, now}};
 
-static TASKS_PATH: &str = "/tasks.db"; // Prod
+pub static TASKS_PATH: &str = "/tasks.db"; // Prod
 // static TASKS_PATH: &str = "/tasks2.db"; // Testing/debug
 
+/// What tasks and lists from elsewhere get when they don't say
+pub const DEFAULT_SIZE: i32 = 2;
+pub const DEFAULT_IMPORTANCE: i32 = 2;
+pub const DEFAULT_COLOR: i32 = 0;
+
 static mut TASKS: Option<TaskDb> = None;
 
 unsafe fn init_tasks() {
@@ -26,20 +32,39 @@ fn close_tasks(_e: Event) {
 
 async unsafe fn load_task_db<R: Runtime>(app: AppHandle<R>) {
     init_tasks();
-    let path = app
+    // The active profile's database
+    let path = active_dir().unwrap_or_else(|| app
         .path()
         .app_data_dir()
         .unwrap()
         .to_str()
         .expect("AppData failed to resolve")
         .to_owned()
-        + TASKS_PATH;
+    ) + TASKS_PATH;
     if !TASKS.as_ref().unwrap().is_loaded {
         let _ = TASKS.as_mut().unwrap().load(&path).await;
         app.listen_any("exit-requested", close_tasks);
     }
 }
 
+/// Closes the task database, e.g. so its file can be replaced.
+pub async fn close_task_db() {
+    unsafe {
+        if let Some(tasks) = TASKS.as_mut() {
+            tasks.close().await;
+        }
+    }
+}
+
+/// Closes the open task database (if any) and opens the one at `path`.
+pub async fn switch_task_db(path: &str) -> Result<(), sqlx::Error> {
+    unsafe {
+        init_tasks();
+        TASKS.as_mut().unwrap().close().await;
+        TASKS.as_mut().unwrap().load(path).await
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct TaskRecord {
     pub name: String,
@@ -87,22 +112,85 @@ impl SubtaskNode {
     }
 }
 
+/// Tasks whose `parent` can't be followed.
+#[derive(Debug, Default, PartialEq)]
+pub struct ParentProblems {
+    /// Tasks whose parent isn't among the entries
+    pub orphans: Vec<String>,
+    /// One task from each loop of parents, the one with the lowest id, whose
+    /// link is cut to break the loop
+    pub loops: Vec<String>
+}
+
+pub fn parent_problems(entries: &[TaskEntry]) -> ParentProblems {
+    let mut problems = ParentProblems::default();
+    let mut parents: HashMap<&str, Option<&str>> = HashMap::new();
+    for e in entries {
+        parents.insert(&e.id, e.parent.as_deref());
+    }
+    for e in entries {
+        if let Some(parent) = &e.parent {
+            if !parents.contains_key(parent.as_str()) {
+                problems.orphans.push(e.id.clone());
+                parents.insert(&e.id, None);
+            }
+        }
+    }
+    let mut ids: Vec<&str> = parents.keys().copied().collect();
+    ids.sort_unstable();
+    // Every walk up the parents stops at the top, at a task an earlier walk
+    // already finished, or at a task seen in this walk, which is a loop
+    let mut done: HashMap<&str, bool> = HashMap::new();
+    for id in ids {
+        let mut path: Vec<&str> = vec![];
+        let mut current = Some(id);
+        while let Some(task) = current {
+            if done.contains_key(task) {
+                break;
+            }
+            done.insert(task, false);
+            path.push(task);
+            current = parents[task];
+        }
+        if let Some(task) = current.filter(|t| !done[t]) {
+            let start = path.iter().position(|t| *t == task).unwrap();
+            problems.loops.push(path[start..].iter().min().unwrap().to_string());
+        }
+        for task in path {
+            done.insert(task, true);
+        }
+    }
+    problems
+}
+
+/// Cuts the links `parent_problems` finds, so the tasks form a tree. Tasks
+/// that only lead into a loop keep their parent.
+pub fn fix_parents(tasks: &mut [TaskEntry]) {
+    let problems = parent_problems(tasks);
+    for task in tasks.iter_mut().filter(|t| problems.orphans.contains(&t.id) || problems.loops.contains(&t.id)) {
+        task.parent = None;
+    }
+}
+
 // Evil, Affront to God
 pub fn load_records(entries: &Vec<TaskEntry>) -> Vec<TaskRecord> {
     let mut ret: Vec<TaskRecord> = Vec::new();
     let mut parents: HashMap<String, Option<String>> = HashMap::new();
     let mut entry_map: HashMap<String, &TaskEntry> = HashMap::new();
+    // Tasks whose parent is missing or in a loop go at the top
+    let problems = parent_problems(entries);
     // Loop 1 -- Populate Maps (god help me)
     for e in entries {
         entry_map.insert(e.id.to_owned(), e);
-        parents.insert(e.id.to_owned(), e.parent.to_owned());
+        let cut = problems.orphans.contains(&e.id) || problems.loops.contains(&e.id);
+        parents.insert(e.id.to_owned(), if cut { None } else { e.parent.to_owned() });
     }
     let mut stack: Vec<String> = Vec::new();
     let mut root: SubtaskNode = SubtaskNode::new("ROOT");
     // Loop 2 -- Populate Tree
     for e in entries {
         stack.push(e.id.to_owned());
-        let mut current = &e.parent;
+        let mut current = &parents[&e.id];
         while !current.is_none() {
             let to_push = current.to_owned().unwrap();
             current = &parents.get(&to_push).unwrap();
@@ -134,10 +222,19 @@ pub struct TaskEntry {
     pub completed: bool,
     pub id: String,
     pub parent: Option<String>,
-    #[serde(deserialize_with = "de_float_guard")]
+    #[serde(default, deserialize_with = "de_float_guard")]
     pub last_edited: Option<i64>,
-    #[serde(deserialize_with = "de_float_guard")]
-    pub created: Option<i64>
+    #[serde(default, deserialize_with = "de_float_guard")]
+    pub created: Option<i64>,
+    /// Encrypted content (see crypto.rs). The plain fields are blanked
+    /// while this is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub sealed: Option<String>,
+    /// Sync fields from newer versions, kept so they aren't dropped in transit.
+    #[serde(flatten)]
+    #[sqlx(skip)]
+    pub extra: Map<String, Value>
 }
 
 impl TaskEntry {
@@ -151,7 +248,9 @@ impl TaskEntry {
             last_edited: None,
             name: task.name.clone(),
             parent: parent,
-            size: task.size
+            size: task.size,
+            sealed: None,
+            extra: Map::new()
         }
     }
 
@@ -162,6 +261,22 @@ impl TaskEntry {
         }
         return ret;
     }
+
+    /// Tags are `#words` in the name, lowercased.
+    pub fn tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = vec![];
+        for word in self.name.split_whitespace() {
+            let tag: String = match word.strip_prefix('#') {
+                Some(rest) => rest.chars().take_while(|c| c.is_alphanumeric() || *c == '-' || *c == '_').collect(),
+                None => continue
+            };
+            let tag = tag.to_lowercase();
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        tags
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -177,10 +292,19 @@ pub struct ListEntry {
     pub name: String,
     pub uuid: String,
     pub color: i32,
-    #[serde(deserialize_with = "de_float_guard")]
+    #[serde(default, deserialize_with = "de_float_guard")]
     pub last_edited: Option<i64>,
-    #[serde(deserialize_with = "de_float_guard")]
-    pub created: Option<i64>
+    #[serde(default, deserialize_with = "de_float_guard")]
+    pub created: Option<i64>,
+    /// Encrypted content (see crypto.rs). The plain fields are blanked
+    /// while this is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub sealed: Option<String>,
+    /// Sync fields from newer versions, kept so they aren't dropped in transit.
+    #[serde(flatten)]
+    #[sqlx(skip)]
+    pub extra: Map<String, Value>
 }
 
 impl ListEntry {
@@ -190,7 +314,9 @@ impl ListEntry {
             created: None,
             last_edited: None,
             name: list.name.clone(),
-            uuid: list.uuid.clone()
+            uuid: list.uuid.clone(),
+            sealed: None,
+            extra: Map::new()
         }
     }
 }
@@ -206,57 +332,90 @@ impl ListRecord {
     }
 }
 
-pub async fn compare_and_save(data: &SyncData) -> Result<Option<SyncData>, sqlx::Error> {
+/// `compare_and_save_in` against the open task database. `None` if it
+/// isn't loaded.
+pub async fn merge_remote(data: &SyncData) -> Result<Option<SyncData>, sqlx::Error> {
     unsafe {
         if TASKS.is_none() { return Ok(None); }
+        compare_and_save_in(TASKS.as_mut().unwrap(), data).await.map(Some)
     }
+}
+
+/// Items edited after `last_sync`.
+pub async fn local_changes_since(last_sync: i64) -> Result<SyncData, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() { return Ok(SyncData::new()); }
+        changes_since_in(TASKS.as_mut().unwrap(), last_sync).await
+    }
+}
+
+/// Items in `db` edited after `last_sync`.
+pub async fn changes_since_in(db: &mut TaskDb, last_sync: i64) -> Result<SyncData, sqlx::Error> {
+    let mut local = SyncData::new();
+    local.lists = db
+        .filter_lists(vec![
+            format!("last_edited > {}", last_sync)
+        ]).await?.unwrap();
+    let all_lists = db.get_lists().await?.unwrap();
+    for l in &all_lists {
+        local.tasks.insert(
+            l.uuid.clone(), 
+            db.filter_tasks(
+                    l.uuid.clone(),
+                    vec![
+                        format!("last_edited > {}", last_sync)
+                    ]).await?.unwrap()
+        );
+    }
+    Ok(local)
+}
+
+/// Merges `data` into `db`: remote items win where they were edited later.
+/// Returns the local changes since `data.last_sync` the other side still
+/// needs.
+pub async fn compare_and_save_in(db: &mut TaskDb, data: &SyncData) -> Result<SyncData, sqlx::Error> {
     // Double check last sync time (sec vs. ms)
     let last_sync = check_timestamp(data.last_sync);
-    // Load local changes
-    let mut local = SyncData::new();
-    unsafe {
-        local.lists = TASKS
-            .as_mut().unwrap()
-            .filter_lists(vec![
-                format!("last_edited > {}", last_sync)
-            ]).await?.unwrap();
-        let all_lists = TASKS.as_mut().unwrap().get_lists().await?.unwrap();
-        for l in &all_lists {
-            local.tasks.insert(
-                l.uuid.clone(), 
-                TASKS
-                    .as_mut().unwrap()
-                    .filter_tasks(
-                        l.uuid.clone(),
-                        vec![
-                            format!("last_edited > {}", last_sync)
-                        ]).await?.unwrap()
-            );
-        }
-    }
-    let local = local;
-    // To apply to remote
-    let mut ret = SyncData::new();
+    let local = changes_since_in(db, last_sync).await?;
+    let merge = compare_and_save(&local, data);
+    save_merged_in(db, &merge.save).await?;
+    Ok(merge.send)
+}
+
+/// Outcome of merging two change sets.
+pub struct Merge {
+    /// Remote items that win, to save locally
+    pub save: SyncData,
+    /// Local items that win or the remote side doesn't have, to send
+    pub send: SyncData
+}
+
+/// Compares local and remote changes. The later edit of an item wins, and
+/// items with a missing timestamp on either side are left alone. `local`
+/// has an entry in `tasks` for every local list, so remote tasks in lists
+/// not in it are for lists that only exist remotely.
+pub fn compare_and_save(local: &SyncData, remote: &SyncData) -> Merge {
+    let mut merge = Merge { save: SyncData::new(), send: SyncData::new() };
     // List maps
     let mut local_lists: HashMap<String, &ListEntry> = HashMap::new();
     for l in &local.lists {
         local_lists.insert(l.uuid.to_owned(), l);
     }
     let mut remote_lists: HashMap<String, &ListEntry> = HashMap::new();
-    for l in &data.lists {
+    for l in &remote.lists {
         remote_lists.insert(l.uuid.to_owned(), l);
     }
     // Compare lists
-    ret.lists = compare_and_save_lists(&local_lists, &remote_lists).await?;
+    merge.send.lists = compare_lists(&local_lists, &remote_lists, &mut merge.save.lists);
     // Compare tasks
     for local_key in local.tasks.keys() {
-        if !data.tasks.contains_key(local_key) {
+        if !remote.tasks.contains_key(local_key) {
             // If remote does NOT contain this list (i.e., no remote tasks have changed in this list)
             // Check that entry isn't empty
             if local.tasks.get(local_key).unwrap().len() == 0 {
                 continue;
             }
-            ret.tasks.insert(local_key.to_owned(), local.tasks.get(local_key).unwrap().to_owned());
+            merge.send.tasks.insert(local_key.to_owned(), local.tasks.get(local_key).unwrap().to_owned());
         } else {
             // Do comparison
             let mut local_tasks: HashMap<String, &TaskEntry> = HashMap::new();
@@ -264,33 +423,31 @@ pub async fn compare_and_save(data: &SyncData) -> Result<Option<SyncData>, sqlx:
                 local_tasks.insert(t.id.to_owned(), t);
             }
             let mut remote_tasks: HashMap<String, &TaskEntry> = HashMap::new();
-            for t in data.tasks.get(local_key).unwrap() {
+            for t in remote.tasks.get(local_key).unwrap() {
                 remote_tasks.insert(t.id.to_owned(), t);
             }
-            ret.tasks.insert(local_key.to_owned(), compare_and_save_tasks(local_key.to_owned(), &local_tasks, &remote_tasks).await?);
+            let mut save = Vec::new();
+            merge.send.tasks.insert(local_key.to_owned(), compare_tasks(&local_tasks, &remote_tasks, &mut save));
+            if save.len() > 0 {
+                merge.save.tasks.insert(local_key.to_owned(), save);
+            }
         }
     }
-    for remote_key in data.tasks.keys() {
+    for remote_key in remote.tasks.keys() {
         if local.tasks.contains_key(remote_key) {
             continue;
         }
-        for task in data.tasks.get(remote_key).unwrap() {
-            unsafe {
-                if TASKS.as_mut().unwrap().get_task(remote_key.clone(), task.id.clone()).await?.is_some() {
-                    TASKS.as_mut().unwrap().edit_task(remote_key.clone(), task).await?;
-                } else {
-                    TASKS.as_mut().unwrap().new_task(remote_key.clone(), task).await?;
-                }
-            }
-        }
+        merge.save.tasks.insert(remote_key.to_owned(), remote.tasks.get(remote_key).unwrap().to_owned());
     }
-    Ok(Some(ret))
+    merge
 }
 
-async fn compare_and_save_lists(
+/// Returns the local lists to send; remote lists to save go in `save`.
+fn compare_lists(
     local_lists: &HashMap<String, &ListEntry>, 
-    remote_lists: &HashMap<String, &ListEntry>
-) -> Result<Vec<ListEntry>, sqlx::Error> {
+    remote_lists: &HashMap<String, &ListEntry>,
+    save: &mut Vec<ListEntry>
+) -> Vec<ListEntry> {
     let mut ret: Vec<ListEntry> = Vec::new();
     for l in local_lists {
         let list = l.1.to_owned();
@@ -303,13 +460,7 @@ async fn compare_and_save_lists(
             }
             if check_timestamp(other_list.last_edited.clone().unwrap()) > list.last_edited.clone().unwrap() {
                 // Server is newer -- save
-                unsafe {
-                    if TASKS.as_mut().unwrap().get_list(list.uuid.clone()).await?.is_some() {
-                        TASKS.as_mut().unwrap().edit_list(other_list).await?;
-                    } else {
-                        TASKS.as_mut().unwrap().new_list(other_list).await?;
-                    }
-                }
+                save.push(other_list.clone());
                 continue;
             }
         }
@@ -327,22 +478,17 @@ async fn compare_and_save_lists(
             // If bad timestamps, change nothing
             continue;
         }
-        unsafe {
-            if TASKS.as_mut().unwrap().get_list(list.uuid.clone()).await?.is_some() {
-                TASKS.as_mut().unwrap().edit_list(list).await?;
-            } else {
-                TASKS.as_mut().unwrap().new_list(list).await?;
-            }
-        }
+        save.push(list.clone());
     }
-    Ok(ret)
+    ret
 }
 
-async fn compare_and_save_tasks(
-    list: String,
+/// Returns the local tasks to send; remote tasks to save go in `save`.
+fn compare_tasks(
     local_tasks: &HashMap<String, &TaskEntry>, 
-    remote_tasks: &HashMap<String, &TaskEntry>
-) -> Result<Vec<TaskEntry>, sqlx::Error> {
+    remote_tasks: &HashMap<String, &TaskEntry>,
+    save: &mut Vec<TaskEntry>
+) -> Vec<TaskEntry> {
     let mut ret: Vec<TaskEntry> = Vec::new();
     for t in local_tasks {
         let task = t.1.to_owned();
@@ -355,13 +501,7 @@ async fn compare_and_save_tasks(
             }
             if check_timestamp(other_task.last_edited.clone().unwrap()) > task.last_edited.clone().unwrap() {
                 // Server is newer -- save
-                unsafe {
-                    if TASKS.as_mut().unwrap().get_task(list.clone(), task.id.clone()).await?.is_some() {
-                        TASKS.as_mut().unwrap().edit_task(list.clone(), other_task).await?;
-                    } else {
-                        TASKS.as_mut().unwrap().new_task(list.clone(), other_task).await?;
-                    }
-                }
+                save.push(other_task.clone());
                 continue;
             }
         }
@@ -379,15 +519,340 @@ async fn compare_and_save_tasks(
             // If bad timestamps, change nothing
             continue;
         }
-        unsafe {
-            if TASKS.as_mut().unwrap().get_task(list.clone(), task.id.clone()).await?.is_some() {
-                TASKS.as_mut().unwrap().edit_task(list.clone(), task).await?;
-            } else {
-                TASKS.as_mut().unwrap().new_task(list.clone(), task).await?;
+        save.push(task.clone());
+    }
+    ret
+}
+
+/// Writes the winning remote items of a merge to `db`, lists first, keeping
+/// their timestamps. Items in lists with an invalid id are skipped. Returns
+/// how many items were saved.
+pub async fn save_merged_in(db: &mut TaskDb, save: &SyncData) -> Result<usize, sqlx::Error> {
+    let mut saved = 0;
+    for list in &save.lists {
+        if !is_valid_list_id(&list.uuid) { continue; }
+        if db.save_synced_list(list).await? {
+            saved += 1;
+        }
+    }
+    for (list, tasks) in &save.tasks {
+        if !is_valid_list_id(list) { continue; }
+        for task in tasks {
+            if db.save_synced_task(list.clone(), task).await? {
+                saved += 1;
             }
         }
     }
-    Ok(ret)
+    Ok(saved)
+}
+
+/// Merges remote changes into the open task database with the rules of
+/// `compare_and_save`, against the local edits after `since`. Returns how
+/// many items were saved.
+pub async fn apply_remote_changes(data: &SyncData, since: i64) -> Result<usize, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() { return Ok(0); }
+        let db = TASKS.as_mut().unwrap();
+        let local = changes_since_in(db, since).await?;
+        save_merged_in(db, &compare_and_save(&local, data).save).await
+    }
+}
+
+/// List ids become table names, so anything that could break out of the
+/// quotes is refused.
+pub fn is_valid_list_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Paged sync progress for `server`, or a fresh state if it has none yet.
+pub async fn load_sync_state(server: &str) -> Result<SyncState, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() { return Ok(SyncState::new(server)); }
+        Ok(TASKS.as_mut().unwrap().get_sync_state(server).await?.unwrap_or(SyncState::new(server)))
+    }
+}
+
+pub async fn save_sync_state(state: &SyncState) -> Result<bool, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() { return Ok(false); }
+        TASKS.as_mut().unwrap().set_sync_state(state).await
+    }
+}
+
+/// A list or task named in a sync preview. For lists `id` is the list UUID.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ItemRef {
+    pub list: String,
+    pub id: String,
+    pub name: String
+}
+
+impl ItemRef {
+    fn from_list(list: &ListEntry) -> ItemRef {
+        ItemRef { list: list.uuid.clone(), id: list.uuid.clone(), name: list.name.clone() }
+    }
+
+    fn from_task(list: &str, task: &TaskEntry) -> ItemRef {
+        ItemRef { list: list.to_string(), id: task.id.clone(), name: task.name.clone() }
+    }
+}
+
+/// What a sync would do to one kind of item on one side. `overwritten`
+/// items have edits there that the other side's newer copy would replace.
+#[derive(Serialize, Default, Debug)]
+pub struct ChangeSet {
+    pub created: Vec<ItemRef>,
+    pub updated: Vec<ItemRef>,
+    pub overwritten: Vec<ItemRef>
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct SideChanges {
+    pub lists: ChangeSet,
+    pub tasks: ChangeSet
+}
+
+/// What a sync would change on this device (`local`) and on the server
+/// (`remote`).
+#[derive(Serialize, Default, Debug)]
+pub struct SyncPreview {
+    pub local: SideChanges,
+    pub remote: SideChanges
+}
+
+/// Works out what syncing `remote` (everything the server would send) would
+/// do: the `compare_and_save` a sync runs against the local edits after
+/// `since`, without saving or sending anything.
+pub async fn preview_changes(remote: &SyncData, since: i64) -> Result<SyncPreview, sqlx::Error> {
+    let mut preview = SyncPreview::default();
+    unsafe {
+        if TASKS.is_none() { return Ok(preview); }
+    }
+    let local = unsafe { changes_since_in(TASKS.as_mut().unwrap(), since).await? };
+    let merge = compare_and_save(&local, remote);
+    // This device
+    for list in &merge.save.lists {
+        if !is_valid_list_id(&list.uuid) { continue; }
+        let edited_here = local.lists.iter().any(|l| l.uuid == list.uuid);
+        let current = unsafe { TASKS.as_mut().unwrap().get_list(list.uuid.clone()).await? };
+        sort_incoming(&mut preview.local.lists, current.map(|l| (l.last_edited, ItemRef::from_list(&l))),
+            edited_here, list.last_edited, ItemRef::from_list(list));
+    }
+    for (list, tasks) in &merge.save.tasks {
+        if !is_valid_list_id(list) { continue; }
+        for task in tasks {
+            let edited_here = local.tasks.get(list).is_some_and(|t| t.iter().any(|t| t.id == task.id));
+            let current = unsafe { TASKS.as_mut().unwrap().get_task(list.clone(), task.id.clone()).await? };
+            sort_incoming(&mut preview.local.tasks, current.map(|t| (t.last_edited, ItemRef::from_task(list, &t))),
+                edited_here, task.last_edited, ItemRef::from_task(list, task));
+        }
+    }
+    // The server
+    for list in &merge.send.lists {
+        let theirs = remote.lists.iter().find(|l| l.uuid == list.uuid).map(|l| l.last_edited);
+        sort_outgoing(&mut preview.remote.lists, theirs, since, list.created, ItemRef::from_list(list));
+    }
+    for (list, tasks) in &merge.send.tasks {
+        for task in tasks {
+            let theirs = remote.tasks.get(list).and_then(|t| t.iter().find(|t| t.id == task.id)).map(|t| t.last_edited);
+            sort_outgoing(&mut preview.remote.tasks, theirs, since, task.created, ItemRef::from_task(list, task));
+        }
+    }
+    Ok(preview)
+}
+
+/// Sorts a remote item a sync would save here. `current` is the copy here,
+/// if any; saving the version that's already here changes nothing.
+fn sort_incoming(changes: &mut ChangeSet, current: Option<(Option<i64>, ItemRef)>, edited_here: bool, edited: Option<i64>, item: ItemRef) {
+    match current {
+        None => changes.created.push(item),
+        Some((current_edited, _)) if current_edited == edited => {},
+        Some((_, current)) if edited_here => changes.overwritten.push(current),
+        Some(_) => changes.updated.push(item)
+    }
+}
+
+/// Sorts a local item a sync would send. `theirs` is when the server's copy
+/// was edited, if it sent one; edited after `since`, it gets overwritten.
+fn sort_outgoing(changes: &mut ChangeSet, theirs: Option<Option<i64>>, since: i64, created: Option<i64>, item: ItemRef) {
+    match theirs {
+        Some(edited) if edited.is_some_and(|e| check_timestamp(e) > since) => changes.overwritten.push(item),
+        None if created.is_some_and(|c| c > since) => changes.created.push(item),
+        _ => changes.updated.push(item)
+    }
+}
+
+/// One list or task waiting to be pushed, with the key it's ordered by.
+pub enum PendingChange {
+    List(ListEntry),
+    Task(String, TaskEntry),
+}
+
+impl PendingChange {
+    /// Lists sort before tasks, so a list always reaches the server before
+    /// (or in the same chunk as) its tasks.
+    pub fn sort_key(&self) -> String {
+        match self {
+            PendingChange::List(l) => list_key(&l.uuid),
+            PendingChange::Task(list, t) => task_key(list, &t.id),
+        }
+    }
+}
+
+fn list_key(uuid: &str) -> String {
+    format!("0:{}", uuid)
+}
+
+fn task_key(list: &str, id: &str) -> String {
+    format!("1:{}:{}", list, id)
+}
+
+/// Whether the task DB is open, so an empty result means no lists.
+pub fn tasks_loaded() -> bool {
+    unsafe { TASKS.as_ref().is_some_and(|t| t.is_loaded) }
+}
+
+pub async fn get_saved_lists() -> Result<Vec<ListEntry>, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() { return Ok(vec![]); }
+        Ok(TASKS.as_mut().unwrap().get_lists().await?.unwrap_or_default())
+    }
+}
+
+/// Saves an imported list and its tasks as new local items, which sync
+/// like anything made in the app. Returns `false` if the list id is taken.
+pub async fn add_imported_list(list: &ListEntry, tasks: &[TaskEntry]) -> Result<bool, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() || !is_valid_list_id(&list.uuid) { return Ok(false); }
+        let db = TASKS.as_mut().unwrap();
+        if !db.new_list(list).await? { return Ok(false); }
+        for task in tasks {
+            db.new_task(list.uuid.clone(), task).await?;
+        }
+    }
+    Ok(true)
+}
+
+/// The saved copy of a list, if any.
+pub async fn get_saved_list(uuid: &str) -> Result<Option<ListEntry>, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() { return Ok(None); }
+        TASKS.as_mut().unwrap().get_list(uuid.to_string()).await
+    }
+}
+
+pub async fn get_saved_tasks(list: &str) -> Result<Vec<TaskEntry>, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() { return Ok(vec![]); }
+        Ok(TASKS.as_mut().unwrap().get_tasks(list.to_string()).await?.unwrap_or_default())
+    }
+}
+
+/// The lists with `ids` (every list if there are none) and their tasks, for
+/// exporting. An id that isn't a list is an error.
+pub async fn lists_to_export(ids: &[String]) -> Result<Vec<(ListEntry, Vec<TaskEntry>)>, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let lists = if ids.is_empty() {
+        get_saved_lists().await.or_else(|e| Err(format!("Export Error: {}", e)))?
+    } else {
+        let mut lists = vec![];
+        for id in ids {
+            let list = get_saved_list(id).await
+                .or_else(|e| Err(format!("Export Error: {}", e)))?;
+            lists.push(list.ok_or(format!("Export Error: no list {}.", id))?);
+        }
+        lists
+    };
+    let mut with_tasks = vec![];
+    for list in lists {
+        let tasks = get_saved_tasks(&list.uuid).await
+            .or_else(|e| Err(format!("Export Error: {}", e)))?;
+        with_tasks.push((list, tasks));
+    }
+    Ok(with_tasks)
+}
+
+/// Saves a list as it is, timestamps included, replacing any local copy.
+pub async fn save_list_entry(list: &ListEntry) -> Result<bool, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() || !is_valid_list_id(&list.uuid) { return Ok(false); }
+        TASKS.as_mut().unwrap().save_synced_list(list).await
+    }
+}
+
+/// Saves a task as it is, timestamps included, replacing any local copy.
+pub async fn save_task_entry(list: &str, task: &TaskEntry) -> Result<bool, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() || !is_valid_list_id(list) { return Ok(false); }
+        TASKS.as_mut().unwrap().save_synced_task(list.to_string(), task).await
+    }
+}
+
+/// Deletes a list that was deleted elsewhere.
+pub async fn remove_synced_list(uuid: &str) -> Result<bool, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() { return Ok(false); }
+        TASKS.as_mut().unwrap().delete_list(uuid.to_string()).await
+    }
+}
+
+/// Deletes a task that was deleted elsewhere.
+pub async fn remove_synced_task(list: &str, id: &str) -> Result<bool, sqlx::Error> {
+    unsafe {
+        if TASKS.is_none() { return Ok(false); }
+        TASKS.as_mut().unwrap().delete_task(list.to_string(), id.to_string()).await
+    }
+}
+
+/// Local changes in `(since, until]`, in a stable order so an interrupted
+/// push can resume after the last acknowledged item.
+pub async fn collect_local_changes(since: i64, until: i64) -> Result<Vec<PendingChange>, sqlx::Error> {
+    let mut changes = Vec::new();
+    unsafe {
+        if TASKS.is_none() { return Ok(changes); }
+        let lists = TASKS.as_mut().unwrap().get_lists().await?.unwrap_or_default();
+        for l in TASKS.as_mut().unwrap().lists_edited_between(since, until).await?.unwrap_or_default() {
+            changes.push(PendingChange::List(l));
+        }
+        for l in &lists {
+            for t in TASKS.as_mut().unwrap().tasks_edited_between(l.uuid.clone(), since, until).await?.unwrap_or_default() {
+                changes.push(PendingChange::Task(l.uuid.clone(), t));
+            }
+        }
+    }
+    changes.sort_by_key(|c| c.sort_key());
+    Ok(changes)
+}
+
+/// Splits pending changes into `SyncData` chunks of at most `size` items,
+/// skipping everything up to and including `after`. Each chunk comes with the
+/// sort key of its last item.
+pub fn chunk_changes(changes: Vec<PendingChange>, after: Option<&str>, size: usize) -> Vec<(SyncData, String)> {
+    let size = size.max(1);
+    let mut chunks = Vec::new();
+    let mut current = SyncData::new();
+    let mut count = 0;
+    let mut last_key = String::new();
+    for change in changes {
+        last_key = change.sort_key();
+        if after.is_some_and(|a| last_key.as_str() <= a) { continue; }
+        match change {
+            PendingChange::List(l) => current.lists.push(l),
+            PendingChange::Task(list, t) => current.tasks.entry(list).or_default().push(t),
+        }
+        count += 1;
+        if count == size {
+            chunks.push((current, last_key.clone()));
+            current = SyncData::new();
+            count = 0;
+        }
+    }
+    if count > 0 {
+        chunks.push((current, last_key));
+    }
+    chunks
 }
 
 #[tauri::command]
@@ -538,4 +1003,22 @@ pub async fn delete_task<R: Runtime>(app: tauri::AppHandle<R>, task: TaskRecord,
             Ok(result.unwrap())
         }
     }
-}
\ No newline at end of file
+}
+
+/// Looks for damage in the task database without changing anything.
+#[tauri::command]
+pub async fn check_database<R: Runtime>(app: tauri::AppHandle<R>) -> Result<Vec<DbIssue>, String> {
+    unsafe {
+        load_task_db(app).await;
+        health::check_in(TASKS.as_mut().unwrap(), false).await.or_else(|e| Err(format!("{}", e)))
+    }
+}
+
+/// Fixes what `check_database` finds and returns what was changed.
+#[tauri::command]
+pub async fn repair_database<R: Runtime>(app: tauri::AppHandle<R>) -> Result<Vec<DbIssue>, String> {
+    unsafe {
+        load_task_db(app).await;
+        health::check_in(TASKS.as_mut().unwrap(), true).await.or_else(|e| Err(format!("{}", e)))
+    }
+}
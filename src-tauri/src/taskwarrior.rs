@@ -0,0 +1,353 @@
+// Taskwarrior's JSON format (`task export` / `task import`). Lists are
+// projects, `#tags` are tags, and a task depends on its subtasks, since it
+// can't be done before them. Task Manager's own ids, sizes and exact
+// importances ride along as the UDAs `tmid`, `tmsize` and `tmimportance`,
+// a list's id and color as `tmlist` and `tmcolor`, and edit times as
+// `modified`, so a file can go through Taskwarrior and back without losing
+// anything but fractions of a second, since Taskwarrior times are whole
+// seconds. Importing a file again updates the lists it came from.
+use std::{collections::{BTreeMap, HashMap}, fs, path::Path};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{from_str, to_string, Map, Value};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::{ics::{format_utc, parse_datetime}, task::{fix_parents, get_saved_lists, get_saved_tasks, is_valid_list_id, lists_to_export, save_list_entry, save_task_entry, tasks_loaded, DEFAULT_COLOR, DEFAULT_IMPORTANCE, DEFAULT_SIZE, ListEntry, TaskEntry}, utils::now};
+
+const PENDING: &str = "pending";
+const COMPLETED: &str = "completed";
+/// Attributes Taskwarrior works out itself, which aren't worth reporting
+const COMPUTED: [&str; 2] = ["id", "urgency"];
+
+/// A task as in `task export`. Attributes with no Task Manager equivalent
+/// (annotations, wait, recur...) end up in `other`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct TwTask {
+    pub uuid: String,
+    pub description: String,
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// `H`, `M` or `L`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// UUIDs of tasks that have to be done first
+    #[serde(default, deserialize_with = "depends_list", skip_serializing_if = "Vec::is_empty")]
+    pub depends: Vec<String>,
+    /// The Task Manager id, when it isn't the UUID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tmid: Option<String>,
+    #[serde(default, deserialize_with = "uda_number", skip_serializing_if = "Option::is_none")]
+    pub tmsize: Option<i32>,
+    #[serde(default, deserialize_with = "uda_number", skip_serializing_if = "Option::is_none")]
+    pub tmimportance: Option<i32>,
+    /// The id of the Task Manager list, so lists with the same name stay apart
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tmlist: Option<String>,
+    #[serde(default, deserialize_with = "uda_number", skip_serializing_if = "Option::is_none")]
+    pub tmcolor: Option<i32>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct TaskwarriorImport {
+    pub lists: usize,
+    pub tasks: usize,
+    /// Attributes and tasks that couldn't be brought over → how many
+    pub dropped: BTreeMap<String, usize>
+}
+
+/// `depends` is an array since Taskwarrior 2.6, and a comma-separated
+/// string before.
+fn depends_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => s.split(',').map(|u| u.trim().to_string()).filter(|u| !u.is_empty()).collect(),
+        Value::Array(items) => items.into_iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect(),
+        _ => vec![]
+    })
+}
+
+/// Numeric UDAs come out as numbers or strings depending on the version.
+fn uda_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i32>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(n) => n.as_f64().map(|n| n as i32),
+        Value::String(s) => s.trim().parse::<f64>().ok().map(|n| n as i32),
+        _ => None
+    })
+}
+
+fn importance_to_priority(importance: i32) -> Option<String> {
+    match importance {
+        4.. => Some("H"),
+        3 => Some("M"),
+        2 => None,
+        _ => Some("L")
+    }.map(|p| p.to_string())
+}
+
+fn priority_to_importance(priority: Option<&str>) -> i32 {
+    match priority {
+        Some("H") => 4,
+        Some("M") => 3,
+        Some("L") => 1,
+        _ => DEFAULT_IMPORTANCE
+    }
+}
+
+/// The UUID a task has in Taskwarrior: its id if that's already a UUID,
+/// otherwise one made from its list and id, so exporting again updates
+/// the same Taskwarrior tasks.
+fn task_uuid(list: &str, id: &str) -> String {
+    if uuid::Uuid::try_parse(id).is_ok_and(|u| u.to_string() == id) {
+        return id.to_string();
+    }
+    let hash = Sha256::digest(format!("{}/{}", list, id));
+    let bytes: [u8; 16] = hash[..16].try_into().unwrap();
+    uuid::Builder::from_random_bytes(bytes).into_uuid().to_string()
+}
+
+/// A name as a description and tags. `#tags` at the end move to the tags,
+/// keeping their case, and ones earlier on stay in the description too.
+fn split_tags(task: &TaskEntry) -> (String, Vec<String>) {
+    let is_tag = |w: &str| w.strip_prefix('#').is_some_and(|t| !t.is_empty() && t.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_'));
+    let mut description = task.name.trim_end();
+    let mut tags = vec![];
+    while let Some((rest, last)) = description.rsplit_once(char::is_whitespace).filter(|(_, last)| is_tag(last)) {
+        tags.insert(0, last[1..].to_string());
+        description = rest.trim_end();
+    }
+    for tag in task.tags() {
+        if !tags.iter().any(|t| t.to_lowercase() == tag) {
+            tags.push(tag);
+        }
+    }
+    (description.to_string(), tags)
+}
+
+/// Every task in `lists`, with subtasks as dependencies of their parent.
+pub fn tasks_to_taskwarrior(lists: &[(ListEntry, Vec<TaskEntry>)]) -> Vec<TwTask> {
+    let mut out = vec![];
+    for (list, tasks) in lists {
+        let uuids: HashMap<&str, String> = tasks.iter().map(|t| (t.id.as_str(), task_uuid(&list.uuid, &t.id))).collect();
+        let mut depends: HashMap<&str, Vec<String>> = HashMap::new();
+        for task in tasks {
+            if let Some(parent) = task.parent.as_deref().filter(|p| uuids.contains_key(p)) {
+                depends.entry(parent).or_default().push(uuids[task.id.as_str()].clone());
+            }
+        }
+        for task in tasks {
+            let uuid = uuids[task.id.as_str()].clone();
+            let (description, tags) = split_tags(task);
+            let edited = task.last_edited.unwrap_or_else(now);
+            out.push(TwTask {
+                tmid: Some(task.id.clone()).filter(|id| *id != uuid),
+                uuid,
+                description,
+                status: if task.completed { COMPLETED } else { PENDING }.to_string(),
+                project: Some(list.name.clone()),
+                priority: importance_to_priority(task.importance),
+                due: Some(format_utc(task.due)),
+                entry: Some(format_utc(task.created.unwrap_or(edited))),
+                modified: Some(format_utc(edited)),
+                end: task.completed.then(|| format_utc(edited)),
+                tags,
+                depends: depends.remove(task.id.as_str()).unwrap_or_default(),
+                tmsize: Some(task.size),
+                tmimportance: Some(task.importance),
+                tmlist: Some(list.uuid.clone()),
+                tmcolor: Some(list.color),
+                other: Map::new()
+            });
+        }
+    }
+    out
+}
+
+/// Tasks as `task export` writes them: a JSON array, one task per line.
+pub fn write_taskwarrior(tasks: &[TwTask]) -> Result<String, String> {
+    let lines = tasks.iter().map(to_string).collect::<Result<Vec<_>, _>>()
+        .or_else(|e| Err(format!("Export Error: {}", e)))?;
+    Ok(format!("[\n{}\n]\n", lines.join(",\n")))
+}
+
+/// Tasks from a JSON array, or from one JSON object per line as older
+/// versions exported.
+pub fn read_taskwarrior(text: &str) -> Result<Vec<TwTask>, String> {
+    let text = text.trim_start_matches('\u{feff}').trim();
+    if text.starts_with('[') {
+        return from_str(text).or_else(|e| Err(format!("Import Error: this isn't a Taskwarrior export ({}).", e)));
+    }
+    text.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| from_str(line.trim().trim_end_matches(','))
+            .or_else(|e| Err(format!("Import Error: line {} isn't a Taskwarrior task ({}).", i + 1, e))))
+        .collect()
+}
+
+/// A list per `tmlist`, or per project for tasks without one, and
+/// `fallback_name` for tasks with neither. Deleted tasks and recurring
+/// templates are skipped; a task's dependencies in the same list become
+/// its subtasks. Each task keeps its `tmid` or UUID as its id.
+pub fn taskwarrior_to_lists(tasks: &[TwTask], fallback_name: &str, dropped: &mut BTreeMap<String, usize>) -> Vec<(ListEntry, Vec<TaskEntry>)> {
+    let mut count = |what: &str| *dropped.entry(what.to_string()).or_default() += 1;
+    let today = now();
+    let mut lists: Vec<(ListEntry, Vec<TaskEntry>)> = vec![];
+    let mut list_of: HashMap<String, usize> = HashMap::new();
+    // UUID → (list index, task index)
+    let mut found: HashMap<&str, (usize, usize)> = HashMap::new();
+    for tw in tasks {
+        match tw.status.as_str() {
+            "deleted" => {
+                count("deleted tasks");
+                continue;
+            },
+            "recurring" => {
+                count("recurring templates");
+                continue;
+            },
+            _ => {}
+        }
+        for key in tw.other.keys().filter(|k| !COMPUTED.contains(&k.as_str())) {
+            count(key);
+        }
+        let project = tw.project.clone().unwrap_or(fallback_name.to_string());
+        let tmlist = tw.tmlist.clone().filter(|l| is_valid_list_id(l));
+        let key = tmlist.as_ref().map_or(format!("project:{}", project), |l| format!("list:{}", l));
+        let index = *list_of.entry(key).or_insert_with(|| {
+            lists.push((ListEntry {
+                name: project.clone(),
+                uuid: tmlist.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+                color: tw.tmcolor.unwrap_or(DEFAULT_COLOR),
+                last_edited: Some(today),
+                created: Some(today),
+                sealed: None,
+                extra: Map::new()
+            }, vec![]));
+            lists.len() - 1
+        });
+        let mut task = TaskEntry {
+            name: tw.description.trim().to_string(),
+            size: tw.tmsize.unwrap_or(DEFAULT_SIZE).clamp(0, 4),
+            importance: tw.tmimportance.unwrap_or_else(|| priority_to_importance(tw.priority.as_deref())).clamp(0, 4),
+            due: tw.due.as_deref().and_then(parse_datetime).unwrap_or(today),
+            completed: tw.status == COMPLETED,
+            id: tw.tmid.clone().unwrap_or(tw.uuid.clone()),
+            parent: None,
+            last_edited: tw.modified.as_deref().and_then(parse_datetime).or(Some(today)),
+            created: tw.entry.as_deref().and_then(parse_datetime).or(Some(today)),
+            sealed: None,
+            extra: Map::new()
+        };
+        // Tags already in the description aren't repeated
+        let has = task.tags();
+        for tag in tw.tags.iter().filter(|t| !has.contains(&t.to_lowercase())) {
+            task.name.push_str(&format!(" #{}", tag));
+        }
+        found.insert(&tw.uuid, (index, lists[index].1.len()));
+        lists[index].1.push(task);
+    }
+    for tw in tasks {
+        let Some(&(list, parent)) = found.get(tw.uuid.as_str()) else { continue };
+        for dep in &tw.depends {
+            match found.get(dep.as_str()) {
+                Some(&(l, child)) if l == list && lists[l].1[child].parent.is_none() => {
+                    lists[l].1[child].parent = Some(lists[list].1[parent].id.clone());
+                },
+                _ => count("dependencies")
+            }
+        }
+    }
+    for (_, tasks) in &mut lists {
+        fix_parents(tasks);
+    }
+    lists
+}
+
+/// Writes `list_ids` (every list if empty) to a Taskwarrior JSON file at
+/// `path`. Returns how many tasks were written.
+#[tauri::command]
+pub async fn export_taskwarrior(list_ids: Vec<String>, path: String) -> Result<usize, String> {
+    let with_tasks = lists_to_export(&list_ids).await?;
+    let tasks = tasks_to_taskwarrior(&with_tasks);
+    fs::write(&path, write_taskwarrior(&tasks)?)
+        .or_else(|e| Err(format!("Export Error: {}", e)))?;
+    Ok(tasks.len())
+}
+
+/// Makes lists from a Taskwarrior JSON file at `path`, or updates the ones
+/// it was exported from, then has the task list reload.
+#[tauri::command]
+pub async fn import_taskwarrior<R: Runtime>(app: AppHandle<R>, path: String) -> Result<TaskwarriorImport, String> {
+    let result = import_taskwarrior_file(&path).await?;
+    let _ = app.emit("tasks-imported", result.tasks);
+    Ok(result)
+}
+
+/// Lists that are already saved get the file's name, color and tasks;
+/// their other tasks stay. Tasks keep their ids, unless a task with that id
+/// is already saved in another list.
+pub async fn import_taskwarrior_file(path: &str) -> Result<TaskwarriorImport, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let text = fs::read_to_string(path)
+        .or_else(|e| Err(format!("Import Error: {}", e)))?;
+    let name = Path::new(path).file_stem().map_or("Taskwarrior".to_string(), |s| s.to_string_lossy().to_string());
+    let mut result = TaskwarriorImport::default();
+    let lists = taskwarrior_to_lists(&read_taskwarrior(&text)?, &name, &mut result.dropped);
+    if lists.is_empty() {
+        return Err("There are no tasks to import.".to_string());
+    }
+
+    // Task id → the list it's in
+    let mut taken = HashMap::new();
+    // List id → when it was made
+    let mut saved = HashMap::new();
+    for list in get_saved_lists().await.or_else(|e| Err(format!("Import Error: {}", e)))? {
+        let tasks = get_saved_tasks(&list.uuid).await
+            .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        taken.extend(tasks.into_iter().map(|t| (t.id, list.uuid.clone())));
+        saved.insert(list.uuid.clone(), list.created);
+    }
+    for (mut list, mut tasks) in lists {
+        if let Some(created) = saved.get(&list.uuid) {
+            list.created = *created;
+        }
+        let renamed: HashMap<String, String> = tasks.iter()
+            .filter(|t| *taken.entry(t.id.clone()).or_insert(list.uuid.clone()) != list.uuid)
+            .map(|t| (t.id.clone(), uuid::Uuid::new_v4().to_string()))
+            .collect();
+        for task in &mut tasks {
+            if let Some(id) = renamed.get(&task.id) {
+                task.id = id.clone();
+            }
+            if let Some(parent) = task.parent.as_ref().and_then(|p| renamed.get(p)) {
+                task.parent = Some(parent.clone());
+            }
+        }
+        let added = save_list_entry(&list).await
+            .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        if !added {
+            return Err(format!("Import Error: couldn't add list {}.", list.name));
+        }
+        for task in &tasks {
+            save_task_entry(&list.uuid, task).await
+                .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        }
+        result.lists += 1;
+        result.tasks += tasks.len();
+    }
+    Ok(result)
+}
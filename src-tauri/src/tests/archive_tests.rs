@@ -0,0 +1,177 @@
+// Whole-database JSON archives.
+use std::fs;
+
+use serde_json::{json, to_value, Value};
+
+use crate::algorithm::{add_stored_due_events, get_stored_due_events, switch_history_db};
+use crate::archive::{export_all, import_archive_file, validate_archive, ImportMode, ItemCounts, ARCHIVE_VERSION};
+use crate::history::StoredDueEvent;
+use crate::task::{get_saved_list, get_saved_lists, get_saved_tasks, save_list_entry, save_task_entry, switch_task_db, ListEntry, TaskEntry};
+use crate::testutils::{test_list, test_task};
+
+const DIR: &str = "testArchive";
+const FILE: &str = "testArchive/archive.json";
+const T: i64 = 1_700_000_000_000;
+
+/// Items made at `T` and edited at `edited`
+fn list_at(uuid: &str, name: &str, edited: i64) -> ListEntry {
+    ListEntry { last_edited: Some(edited), created: Some(T), ..test_list(uuid, name) }
+}
+
+fn task_at(id: &str, name: &str, edited: i64) -> TaskEntry {
+    TaskEntry { size: 3, importance: 2, last_edited: Some(edited), created: Some(T), ..test_task(id, name, None, T + 86_400_000) }
+}
+
+fn test_event(id: &str, event_type: i32, time: i64) -> StoredDueEvent {
+    StoredDueEvent { event_type, time, id: id.to_string(), list: "school".to_string(), importance: 2, size: 3, due: T + 86_400_000 }
+}
+
+async fn open_device(name: &str) {
+    switch_task_db(&format!("{}/{}-tasks.db", DIR, name)).await.unwrap();
+    switch_history_db(&format!("{}/{}-history.db", DIR, name)).await.unwrap();
+}
+
+async fn task_names(list: &str) -> Vec<String> {
+    let mut names: Vec<String> = get_saved_tasks(list).await.unwrap().into_iter().map(|t| t.name).collect();
+    names.sort();
+    names
+}
+
+fn counts(added: usize, updated: usize, kept: usize, removed: usize) -> ItemCounts {
+    ItemCounts { added, updated, kept, removed }
+}
+
+#[tokio::test]
+async fn test_archive_round_trip() {
+    let _ = fs::remove_dir_all(DIR);
+    fs::create_dir_all(DIR).unwrap();
+    open_device("a").await;
+    save_list_entry(&list_at("school", "School", T)).await.unwrap();
+    save_task_entry("school", &task_at("essay", "Essay", T)).await.unwrap();
+    let mut outline = task_at("outline", "Outline", T);
+    outline.parent = Some("essay".to_string());
+    save_task_entry("school", &outline).await.unwrap();
+    save_list_entry(&list_at("empty", "Empty", T)).await.unwrap();
+    let events = vec![test_event("essay", 0, T), test_event("quiz", 1, T + 5)];
+    add_stored_due_events(&events, false).await.unwrap();
+    let settings = json!({ "theme": "dark", "lists": ["school"] });
+    export_all(FILE.to_string(), Some(settings.clone())).await.unwrap();
+
+    open_device("b").await;
+    let report = import_archive_file(FILE, ImportMode::Merge).await.unwrap();
+    assert_eq!(report.lists, counts(2, 0, 0, 0));
+    assert_eq!(report.tasks, counts(2, 0, 0, 0));
+    assert_eq!(report.due_events, counts(2, 0, 0, 0));
+    assert_eq!(report.settings, Some(settings));
+    assert!(report.skipped.is_empty());
+    assert_eq!(to_value(get_saved_list("school").await.unwrap().unwrap()).unwrap(), to_value(list_at("school", "School", T)).unwrap());
+    let saved = get_saved_tasks("school").await.unwrap();
+    assert_eq!(to_value(saved.iter().find(|t| t.id == "outline").unwrap()).unwrap(), to_value(&outline).unwrap());
+    // Event types are kept as stored, not flipped by the enum conversion
+    let mut saved_events = get_stored_due_events().await.unwrap();
+    saved_events.sort_by_key(|e| e.time);
+    assert_eq!(saved_events, events);
+
+    // Importing twice changes nothing
+    let report = import_archive_file(FILE, ImportMode::Merge).await.unwrap();
+    assert_eq!(report.lists, counts(0, 0, 2, 0));
+    assert_eq!(report.tasks, counts(0, 0, 2, 0));
+    assert_eq!(report.due_events, counts(0, 0, 2, 0));
+    assert_eq!(get_stored_due_events().await.unwrap().len(), 2);
+
+    let _ = fs::remove_dir_all(DIR);
+}
+
+#[tokio::test]
+async fn test_archive_merge_and_replace() {
+    let _ = fs::remove_dir_all(DIR);
+    fs::create_dir_all(DIR).unwrap();
+    open_device("a").await;
+    save_list_entry(&list_at("school", "School", T + 10)).await.unwrap();
+    save_task_entry("school", &task_at("essay", "Essay v2", T + 10)).await.unwrap();
+    save_task_entry("school", &task_at("quiz", "Quiz", T)).await.unwrap();
+    add_stored_due_events(&[test_event("essay", 0, T)], false).await.unwrap();
+    export_all(FILE.to_string(), None).await.unwrap();
+
+    open_device("b").await;
+    save_list_entry(&list_at("school", "School (mine)", T + 20)).await.unwrap();
+    save_task_entry("school", &task_at("essay", "Essay v1", T)).await.unwrap();
+    save_task_entry("school", &task_at("quiz", "Quiz (mine)", T + 20)).await.unwrap();
+    save_task_entry("school", &task_at("notes", "Notes", T)).await.unwrap();
+    save_list_entry(&list_at("home", "Home", T)).await.unwrap();
+    save_task_entry("home", &task_at("dishes", "Dishes", T)).await.unwrap();
+    add_stored_due_events(&[test_event("notes", 1, T + 1)], false).await.unwrap();
+
+    // Merging keeps whichever copy was edited last, and everything local
+    let report = import_archive_file(FILE, ImportMode::Merge).await.unwrap();
+    assert_eq!(report.lists, counts(0, 0, 1, 0));
+    assert_eq!(report.tasks, counts(0, 1, 1, 0));
+    assert_eq!(report.due_events, counts(1, 0, 0, 0));
+    assert_eq!(report.settings, None);
+    assert_eq!(get_saved_list("school").await.unwrap().unwrap().name, "School (mine)");
+    assert_eq!(task_names("school").await, vec!["Essay v2", "Notes", "Quiz (mine)"]);
+    assert_eq!(get_saved_lists().await.unwrap().len(), 2);
+    assert_eq!(get_stored_due_events().await.unwrap().len(), 2);
+
+    // Replacing makes the database match the archive
+    let report = import_archive_file(FILE, ImportMode::Replace).await.unwrap();
+    assert_eq!(report.lists, counts(0, 1, 0, 1));
+    assert_eq!(report.tasks, counts(0, 2, 0, 2));
+    assert_eq!(report.due_events, counts(1, 0, 0, 2));
+    assert_eq!(get_saved_list("school").await.unwrap().unwrap().name, "School");
+    assert_eq!(task_names("school").await, vec!["Essay v2", "Quiz"]);
+    assert!(get_saved_list("home").await.unwrap().is_none());
+    assert!(get_saved_tasks("home").await.unwrap().is_empty());
+    assert_eq!(get_stored_due_events().await.unwrap(), vec![test_event("essay", 0, T)]);
+
+    let _ = fs::remove_dir_all(DIR);
+}
+
+#[tokio::test]
+async fn test_archive_rejected() {
+    let _ = fs::remove_dir_all(DIR);
+    fs::create_dir_all(DIR).unwrap();
+    open_device("a").await;
+    save_list_entry(&list_at("school", "School", T)).await.unwrap();
+    export_all(FILE.to_string(), None).await.unwrap();
+    let archive: Value = serde_json::from_str(&fs::read_to_string(FILE).unwrap()).unwrap();
+
+    let mut bad = archive.clone();
+    bad["lists"][0]["uuid"] = json!("no spaces allowed");
+    bad["tasks"] = json!({ "school": [{ "name": "No id", "size": 1, "importance": 1, "due": T, "completed": false }] });
+    bad["due_events"] = json!([to_value(test_event("x", 7, T)).unwrap()]);
+    let errors = validate_archive(&bad);
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors.iter().any(|e| e.starts_with("/lists/0/uuid: ")));
+    assert!(errors.iter().any(|e| e.starts_with("/tasks/school/0: ") && e.contains("\"id\"")));
+    assert!(errors.iter().any(|e| e.starts_with("/due_events/0/type: ")));
+    let errors = validate_archive(&json!({ "format": "something else" }));
+    assert!(errors.iter().any(|e| e.starts_with("/: ") && e.contains("\"lists\"")));
+
+    let mut newer = archive.clone();
+    newer["version"] = json!(ARCHIVE_VERSION + 1);
+    let errors = validate_archive(&newer);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("newer version"));
+
+    // A rejected archive changes nothing
+    open_device("b").await;
+    fs::write(FILE, bad.to_string()).unwrap();
+    let err = import_archive_file(FILE, ImportMode::Replace).await.unwrap_err();
+    assert!(err.starts_with("Invalid archive:"), "{}", err);
+    assert!(get_saved_lists().await.unwrap().is_empty());
+    fs::write(FILE, "{ not json").unwrap();
+    assert!(import_archive_file(FILE, ImportMode::Merge).await.unwrap_err().starts_with("Not a JSON file"));
+
+    // Tasks for a list that's nowhere are skipped, not saved
+    let mut orphan = archive;
+    orphan["tasks"]["gone"] = json!([to_value(task_at("lost", "Lost", T)).unwrap()]);
+    fs::write(FILE, orphan.to_string()).unwrap();
+    let report = import_archive_file(FILE, ImportMode::Merge).await.unwrap();
+    assert_eq!(report.lists, counts(1, 0, 0, 0));
+    assert_eq!(report.tasks, counts(0, 0, 0, 0));
+    assert_eq!(report.skipped.len(), 1);
+    assert!(get_saved_list("gone").await.unwrap().is_none());
+
+    let _ = fs::remove_dir_all(DIR);
+}
@@ -0,0 +1,207 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+use axum::{extract::State, http::StatusCode as MockStatus, routing::post, Form, Json, Router};
+use reqwest::StatusCode;
+use serde_json::{from_str, json, to_string, Value};
+
+use crate::auth::*;
+use crate::http::{api_root, is_logged_in, log_out, set_server_endpoint, SessionCookie};
+
+#[test]
+fn test_auth_error_from_status() {
+    assert_eq!(AuthError::from_status(StatusCode::OK, None), None);
+    assert_eq!(AuthError::from_status(StatusCode::NO_CONTENT, None), None);
+    assert_eq!(AuthError::from_status(StatusCode::UNAUTHORIZED, None), Some(AuthError::SessionExpired));
+    assert_eq!(AuthError::from_status(StatusCode::FORBIDDEN, None), Some(AuthError::Forbidden));
+    assert_eq!(AuthError::from_status(StatusCode::TOO_MANY_REQUESTS, Some(42)), Some(AuthError::RateLimited(Some(42))));
+    assert_eq!(AuthError::from_status(StatusCode::INTERNAL_SERVER_ERROR, None), Some(AuthError::Server(500)));
+    assert_eq!(AuthError::from_status(StatusCode::BAD_GATEWAY, None), Some(AuthError::Server(502)));
+    assert_eq!(AuthError::from_status(StatusCode::NOT_FOUND, None), Some(AuthError::Unexpected(404)));
+
+    assert_eq!(AuthError::RateLimited(Some(42)).to_string(), "Too many attempts. Try again in 42 seconds.");
+    assert!(AuthError::Server(503).to_string().contains("503"));
+}
+
+#[test]
+fn test_session_serialization() {
+    let tokens = TokenSet {
+        access_token: "access".to_string(),
+        refresh_token: "refresh".to_string(),
+        access_expires: 1718000900000,
+        refresh_expires: Some(1720592000000)
+    };
+    let session = Session::Bearer(tokens.clone());
+    let value: serde_json::Value = from_str(&to_string(&session).unwrap()).unwrap();
+    assert_eq!(value["type"], json!("bearer"));
+    assert_eq!(value["access_token"], json!("access"));
+    assert_eq!(from_str::<Session>(&to_string(&session).unwrap()).unwrap(), session);
+
+    // Sessions stored before token auth were bare cookies, which need the
+    // fallback in read_session
+    let cookie = SessionCookie { name: "session".to_string(), value: "abc".to_string(), expires: None };
+    assert!(from_str::<Session>(&to_string(&cookie).unwrap()).is_err());
+    let session = Session::Cookie(cookie);
+    assert_eq!(from_str::<Session>(&to_string(&session).unwrap()).unwrap(), session);
+
+    assert!(!tokens.needs_refresh(1718000000000));
+    assert!(tokens.needs_refresh(1718000880000));
+    assert!(!Session::Bearer(tokens.clone()).is_expired(1718000900000));
+    assert!(Session::Bearer(tokens).is_expired(1720592000000));
+}
+
+#[test]
+fn test_device_poll_parse() {
+    let now = 1718000000000;
+    let pending = r#"{"error": "authorization_pending"}"#;
+    assert_eq!(DevicePoll::parse(StatusCode::BAD_REQUEST, pending, now), Ok(DevicePoll::Pending));
+    let slow = r#"{"error": "slow_down", "error_description": "Easy there"}"#;
+    assert_eq!(DevicePoll::parse(StatusCode::BAD_REQUEST, slow, now), Ok(DevicePoll::SlowDown));
+    assert_eq!(DevicePoll::parse(StatusCode::BAD_REQUEST, r#"{"error": "access_denied"}"#, now), Ok(DevicePoll::Denied));
+    assert_eq!(DevicePoll::parse(StatusCode::BAD_REQUEST, r#"{"error": "expired_token"}"#, now), Ok(DevicePoll::Expired));
+    assert!(DevicePoll::parse(StatusCode::BAD_REQUEST, r#"{"error": "invalid_client"}"#, now).is_err());
+    assert_eq!(DevicePoll::parse(StatusCode::BAD_GATEWAY, "", now), Err(AuthError::Server(502)));
+
+    let approved = r#"{"access_token": "a", "token_type": "bearer", "expires_in": 60, "refresh_token": "r"}"#;
+    assert_eq!(DevicePoll::parse(StatusCode::OK, approved, now), Ok(DevicePoll::Approved(TokenSet {
+        access_token: "a".to_string(),
+        refresh_token: "r".to_string(),
+        access_expires: now + 60_000,
+        refresh_expires: None
+    })));
+    // Without a refresh token the session ends with the access token
+    let approved = r#"{"access_token": "a", "token_type": "Bearer", "expires_in": 60}"#;
+    match DevicePoll::parse(StatusCode::OK, approved, now).unwrap() {
+        DevicePoll::Approved(tokens) => assert_eq!(tokens.refresh_expires, Some(now + 60_000)),
+        other => panic!("{:?}", other)
+    }
+}
+
+/// Minimal RFC 8628 authorization server. Approves (or denies) the device
+/// code after `pending_polls` polls.
+#[derive(Default)]
+struct MockAuth {
+    pending_polls: u32,
+    deny: bool,
+    expires_in: i64,
+    /// Left out of the response if `None`
+    interval: Option<u64>,
+    polls: u32
+}
+
+async fn mock_device(State(mock): State<Arc<Mutex<MockAuth>>>, Form(form): Form<HashMap<String, String>>) -> (MockStatus, Json<Value>) {
+    if form.get("client_id").map(|c| c.as_str()) != Some(OAUTH_CLIENT_ID) {
+        return (MockStatus::BAD_REQUEST, Json(json!({ "error": "invalid_client" })));
+    }
+    let (expires_in, interval) = {
+        let mock = mock.lock().unwrap();
+        (mock.expires_in, mock.interval)
+    };
+    let mut body = json!({
+        "device_code": "device-123",
+        "user_code": "WDJB-MJHT",
+        "verification_uri": "https://example.com/device",
+        "verification_uri_complete": "https://example.com/device?user_code=WDJB-MJHT",
+        "expires_in": expires_in
+    });
+    if let Some(interval) = interval {
+        body["interval"] = json!(interval);
+    }
+    (MockStatus::OK, Json(body))
+}
+
+async fn mock_token(State(mock): State<Arc<Mutex<MockAuth>>>, Form(form): Form<HashMap<String, String>>) -> (MockStatus, Json<Value>) {
+    // Refuses every password and refresh token the way OAuth servers do
+    if matches!(form.get("grant_type").map(|g| g.as_str()), Some("password") | Some("refresh_token")) {
+        return (MockStatus::BAD_REQUEST, Json(json!({ "error": "invalid_grant" })));
+    }
+    if form.get("grant_type").map(|g| g.as_str()) != Some(DEVICE_CODE_GRANT) {
+        return (MockStatus::BAD_REQUEST, Json(json!({ "error": "unsupported_grant_type" })));
+    }
+    if form.get("device_code").map(|c| c.as_str()) != Some("device-123") {
+        return (MockStatus::BAD_REQUEST, Json(json!({ "error": "invalid_grant" })));
+    }
+    let mut mock = mock.lock().unwrap();
+    mock.polls += 1;
+    if mock.polls <= mock.pending_polls {
+        return (MockStatus::BAD_REQUEST, Json(json!({ "error": "authorization_pending" })));
+    }
+    if mock.deny {
+        return (MockStatus::BAD_REQUEST, Json(json!({ "error": "access_denied" })));
+    }
+    (MockStatus::OK, Json(json!({
+        "access_token": "mock-access",
+        "token_type": "Bearer",
+        "expires_in": 3600,
+        "refresh_token": "mock-refresh"
+    })))
+}
+
+async fn start_mock(mock: MockAuth) -> Arc<Mutex<MockAuth>> {
+    let mock = Arc::new(Mutex::new(mock));
+    let app = Router::new()
+        .route("/auth/device", post(mock_device))
+        .route("/auth/token", post(mock_token))
+        .with_state(mock.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let root = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    set_server_endpoint(Some(root)).unwrap();
+    let _ = log_out().await;
+    mock
+}
+
+#[tokio::test]
+async fn test_device_login_approved() {
+    let mock = start_mock(MockAuth { pending_polls: 2, expires_in: 600, interval: Some(0), ..Default::default() }).await;
+    let login = start_device_login().await.unwrap();
+    assert_eq!(login.user_code, "WDJB-MJHT");
+    assert_eq!(login.verification_uri, "https://example.com/device");
+    // The mock asks for no wait between polls
+    assert_eq!(login.interval, 0);
+    assert!(!is_logged_in());
+
+    assert!(poll_device_login_with(Duration::ZERO).await.unwrap());
+    assert_eq!(mock.lock().unwrap().polls, 3);
+    assert!(is_logged_in());
+    // The code can't be polled again
+    assert!(poll_device_login_with(Duration::ZERO).await.is_err());
+
+    let _ = log_out().await;
+    let _ = set_server_endpoint(None);
+}
+
+#[tokio::test]
+async fn test_device_login_denied_or_expired() {
+    start_mock(MockAuth { pending_polls: 1, deny: true, expires_in: 600, interval: Some(0), ..Default::default() }).await;
+    start_device_login().await.unwrap();
+    assert_eq!(poll_device_login_with(Duration::ZERO).await.unwrap_err(), "Sign-in was denied.");
+    assert!(!is_logged_in());
+
+    // Never approved; the app gives up once the code expires
+    let mock = start_mock(MockAuth { pending_polls: u32::MAX, expires_in: 1, interval: Some(1), ..Default::default() }).await;
+    start_device_login().await.unwrap();
+    assert!(poll_device_login_with(Duration::ZERO).await.unwrap_err().contains("expired"));
+    assert!(mock.lock().unwrap().polls > 0);
+    assert!(!is_logged_in());
+
+    // Without an interval the RFC's default applies
+    start_mock(MockAuth { expires_in: 600, ..Default::default() }).await;
+    assert_eq!(start_device_login().await.unwrap().interval, 5);
+    cancel_device_login();
+    assert_eq!(poll_device_login().await.unwrap_err(), "Sign-in was cancelled.");
+    let _ = set_server_endpoint(None);
+}
+
+#[tokio::test]
+async fn test_invalid_grant() {
+    start_mock(MockAuth::default()).await;
+    assert_eq!(password_login(&api_root(), "alice", "wrong").await.unwrap_err(), AuthError::InvalidCredentials);
+    let tokens = TokenSet {
+        access_token: "access".to_string(),
+        refresh_token: "revoked".to_string(),
+        access_expires: 0,
+        refresh_expires: None
+    };
+    assert_eq!(refresh(&api_root(), &tokens).await.unwrap_err(), AuthError::SessionExpired);
+    let _ = set_server_endpoint(None);
+}
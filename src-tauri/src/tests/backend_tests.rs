@@ -0,0 +1,167 @@
+// Merging change sets, and the sync run against the in-memory backend.
+use crate::backend::{preview_with, sync_with, SyncBackend};
+use crate::http::{SyncData, SyncOptions};
+use crate::task::{compare_and_save, compare_and_save_in, get_saved_list, get_saved_tasks, switch_task_db, ListEntry, TaskEntry};
+use crate::testutils::{open_device, remove_files, test_list, test_task, MemoryBackend};
+
+const DEVICE_A: &str = "testBackendA.db";
+const DEVICE_B: &str = "testBackendB.db";
+/// Edit times in the merge test
+const T: i64 = 1_700_000_000_000;
+
+fn ids(tasks: Option<&Vec<TaskEntry>>) -> Vec<String> {
+    let mut ids: Vec<String> = tasks.map_or(vec![], |t| t.iter().map(|t| t.id.clone()).collect());
+    ids.sort();
+    ids
+}
+
+#[test]
+fn test_compare_and_save() {
+    let mut local = SyncData::new();
+    local.lists = vec![ListEntry { last_edited: Some(T + 2), ..test_list("both", "both") }, ListEntry { last_edited: Some(T + 2), ..test_list("mine", "mine") }];
+    local.tasks.insert("both".to_string(), vec![
+        TaskEntry { last_edited: Some(T + 3), ..test_task("newer_here", "Local", None, 0) },
+        TaskEntry { last_edited: Some(T + 1), ..test_task("newer_there", "Local", None, 0) },
+        test_task("no_time", "Local", None, 0),
+        TaskEntry { last_edited: Some(T + 1), ..test_task("only_here", "Local", None, 0) }
+    ]);
+    local.tasks.insert("mine".to_string(), vec![]);
+    let mut remote = SyncData::new();
+    remote.lists = vec![ListEntry { last_edited: Some(T + 3), ..test_list("both", "both") }, ListEntry { last_edited: Some(T + 1), ..test_list("theirs", "theirs") }, test_list("untimed", "untimed")];
+    remote.tasks.insert("both".to_string(), vec![
+        TaskEntry { last_edited: Some(T + 2), ..test_task("newer_here", "Remote", None, 0) },
+        TaskEntry { last_edited: Some(T + 2), ..test_task("newer_there", "Remote", None, 0) },
+        TaskEntry { last_edited: Some(T + 2), ..test_task("no_time", "Remote", None, 0) },
+        TaskEntry { last_edited: Some(T + 1), ..test_task("only_there", "Remote", None, 0) }
+    ]);
+    remote.tasks.insert("theirs".to_string(), vec![TaskEntry { last_edited: Some(T + 1), ..test_task("new", "Remote", None, 0) }]);
+
+    let merge = compare_and_save(&local, &remote);
+    let mut saved: Vec<String> = merge.save.lists.iter().map(|l| l.uuid.clone()).collect();
+    saved.sort();
+    assert_eq!(saved, vec!["both", "theirs"]);
+    let sent: Vec<String> = merge.send.lists.iter().map(|l| l.uuid.clone()).collect();
+    assert_eq!(sent, vec!["mine"]);
+    assert_eq!(ids(merge.save.tasks.get("both")), vec!["newer_there", "only_there"]);
+    assert_eq!(ids(merge.save.tasks.get("theirs")), vec!["new"]);
+    assert_eq!(ids(merge.send.tasks.get("both")), vec!["newer_here", "only_here"]);
+    // Empty lists aren't sent
+    assert!(!merge.send.tasks.contains_key("mine"));
+
+    // Nothing changed on either side
+    let merge = compare_and_save(&SyncData::new(), &SyncData::new());
+    assert!(merge.save.lists.is_empty() && merge.save.tasks.is_empty());
+    assert!(merge.send.lists.is_empty() && merge.send.tasks.is_empty());
+}
+
+#[tokio::test]
+async fn test_merge_skips_invalid_list_ids() {
+    remove_files(&[DEVICE_A]);
+    let mut db = open_device(DEVICE_A).await;
+    let bad = "x' (id TEXT); DROP TABLE Lists; --";
+    let mut remote = SyncData::new();
+    remote.last_sync = T;
+    remote.lists = vec![ListEntry { last_edited: Some(T), ..test_list(bad, "Bad") }, ListEntry { last_edited: Some(T), ..test_list("good", "Good") }];
+    remote.tasks.insert(bad.to_string(), vec![TaskEntry { last_edited: Some(T), ..test_task("t", "Bad", None, 0) }]);
+    compare_and_save_in(&mut db, &remote).await.unwrap();
+    let lists = db.get_lists().await.unwrap().unwrap();
+    assert_eq!(lists.iter().map(|l| l.uuid.as_str()).collect::<Vec<_>>(), vec!["good"]);
+    db.close().await;
+    remove_files(&[DEVICE_A]);
+}
+
+async fn add_tasks(db: &str, list: &str, count: usize) {
+    let mut tasks = open_device(db).await;
+    tasks.new_list(&test_list(list, list)).await.unwrap();
+    for i in 0..count {
+        tasks.new_task(list.to_string(), &test_task(&format!("task{:02}", i), "Chore", None, 0)).await.unwrap();
+    }
+    tasks.close().await;
+}
+
+#[tokio::test]
+async fn test_memory_backend_sync() {
+    remove_files(&[DEVICE_A, DEVICE_B]);
+    let backend = MemoryBackend::new("paged");
+    let small = |max_pages| SyncOptions { page_size: 5, push_chunk: 4, max_pages };
+
+    // Device A pushes a list with 12 tasks in chunks of 4
+    add_tasks(DEVICE_A, "chores", 12).await;
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert!(sync_with(&backend, &small(None)).await.unwrap());
+    assert_eq!(backend.lists().len(), 1);
+    assert_eq!(backend.tasks("chores").len(), 12);
+
+    // Device B stops after one page, then picks up where it left off
+    switch_task_db(DEVICE_B).await.unwrap();
+    assert!(!sync_with(&backend, &small(Some(1))).await.unwrap());
+    assert_eq!(get_saved_tasks("chores").await.unwrap().len(), 4);
+    assert!(sync_with(&backend, &small(None)).await.unwrap());
+    assert!(get_saved_list("chores").await.unwrap().is_some());
+    assert_eq!(get_saved_tasks("chores").await.unwrap().len(), 12);
+
+    // An edit on B reaches A
+    let mut task = get_saved_tasks("chores").await.unwrap().into_iter().find(|t| t.id == "task03").unwrap();
+    task.name = "Dishes".to_string();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    let mut b = open_device(DEVICE_B).await;
+    b.edit_task("chores".to_string(), &task).await.unwrap();
+    b.close().await;
+    switch_task_db(DEVICE_B).await.unwrap();
+    assert!(sync_with(&backend, &small(None)).await.unwrap());
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert!(sync_with(&backend, &small(None)).await.unwrap());
+    let task = get_saved_tasks("chores").await.unwrap().into_iter().find(|t| t.id == "task03").unwrap();
+    assert_eq!(task.name, "Dishes");
+
+    remove_files(&[DEVICE_A, DEVICE_B]);
+}
+
+#[tokio::test]
+async fn test_memory_backend_unpaged() {
+    remove_files(&[DEVICE_A, DEVICE_B]);
+    let backend = MemoryBackend::unpaged("legacy");
+    add_tasks(DEVICE_A, "errands", 3).await;
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert!(sync_with(&backend, &SyncOptions::default()).await.unwrap());
+    assert_eq!(backend.tasks("errands").len(), 3);
+
+    switch_task_db(DEVICE_B).await.unwrap();
+    assert!(sync_with(&backend, &SyncOptions::default()).await.unwrap());
+    assert!(get_saved_list("errands").await.unwrap().is_some());
+    assert_eq!(get_saved_tasks("errands").await.unwrap().len(), 3);
+
+    remove_files(&[DEVICE_A, DEVICE_B]);
+}
+
+#[tokio::test]
+async fn test_preview_matches_sync() {
+    remove_files(&[DEVICE_A, DEVICE_B]);
+    let backend = MemoryBackend::unpaged("preview");
+    add_tasks(DEVICE_A, "errands", 2).await;
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert!(sync_with(&backend, &SyncOptions::default()).await.unwrap());
+    switch_task_db(DEVICE_B).await.unwrap();
+    assert!(sync_with(&backend, &SyncOptions::default()).await.unwrap());
+
+    // A task without an edit time isn't merged, so it isn't previewed either
+    let mut data = SyncData::new();
+    data.tasks.insert("errands".to_string(), vec![test_task("untimed", "Untimed", None, 0)]);
+    backend.push(&data).await.unwrap();
+    let preview = preview_with(&backend, &SyncOptions::default()).await.unwrap();
+    assert!(preview.local.tasks.created.is_empty() && preview.local.tasks.updated.is_empty());
+    assert!(sync_with(&backend, &SyncOptions::default()).await.unwrap());
+    assert_eq!(get_saved_tasks("errands").await.unwrap().len(), 2);
+
+    remove_files(&[DEVICE_A, DEVICE_B]);
+}
+
+#[tokio::test]
+async fn test_memory_backend_signed_out() {
+    remove_files(&[DEVICE_A, DEVICE_B]);
+    let backend = MemoryBackend::new("signed_out");
+    backend.set_signed_in(false);
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert_eq!(sync_with(&backend, &SyncOptions::default()).await.unwrap_err(), "Not logged in.");
+    remove_files(&[DEVICE_A, DEVICE_B]);
+}
@@ -0,0 +1,107 @@
+// Local backups: verified copies, retention and restoring.
+use std::fs;
+use std::path::Path;
+
+use crate::algorithm::switch_history_db;
+use crate::backup::{backup_in, backups_in, restore_in};
+use crate::history::History;
+use crate::storage::check_db;
+use crate::task::{get_saved_list, switch_task_db};
+use crate::testutils::{open_device, test_list};
+
+const DIR: &str = "testBackup";
+
+/// Fresh task and history databases in `DIR`, opened as the globals.
+async fn setup() {
+    let _ = fs::remove_dir_all(DIR);
+    fs::create_dir_all(DIR).unwrap();
+    let mut tasks = open_device(&format!("{}/tasks.db", DIR)).await;
+    tasks.new_list(&test_list("work", "Work")).await.unwrap();
+    tasks.close().await;
+    let mut history = History::new();
+    history.load(&format!("{}/history2.db", DIR)).await.unwrap();
+    history.close().await;
+    switch_task_db(&format!("{}/tasks.db", DIR)).await.unwrap();
+    switch_history_db(&format!("{}/history2.db", DIR)).await.unwrap();
+}
+
+/// A copy of the backup `from` that looks like it was made at `name`.
+fn copy_backup(from: &str, name: &str) {
+    let folder = format!("{}/backups/{}", DIR, name);
+    fs::create_dir_all(&folder).unwrap();
+    for db in ["tasks.db", "history2.db"] {
+        fs::copy(format!("{}/backups/{}/{}", DIR, from, db), format!("{}/{}", folder, db)).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_backup_and_restore() {
+    let _ = fs::remove_dir_all(DIR);
+    assert!(backup_in(DIR, 7, 4).await.is_err());
+    setup().await;
+    let backup = backup_in(DIR, 7, 4).await.unwrap();
+    assert_eq!(backup.removed, 0);
+    let tables = check_db(&format!("{}/backups/{}/tasks.db", DIR, backup.name)).await.unwrap();
+    assert!(tables.iter().any(|t| t == "Lists"));
+    let backups = backups_in(DIR).unwrap();
+    assert_eq!(backups.len(), 1);
+    assert_eq!(backups[0].name, backup.name);
+    assert!(backups[0].size > 0);
+
+    let mut tasks = open_device(&format!("{}/tasks.db", DIR)).await;
+    tasks.new_list(&test_list("later", "Later")).await.unwrap();
+    tasks.close().await;
+    // A failed swap puts the old databases back
+    fs::create_dir_all(format!("{}/history2.db.pre-restore", DIR)).unwrap();
+    assert!(restore_in(DIR, &backup.name).await.is_err());
+    assert!(get_saved_list("later").await.unwrap().is_some());
+    assert!(!Path::new(&format!("{}/tasks.db.restore", DIR)).exists());
+    fs::remove_dir(format!("{}/history2.db.pre-restore", DIR)).unwrap();
+
+    restore_in(DIR, &backup.name).await.unwrap();
+    assert!(get_saved_list("work").await.unwrap().is_some());
+    assert!(get_saved_list("later").await.unwrap().is_none());
+    assert!(Path::new(&format!("{}/tasks.db.pre-restore", DIR)).exists());
+    assert!(restore_in(DIR, "../tasks.db").await.is_err());
+
+    let _ = fs::remove_dir_all(DIR);
+}
+
+#[tokio::test]
+async fn test_backup_retention() {
+    setup().await;
+    let first = backup_in(DIR, 7, 4).await.unwrap();
+    for day in 1..=5 {
+        copy_backup(&first.name, &format!("202401{:02}T120000Z", day));
+    }
+    // Not backups
+    fs::create_dir_all(format!("{}/backups/notes", DIR)).unwrap();
+    fs::write(format!("{}/backups/20240107T120000Z", DIR), "").unwrap();
+    assert_eq!(backups_in(DIR).unwrap().len(), 6);
+
+    // Backups are named to the second
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    // The newest one today replaces today's others
+    let res = backup_in(DIR, 3, 0).await.unwrap();
+    assert_eq!(res.removed, 4);
+    let names: Vec<String> = backups_in(DIR).unwrap().into_iter().map(|b| b.name).collect();
+    assert_eq!(names, vec![res.name, "20240105T120000Z".to_string(), "20240104T120000Z".to_string()]);
+    assert!(Path::new(&format!("{}/backups/notes", DIR)).exists());
+
+    let _ = fs::remove_dir_all(DIR);
+}
+
+#[tokio::test]
+async fn test_restore_rejects_damaged_backup() {
+    setup().await;
+    let backup = backup_in(DIR, 7, 4).await.unwrap();
+    fs::write(format!("{}/backups/{}/history2.db", DIR, backup.name), "not a database").unwrap();
+
+    assert!(restore_in(DIR, &backup.name).await.unwrap_err().contains("damaged"));
+    // Nothing was replaced
+    assert!(get_saved_list("work").await.unwrap().is_some());
+    assert!(!Path::new(&format!("{}/tasks.db.pre-restore", DIR)).exists());
+    assert!(!Path::new(&format!("{}/tasks.db.restore", DIR)).exists());
+
+    let _ = fs::remove_dir_all(DIR);
+}
@@ -0,0 +1,378 @@
+// CalDAV sync against a small in-process stand-in for a Radicale-style
+// server: one calendar home, calendars as collections, one VTODO per
+// resource, and ctag/etag bumped on every write.
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use axum::{extract::State, http::{HeaderMap, Method, StatusCode as MockStatus, Uri}, response::{IntoResponse, Response}, Router};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use quick_xml::escape::escape;
+
+use crate::caldav::{CalDavClient, CalDavSyncResult};
+use crate::ics::format_utc;
+use crate::task::{switch_task_db, ListEntry, TaskEntry};
+use crate::testutils::{open_device, remove_files, test_list, test_task};
+use crate::utils::now;
+
+const DEVICE_A: &str = "testCalDavA.db";
+const DEVICE_B: &str = "testCalDavB.db";
+const STATE_A: &str = "testCalDavStateA";
+const STATE_B: &str = "testCalDavStateB";
+/// When the test tasks are due
+const DUE: i64 = 1706693400000;
+const HOME: &str = "/dav/alice/";
+
+#[derive(Default)]
+struct MockCalendar {
+    name: String,
+    components: Vec<&'static str>,
+    ctag: u32,
+    /// File name → (etag, data)
+    items: BTreeMap<String, (String, String)>
+}
+
+#[derive(Default)]
+struct MockDav {
+    calendars: BTreeMap<String, MockCalendar>,
+    next_etag: u32,
+    reports: usize,
+    last_report_hrefs: usize
+}
+
+type Dav = Arc<Mutex<MockDav>>;
+
+impl MockDav {
+    fn put(&mut self, calendar: &str, file: &str, data: &str) -> String {
+        self.next_etag += 1;
+        let etag = format!("\"{}\"", self.next_etag);
+        let cal = self.calendars.get_mut(calendar).unwrap();
+        cal.items.insert(file.to_string(), (etag.clone(), data.to_string()));
+        cal.ctag += 1;
+        etag
+    }
+
+    fn item(&self, calendar: &str, uid: &str) -> Option<String> {
+        self.calendars.get(calendar)?.items.get(&format!("{}.ics", uid)).map(|i| i.1.clone())
+    }
+}
+
+fn multistatus(responses: &[String]) -> Response {
+    let body = format!(
+        r#"<?xml version="1.0"?><multistatus xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav" xmlns:CS="http://calendarserver.org/ns/">{}</multistatus>"#,
+        responses.concat()
+    );
+    (MockStatus::MULTI_STATUS, body).into_response()
+}
+
+fn dav_response(href: &str, props: &str) -> String {
+    format!("<response><href>{}</href><propstat><prop>{}</prop><status>HTTP/1.1 200 OK</status></propstat></response>", href, props)
+}
+
+fn between<'a>(text: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let i = text.find(start)? + start.len();
+    Some(&text[i..i + text[i..].find(end)?])
+}
+
+async fn mock_dav(State(dav): State<Dav>, method: Method, uri: Uri, headers: HeaderMap, body: String) -> Response {
+    let auth = format!("Basic {}", STANDARD.encode("alice:secret"));
+    if headers.get("authorization").and_then(|a| a.to_str().ok()) != Some(&auth) {
+        return MockStatus::UNAUTHORIZED.into_response();
+    }
+    let mut dav = dav.lock().unwrap();
+    let path = uri.path().to_string();
+    let rest = match path.strip_prefix(HOME) {
+        Some(rest) => rest.to_string(),
+        None => return MockStatus::NOT_FOUND.into_response()
+    };
+    let mut parts = rest.splitn(2, '/');
+    let calendar = parts.next().unwrap_or_default().to_string();
+    let file = parts.next().unwrap_or_default().to_string();
+    match (method.as_str(), calendar.is_empty(), file.is_empty()) {
+        ("PROPFIND", true, _) => {
+            let mut responses = vec![dav_response(HOME, "<resourcetype><collection/></resourcetype>")];
+            for (id, cal) in &dav.calendars {
+                let comps: String = cal.components.iter().map(|c| format!(r#"<C:comp name="{}"/>"#, c)).collect();
+                responses.push(dav_response(&format!("{}{}/", HOME, id), &format!(
+                    "<resourcetype><collection/><C:calendar/></resourcetype><displayname>{}</displayname><CS:getctag>{}</CS:getctag><C:supported-calendar-component-set>{}</C:supported-calendar-component-set>",
+                    escape(cal.name.as_str()), cal.ctag, comps
+                )));
+            }
+            multistatus(&responses)
+        },
+        ("PROPFIND", false, true) => {
+            let cal = match dav.calendars.get(&calendar) {
+                Some(cal) => cal,
+                None => return MockStatus::NOT_FOUND.into_response()
+            };
+            let mut responses = vec![dav_response(&path, "<resourcetype><collection/><C:calendar/></resourcetype>")];
+            for (file, (etag, _)) in &cal.items {
+                responses.push(dav_response(&format!("{}{}", path, file), &format!("<resourcetype/><getetag>{}</getetag>", escape(etag.as_str()))));
+            }
+            multistatus(&responses)
+        },
+        ("REPORT", false, true) => {
+            dav.reports += 1;
+            let hrefs: Vec<String> = body.split("<d:href>").skip(1).filter_map(|h| h.split("</d:href>").next()).map(|h| h.to_string()).collect();
+            dav.last_report_hrefs = hrefs.len();
+            let cal = dav.calendars.get(&calendar).unwrap();
+            let responses: Vec<String> = hrefs.iter()
+                .filter_map(|href| {
+                    let (etag, data) = cal.items.get(href.rsplit('/').next()?)?;
+                    Some(dav_response(href, &format!("<getetag>{}</getetag><C:calendar-data>{}</C:calendar-data>", escape(etag.as_str()), escape(data.as_str()))))
+                })
+                .collect();
+            multistatus(&responses)
+        },
+        ("MKCALENDAR", false, true) => {
+            if dav.calendars.contains_key(&calendar) {
+                return MockStatus::METHOD_NOT_ALLOWED.into_response();
+            }
+            let name = between(&body, "<d:displayname>", "</d:displayname>").unwrap_or_default().to_string();
+            dav.calendars.insert(calendar, MockCalendar { name, components: vec!["VTODO"], ..Default::default() });
+            MockStatus::CREATED.into_response()
+        },
+        ("PROPPATCH", false, true) => {
+            let cal = dav.calendars.get_mut(&calendar).unwrap();
+            cal.name = between(&body, "<d:displayname>", "</d:displayname>").unwrap_or_default().to_string();
+            cal.ctag += 1;
+            multistatus(&[dav_response(&path, "<displayname/>")])
+        },
+        ("PUT", false, false) => {
+            let existing = match dav.calendars.get(&calendar) {
+                Some(cal) => cal.items.get(&file).map(|i| i.0.clone()),
+                None => return MockStatus::CONFLICT.into_response()
+            };
+            let if_match = headers.get("if-match").and_then(|h| h.to_str().ok());
+            let if_none_match = headers.get("if-none-match").is_some();
+            if (if_none_match && existing.is_some()) || if_match.is_some_and(|m| Some(m) != existing.as_deref()) {
+                return MockStatus::PRECONDITION_FAILED.into_response();
+            }
+            let etag = dav.put(&calendar, &file, &body);
+            (MockStatus::CREATED, [("etag", etag)]).into_response()
+        },
+        ("DELETE", false, true) => {
+            match dav.calendars.remove(&calendar) {
+                Some(_) => MockStatus::NO_CONTENT.into_response(),
+                None => MockStatus::NOT_FOUND.into_response()
+            }
+        },
+        ("DELETE", false, false) => {
+            let cal = match dav.calendars.get_mut(&calendar) {
+                Some(cal) => cal,
+                None => return MockStatus::NOT_FOUND.into_response()
+            };
+            let if_match = headers.get("if-match").and_then(|h| h.to_str().ok());
+            if if_match.is_some_and(|m| Some(m) != cal.items.get(&file).map(|i| i.0.as_str())) {
+                return MockStatus::PRECONDITION_FAILED.into_response();
+            }
+            cal.items.remove(&file);
+            cal.ctag += 1;
+            MockStatus::NO_CONTENT.into_response()
+        },
+        _ => MockStatus::METHOD_NOT_ALLOWED.into_response()
+    }
+}
+
+async fn start_mock() -> (Dav, String) {
+    let dav: Dav = Arc::new(Mutex::new(MockDav::default()));
+    let app = Router::new().fallback(mock_dav).with_state(dav.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}{}", listener.local_addr().unwrap(), HOME);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (dav, url)
+}
+
+fn reset() {
+    remove_files(&[DEVICE_A, DEVICE_B]);
+    for dir in [STATE_A, STATE_B] {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+    }
+}
+
+async fn get_task(path: &str, list: &str, id: &str) -> Option<TaskEntry> {
+    let mut db = open_device(path).await;
+    let task = db.get_task(list.to_string(), id.to_string()).await.unwrap();
+    db.close().await;
+    task
+}
+
+async fn get_list(path: &str, uuid: &str) -> Option<ListEntry> {
+    let mut db = open_device(path).await;
+    let list = db.get_list(uuid.to_string()).await.unwrap();
+    db.close().await;
+    list
+}
+
+async fn sync(db: &str, state: &str, url: &str) -> CalDavSyncResult {
+    switch_task_db(db).await.unwrap();
+    CalDavClient::new(url, "alice", "secret", state).unwrap().sync().await.unwrap()
+}
+
+fn result(pulled: usize, pushed: usize, deleted: usize) -> CalDavSyncResult {
+    CalDavSyncResult { pulled, pushed, deleted }
+}
+
+fn vtodo(uid: &str, summary: &str, modified: i64) -> String {
+    format!(
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Other//EN\r\nBEGIN:VTODO\r\nUID:{}\r\nSUMMARY:{}\r\nLAST-MODIFIED:{}\r\nPRIORITY:1\r\nEND:VTODO\r\nEND:VCALENDAR\r\n",
+        uid, summary, format_utc(modified)
+    )
+}
+
+async fn wait() {
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+}
+
+#[tokio::test]
+async fn test_caldav_push_and_pull() {
+    reset();
+    let (dav, url) = start_mock().await;
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("work", "Work")).await.unwrap();
+    a.new_task("work".to_string(), &test_task("t0", "Project", None, DUE)).await.unwrap();
+    let mut sub = test_task("t1", "Report", Some("t0"), DUE);
+    sub.importance = 4;
+    sub.completed = true;
+    a.new_task("work".to_string(), &sub).await.unwrap();
+    a.close().await;
+
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 3, 0));
+    {
+        let dav = dav.lock().unwrap();
+        assert_eq!(dav.calendars["work"].name, "Work");
+        let data = dav.item("work", "t1").unwrap();
+        assert!(data.contains("RELATED-TO;RELTYPE=PARENT:t0\r\n"));
+        assert!(data.contains("PRIORITY:1\r\n"));
+        assert!(data.contains("DUE:20240131T093000Z\r\n"));
+        assert!(data.contains("STATUS:COMPLETED\r\n"));
+    }
+    // Unchanged ctags aren't looked into
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 0, 0));
+    assert_eq!(dav.lock().unwrap().reports, 0);
+
+    // Another device gets everything, and sends none of it back
+    assert_eq!(sync(DEVICE_B, STATE_B, &url).await, result(3, 0, 0));
+    let t1 = get_task(DEVICE_B, "work", "t1").await.unwrap();
+    assert_eq!(t1.parent.as_deref(), Some("t0"));
+    assert_eq!(t1.importance, 4);
+    assert!(t1.completed);
+    assert_eq!(get_list(DEVICE_B, "work").await.unwrap().name, "Work");
+
+    // Edited by another client: only that resource is fetched
+    {
+        let mut dav = dav.lock().unwrap();
+        dav.put("work", "t0.ics", &vtodo("t0", "Project v2", now() + 2000));
+    }
+    let reports = dav.lock().unwrap().reports;
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(1, 0, 0));
+    assert_eq!(dav.lock().unwrap().reports, reports + 1);
+    assert_eq!(dav.lock().unwrap().last_report_hrefs, 1);
+    assert_eq!(get_task(DEVICE_A, "work", "t0").await.unwrap().name, "Project v2");
+
+    // Renamed here
+    wait().await;
+    let mut a = open_device(DEVICE_A).await;
+    a.edit_list(&test_list("work", "Office")).await.unwrap();
+    a.close().await;
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 1, 0));
+    assert_eq!(dav.lock().unwrap().calendars["work"].name, "Office");
+
+    // Created by another client. Calendars without tasks are left alone.
+    {
+        let mut dav = dav.lock().unwrap();
+        dav.calendars.insert("home".to_string(), MockCalendar { name: "Home".to_string(), components: vec!["VTODO"], ..Default::default() });
+        dav.calendars.insert("events".to_string(), MockCalendar { name: "Events".to_string(), components: vec!["VEVENT"], ..Default::default() });
+        dav.put("home", "x1.ics", &vtodo("x1", "Groceries", 1706690000000));
+    }
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(2, 0, 0));
+    assert_eq!(get_list(DEVICE_A, "home").await.unwrap().name, "Home");
+    assert_eq!(get_task(DEVICE_A, "home", "x1").await.unwrap().importance, 4);
+    assert!(get_list(DEVICE_A, "events").await.is_none());
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 0, 0));
+
+    reset();
+}
+
+#[tokio::test]
+async fn test_caldav_deletions() {
+    reset();
+    let (dav, url) = start_mock().await;
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("work", "Work")).await.unwrap();
+    a.new_list(&test_list("old", "Old")).await.unwrap();
+    a.new_task("work".to_string(), &test_task("t1", "One", None, DUE)).await.unwrap();
+    a.new_task("work".to_string(), &test_task("t2", "Two", None, DUE)).await.unwrap();
+    a.close().await;
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 4, 0));
+
+    // Deleted here
+    let mut a = open_device(DEVICE_A).await;
+    a.delete_task("work".to_string(), "t2".to_string()).await.unwrap();
+    a.delete_list("old".to_string()).await.unwrap();
+    a.close().await;
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 0, 2));
+    assert!(dav.lock().unwrap().item("work", "t2").is_none());
+    assert!(!dav.lock().unwrap().calendars.contains_key("old"));
+
+    // Deleted by another client
+    {
+        let mut dav = dav.lock().unwrap();
+        let cal = dav.calendars.get_mut("work").unwrap();
+        cal.items.remove("t1.ics");
+        cal.ctag += 1;
+    }
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 0, 1));
+    assert!(get_task(DEVICE_A, "work", "t1").await.is_none());
+
+    dav.lock().unwrap().calendars.remove("work");
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 0, 1));
+    assert!(get_list(DEVICE_A, "work").await.is_none());
+
+    reset();
+}
+
+#[tokio::test]
+async fn test_caldav_retries_precondition_failed() {
+    reset();
+    let (dav, url) = start_mock().await;
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("work", "Work")).await.unwrap();
+    a.new_task("work".to_string(), &test_task("t1", "One", None, DUE)).await.unwrap();
+    a.close().await;
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 2, 0));
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 0, 0));
+
+    // Another client writes between the pull and the push
+    let edited = now();
+    wait().await;
+    let mut a = open_device(DEVICE_A).await;
+    a.edit_task("work".to_string(), &test_task("t1", "Mine", None, DUE)).await.unwrap();
+    a.close().await;
+    {
+        let mut dav = dav.lock().unwrap();
+        let cal = dav.calendars.get_mut("work").unwrap();
+        cal.items.insert("t1.ics".to_string(), ("\"other\"".to_string(), vtodo("t1", "Theirs", edited)));
+    }
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 0, 0));
+
+    // Once the change shows up the newer edit here still goes up
+    dav.lock().unwrap().calendars.get_mut("work").unwrap().ctag += 1;
+    assert_eq!(sync(DEVICE_A, STATE_A, &url).await, result(0, 1, 0));
+    assert!(dav.lock().unwrap().item("work", "t1").unwrap().contains("SUMMARY:Mine"));
+    assert_eq!(get_task(DEVICE_A, "work", "t1").await.unwrap().name, "Mine");
+
+    reset();
+}
+
+#[tokio::test]
+async fn test_caldav_wrong_password() {
+    reset();
+    let (_, url) = start_mock().await;
+    switch_task_db(DEVICE_A).await.unwrap();
+    let client = CalDavClient::new(&url, "alice", "wrong", STATE_A).unwrap();
+    assert!(client.sync().await.unwrap_err().contains("password"));
+    assert!(CalDavClient::new("ftp://example.com", "alice", "secret", STATE_A).is_err());
+    reset();
+}
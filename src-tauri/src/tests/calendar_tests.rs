@@ -0,0 +1,151 @@
+// .ics export and import.
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::calendar::{export_ics, import_ics_file, read_calendar};
+use crate::task::{get_saved_list, get_saved_lists, get_saved_tasks, load_records, switch_task_db, TaskEntry, TaskRecord};
+use crate::testutils::{open_device, remove_files, test_list, test_task};
+
+const DEVICE_A: &str = "testCalendarA.db";
+const DEVICE_B: &str = "testCalendarB.db";
+const FILE: &str = "testCalendar.ics";
+/// When the test tasks are due
+const DUE: i64 = 1_717_200_000_000;
+
+/// The tree `load_records` builds, with subtasks in a fixed order.
+fn tree(tasks: Vec<TaskEntry>) -> serde_json::Value {
+    fn sort(records: &mut Vec<TaskRecord>) {
+        records.sort_by(|a, b| a.id.cmp(&b.id));
+        for r in records {
+            sort(&mut r.subtasks);
+        }
+    }
+    let mut records = load_records(&tasks);
+    sort(&mut records);
+    serde_json::to_value(&records).unwrap()
+}
+
+#[tokio::test]
+async fn test_ics_round_trip() {
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("school", "School; Spring")).await.unwrap();
+    a.new_task("school".to_string(), &test_task("essay", "Essay, draft 1", None, DUE)).await.unwrap();
+    a.new_task("school".to_string(), &test_task("outline", "Outline", Some("essay"), DUE)).await.unwrap();
+    a.new_task("school".to_string(), &test_task("sources", "Find sources", Some("outline"), DUE)).await.unwrap();
+    let mut done = test_task("quiz", "Quiz\nChapter 4", None, DUE);
+    done.completed = true;
+    done.importance = 4;
+    done.size = 0;
+    a.new_task("school".to_string(), &done).await.unwrap();
+    a.new_list(&test_list("home", "Home")).await.unwrap();
+    a.new_task("home".to_string(), &test_task("dishes", "Dishes", None, DUE)).await.unwrap();
+    a.close().await;
+
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert_eq!(export_ics(vec!["school".to_string()], FILE.to_string()).await.unwrap(), 4);
+    let exported = get_saved_tasks("school").await.unwrap();
+    let text = fs::read_to_string(FILE).unwrap();
+    assert!(text.contains("RELATED-TO;RELTYPE=PARENT:outline"));
+    assert!(!text.contains("Dishes"));
+
+    switch_task_db(DEVICE_B).await.unwrap();
+    let res = import_ics_file(FILE).await.unwrap();
+    assert_eq!((res.lists, res.tasks), (1, 4));
+    assert!(res.unsupported.is_empty(), "{:?}", res.unsupported);
+    let list = get_saved_list("school").await.unwrap().unwrap();
+    assert_eq!(list.name, "School; Spring");
+    assert_eq!(list.color, 2);
+    assert_eq!(tree(get_saved_tasks("school").await.unwrap()), tree(exported.clone()));
+
+    // Importing again makes a copy instead of touching the first one
+    let res = import_ics_file(FILE).await.unwrap();
+    assert_eq!(res.tasks, 4);
+    let lists = get_saved_lists().await.unwrap();
+    assert_eq!(lists.len(), 2);
+    let copy = lists.iter().find(|l| l.uuid != "school").unwrap();
+    assert_eq!(tree(get_saved_tasks(&copy.uuid).await.unwrap()), tree(exported));
+
+    // Every list when none are picked
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert_eq!(export_ics(vec![], FILE.to_string()).await.unwrap(), 5);
+    assert!(export_ics(vec!["missing".to_string()], FILE.to_string()).await.is_err());
+
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+}
+
+const FOREIGN: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Example//Calendar//EN\r
+X-WR-CALNAME:Term\r
+BEGIN:VTIMEZONE\r
+TZID:Europe/Berlin\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+UID:exam@example.com\r
+SUMMARY:Final exam\r
+DTSTART;VALUE=DATE:20240612\r
+DESCRIPTION:Room 101\r
+STATUS:CONFIRMED\r
+BEGIN:VALARM\r
+ACTION:DISPLAY\r
+TRIGGER:-PT1H\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VTODO\r
+SUMMARY:No uid\r
+DUE:20240601T090000Z\r
+RRULE:FREQ=WEEKLY\r
+END:VTODO\r
+BEGIN:VTODO\r
+UID:orphan\r
+SUMMARY:Orphan\r
+RELATED-TO:gone\r
+END:VTODO\r
+BEGIN:VTODO\r
+UID:loop-a\r
+SUMMARY:Loop A\r
+RELATED-TO:loop-b\r
+END:VTODO\r
+BEGIN:VTODO\r
+UID:loop-b\r
+SUMMARY:Loop B\r
+RELATED-TO:loop-a\r
+END:VTODO\r
+BEGIN:VJOURNAL\r
+UID:notes\r
+END:VJOURNAL\r
+END:VCALENDAR\r
+";
+
+#[test]
+fn test_read_foreign_calendar() {
+    let mut unsupported = BTreeMap::new();
+    let lists = read_calendar(FOREIGN, "fallback", &mut unsupported).unwrap();
+    assert_eq!(lists.len(), 1);
+    assert_eq!(lists[0].list.name, "Term");
+    let tasks = &lists[0].tasks;
+    assert_eq!(tasks.len(), 5);
+    let exam = tasks.iter().find(|t| t.id == "exam@example.com").unwrap();
+    assert_eq!(exam.name, "Final exam");
+    assert_eq!(exam.due, 1_718_150_400_000);
+    assert!(!exam.completed);
+    let no_uid = tasks.iter().find(|t| t.name == "No uid").unwrap();
+    assert!(!no_uid.id.is_empty());
+    assert_eq!(tasks.iter().find(|t| t.id == "orphan").unwrap().parent, None);
+    // One side of the loop is cut, so the tasks still make a tree
+    let parents: Vec<Option<String>> = tasks.iter().filter(|t| t.id.starts_with("loop")).map(|t| t.parent.clone()).collect();
+    assert_eq!(parents.iter().filter(|p| p.is_none()).count(), 1);
+    assert_eq!(load_records(tasks).len(), 4);
+
+    let expected: BTreeMap<String, usize> = [
+        ("VEVENT DESCRIPTION", 1), ("VEVENT VALARM", 1), ("VTODO RRULE", 1), ("VJOURNAL", 1)
+    ].into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+    assert_eq!(unsupported, expected);
+
+    // Without a calendar name the list is named after the file
+    let mut unsupported = BTreeMap::new();
+    let lists = read_calendar(&FOREIGN.replace("X-WR-CALNAME:Term\r\n", ""), "fallback", &mut unsupported).unwrap();
+    assert_eq!(lists[0].list.name, "fallback");
+    assert!(read_calendar("BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\n", "x", &mut unsupported).is_err());
+}
@@ -0,0 +1,132 @@
+use crate::crypto::*;
+use crate::http::SyncData;
+use crate::task::{ListEntry, TaskEntry};
+use crate::testutils::{test_list, test_task};
+
+// Cheap Argon2 settings so the tests run quickly
+fn test_kdf() -> KdfParams {
+    KdfParams::with_costs(64, 1, 1)
+}
+
+fn secret_list() -> ListEntry {
+    ListEntry {
+        color: 3,
+        last_edited: Some(1718000000000),
+        created: Some(1717000000000),
+        ..test_list("6f1c2a9e-0d5b-4c37-9a51-2b7c8e4d1f00", "Secret plans")
+    }
+}
+
+fn secret_task(id: &str) -> TaskEntry {
+    TaskEntry {
+        importance: 4,
+        completed: true,
+        last_edited: Some(1718000000400),
+        created: Some(1717000000100),
+        ..test_task(id, "Hide the cake", Some("p1"), 1718100000000)
+    }
+}
+
+#[test]
+fn test_seal_and_open_sync_data() {
+    let keyring = Keyring::generate();
+    let list = secret_list();
+    let mut data = SyncData::new();
+    data.lists.push(list.clone());
+    data.tasks.insert(list.uuid.clone(), vec![secret_task("t1")]);
+
+    keyring.seal_sync_data(&mut data);
+    let json = serde_json::to_string(&data).unwrap();
+    assert!(!json.contains("Secret plans"));
+    assert!(!json.contains("Hide the cake"));
+    // Ids, parents and timestamps stay visible for merging
+    let task = &data.tasks.get(&list.uuid).unwrap()[0];
+    assert_eq!(task.id, "t1");
+    assert_eq!(task.parent, Some("p1".to_string()));
+    assert_eq!(task.last_edited, Some(1718000000400));
+    assert!(task.sealed.as_ref().unwrap().starts_with("v1.1."));
+
+    keyring.open_sync_data(&mut data).unwrap();
+    assert_eq!(data.lists[0].name, "Secret plans");
+    assert_eq!(data.lists[0].color, 3);
+    let task = &data.tasks.get(&list.uuid).unwrap()[0];
+    assert_eq!(task.name, "Hide the cake");
+    assert_eq!((task.size, task.importance, task.due, task.completed), (2, 4, 1718100000000, true));
+    assert!(task.sealed.is_none());
+}
+
+#[test]
+fn test_sealed_content_is_bound_to_its_item() {
+    let keyring = Keyring::generate();
+    let mut task = secret_task("t1");
+    keyring.seal_task("list-a", &mut task);
+
+    // Moved to another task or list by the server
+    let mut moved = task.clone();
+    moved.id = "t2".to_string();
+    assert!(keyring.open_task("list-a", &mut moved).is_err());
+    assert!(keyring.open_task("list-b", &mut task.clone()).is_err());
+
+    // Tampered with
+    let mut tampered = task.clone();
+    let mut chars: Vec<char> = tampered.sealed.clone().unwrap().chars().collect();
+    let i = chars.len() - 5;
+    chars[i] = if chars[i] == 'A' { 'B' } else { 'A' };
+    tampered.sealed = Some(chars.into_iter().collect());
+    assert!(keyring.open_task("list-a", &mut tampered).is_err());
+
+    // Different keyring
+    assert!(Keyring::generate().open_task("list-a", &mut task.clone()).is_err());
+    assert!(keyring.open_task("list-a", &mut task).is_ok());
+}
+
+#[test]
+fn test_rotation_keeps_old_keys() {
+    let mut keyring = Keyring::generate();
+    let mut old = secret_task("t1");
+    keyring.seal_task("list", &mut old);
+
+    assert_eq!(keyring.rotate(), 2);
+    assert_eq!(keyring.current_key_id(), 2);
+    let mut new = secret_task("t2");
+    keyring.seal_task("list", &mut new);
+    assert!(new.sealed.as_ref().unwrap().starts_with("v1.2."));
+
+    // Survives a lock/unlock round trip
+    let keyring = keyring.lock("correct horse", test_kdf()).unwrap().unlock("correct horse").unwrap();
+    assert!(keyring.open_task("list", &mut old).is_ok());
+    assert!(keyring.open_task("list", &mut new).is_ok());
+}
+
+#[test]
+fn test_keyring_blob_unlock() {
+    let keyring = Keyring::generate();
+    let blob = keyring.lock("correct horse", test_kdf()).unwrap();
+    let json = serde_json::to_string(&blob).unwrap();
+    assert!(!json.contains("correct horse"));
+
+    let blob: KeyringBlob = serde_json::from_str(&json).unwrap();
+    assert!(blob.unlock("wrong horse").is_err());
+    let unlocked = blob.unlock("correct horse").unwrap();
+    assert_eq!(unlocked.recovery_phrase(), keyring.recovery_phrase());
+}
+
+#[test]
+fn test_recovery_phrase() {
+    let keyring = Keyring::generate();
+    let blob = keyring.lock("correct horse", test_kdf()).unwrap();
+    let phrase = keyring.recovery_phrase();
+    assert_eq!(phrase.split('-').count(), 11);
+
+    assert!(blob.recover(&phrase).is_ok());
+    // Forgiving about case, spacing and look-alike characters
+    let sloppy = phrase.to_lowercase().replace('-', " ").replace('0', "o").replace('1', "l");
+    assert!(blob.recover(&sloppy).is_ok());
+
+    // A typo fails the checksum rather than producing the wrong key
+    let first = phrase.chars().next().unwrap();
+    let typo = (if first == 'A' { 'B' } else { 'A' }).to_string() + &phrase[1..];
+    assert_eq!(parse_recovery_phrase(&typo).unwrap_err(), "The recovery phrase has a typo in it.");
+    assert!(parse_recovery_phrase("not a phrase").is_err());
+    assert!(Keyring::generate().lock("x", test_kdf()).unwrap().recover(&phrase).is_err());
+}
@@ -0,0 +1,99 @@
+// The subscribable due date feed.
+use reqwest::Url;
+
+use crate::feed::{feed_calendar, listen, FeedFilter};
+use crate::ics::{self, parse_datetime};
+use crate::task::{switch_task_db, ListEntry, TaskEntry};
+use crate::testutils::{open_device, remove_files, test_list, test_task};
+
+const DB: &str = "testFeed.db";
+const TOKEN: &str = "s3cret-token_123";
+
+fn school() -> Vec<(ListEntry, Vec<TaskEntry>)> {
+    vec![
+        (test_list("school", "School"), vec![
+            test_task("essay", "Essay #English", None, parse_datetime("20240610T230000Z").unwrap()),
+            test_task("quiz", "Quiz #math", None, parse_datetime("20240611T090000Z").unwrap()),
+            TaskEntry { completed: true, ..test_task("done", "Done #math", None, parse_datetime("20240612T090000Z").unwrap()) }
+        ]),
+        (test_list("home", "Home"), vec![
+            test_task("dishes", "Dishes", None, parse_datetime("20240610T180000Z").unwrap())
+        ])
+    ]
+}
+
+fn summaries(cal: &ics::Component) -> Vec<String> {
+    let mut names: Vec<String> = cal.find_all("VEVENT").iter().filter_map(|e| e.text("SUMMARY")).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_task_tags() {
+    let task = test_task("t", "Read ch. 4 #Reading #exam-prep, #reading #", None, parse_datetime("20240101").unwrap());
+    assert_eq!(task.tags(), vec!["reading", "exam-prep"]);
+    assert!(test_task("t", "No tags here", None, parse_datetime("20240101").unwrap()).tags().is_empty());
+}
+
+#[test]
+fn test_feed_calendar() {
+    let all = feed_calendar(&school(), &FeedFilter::default(), 0);
+    assert_eq!(summaries(&all), vec!["Dishes", "Essay #English", "Quiz #math"]);
+    let quiz = all.find_all("VEVENT").into_iter().find(|e| e.value("UID") == Some("quiz@school")).unwrap();
+    assert_eq!(quiz.value("DTSTART"), Some("20240611T090000Z"));
+    assert!(quiz.components.is_empty());
+
+    let url = Url::parse("http://localhost/feed/x.ics?list=school&tag=%23English&tag=math&allday=1&alarm=30").unwrap();
+    let filter = FeedFilter::from_query(&url);
+    assert_eq!(filter, FeedFilter {
+        lists: vec!["school".to_string()],
+        tags: vec!["english".to_string(), "math".to_string()],
+        all_day: true,
+        alarm: Some(30)
+    });
+    // Two hours east of UTC, 23:00 UTC is already the next day
+    let cal = feed_calendar(&school(), &filter, 120);
+    assert_eq!(summaries(&cal), vec!["Essay #English", "Quiz #math"]);
+    let essay = cal.find_all("VEVENT").into_iter().find(|e| e.value("UID") == Some("essay@school")).unwrap();
+    assert_eq!(essay.value("DTSTART"), Some("20240611"));
+    assert_eq!(essay.value("DTEND"), Some("20240612"));
+    assert_eq!(essay.components[0].value("TRIGGER"), Some("-PT30M"));
+
+    let only_home = FeedFilter { tags: vec!["math".to_string()], lists: vec!["home".to_string()], ..Default::default() };
+    assert!(summaries(&feed_calendar(&school(), &only_home, 0)).is_empty());
+}
+
+#[tokio::test]
+async fn test_feed_server() {
+    remove_files(&[DB]);
+    let mut db = open_device(DB).await;
+    for (list, tasks) in school() {
+        db.new_list(&list).await.unwrap();
+        for task in tasks {
+            db.new_task(list.uuid.clone(), &task).await.unwrap();
+        }
+    }
+    db.close().await;
+    switch_task_db(DB).await.unwrap();
+    let (server, addr) = listen("127.0.0.1:0", TOKEN, 0).await.unwrap();
+    let base = format!("http://{}/feed/", addr);
+    let client = reqwest::Client::new();
+
+    let res = client.get(format!("{}{}.ics?tag=math", base, TOKEN)).send().await.unwrap();
+    assert_eq!(res.status(), 200);
+    assert!(res.headers()["content-type"].to_str().unwrap().starts_with("text/calendar"));
+    let cal = ics::parse(&res.text().await.unwrap()).unwrap().remove(0);
+    assert_eq!(summaries(&cal), vec!["Quiz #math"]);
+
+    for bad in ["wrong.ics", "s3cret-token_12.ics", "", "s3cret-token_123"] {
+        let res = client.get(format!("{}{}", base, bad)).send().await.unwrap();
+        assert_eq!(res.status(), 404, "{}", bad);
+    }
+    let res = client.post(format!("{}{}.ics", base, TOKEN)).send().await.unwrap();
+    assert_eq!(res.status(), 405);
+    let res = client.head(format!("{}{}.ics", base, TOKEN)).send().await.unwrap();
+    assert_eq!(res.status(), 200);
+
+    server.abort();
+    remove_files(&[DB]);
+}
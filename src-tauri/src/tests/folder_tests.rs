@@ -0,0 +1,113 @@
+// Shared-folder sync between two "devices" (task databases) in one process.
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use crate::folder::{sync_folder_with, FolderSyncResult};
+use crate::task::switch_task_db;
+use crate::testutils::{open_device, remove_files, test_list, test_task};
+use crate::utils::now;
+
+const FOLDER: &str = "testSyncFolder";
+const DEVICE_A: &str = "testFolderA.db";
+const DEVICE_B: &str = "testFolderB.db";
+
+fn reset() {
+    let _ = fs::remove_dir_all(FOLDER);
+    fs::create_dir_all(FOLDER).unwrap();
+    remove_files(&[DEVICE_A, DEVICE_B]);
+}
+
+fn log(device: &str) -> String {
+    format!("{}/taskmgr-sync/{}.jsonl", FOLDER, device)
+}
+
+fn append(path: &str, text: &str) {
+    let mut file = OpenOptions::new().create(true).append(true).open(path).unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+}
+
+async fn task_name(path: &str, list: &str, id: &str) -> Option<String> {
+    let mut db = open_device(path).await;
+    let task = db.get_task(list.to_string(), id.to_string()).await.unwrap();
+    db.close().await;
+    task.map(|t| t.name)
+}
+
+async fn sync(db: &str, device: &str) -> FolderSyncResult {
+    switch_task_db(db).await.unwrap();
+    sync_folder_with(FOLDER, device).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_folder_sync() {
+    reset();
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("work", "Work")).await.unwrap();
+    a.new_task("work".to_string(), &test_task("t1", "Report", None, now())).await.unwrap();
+    a.close().await;
+
+    assert_eq!(sync(DEVICE_A, "a").await, FolderSyncResult { imported: 0, exported: 2 });
+    assert_eq!(fs::read_to_string(log("a")).unwrap().lines().count(), 2);
+    // Nothing new, nothing written
+    assert_eq!(sync(DEVICE_A, "a").await, FolderSyncResult { imported: 0, exported: 0 });
+
+    // What B reads isn't written back to B's log
+    assert_eq!(sync(DEVICE_B, "b").await, FolderSyncResult { imported: 2, exported: 0 });
+    assert_eq!(task_name(DEVICE_B, "work", "t1").await.as_deref(), Some("Report"));
+    assert!(!fs::metadata(log("b")).is_ok_and(|m| m.len() > 0));
+
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    let mut b = open_device(DEVICE_B).await;
+    b.edit_task("work".to_string(), &test_task("t1", "Report v2", None, now())).await.unwrap();
+    b.close().await;
+    assert_eq!(sync(DEVICE_B, "b").await.exported, 1);
+    assert_eq!(sync(DEVICE_A, "a").await, FolderSyncResult { imported: 1, exported: 0 });
+    assert_eq!(task_name(DEVICE_A, "work", "t1").await.as_deref(), Some("Report v2"));
+
+    reset();
+}
+
+#[tokio::test]
+async fn test_folder_sync_partial_and_duplicate_logs() {
+    reset();
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("work", "Work")).await.unwrap();
+    a.close().await;
+    sync(DEVICE_A, "a").await;
+    sync(DEVICE_B, "b").await;
+
+    // A line that's still arriving is left for later
+    let line = format!(
+        r#"{{"device":"a","time":{},"op":"task","list":"work","task":{{"name":"Late","size":1,"importance":1,"due":0,"completed":false,"id":"t2","parent":null,"created":1,"last_edited":{}}}}}"#,
+        now(), now()
+    );
+    let (start, end) = line.split_at(40);
+    append(&log("a"), start);
+    assert_eq!(sync(DEVICE_B, "b").await.imported, 0);
+    append(&log("a"), &format!("{}\n", end));
+    assert_eq!(sync(DEVICE_B, "b").await.imported, 1);
+    assert_eq!(task_name(DEVICE_B, "work", "t2").await.as_deref(), Some("Late"));
+
+    // Broken lines are skipped
+    append(&log("a"), "not json\n");
+    assert_eq!(sync(DEVICE_B, "b").await.imported, 0);
+
+    // Conflict copies and replaced logs can repeat old edits, which never
+    // win over newer ones
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    let mut b = open_device(DEVICE_B).await;
+    b.edit_task("work".to_string(), &test_task("t2", "Late v2", None, now())).await.unwrap();
+    b.close().await;
+    fs::copy(log("a"), format!("{}/taskmgr-sync/a.sync-conflict-1.jsonl", FOLDER)).unwrap();
+    fs::write(log("a"), format!("{}\n", line)).unwrap();
+    sync(DEVICE_B, "b").await;
+    assert_eq!(task_name(DEVICE_B, "work", "t2").await.as_deref(), Some("Late v2"));
+
+    // A copy of a device's own log is ignored by that device
+    fs::copy(log("b"), format!("{}/taskmgr-sync/b (copy).jsonl", FOLDER)).unwrap();
+    assert_eq!(sync(DEVICE_B, "b").await.imported, 0);
+    assert_eq!(sync(DEVICE_A, "a").await.imported, 1);
+    assert_eq!(task_name(DEVICE_A, "work", "t2").await.as_deref(), Some("Late v2"));
+
+    reset();
+}
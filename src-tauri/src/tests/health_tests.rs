@@ -0,0 +1,129 @@
+// Finding and repairing damage in the task database.
+use std::collections::BTreeMap;
+
+use crate::health::{check_in, DbIssue, IssueKind};
+use crate::ics::parse_datetime;
+use crate::task::{fix_parents, load_records, parent_problems, TaskRecord};
+use crate::testutils::{dated_task, find_task, open_device, remove_files, test_list};
+
+const DEVICE: &str = "testHealth.db";
+
+fn count_records(records: &[TaskRecord]) -> usize {
+    records.iter().map(|r| 1 + count_records(&r.subtasks)).sum()
+}
+
+fn kinds(issues: &[DbIssue]) -> BTreeMap<String, usize> {
+    let mut kinds = BTreeMap::new();
+    for issue in issues {
+        *kinds.entry(format!("{:?}", issue.kind)).or_insert(0) += 1;
+    }
+    kinds
+}
+
+#[test]
+fn test_parent_problems() {
+    let tasks = vec![
+        dated_task("a", "a", None),
+        dated_task("b", "b", Some("a")),
+        dated_task("c", "c", Some("gone")),
+        dated_task("d", "d", Some("c")),
+        dated_task("e", "e", Some("e")),
+        // f → g → h → g, with f hanging off the loop
+        dated_task("f", "f", Some("g")),
+        dated_task("h", "h", Some("g")),
+        dated_task("g", "g", Some("h"))
+    ];
+    let problems = parent_problems(&tasks);
+    assert_eq!(problems.orphans, vec!["c"]);
+    assert_eq!(problems.loops, vec!["e", "g"]);
+
+    // Every task shows up once
+    let records = load_records(&tasks);
+    assert_eq!(count_records(&records), tasks.len());
+    let top: Vec<&str> = records.iter().map(|r| r.id.as_str()).collect();
+    for id in ["a", "c", "e", "g"] {
+        assert!(top.contains(&id), "{}", id);
+    }
+
+    // Importers cut the same links, and only those
+    let mut fixed = tasks.clone();
+    fix_parents(&mut fixed);
+    let parents: Vec<Option<&str>> = fixed.iter().map(|t| t.parent.as_deref()).collect();
+    assert_eq!(parents, vec![None, Some("a"), None, Some("c"), None, Some("g"), Some("g"), None]);
+}
+
+#[tokio::test]
+async fn test_check_and_repair() {
+    remove_files(&[DEVICE]);
+    let mut db = open_device(DEVICE).await;
+    let mut work = test_list("work", "work");
+    work.created = parse_datetime("20240601T090000Z");
+    work.last_edited = work.created;
+    db.save_synced_list(&work).await.unwrap();
+    let mut seconds = dated_task("seconds", "seconds", None);
+    seconds.created = Some(1717232400);
+    let mut unstamped = dated_task("unstamped", "unstamped", None);
+    unstamped.last_edited = None;
+    for task in [
+        dated_task("shared", "shared", None), dated_task("child", "child", Some("shared")), dated_task("orphan", "orphan", Some("deleted")),
+        dated_task("loop1", "loop1", Some("loop2")), dated_task("loop2", "loop2", Some("loop1")), seconds, unstamped
+    ] {
+        db.save_synced_task("work".to_string(), &task).await.unwrap();
+    }
+    let mut home = test_list("home", "home");
+    home.created = work.created;
+    home.last_edited = work.created;
+    db.save_synced_list(&home).await.unwrap();
+    db.save_synced_task("home".to_string(), &dated_task("shared", "shared", None)).await.unwrap();
+    db.save_synced_task("home".to_string(), &dated_task("sub", "sub", Some("shared"))).await.unwrap();
+    // Tasks that arrived before their list, a deleted list's empty table, and
+    // a list whose table is gone
+    db.save_synced_task("lost".to_string(), &dated_task("stray", "stray", None)).await.unwrap();
+    db.save_synced_list(&test_list("deleted", "deleted")).await.unwrap();
+    db.delete_list("deleted".to_string()).await.unwrap();
+    let mut bare = test_list("bare", "bare");
+    bare.created = work.created;
+    bare.last_edited = work.created;
+    db.save_synced_list(&bare).await.unwrap();
+    db.drop_task_table("bare").await.unwrap();
+
+    let expected: BTreeMap<String, usize> = [
+        ("DuplicateId", 1), ("InvalidTimestamp", 2), ("MissingList", 2),
+        ("MissingTable", 1), ("OrphanedSubtask", 1), ("ParentCycle", 1)
+    ].iter().map(|(k, v)| (k.to_string(), *v)).collect();
+    let found = check_in(&mut db, false).await.unwrap();
+    assert_eq!(kinds(&found), expected, "{:#?}", found);
+    // Checking changes nothing
+    assert_eq!(kinds(&check_in(&mut db, false).await.unwrap()), expected);
+    let issue = found.iter().find(|i| i.kind == IssueKind::OrphanedSubtask).unwrap();
+    assert_eq!((issue.list.as_str(), issue.task.as_deref()), ("work", Some("orphan")));
+    assert!(found.iter().any(|i| i.problem == "created 1717232400 is in seconds" && i.fix == "Set created to 20240601T090000Z"));
+
+    let repaired = check_in(&mut db, true).await.unwrap();
+    assert_eq!(kinds(&repaired), expected);
+    assert!(check_in(&mut db, false).await.unwrap().is_empty());
+
+    let tasks = db.get_tasks("work".to_string()).await.unwrap().unwrap();
+    assert_eq!(find_task(&tasks, "child").parent.as_deref(), Some("shared"));
+    assert_eq!(find_task(&tasks, "orphan").parent, None);
+    assert_eq!((find_task(&tasks, "loop1").parent.clone(), find_task(&tasks, "loop2").parent.as_deref()), (None, Some("loop1")));
+    assert_eq!(find_task(&tasks, "seconds").created, parse_datetime("20240601T090000Z"));
+    assert!(find_task(&tasks, "unstamped").last_edited.is_some());
+    // Untouched tasks keep their edit time
+    assert_eq!(find_task(&tasks, "child").last_edited, parse_datetime("20240602T100000Z"));
+    assert_eq!(count_records(&load_records(&tasks)), tasks.len());
+
+    // The copy in the later list gets a new id and keeps its subtask
+    let tasks = db.get_tasks("home".to_string()).await.unwrap().unwrap();
+    assert_eq!(tasks.len(), 2);
+    let copy = find_task(&tasks, "shared");
+    assert_ne!(copy.id, "shared");
+    assert_eq!(find_task(&tasks, "sub").parent.as_ref(), Some(&copy.id));
+
+    assert_eq!(db.get_list("lost".to_string()).await.unwrap().unwrap().name, "Recovered tasks");
+    let tables = db.task_tables().await.unwrap().unwrap();
+    assert_eq!(tables, vec!["bare", "home", "lost", "work"]);
+
+    db.close().await;
+    remove_files(&[DEVICE]);
+}
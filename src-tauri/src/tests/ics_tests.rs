@@ -0,0 +1,88 @@
+use crate::ics::{self, Component};
+use crate::task::TaskEntry;
+
+fn test_task() -> TaskEntry {
+    TaskEntry {
+        name: "Write report, part 2; draft".to_string(),
+        size: 3,
+        importance: 4,
+        due: 1706693400000,
+        completed: true,
+        id: "t1".to_string(),
+        parent: Some("t0".to_string()),
+        last_edited: Some(1706690000000),
+        created: Some(1706600000000),
+        sealed: None,
+        extra: Default::default()
+    }
+}
+
+#[test]
+fn test_vtodo_round_trip() {
+    let task = test_task();
+    let text = Component::calendar(vec![ics::task_to_vtodo(&task)]).to_ics();
+    assert!(text.contains("DUE:20240131T093000Z\r\n"));
+    assert!(text.contains("PRIORITY:1\r\n"));
+    assert!(text.contains("STATUS:COMPLETED\r\n"));
+    assert!(text.contains("RELATED-TO;RELTYPE=PARENT:t0\r\n"));
+    assert!(text.contains("SUMMARY:Write report\\, part 2\\; draft\r\n"));
+
+    let calendars = ics::parse(&text).unwrap();
+    let todo = calendars[0].find_all("VTODO")[0];
+    let parsed = ics::vtodo_to_task(todo).unwrap();
+    assert_eq!(serde_json::to_value(parsed).unwrap(), serde_json::to_value(task).unwrap());
+}
+
+#[test]
+fn test_vtodo_from_other_clients() {
+    let text = "BEGIN:VCALENDAR\nVERSION:2.0\nBEGIN:VTODO\nUID:abc\nSUMMARY:Buy milk\nDTSTART;VALUE=DATE:20240201\nLAST-MODIFIED:20240130T120000Z\nEND:VTODO\nEND:VCALENDAR\n";
+    let calendars = ics::parse(text).unwrap();
+    let task = ics::vtodo_to_task(calendars[0].find_all("VTODO")[0]).unwrap();
+    assert_eq!(task.name, "Buy milk");
+    assert_eq!(task.due, ics::parse_datetime("20240201").unwrap());
+    assert_eq!(task.importance, 2);
+    assert_eq!(task.size, 2);
+    assert!(!task.completed);
+    assert_eq!(task.parent, None);
+
+    // Needs a UID
+    let text = "BEGIN:VCALENDAR\nBEGIN:VTODO\nSUMMARY:x\nEND:VTODO\nEND:VCALENDAR\n";
+    assert!(ics::vtodo_to_task(ics::parse(text).unwrap()[0].find_all("VTODO")[0]).is_none());
+    assert!(ics::parse("BEGIN:VCALENDAR\nBEGIN:VTODO\nEND:VCALENDAR\n").is_err());
+}
+
+#[test]
+fn test_line_folding() {
+    let mut task = test_task();
+    task.name = "é".repeat(100);
+    let text = Component::calendar(vec![ics::task_to_vtodo(&task)]).to_ics();
+    assert!(text.split("\r\n").all(|line| line.len() <= 75));
+    let calendars = ics::parse(&text).unwrap();
+    assert_eq!(calendars[0].find_all("VTODO")[0].text("SUMMARY").unwrap(), task.name);
+}
+
+#[test]
+fn test_priority_mapping() {
+    for importance in 0..=4 {
+        assert_eq!(ics::priority_to_importance(ics::importance_to_priority(importance)), importance);
+    }
+    assert_eq!(ics::importance_to_priority(4), 1);
+    assert_eq!(ics::importance_to_priority(0), 9);
+    // Undefined
+    assert_eq!(ics::priority_to_importance(0), 2);
+    assert_eq!(ics::priority_to_importance(5), 2);
+}
+
+#[test]
+fn test_parse_datetime() {
+    assert_eq!(ics::parse_datetime("19700101T000001Z"), Some(1000));
+    assert_eq!(ics::parse_datetime("19700102"), Some(86400000));
+    assert_eq!(ics::format_utc(1706693400000), "20240131T093000Z");
+    assert_eq!(ics::format_date(1706693400000), "20240131");
+    assert_eq!(ics::parse_datetime("2024"), None);
+    assert_eq!(ics::parse_datetime("20241301"), None);
+    assert_eq!(ics::parse_datetime("20240101X"), None);
+    // Multi-byte characters where digits should be
+    assert_eq!(ics::parse_datetime("123é5678"), None);
+    assert_eq!(ics::parse_datetime("20240101T1é2345Z"), None);
+}
@@ -0,0 +1,127 @@
+// Two LAN sync nodes in one process, talking over loopback.
+use std::{fs, sync::Arc, time::Duration};
+
+use crate::lan::LanNode;
+use crate::storage::TaskDb;
+use crate::testutils::{open_device, test_list, test_task};
+use crate::utils::now;
+
+const DIR_A: &str = "testLanA";
+const DIR_B: &str = "testLanB";
+const DIR_C: &str = "testLanC";
+
+fn reset() {
+    for dir in [DIR_A, DIR_B, DIR_C] {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+    }
+}
+
+fn node(dir: &str, name: &str) -> Arc<LanNode> {
+    Arc::new(LanNode::open(dir, name, Some(format!("{}/tasks.db", dir))).unwrap())
+}
+
+async fn open_db(dir: &str) -> TaskDb {
+    let mut tasks = open_device(&format!("{}/tasks.db", dir)).await;
+    tasks
+}
+
+async fn task_name(dir: &str, list: &str, id: &str) -> Option<String> {
+    let mut db = open_db(dir).await;
+    let task = db.get_task(list.to_string(), id.to_string()).await.unwrap();
+    db.close().await;
+    task.map(|t| t.name)
+}
+
+#[tokio::test]
+async fn test_lan_pairing() {
+    reset();
+    let a = node(DIR_A, "Laptop");
+    let b = node(DIR_B, "Desktop");
+    let (listener, addr) = a.clone().listen("127.0.0.1:0").await.unwrap();
+    let addr = addr.to_string();
+
+    // The key survives a restart
+    assert_eq!(LanNode::open(DIR_A, "Laptop", None).unwrap().device_id(), a.device_id());
+
+    // Nothing to pair with until a PIN is shown
+    assert!(b.pair(&addr, "123456").await.is_err());
+    assert!(b.pair(&addr, "12345").await.is_err());
+
+    // A wrong PIN uses up the attempt
+    let pin = a.start_pairing();
+    let wrong = if pin == "000000" { "000001" } else { "000000" };
+    assert!(b.pair(&addr, wrong).await.is_err());
+    assert!(b.pair(&addr, &pin).await.is_err());
+    assert!(a.peers().is_empty());
+    assert!(b.peers().is_empty());
+
+    let pin = a.start_pairing();
+    let peer = b.pair(&addr, &pin).await.unwrap();
+    assert_eq!(peer.id, a.device_id());
+    assert_eq!(peer.name, "Laptop");
+    assert_eq!(b.peers(), vec![peer]);
+    assert_eq!(a.peers().len(), 1);
+    assert_eq!(a.peers()[0].id, b.device_id());
+    assert_eq!(a.peers()[0].name, "Desktop");
+
+    listener.abort();
+    let _ = fs::remove_dir_all(DIR_A);
+    let _ = fs::remove_dir_all(DIR_B);
+    let _ = fs::remove_dir_all(DIR_C);
+}
+
+#[tokio::test]
+async fn test_lan_sync() {
+    reset();
+    let a = node(DIR_A, "Laptop");
+    let b = node(DIR_B, "Desktop");
+    let c = node(DIR_C, "Stranger");
+    let (listener, addr) = a.clone().listen("127.0.0.1:0").await.unwrap();
+    let addr = addr.to_string();
+    let pin = a.start_pairing();
+    b.pair(&addr, &pin).await.unwrap();
+
+    let mut db = open_db(DIR_A).await;
+    db.new_list(&test_list("work", "Work")).await.unwrap();
+    db.new_task("work".to_string(), &test_task("t1", "Report", None, now())).await.unwrap();
+    db.close().await;
+    let mut db = open_db(DIR_B).await;
+    db.new_list(&test_list("home", "Home")).await.unwrap();
+    db.new_task("home".to_string(), &test_task("t2", "Dishes", None, now())).await.unwrap();
+    db.close().await;
+    tokio::time::sleep(Duration::from_millis(5)).await;
+
+    let peer = b.sync_with(&addr).await.unwrap();
+    assert_eq!(peer.id, a.device_id());
+    assert_eq!(task_name(DIR_A, "home", "t2").await.as_deref(), Some("Dishes"));
+    assert_eq!(task_name(DIR_B, "work", "t1").await.as_deref(), Some("Report"));
+
+    // Later edits win, whichever side made them
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let mut db = open_db(DIR_A).await;
+    db.edit_task("work".to_string(), &test_task("t1", "Report v1", None, now())).await.unwrap();
+    db.close().await;
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let mut db = open_db(DIR_B).await;
+    db.edit_task("work".to_string(), &test_task("t1", "Report v2", None, now())).await.unwrap();
+    db.edit_task("home".to_string(), &test_task("t2", "Dishes done", None, now())).await.unwrap();
+    db.close().await;
+    tokio::time::sleep(Duration::from_millis(5)).await;
+
+    b.sync_with(&addr).await.unwrap();
+    assert_eq!(task_name(DIR_A, "work", "t1").await.as_deref(), Some("Report v2"));
+    assert_eq!(task_name(DIR_A, "home", "t2").await.as_deref(), Some("Dishes done"));
+    assert_eq!(task_name(DIR_B, "work", "t1").await.as_deref(), Some("Report v2"));
+
+    // Only paired devices can sync
+    assert!(c.sync_with(&addr).await.is_err());
+    assert_eq!(task_name(DIR_C, "work", "t1").await, None);
+    b.forget_peer(a.device_id()).unwrap();
+    assert!(b.sync_with(&addr).await.is_err());
+
+    listener.abort();
+    let _ = fs::remove_dir_all(DIR_A);
+    let _ = fs::remove_dir_all(DIR_B);
+    let _ = fs::remove_dir_all(DIR_C);
+}
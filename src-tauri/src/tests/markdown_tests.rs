@@ -0,0 +1,113 @@
+// Markdown checklist export and import.
+use crate::ics::parse_datetime;
+use crate::markdown::{export_markdown, import_markdown_text, read_markdown, write_markdown};
+use crate::task::{get_saved_lists, get_saved_tasks, load_records, switch_task_db, TaskEntry, TaskRecord};
+use crate::testutils::{open_device, remove_files, test_list, test_task};
+
+const DEVICE_A: &str = "testMarkdownA.db";
+const DEVICE_B: &str = "testMarkdownB.db";
+
+/// The tree of (name, due, completed), without ids.
+fn shape(tasks: &[TaskEntry]) -> Vec<String> {
+    fn walk(records: &[TaskRecord], depth: usize, out: &mut Vec<String>) {
+        let mut records = records.to_vec();
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        for r in &records {
+            out.push(format!("{}{} {} {}", "  ".repeat(depth), r.name, r.due, r.completed));
+            walk(&r.subtasks, depth + 1, out);
+        }
+    }
+    let mut out = vec![];
+    walk(&load_records(&tasks.to_vec()), 0, &mut out);
+    out
+}
+
+#[test]
+fn test_write_markdown() {
+    let mut done = test_task("sources", "Find\nsources", Some("outline"), parse_datetime("20240608T000000Z").unwrap());
+    done.completed = true;
+    let tasks = vec![
+        test_task("essay", "Essay #english", None, parse_datetime("20240610T143000Z").unwrap()),
+        test_task("outline", "Outline", Some("essay"), parse_datetime("20240609T000000Z").unwrap()),
+        done
+    ];
+    assert_eq!(write_markdown(&test_list("school", "School"), &tasks, 0), "# School
+
+- [ ] Essay #english (due: 2024-06-10 14:30)
+  - [ ] Outline (due: 2024-06-09)
+    - [x] Find sources (due: 2024-06-08)
+");
+    // Due dates are local
+    assert!(write_markdown(&test_list("school", "School"), &tasks, -120).contains("Outline (due: 2024-06-08 22:00)"));
+}
+
+#[test]
+fn test_read_markdown() {
+    let text = "Notes from the meeting:
+
+* [X] Book room (due: 6/3/2024)
+1. Agenda
+\t- [ ] Budget (due: soon)
+## Sprint 4 ##
+- [ ] Ship it
+    + [x] Tests
+        - [ ] Flaky one
+    - [ ] Docs (due: 2024-06-10 09:00)
+  - [ ] Changelog
+-[ ] not an item
+- [ ]
+";
+    let lists = read_markdown(text, "Pasted", 60);
+    let names: Vec<&str> = lists.iter().map(|l| l.list.name.as_str()).collect();
+    assert_eq!(names, vec!["Pasted", "Sprint 4"]);
+
+    let pasted = &lists[0].tasks;
+    assert_eq!(pasted.len(), 3);
+    assert_eq!((pasted[0].name.as_str(), pasted[0].completed), ("Book room", true));
+    assert_eq!(pasted[0].due, parse_datetime("20240603").unwrap() - 3_600_000);
+    assert_eq!(pasted[2].name, "Budget (due: soon)");
+    assert_eq!(pasted[2].parent.as_ref(), Some(&pasted[1].id));
+
+    let sprint = &lists[1].tasks;
+    let id = |name: &str| Some(sprint.iter().find(|t| t.name == name).unwrap().id.clone());
+    let parent = |name: &str| sprint.iter().find(|t| t.name == name).unwrap().parent.clone();
+    assert_eq!(sprint.len(), 5);
+    assert_eq!(parent("Ship it"), None);
+    assert_eq!(parent("Tests"), id("Ship it"));
+    assert_eq!(parent("Flaky one"), id("Tests"));
+    assert_eq!(parent("Docs"), id("Ship it"));
+    // Less indented than its siblings above, but still under Ship it
+    assert_eq!(parent("Changelog"), id("Ship it"));
+    assert_eq!(sprint.iter().find(|t| t.name == "Docs").unwrap().due, parse_datetime("20240610T080000Z").unwrap());
+
+    assert!(read_markdown("Just text\n# Heading only\n", "x", 0).is_empty());
+}
+
+#[tokio::test]
+async fn test_markdown_round_trip() {
+    remove_files(&[DEVICE_A, DEVICE_B]);
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("school", "School")).await.unwrap();
+    a.new_task("school".to_string(), &test_task("essay", "Essay (final)", None, parse_datetime("20240610T143000Z").unwrap())).await.unwrap();
+    a.new_task("school".to_string(), &test_task("outline", "Outline", Some("essay"), parse_datetime("20240609T000000Z").unwrap())).await.unwrap();
+    let mut done = test_task("sources", "Sources", Some("outline"), parse_datetime("20240608T120000Z").unwrap());
+    done.completed = true;
+    a.new_task("school".to_string(), &done).await.unwrap();
+    a.new_task("school".to_string(), &test_task("quiz", "Quiz", None, parse_datetime("20240611T090000Z").unwrap())).await.unwrap();
+    a.close().await;
+
+    switch_task_db(DEVICE_A).await.unwrap();
+    let text = export_markdown("school".to_string(), 330).await.unwrap();
+    let exported = get_saved_tasks("school").await.unwrap();
+    assert!(export_markdown("missing".to_string(), 0).await.is_err());
+
+    switch_task_db(DEVICE_B).await.unwrap();
+    let res = import_markdown_text(&text, "Pasted", 330).await.unwrap();
+    assert_eq!((res.lists, res.tasks), (1, 4));
+    let list = get_saved_lists().await.unwrap().remove(0);
+    assert_eq!(list.name, "School");
+    assert_eq!(shape(&get_saved_tasks(&list.uuid).await.unwrap()), shape(&exported));
+    assert!(import_markdown_text("nothing here", "Pasted", 0).await.is_err());
+
+    remove_files(&[DEVICE_A, DEVICE_B]);
+}
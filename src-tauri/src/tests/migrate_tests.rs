@@ -0,0 +1,163 @@
+// Importing Todoist and Microsoft To Do exports, from the samples in golden/.
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::ics::parse_datetime;
+use crate::migrate::{import_mstodo_file, import_todoist_files, read_mstodo_json, read_todoist_csv, read_todoist_json};
+use crate::task::{get_saved_lists, get_saved_tasks, switch_task_db};
+use crate::testutils::{find_task, remove_files};
+use crate::utils::now;
+
+const TODOIST_CSV: &str = include_str!("golden/todoist_school.csv");
+const TODOIST_JSON: &str = include_str!("golden/todoist_backup.json");
+const MSTODO_JSON: &str = include_str!("golden/mstodo_export.json");
+const DEVICE: &str = "testMigrate.db";
+const CSV_FILE: &str = "testMigrate [2203306141].csv";
+const JSON_FILE: &str = "testMigrate.json";
+
+fn dropped(entries: &[(&str, usize)]) -> BTreeMap<String, usize> {
+    entries.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+}
+
+/// Whether `due` was stamped during the test, for dates that couldn't be
+/// brought over.
+fn is_today(due: i64, start: i64) -> bool {
+    (start..=now()).contains(&due)
+}
+
+#[test]
+fn test_read_todoist_csv() {
+    let start = now();
+    let mut report = BTreeMap::new();
+    let read = read_todoist_csv(TODOIST_CSV, "School [2203306141]", 60, &mut report).unwrap();
+    assert_eq!(read.list.name, "School");
+    let tasks = &read.tasks;
+    assert_eq!(tasks.len(), 9);
+    let parent = |name: &str| find_task(tasks, name).parent.clone();
+    let id = |name: &str| Some(find_task(tasks, name).id.clone());
+
+    let syllabus = find_task(tasks, "Read syllabus #reading");
+    assert_eq!((syllabus.importance, syllabus.due, syllabus.parent.as_ref()), (2, parse_datetime("20240603").unwrap() - 3_600_000, None));
+    // Sections are top-level tasks over their own
+    assert_eq!(parent("Essay"), None);
+    assert_eq!(parent("Write essay"), id("Essay"));
+    assert_eq!(parent("Outline"), id("Write essay"));
+    assert_eq!(parent("Find sources #library"), id("Outline"));
+    assert_eq!(parent("Proofread"), id("Write essay"));
+    assert_eq!(parent("Review notes"), id("Exams"));
+    let essay = find_task(tasks, "Write essay");
+    assert_eq!((essay.importance, essay.due), (4, parse_datetime("20240610T133000Z").unwrap()));
+    assert_eq!(find_task(tasks, "Essay").due, essay.due);
+    assert_eq!(find_task(tasks, "Outline").importance, 3);
+    assert_eq!(find_task(tasks, "Find sources #library").importance, 2);
+    for name in ["Proofread", "Review notes", "Later"] {
+        assert!(is_today(find_task(tasks, name).due, start), "{}", name);
+    }
+    assert!(tasks.iter().all(|t| !t.completed));
+
+    assert_eq!(report, dropped(&[
+        ("assignees", 1), ("comments", 1), ("deadlines", 1), ("descriptions", 1),
+        ("durations", 1), ("recurring due dates", 1), ("unreadable due dates", 1)
+    ]));
+    assert!(read_todoist_csv("name,due\nEssay,2024-06-10\n", "Essays", 0, &mut report).is_err());
+}
+
+#[test]
+fn test_read_todoist_json() {
+    let start = now();
+    let mut report = BTreeMap::new();
+    let lists = read_todoist_json(TODOIST_JSON, 60, &mut report).unwrap();
+    let names: Vec<&str> = lists.iter().map(|l| l.list.name.as_str()).collect();
+    assert_eq!(names, vec!["Inbox", "School"]);
+
+    let inbox = &lists[0].tasks;
+    assert_eq!(inbox.len(), 2);
+    let milk = find_task(inbox, "Buy milk #errands");
+    assert_eq!((milk.importance, milk.due), (2, parse_datetime("20240603").unwrap() - 3_600_000));
+    // Recurring tasks keep their next date
+    let review = find_task(inbox, "Weekly review #deep_work");
+    assert_eq!((review.importance, review.due), (3, parse_datetime("20240607").unwrap() - 3_600_000));
+
+    let school = &lists[1].tasks;
+    assert_eq!(school.len(), 4);
+    let section = find_task(school, "Essay");
+    let essay = find_task(school, "Write essay");
+    let outline = find_task(school, "Outline");
+    assert_eq!((essay.importance, essay.due, essay.parent.as_ref()), (4, parse_datetime("20240610T143000Z").unwrap(), Some(&section.id)));
+    assert_eq!(section.due, essay.due);
+    assert_eq!((outline.importance, outline.completed, outline.parent.as_ref()), (2, true, Some(&essay.id)));
+    // Numeric ids and flags from older backups
+    let old = find_task(school, "Task from an old backup");
+    assert!(old.parent.is_none() && !old.completed && is_today(old.due, start));
+
+    assert_eq!(report, dropped(&[
+        ("assignees", 1), ("comments", 1), ("descriptions", 1), ("durations", 1),
+        ("recurrence", 1), ("reminders", 1), ("tasks in missing projects", 1)
+    ]));
+    // Counts from more than one backup add up
+    read_todoist_json(TODOIST_JSON, 60, &mut report).unwrap();
+    assert_eq!((report["comments"], report["reminders"], report["assignees"]), (2, 2, 2));
+    assert!(read_todoist_json("{\"items\": 3}", 0, &mut report).is_err());
+}
+
+#[test]
+fn test_read_mstodo_json() {
+    let start = now();
+    let mut report = BTreeMap::new();
+    let lists = read_mstodo_json(MSTODO_JSON, 120, &mut report).unwrap();
+    let names: Vec<&str> = lists.iter().map(|l| l.list.name.as_str()).collect();
+    assert_eq!(names, vec!["Tasks", "Groceries", "Empty"]);
+
+    let tasks = &lists[0].tasks;
+    assert_eq!(tasks.len(), 4);
+    let rent = find_task(tasks, "Pay rent #Home #Money_stuff");
+    assert_eq!((rent.importance, rent.due, rent.completed), (3, parse_datetime("20240601T000000Z").unwrap(), false));
+    // Checklist steps are subtasks due with their task
+    for (name, completed) in [("Find IBAN", true), ("Transfer", false)] {
+        let step = find_task(tasks, name);
+        assert_eq!((step.parent.as_ref(), step.due, step.completed), (Some(&rent.id), rent.due, completed));
+    }
+    // Zones other than UTC are local
+    let dentist = find_task(tasks, "Call dentist");
+    assert_eq!((dentist.importance, dentist.due, dentist.completed), (2, parse_datetime("20240605T073000Z").unwrap(), true));
+
+    let apples = &lists[1].tasks[0];
+    assert!(apples.importance == 1 && is_today(apples.due, start));
+    assert!(lists[2].tasks.is_empty());
+
+    assert_eq!(report, dropped(&[
+        ("attachments", 1), ("linked resources", 1), ("notes", 1), ("recurrence", 1), ("reminders", 1)
+    ]));
+    assert!(read_mstodo_json(TODOIST_JSON, 0, &mut report).is_err());
+}
+
+#[tokio::test]
+async fn test_import_files() {
+    remove_files(&[DEVICE, CSV_FILE, JSON_FILE]);
+    fs::write(CSV_FILE, TODOIST_CSV).unwrap();
+    fs::write(JSON_FILE, TODOIST_JSON).unwrap();
+    switch_task_db(DEVICE).await.unwrap();
+
+    let res = import_todoist_files(&[CSV_FILE.to_string(), JSON_FILE.to_string()], 60).await.unwrap();
+    assert_eq!((res.lists, res.tasks), (3, 15));
+    // Counts add up across files
+    assert_eq!(res.dropped["descriptions"], 2);
+    let lists = get_saved_lists().await.unwrap();
+    let csv_list = lists.iter().find(|l| l.name == "testMigrate").unwrap();
+    let tasks = get_saved_tasks(&csv_list.uuid).await.unwrap();
+    assert_eq!(find_task(&tasks, "Outline").parent.as_ref(), Some(&find_task(&tasks, "Write essay").id));
+
+    // Nothing is saved if any file can't be read
+    fs::write(CSV_FILE, "name,due\nEssay,2024-06-10\n").unwrap();
+    assert!(import_todoist_files(&[JSON_FILE.to_string(), CSV_FILE.to_string()], 60).await.is_err());
+    assert_eq!(get_saved_lists().await.unwrap().len(), 3);
+
+    fs::write(JSON_FILE, MSTODO_JSON).unwrap();
+    let res = import_mstodo_file(JSON_FILE, 120).await.unwrap();
+    assert_eq!((res.lists, res.tasks), (3, 5));
+    assert_eq!(get_saved_lists().await.unwrap().len(), 6);
+    fs::write(JSON_FILE, "{\"lists\": []}").unwrap();
+    assert!(import_mstodo_file(JSON_FILE, 0).await.is_err());
+
+    remove_files(&[DEVICE, CSV_FILE, JSON_FILE]);
+}
@@ -8,4 +8,88 @@ mod storage_tests;
 
 #[cfg(test)]
 #[allow(unused)]
-mod history_tests;
\ No newline at end of file
+mod history_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod http_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod sync_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod crypto_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod auth_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod profile_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod lan_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod folder_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod ics_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod caldav_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod webdav_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod backend_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod calendar_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod feed_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod archive_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod spreadsheet_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod todotxt_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod markdown_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod migrate_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod taskwarrior_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod backup_tests;
+
+#[cfg(test)]
+#[allow(unused)]
+mod health_tests;
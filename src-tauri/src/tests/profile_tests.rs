@@ -0,0 +1,70 @@
+use std::{fs, path::Path};
+
+use crate::auth::Session;
+use crate::http::{api_root, default_api_root, is_logged_in, start_session, SessionCookie};
+use crate::profile::*;
+use crate::storage::SyncState;
+use crate::task::{load_sync_state, save_sync_state};
+
+const BASE: &str = "testProfiles";
+
+fn reset() {
+    let _ = fs::remove_dir_all(BASE);
+    fs::create_dir_all(BASE).unwrap();
+}
+
+#[tokio::test]
+async fn test_profiles_keep_data_apart() {
+    reset();
+    init_profiles(BASE.to_string());
+    let profiles = list_profiles().unwrap();
+    assert_eq!(profiles.len(), 1);
+    assert_eq!(profiles[0].id, DEFAULT_PROFILE);
+    assert!(profiles[0].active);
+    assert_eq!(profiles[0].endpoint, default_api_root());
+
+    let school = add_profile("School".to_string(), Some("http://127.0.0.1:5999/".to_string())).unwrap();
+    assert!(add_profile(" school ".to_string(), None).is_err());
+    assert!(add_profile("".to_string(), None).is_err());
+    assert!(add_profile("Work".to_string(), Some("http://example.com".to_string())).is_err());
+    let school_info = list_profiles().unwrap().into_iter().find(|p| p.id == school.id).unwrap();
+    assert_eq!(school_info.endpoint, "http://127.0.0.1:5999");
+    assert!(!school_info.active);
+
+    // Each profile has its own task database, server and session
+    activate_profile(DEFAULT_PROFILE).await.unwrap();
+    let mut state = SyncState::new("server");
+    state.pushed_until = 5;
+    save_sync_state(&state).await.unwrap();
+    start_session(Session::Cookie(SessionCookie { name: "session".to_string(), value: "personal".to_string(), expires: None }));
+
+    activate_profile(&school.id).await.unwrap();
+    assert_eq!(api_root(), "http://127.0.0.1:5999");
+    assert_eq!(load_sync_state("server").await.unwrap().pushed_until, 0);
+    assert!(!is_logged_in());
+    assert!(Path::new(&format!("{}/profiles/{}/tasks.db", BASE, school.id)).exists());
+
+    activate_profile(DEFAULT_PROFILE).await.unwrap();
+    assert_eq!(api_root(), default_api_root());
+    assert_eq!(load_sync_state("server").await.unwrap().pushed_until, 5);
+    assert!(is_logged_in());
+
+    // The active profile is remembered
+    activate_profile(&school.id).await.unwrap();
+    close_profiles();
+    init_profiles(BASE.to_string());
+    assert!(list_profiles().unwrap().iter().any(|p| p.id == school.id && p.active));
+
+    rename_profile(school.id.clone(), "Uni".to_string()).unwrap();
+    assert!(rename_profile(school.id.clone(), "personal".to_string()).is_err());
+    assert!(remove_profile(school.id.clone()).is_err());
+    assert!(remove_profile(DEFAULT_PROFILE.to_string()).is_err());
+    activate_profile(DEFAULT_PROFILE).await.unwrap();
+    remove_profile(school.id.clone()).unwrap();
+    assert_eq!(list_profiles().unwrap().len(), 1);
+    assert!(!Path::new(&format!("{}/profiles/{}", BASE, school.id)).exists());
+    assert!(activate_profile(&school.id).await.is_err());
+
+    close_profiles();
+    let _ = fs::remove_dir_all(BASE);
+}
@@ -0,0 +1,177 @@
+// CSV export and mapped import.
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::ics::parse_datetime;
+use crate::spreadsheet::{export_csv, format_iso, import_csv_file, parse_date, read_csv, ColumnMapping, DateOrder};
+use crate::task::{get_saved_lists, get_saved_tasks, load_records, switch_task_db, TaskEntry, TaskRecord};
+use crate::testutils::{open_device, remove_files, test_list, test_task};
+
+const DEVICE_A: &str = "testSpreadsheetA.db";
+const DEVICE_B: &str = "testSpreadsheetB.db";
+const FILE: &str = "testSpreadsheet.csv";
+/// When the test tasks are due
+const DUE: i64 = 1_717_200_000_000;
+
+/// The tree of (name, size, importance, due, completed), without ids.
+fn shape(tasks: &[TaskEntry]) -> Vec<String> {
+    fn walk(records: &[TaskRecord], depth: usize, out: &mut Vec<String>) {
+        let mut records = records.to_vec();
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        for r in &records {
+            out.push(format!("{}{} {} {} {} {}", "  ".repeat(depth), r.name, r.size, r.importance, r.due, r.completed));
+            walk(&r.subtasks, depth + 1, out);
+        }
+    }
+    let mut out = vec![];
+    walk(&load_records(&tasks.to_vec()), 0, &mut out);
+    out
+}
+
+fn date(value: &str) -> Option<i64> {
+    parse_date(value, DateOrder::Mdy, 0)
+}
+
+#[test]
+fn test_parse_date() {
+    let june_10 = parse_datetime("20240610").unwrap();
+    let at_1430 = parse_datetime("20240610T143000Z").unwrap();
+    for value in ["2024-06-10", "20240610", "6/10/2024", "06/10/24", "2024/06/10", "Jun 10, 2024", "10 June 2024", "Monday, June 10 2024"] {
+        assert_eq!(date(value), Some(june_10), "{}", value);
+    }
+    for value in ["2024-06-10T14:30:00Z", "2024-06-10T14:30", "20240610T143000Z", "6/10/2024 2:30 PM", "6/10/2024 2:30pm", "2024-06-10 16:30:00+02:00", "2024-06-10T09:30:00.000-0500"] {
+        assert_eq!(date(value), Some(at_1430), "{}", value);
+    }
+    assert_eq!(parse_date("10/06/2024", DateOrder::Dmy, 0), Some(june_10));
+    assert_eq!(parse_date("10.06.2024", DateOrder::Dmy, 0), Some(june_10));
+    // Times without a zone are local
+    assert_eq!(parse_date("2024-06-10 16:30", DateOrder::Mdy, 120), Some(at_1430));
+    assert_eq!(parse_date("2024-06-10T14:30Z", DateOrder::Mdy, 120), Some(at_1430));
+    assert_eq!(date("1718029800000"), Some(at_1430));
+    for bad in ["", "soon", "13/45/2024", "2024-06", "25:00 6/10/2024", "12345", "2024-06-10 10:00+1é2", "2024-06-10 10:00+99999999", "2024-06-10 10:00+15:00", "2024-06-10 10:00+02:60"] {
+        assert_eq!(date(bad), None, "{}", bad);
+    }
+    // A compact time cut short by a multi-byte character is a stray word
+    assert_eq!(date("2024-06-10T12345é"), date("2024-06-10"));
+
+    assert_eq!(format_iso(at_1430, 0), "2024-06-10T14:30:00Z");
+    assert_eq!(format_iso(at_1430, -300), "2024-06-10T09:30:00-05:00");
+    assert_eq!(parse_date(&format_iso(at_1430, 330), DateOrder::Mdy, 0), Some(at_1430));
+}
+
+const SHEET: &str = "\u{feff}Course;Assignment;Priority;Effort;Deadline;Done;Level
+Math;Problem set 3;High;small;03/06/2024;;0
+Math;  Question 1;p1;tiny;04/06/2024;x;1
+Math;  Question 2;;;04/06/2024;;1
+Math;    Part b;whenever;enormous;someday;;2
+;Read syllabus;low;;01/06/2024;yes;0
+Math;Quiz;3;4;05/06/2024;no;0
+;;;;;;
+English;;high;;;;
+";
+
+#[test]
+fn test_read_csv_mapping() {
+    let mut priorities = BTreeMap::new();
+    priorities.insert("Whenever".to_string(), 0);
+    let mapping = ColumnMapping {
+        name: Some("assignment".to_string()),
+        indent: Some("Level".to_string()),
+        date_order: DateOrder::Dmy,
+        priorities,
+        ..Default::default()
+    };
+    let read = read_csv(SHEET, &mapping, 0, "Sheet").unwrap();
+    assert_eq!(read.headers, vec!["Course", "Assignment", "Priority", "Effort", "Deadline", "Done", "Level"]);
+    // The rest is guessed from the headers
+    assert_eq!(read.mapping.list.as_deref(), Some("Course"));
+    assert_eq!(read.mapping.importance.as_deref(), Some("Priority"));
+    assert_eq!(read.mapping.size.as_deref(), Some("Effort"));
+    assert_eq!(read.mapping.due.as_deref(), Some("Deadline"));
+    assert_eq!(read.mapping.completed.as_deref(), Some("Done"));
+    assert_eq!(read.mapping.path.as_deref(), Some(""));
+
+    let names: Vec<&str> = read.lists.iter().map(|l| l.list.name.as_str()).collect();
+    assert_eq!(names, vec!["Math", "Sheet"]);
+    let day = |d: &str| parse_datetime(d).unwrap();
+    let math = shape(&read.lists[0].tasks);
+    assert_eq!(math.len(), 5);
+    assert_eq!(math[..3], [
+        format!("Problem set 3 1 3 {} false", day("20240603")),
+        format!("  Question 1 0 4 {} true", day("20240604")),
+        format!("  Question 2 2 2 {} false", day("20240604"))
+    ]);
+    // Unreadable cells fall back to defaults
+    assert!(math[3].starts_with("    Part b 2 0 ") && math[3].ends_with(" false"), "{}", math[3]);
+    assert_eq!(math[4], format!("Quiz 4 3 {} false", day("20240605")));
+    assert_eq!(shape(&read.lists[1].tasks), vec![format!("Read syllabus 2 1 {} true", day("20240601"))]);
+    assert_eq!(read.problems, vec![
+        "Row 5: unknown size \"enormous\", used medium.",
+        "Row 5: couldn't read date \"someday\", used today.",
+        "Row 9: no task name, skipped."
+    ]);
+
+    // Indents in the name column itself, and unmapping a guessed column
+    let mapping = ColumnMapping {
+        name: Some("Assignment".to_string()),
+        indent: Some("Assignment".to_string()),
+        completed: Some(String::new()),
+        ..Default::default()
+    };
+    let read = read_csv(SHEET, &mapping, 0, "Sheet").unwrap();
+    let math = &read.lists[0].tasks;
+    let part_b = math.iter().find(|t| t.name == "Part b").unwrap();
+    let question_2 = math.iter().find(|t| t.name == "Question 2").unwrap();
+    assert_eq!(part_b.parent.as_ref(), Some(&question_2.id));
+    assert!(math.iter().all(|t| !t.completed));
+
+    assert!(read_csv(SHEET, &ColumnMapping { name: Some("Nope".to_string()), ..Default::default() }, 0, "x").is_err());
+    assert!(read_csv("a,b\n1,2\n", &ColumnMapping::default(), 0, "x").err().unwrap().contains("task names"));
+}
+
+#[test]
+fn test_read_csv_paths() {
+    let sheet = "title,parent,due\nEssay,,2024-06-10\nOutline,Essay,2024-06-09\nSources,Essay > Outline,2024-06-08\nStray,Essay > Missing,2024-06-08\n";
+    let read = read_csv(sheet, &ColumnMapping::default(), 0, "Essays").unwrap();
+    let tasks = &read.lists[0].tasks;
+    let id = |name: &str| tasks.iter().find(|t| t.name == name).unwrap().id.clone();
+    let parent = |name: &str| tasks.iter().find(|t| t.name == name).unwrap().parent.clone();
+    assert_eq!(parent("Essay"), None);
+    assert_eq!(parent("Outline"), Some(id("Essay")));
+    assert_eq!(parent("Sources"), Some(id("Outline")));
+    assert_eq!(parent("Stray"), None);
+    assert_eq!(read.problems, vec!["Row 5: no task \"Essay > Missing\" in Essays, so it's not a subtask."]);
+}
+
+#[tokio::test]
+async fn test_csv_round_trip() {
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("school", "School, Spring")).await.unwrap();
+    a.new_task("school".to_string(), &test_task("essay", "Essay \"final\"", None, DUE)).await.unwrap();
+    a.new_task("school".to_string(), &test_task("outline", "Outline", Some("essay"), DUE)).await.unwrap();
+    let mut sources = test_task("sources", "Find sources", Some("outline"), DUE);
+    sources.completed = true;
+    sources.size = 4;
+    a.new_task("school".to_string(), &sources).await.unwrap();
+    a.new_list(&test_list("home", "Home")).await.unwrap();
+    a.new_task("home".to_string(), &test_task("dishes", "Dishes", None, DUE)).await.unwrap();
+    a.close().await;
+
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert_eq!(export_csv(vec![], FILE.to_string(), 120).await.unwrap(), 4);
+    let text = fs::read_to_string(FILE).unwrap();
+    assert!(text.starts_with("list,path,name,size,importance,due,completed\n"));
+    assert!(text.contains("\"School, Spring\",\"Essay \"\"final\"\" > Outline\",Find sources,4,3,2024-06-01T02:00:00+02:00,true\n"));
+    let school = get_saved_tasks("school").await.unwrap();
+
+    switch_task_db(DEVICE_B).await.unwrap();
+    let res = import_csv_file(FILE, &ColumnMapping::default(), 120).await.unwrap();
+    assert_eq!((res.lists, res.tasks), (2, 4));
+    assert!(res.problems.is_empty(), "{:?}", res.problems);
+    let lists = get_saved_lists().await.unwrap();
+    let copy = lists.iter().find(|l| l.name == "School, Spring").unwrap();
+    assert_eq!(shape(&get_saved_tasks(&copy.uuid).await.unwrap()), shape(&school));
+
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+}
@@ -18,7 +18,9 @@ async fn test_new_list() {
         uuid: uuid::Uuid::new_v4().to_string(),
         color: 4,
         last_edited: None,
-        created: None
+        created: None,
+        sealed: None,
+        extra: Default::default()
     };
     let before = tasks.get_lists().await.unwrap();
 
@@ -45,7 +47,9 @@ async fn test_edit_list() {
         uuid: list_id.clone(),
         color: 4,
         last_edited: None,
-        created: None
+        created: None,
+        sealed: None,
+        extra: Default::default()
     };
     tasks.new_list(&list).await;
     tasks.edit_list(&ListEntry { 
@@ -53,7 +57,9 @@ async fn test_edit_list() {
         uuid: list_id.clone(), 
         color: 4, 
         last_edited: None, 
-        created: None 
+        created: None,
+        sealed: None,
+        extra: Default::default()
     }).await;
     let list = tasks.get_list(list_id.clone()).await.unwrap();
     assert!(!list.is_none());
@@ -74,7 +80,9 @@ async fn test_delete_list() {
         uuid: list_id.clone(),
         color: 4,
         last_edited: None,
-        created: None
+        created: None,
+        sealed: None,
+        extra: Default::default()
     };
 
     tasks.new_list(&list).await;
@@ -97,7 +105,9 @@ async fn test_new_task() {
         uuid: list_id.clone(),
         color: 4,
         last_edited: None,
-        created: None
+        created: None,
+        sealed: None,
+        extra: Default::default()
     };
     tasks.new_list(&list).await;
 
@@ -110,7 +120,9 @@ async fn test_new_task() {
         name: "testTask".to_string(),
         importance: 4,
         size: 1,
-        parent: None
+        parent: None,
+        sealed: None,
+        extra: Default::default()
     }).await.or_else(|e| {
         println!("{e}");
         assert!(false);
@@ -148,7 +160,9 @@ async fn test_get_task() {
         uuid: list_id.clone(),
         color: 4,
         last_edited: None,
-        created: None
+        created: None,
+        sealed: None,
+        extra: Default::default()
     };
     tasks.new_list(&list).await;
 
@@ -161,7 +175,9 @@ async fn test_get_task() {
         name: "testTask".to_string(),
         importance: 4,
         size: 1,
-        parent: None
+        parent: None,
+        sealed: None,
+        extra: Default::default()
     }).await;
 
     let result = tasks.get_task(list_id.clone(), "123456".to_string()).await.or_else(|e| {
@@ -189,7 +205,9 @@ async fn test_edit_task() {
         uuid: list_id.clone(),
         color: 4,
         last_edited: None,
-        created: None
+        created: None,
+        sealed: None,
+        extra: Default::default()
     };
     tasks.new_list(&list).await;
 
@@ -202,7 +220,9 @@ async fn test_edit_task() {
         name: "testTask".to_string(),
         importance: 4,
         size: 1,
-        parent: None
+        parent: None,
+        sealed: None,
+        extra: Default::default()
     }).await;
 
     let result = tasks.edit_task(list_id.clone(), &TaskEntry {
@@ -214,7 +234,9 @@ async fn test_edit_task() {
         name: "testTaskk".to_string(),
         importance: 4,
         size: 1,
-        parent: None
+        parent: None,
+        sealed: None,
+        extra: Default::default()
     }).await.or_else(|e| {
         println!("{e}");
         assert!(false);
@@ -238,7 +260,9 @@ async fn test_delete_task() {
         uuid: list_id.clone(),
         color: 4,
         last_edited: None,
-        created: None
+        created: None,
+        sealed: None,
+        extra: Default::default()
     };
     tasks.new_list(&list).await;
 
@@ -251,7 +275,9 @@ async fn test_delete_task() {
         name: "testTask".to_string(),
         importance: 4,
         size: 1,
-        parent: None
+        parent: None,
+        sealed: None,
+        extra: Default::default()
     }).await;
 
     let result = tasks.delete_task(list_id.clone(), "123456".to_string()).await.or_else(|e| {
@@ -268,5 +294,71 @@ async fn test_delete_task() {
     delete_test_db();
 }
 
+#[tokio::test]
+async fn test_sync_state() {
+    let mut tasks = load_tasks().await;
+    assert!(tasks.get_sync_state("https://a.example").await.unwrap().is_none());
+
+    let mut state = SyncState::new("https://a.example");
+    state.cursor = Some("42".to_string());
+    state.push_started = Some(now());
+    state.push_position = Some("1:list:task".to_string());
+    assert!(tasks.set_sync_state(&state).await.unwrap());
+    assert_eq!(tasks.get_sync_state("https://a.example").await.unwrap(), Some(state.clone()));
+    assert!(tasks.get_sync_state("https://b.example").await.unwrap().is_none());
+
+    state.push_started = None;
+    state.push_position = None;
+    tasks.set_sync_state(&state).await.unwrap();
+    assert_eq!(tasks.get_sync_state("https://a.example").await.unwrap(), Some(state));
+
+    tasks.close().await;
+    delete_test_db();
+}
+
+#[tokio::test]
+async fn test_save_synced_task_keeps_timestamps() {
+    let mut tasks = load_tasks().await;
+    let list_id = uuid::Uuid::new_v4().to_string();
+    let task = TaskEntry {
+        completed: false,
+        created: Some(1000),
+        last_edited: Some(2000),
+        due: now(),
+        id: "123456".to_string(),
+        name: "testTask".to_string(),
+        importance: 4,
+        size: 1,
+        parent: Some("parent".to_string()),
+        sealed: None,
+        extra: Default::default()
+    };
+    // The list's table doesn't exist yet
+    assert!(tasks.save_synced_task(list_id.clone(), &task).await.unwrap());
+    assert!(tasks.save_synced_list(&ListEntry {
+        name: "test".to_string(),
+        uuid: list_id.clone(),
+        color: 4,
+        last_edited: Some(1500),
+        created: Some(1000),
+        sealed: None,
+        extra: Default::default()
+    }).await.unwrap());
+    assert_eq!(tasks.get_list(list_id.clone()).await.unwrap().unwrap().last_edited, Some(1500));
+
+    let saved = tasks.get_task(list_id.clone(), "123456".to_string()).await.unwrap().unwrap();
+    assert_eq!(saved.last_edited, Some(2000));
+    assert_eq!(saved.created, Some(1000));
+    assert_eq!(saved.parent, Some("parent".to_string()));
+
+    let between = tasks.tasks_edited_between(list_id.clone(), 1999, 2000).await.unwrap().unwrap();
+    assert_eq!(between.len(), 1);
+    let between = tasks.tasks_edited_between(list_id.clone(), 2000, 3000).await.unwrap().unwrap();
+    assert!(between.is_empty());
+
+    tasks.close().await;
+    delete_test_db();
+}
+
 // Test edits apply only to target
 // Test deletes only apply to target
\ No newline at end of file
@@ -0,0 +1,411 @@
+// End-to-end sync through the reference server (sync-server crate).
+use sync_server::ServerDb;
+
+use crate::auth::{register_account, request_password_reset, reset_password};
+use crate::crypto::{enable_encryption, lock_encryption, recover_encryption, rotate_encryption_key, unlock_encryption};
+use crate::http::{api_root, do_sync, do_sync_with, is_logged_in, login_request, log_out, preview_sync, set_server_endpoint, SyncData, SyncOptions};
+use crate::task::{apply_remote_changes, load_sync_state, switch_task_db, ItemRef, TaskEntry};
+use crate::testutils::{open_device, remove_files, test_list, test_task};
+use crate::utils::now;
+
+const SERVER_DB: &str = "testSyncServer.db";
+const DEVICE_A: &str = "testSyncA.db";
+const DEVICE_B: &str = "testSyncB.db";
+
+async fn start_server() -> String {
+    let db = ServerDb::open(SERVER_DB).await.unwrap();
+    db.add_user("alice", "hunter2").await.unwrap();
+    let root = sync_server::spawn_local(db).await.unwrap();
+    set_server_endpoint(Some(root.clone())).unwrap();
+    root
+}
+
+/// Signs in as a new session (a new "device") on `db` and syncs.
+async fn sync_device(db: &str) {
+    switch_task_db(db).await.unwrap();
+    login_request("alice", "hunter2").await.unwrap();
+    do_sync().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_login_rejects_bad_password() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    assert!(login_request("alice", "wrong").await.is_err());
+    let _ = log_out().await;
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
+
+#[tokio::test]
+async fn test_sync_requires_login() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    let _ = log_out().await;
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert!(do_sync().await.is_err());
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
+
+async fn server_session_count() -> i64 {
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", SERVER_DB)).await.unwrap();
+    let row: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM Sessions").fetch_one(&pool).await.unwrap();
+    pool.close().await;
+    row.0
+}
+
+#[tokio::test]
+async fn test_log_out_revokes_session() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    login_request("alice", "hunter2").await.unwrap();
+    assert!(is_logged_in());
+    assert_eq!(server_session_count().await, 1);
+
+    log_out().await.unwrap();
+    assert!(!is_logged_in());
+    assert_eq!(server_session_count().await, 0);
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert!(do_sync().await.is_err());
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
+
+async fn server_refresh_token() -> String {
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", SERVER_DB)).await.unwrap();
+    let row: (String,) = sqlx::query_as("SELECT refresh_token FROM Sessions").fetch_one(&pool).await.unwrap();
+    pool.close().await;
+    row.0
+}
+
+#[tokio::test]
+async fn test_expired_access_token_is_refreshed() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    login_request("alice", "hunter2").await.unwrap();
+    let refresh_token = server_refresh_token().await;
+
+    // The server forgets the access token early; the next request gets a
+    // 401, refreshes and is retried
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", SERVER_DB)).await.unwrap();
+    sqlx::query("UPDATE Sessions SET access_expires=0").execute(&pool).await.unwrap();
+    pool.close().await;
+    switch_task_db(DEVICE_A).await.unwrap();
+    do_sync().await.unwrap();
+    assert!(is_logged_in());
+    assert_ne!(server_refresh_token().await, refresh_token);
+
+    // Once the refresh token is gone too, the session ends
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", SERVER_DB)).await.unwrap();
+    sqlx::query("UPDATE Sessions SET access_expires=0, refresh_expires=0").execute(&pool).await.unwrap();
+    pool.close().await;
+    let err = do_sync().await.unwrap_err();
+    assert!(err.contains("sign in again"), "{}", err);
+    assert!(!is_logged_in());
+
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
+
+#[tokio::test]
+async fn test_failed_logins_are_rate_limited() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    for _ in 0..sync_server::MAX_FAILED_LOGINS {
+        assert_eq!(login_request("alice", "wrong").await.unwrap_err(), "Invalid credentials.");
+    }
+    let err = login_request("alice", "hunter2").await.unwrap_err();
+    assert!(err.starts_with("Too many attempts."), "{}", err);
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
+
+#[tokio::test]
+async fn test_register_and_reset_password() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    assert!(register_account("alice".to_string(), "longenough".to_string()).await.unwrap_err().contains("taken"));
+    assert!(register_account("bob".to_string(), "short".to_string()).await.is_err());
+    assert!(register_account("bob".to_string(), "longenough".to_string()).await.unwrap());
+    assert!(is_logged_in());
+    let _ = log_out().await;
+
+    // Unknown accounts look the same as known ones
+    request_password_reset("nobody".to_string()).await.unwrap();
+    request_password_reset("bob".to_string()).await.unwrap();
+    let server = ServerDb::open(SERVER_DB).await.unwrap();
+    let code = server.create_reset_code("bob").await.unwrap().unwrap();
+    assert!(reset_password("bob".to_string(), "WRONGCODE1".to_string(), "newpassword".to_string()).await.is_err());
+    reset_password("bob".to_string(), code.clone(), "newpassword".to_string()).await.unwrap();
+    // Codes only work once
+    assert!(reset_password("bob".to_string(), code, "another1".to_string()).await.is_err());
+    assert!(login_request("bob", "longenough").await.is_err());
+    login_request("bob", "newpassword").await.unwrap();
+
+    let _ = log_out().await;
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
+
+#[tokio::test]
+async fn test_sync_between_devices() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    let list_id = uuid::Uuid::new_v4().to_string();
+
+    // Device A creates a list with a task and a subtask
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list(&list_id, "School")).await.unwrap();
+    a.new_task(list_id.clone(), &test_task("parent", "Essay", None, now())).await.unwrap();
+    a.new_task(list_id.clone(), &test_task("child", "Outline", Some("parent"), now())).await.unwrap();
+    a.close().await;
+    sync_device(DEVICE_A).await;
+
+    // Device B starts empty and pulls everything
+    sync_device(DEVICE_B).await;
+    let mut b = open_device(DEVICE_B).await;
+    let list = b.get_list(list_id.clone()).await.unwrap();
+    assert!(list.is_some());
+    assert_eq!(list.unwrap().name, "School");
+    let tasks = b.get_tasks(list_id.clone()).await.unwrap().unwrap();
+    assert_eq!(tasks.len(), 2);
+    let child = tasks.iter().find(|t| t.id == "child").unwrap();
+    assert_eq!(child.parent, Some("parent".to_string()));
+
+    // Device B edits the task, A picks the edit up
+    let mut edited = b.get_task(list_id.clone(), "parent".to_string()).await.unwrap().unwrap();
+    edited.name = "Essay draft".to_string();
+    edited.completed = true;
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    b.edit_task(list_id.clone(), &edited).await.unwrap();
+    b.close().await;
+    switch_task_db(DEVICE_B).await.unwrap();
+    do_sync().await.unwrap();
+
+    sync_device(DEVICE_A).await;
+    let mut a = open_device(DEVICE_A).await;
+    let task = a.get_task(list_id.clone(), "parent".to_string()).await.unwrap().unwrap();
+    assert_eq!(task.name, "Essay draft");
+    assert!(task.completed);
+    a.close().await;
+
+    let _ = log_out().await;
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
+
+#[tokio::test]
+async fn test_paged_sync_resumes() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    let list_id = uuid::Uuid::new_v4().to_string();
+    let small = |max_pages| SyncOptions { page_size: 5, push_chunk: 7, max_pages };
+
+    // Device A pushes a list with 25 tasks in chunks of 7
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list(&list_id, "Chores")).await.unwrap();
+    for i in 0..25 {
+        a.new_task(list_id.clone(), &test_task(&format!("task{:02}", i), "Chore", None, now())).await.unwrap();
+    }
+    a.close().await;
+    switch_task_db(DEVICE_A).await.unwrap();
+    login_request("alice", "hunter2").await.unwrap();
+    assert!(do_sync_with(&small(None)).await.unwrap());
+    let state = load_sync_state(&api_root()).await.unwrap();
+    assert!(state.pushed_until > 0);
+    assert!(state.push_started.is_none());
+
+    // Device B stops after two pages of five...
+    switch_task_db(DEVICE_B).await.unwrap();
+    login_request("alice", "hunter2").await.unwrap();
+    assert!(!do_sync_with(&small(Some(2))).await.unwrap());
+    let state = load_sync_state(&api_root()).await.unwrap();
+    assert!(state.cursor.is_some());
+    let mut b = open_device(DEVICE_B).await;
+    let partial = b.get_tasks(list_id.clone()).await.unwrap().unwrap_or_default().len();
+    b.close().await;
+    assert!(partial > 0 && partial < 25);
+
+    // ...and picks up from the saved cursor
+    switch_task_db(DEVICE_B).await.unwrap();
+    assert!(do_sync_with(&small(None)).await.unwrap());
+    let mut b = open_device(DEVICE_B).await;
+    assert_eq!(b.get_tasks(list_id.clone()).await.unwrap().unwrap().len(), 25);
+    assert_eq!(b.get_list(list_id.clone()).await.unwrap().unwrap().name, "Chores");
+    b.close().await;
+
+    let _ = log_out().await;
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
+
+#[tokio::test]
+async fn test_remote_changes_in_seconds() {
+    remove_files(&[DEVICE_A]);
+    let edited = 1_718_000_000_000;
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("list", "List")).await.unwrap();
+    for id in ["older", "newer"] {
+        a.save_synced_task("list".to_string(), &TaskEntry { last_edited: Some(edited), ..test_task(id, "Local", None, 0) }).await.unwrap();
+    }
+    a.close().await;
+
+    // Servers that predate millisecond timestamps still win when they're newer
+    switch_task_db(DEVICE_A).await.unwrap();
+    let mut data = SyncData::new();
+    data.tasks.insert("list".to_string(), vec![
+        TaskEntry { last_edited: Some(edited / 1000 - 100), ..test_task("older", "Remote", None, 0) },
+        TaskEntry { last_edited: Some(edited / 1000 + 100), ..test_task("newer", "Remote", None, 0) }
+    ]);
+    assert_eq!(apply_remote_changes(&data, 0).await.unwrap(), 1);
+    let mut a = open_device(DEVICE_A).await;
+    assert_eq!(a.get_task("list".to_string(), "older".to_string()).await.unwrap().unwrap().name, "Local");
+    assert_eq!(a.get_task("list".to_string(), "newer".to_string()).await.unwrap().unwrap().name, "Remote");
+    a.close().await;
+    remove_files(&[DEVICE_A]);
+}
+
+/// (name, sealed) of every task the server holds.
+async fn server_tasks() -> Vec<(String, Option<String>)> {
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", SERVER_DB)).await.unwrap();
+    let rows = sqlx::query_as("SELECT name, sealed FROM Tasks").fetch_all(&pool).await.unwrap();
+    pool.close().await;
+    rows
+}
+
+#[tokio::test]
+async fn test_encrypted_sync() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    lock_encryption();
+    let list_id = uuid::Uuid::new_v4().to_string();
+
+    // Device A turns on encryption and pushes its data sealed
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list(&list_id, "Secret plans")).await.unwrap();
+    a.new_task(list_id.clone(), &test_task("cake", "Hide the cake", None, now())).await.unwrap();
+    a.close().await;
+    switch_task_db(DEVICE_A).await.unwrap();
+    login_request("alice", "hunter2").await.unwrap();
+    let phrase = enable_encryption("correct horse".to_string()).await.unwrap();
+    do_sync().await.unwrap();
+    let stored = server_tasks().await;
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].0, "");
+    assert!(stored[0].1.as_ref().unwrap().starts_with("v1.1."));
+
+    // Device B can't sync until it unlocks the account's keyring
+    lock_encryption();
+    switch_task_db(DEVICE_B).await.unwrap();
+    login_request("alice", "hunter2").await.unwrap();
+    assert!(do_sync().await.is_err());
+    assert!(unlock_encryption("wrong horse".to_string()).await.is_err());
+    unlock_encryption("correct horse".to_string()).await.unwrap();
+    do_sync().await.unwrap();
+    let mut b = open_device(DEVICE_B).await;
+    assert_eq!(b.get_list(list_id.clone()).await.unwrap().unwrap().name, "Secret plans");
+    assert_eq!(b.get_task(list_id.clone(), "cake".to_string()).await.unwrap().unwrap().name, "Hide the cake");
+    b.close().await;
+
+    // Rotating re-sends everything under the new key
+    switch_task_db(DEVICE_B).await.unwrap();
+    assert_eq!(rotate_encryption_key("correct horse".to_string()).await.unwrap(), 2);
+    do_sync().await.unwrap();
+    assert!(server_tasks().await[0].1.as_ref().unwrap().starts_with("v1.2."));
+
+    // The recovery phrase still works after rotation and sets a new passphrase
+    lock_encryption();
+    recover_encryption(phrase, "battery staple".to_string()).await.unwrap();
+    lock_encryption();
+    assert!(unlock_encryption("correct horse".to_string()).await.is_err());
+    unlock_encryption("battery staple".to_string()).await.unwrap();
+
+    lock_encryption();
+    let _ = log_out().await;
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
+
+fn names(items: &[ItemRef]) -> Vec<&str> {
+    let mut names: Vec<&str> = items.iter().map(|i| i.name.as_str()).collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn test_preview_sync() {
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+    start_server().await;
+    let list_id = uuid::Uuid::new_v4().to_string();
+    let pause = || std::thread::sleep(std::time::Duration::from_millis(5));
+
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list(&list_id, "Chores")).await.unwrap();
+    a.new_task(list_id.clone(), &test_task("dishes", "Dishes", None, now())).await.unwrap();
+    a.new_task(list_id.clone(), &test_task("laundry", "Laundry", None, now())).await.unwrap();
+    a.close().await;
+    sync_device(DEVICE_A).await;
+    sync_device(DEVICE_B).await;
+
+    // A edits the dishes, then B edits them later and adds a task
+    pause();
+    let mut a = open_device(DEVICE_A).await;
+    let mut dishes = a.get_task(list_id.clone(), "dishes".to_string()).await.unwrap().unwrap();
+    dishes.name = "Dishes (A)".to_string();
+    a.edit_task(list_id.clone(), &dishes).await.unwrap();
+    a.close().await;
+    pause();
+    let mut b = open_device(DEVICE_B).await;
+    let mut dishes = b.get_task(list_id.clone(), "dishes".to_string()).await.unwrap().unwrap();
+    dishes.name = "Dishes (B)".to_string();
+    b.edit_task(list_id.clone(), &dishes).await.unwrap();
+    b.new_task(list_id.clone(), &test_task("trash", "Trash", None, now())).await.unwrap();
+    b.close().await;
+    switch_task_db(DEVICE_B).await.unwrap();
+    do_sync().await.unwrap();
+
+    // A also renames the laundry and adds a list
+    pause();
+    let other_id = uuid::Uuid::new_v4().to_string();
+    let mut a = open_device(DEVICE_A).await;
+    let mut laundry = a.get_task(list_id.clone(), "laundry".to_string()).await.unwrap().unwrap();
+    laundry.name = "Laundry (A)".to_string();
+    a.edit_task(list_id.clone(), &laundry).await.unwrap();
+    a.new_list(&test_list(&other_id, "Garden")).await.unwrap();
+    a.close().await;
+
+    switch_task_db(DEVICE_A).await.unwrap();
+    login_request("alice", "hunter2").await.unwrap();
+    let before = load_sync_state(&api_root()).await.unwrap();
+    let preview = preview_sync().await.unwrap();
+    assert_eq!(names(&preview.local.tasks.created), vec!["Trash"]);
+    assert_eq!(names(&preview.local.tasks.overwritten), vec!["Dishes (A)"]);
+    assert!(preview.local.tasks.updated.is_empty());
+    // Lists B only echoed back are the same version
+    assert!(preview.local.lists.created.is_empty() && preview.local.lists.updated.is_empty());
+    assert_eq!(names(&preview.remote.tasks.updated), vec!["Laundry (A)"]);
+    assert_eq!(names(&preview.remote.lists.created), vec!["Garden"]);
+    assert!(preview.remote.tasks.overwritten.is_empty());
+
+    // Nothing was saved or pushed
+    let after = load_sync_state(&api_root()).await.unwrap();
+    assert_eq!(after.cursor, before.cursor);
+    assert_eq!(after.pushed_until, before.pushed_until);
+    assert!(!server_tasks().await.iter().any(|t| t.0 == "Laundry (A)"));
+    let mut a = open_device(DEVICE_A).await;
+    assert_eq!(a.get_task(list_id.clone(), "dishes".to_string()).await.unwrap().unwrap().name, "Dishes (A)");
+    assert!(a.get_task(list_id.clone(), "trash".to_string()).await.unwrap().is_none());
+    a.close().await;
+
+    // After a real sync there's nothing left to do
+    switch_task_db(DEVICE_A).await.unwrap();
+    do_sync().await.unwrap();
+    let preview = preview_sync().await.unwrap();
+    assert!(preview.local.tasks.created.is_empty() && preview.local.tasks.overwritten.is_empty());
+    assert!(preview.remote.tasks.updated.is_empty() && preview.remote.lists.created.is_empty());
+
+    let _ = log_out().await;
+    let _ = set_server_endpoint(None);
+    remove_files(&[SERVER_DB, DEVICE_A, DEVICE_B]);
+}
@@ -14,6 +14,8 @@ fn test_from_entry() {
         parent: None,
         last_edited: Some(now()),
         created: Some(now()),
+        sealed: None,
+        extra: Default::default()
     };
     let record = TaskRecord::from_entry(&entry);
     assert_eq!(record.name, entry.name);
@@ -37,6 +39,8 @@ fn test_from_entries_basic() {
             parent: None,
             last_edited: Some(now()),
             created: Some(now()),
+            sealed: None,
+            extra: Default::default()
         },
         TaskEntry {
             name: "test2".to_owned(),
@@ -48,6 +52,8 @@ fn test_from_entries_basic() {
             parent: None,
             last_edited: Some(now()),
             created: Some(now()),
+            sealed: None,
+            extra: Default::default()
         },
         TaskEntry {
             name: "test3".to_owned(),
@@ -59,6 +65,8 @@ fn test_from_entries_basic() {
             parent: Some("123456".to_owned()),
             last_edited: Some(now()),
             created: Some(now()),
+            sealed: None,
+            extra: Default::default()
         },
     ]);
     let records = load_records(&entries);
@@ -72,3 +80,54 @@ fn test_from_entries_basic() {
     }
     // print!("{}", serde_json::to_string(&records).unwrap());
 }
+
+#[test]
+fn test_chunk_changes() {
+    let list = ListEntry {
+        name: "test".to_owned(),
+        uuid: "b-list".to_owned(),
+        color: 1,
+        last_edited: Some(now()),
+        created: Some(now()),
+        sealed: None,
+        extra: Default::default()
+    };
+    let task = |id: &str| TaskEntry {
+        name: "test".to_owned(),
+        size: 4,
+        importance: 2,
+        due: now(),
+        completed: false,
+        id: id.to_owned(),
+        parent: None,
+        last_edited: Some(now()),
+        created: Some(now()),
+        sealed: None,
+        extra: Default::default()
+    };
+    let changes = || {
+        let mut changes = vec![
+            PendingChange::Task("a-list".to_owned(), task("2")),
+            PendingChange::Task("a-list".to_owned(), task("1")),
+            PendingChange::List(list.clone()),
+        ];
+        changes.sort_by_key(|c| c.sort_key());
+        changes
+    };
+
+    let chunks = chunk_changes(changes(), None, 2);
+    assert_eq!(chunks.len(), 2);
+    // Lists go first
+    assert_eq!(chunks[0].0.lists.len(), 1);
+    assert_eq!(chunks[0].0.tasks.get("a-list").unwrap()[0].id, "1");
+    assert_eq!(chunks[0].1, "1:a-list:1");
+    assert_eq!(chunks[1].0.tasks.get("a-list").unwrap()[0].id, "2");
+
+    // Resuming skips what was already acknowledged
+    let chunks = chunk_changes(changes(), Some("1:a-list:1"), 2);
+    assert_eq!(chunks.len(), 1);
+    assert!(chunks[0].0.lists.is_empty());
+    assert_eq!(chunks[0].1, "1:a-list:2");
+
+    assert!(chunk_changes(changes(), Some("1:a-list:2"), 2).is_empty());
+}
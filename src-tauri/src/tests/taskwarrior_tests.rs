@@ -0,0 +1,197 @@
+// Taskwarrior JSON export and import, and round trips both ways.
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::ics::parse_datetime;
+use crate::task::{get_saved_lists, get_saved_tasks, switch_task_db, ListEntry, TaskEntry};
+use crate::taskwarrior::{export_taskwarrior, import_taskwarrior_file, read_taskwarrior, taskwarrior_to_lists, tasks_to_taskwarrior, write_taskwarrior, TwTask};
+use crate::testutils::{dated_task, find_task, open_device, remove_files, test_list};
+use crate::utils::now;
+
+const SAMPLE: &str = include_str!("golden/taskwarrior_export.json");
+const DEVICE_A: &str = "testTaskwarriorA.db";
+const DEVICE_B: &str = "testTaskwarriorB.db";
+const FILE: &str = "testTaskwarrior.json";
+
+/// The fields that have to survive a trip through Task Manager.
+fn tw_fields(task: &TwTask) -> String {
+    let mut depends = task.depends.clone();
+    let mut tags = task.tags.clone();
+    depends.sort();
+    tags.sort();
+    format!("{} {:?} {:?} {:?} {:?} {} {:?} {:?} {:?}", task.uuid, task.description, task.project, task.priority, task.due, task.status, depends, tags, task.entry)
+}
+
+#[test]
+fn test_read_taskwarrior() {
+    let start = now();
+    let mut dropped = BTreeMap::new();
+    let lists = taskwarrior_to_lists(&read_taskwarrior(SAMPLE).unwrap(), "Taskwarrior", &mut dropped);
+    let names: Vec<&str> = lists.iter().map(|(l, _)| l.name.as_str()).collect();
+    assert_eq!(names, vec!["School", "Home.Family", "Taskwarrior", "Home"]);
+
+    let school = &lists[0].1;
+    assert_eq!(school.len(), 3);
+    let essay = find_task(school, "Write essay #english");
+    assert_eq!(essay.id, "9f6c2a1e-3b7d-4c52-8e0f-1a2b3c4d5e6f");
+    assert_eq!((essay.importance, essay.due, essay.created), (4, parse_datetime("20240610T143000Z").unwrap(), parse_datetime("20240601T090000Z")));
+    // Dependencies are subtasks
+    let outline = find_task(school, "Outline");
+    let sources = find_task(school, "Find sources");
+    assert_eq!((outline.importance, outline.parent.as_ref()), (3, Some(&essay.id)));
+    assert_eq!((sources.importance, sources.completed, sources.parent.as_ref()), (2, true, Some(&essay.id)));
+    // Tags already in the description aren't added again
+    assert_eq!(lists[1].1[0].name, "Call #mom about the trip #phone");
+    assert_eq!(lists[1].1[0].importance, 1);
+    let milk = &lists[2].1[0];
+    assert!(milk.name == "Buy milk #errands" && (start..=now()).contains(&milk.due));
+    assert_eq!(lists[3].1.len(), 1);
+
+    let expected: BTreeMap<String, usize> = [
+        ("annotations", 1), ("deleted tasks", 1), ("imask", 1), ("parent", 1),
+        ("recur", 1), ("recurring templates", 1), ("rtype", 1), ("wait", 1)
+    ].iter().map(|(k, v)| (k.to_string(), *v)).collect();
+    assert_eq!(dropped, expected);
+
+    // Older versions: one task per line, dependencies as a string
+    let old = "{\"description\":\"A\",\"status\":\"pending\",\"uuid\":\"a\",\"depends\":\"b,c\"}\n{\"description\":\"B\",\"status\":\"pending\",\"uuid\":\"b\"}\n";
+    let tasks = read_taskwarrior(old).unwrap();
+    assert_eq!(tasks[0].depends, vec!["b", "c"]);
+    let mut dropped = BTreeMap::new();
+    let lists = taskwarrior_to_lists(&tasks, "x", &mut dropped);
+    assert_eq!(find_task(&lists[0].1, "B").parent.as_deref(), Some("a"));
+    assert_eq!(dropped["dependencies"], 1);
+    assert!(read_taskwarrior("{\"description\": 3}").is_err());
+}
+
+#[test]
+fn test_write_taskwarrior() {
+    let mut done = TaskEntry { importance: 0, ..dated_task("274018", "Find sources", Some("531902")) };
+    done.completed = true;
+    let lists = vec![(test_list("school", "School"), vec![
+        TaskEntry { importance: 4, ..dated_task("531902", "Essay #English #final", None) },
+        dated_task("885127", "Call #mom", Some("531902")),
+        done
+    ])];
+    let tasks = tasks_to_taskwarrior(&lists);
+    // Ids that aren't UUIDs get the same made-up one each time
+    assert_eq!(tasks, tasks_to_taskwarrior(&lists));
+    let essay = &tasks[0];
+    assert!(uuid::Uuid::try_parse(&essay.uuid).is_ok());
+    assert_eq!(essay.tmid.as_deref(), Some("531902"));
+    assert_eq!((essay.description.as_str(), essay.tags.clone()), ("Essay", vec!["English".to_string(), "final".to_string()]));
+    assert_eq!((essay.priority.as_deref(), essay.due.as_deref(), essay.entry.as_deref()), (Some("H"), Some("20240610T143000Z"), Some("20240601T090000Z")));
+    let mut depends = essay.depends.clone();
+    depends.sort();
+    let mut children = vec![tasks[1].uuid.clone(), tasks[2].uuid.clone()];
+    children.sort();
+    assert_eq!(depends, children);
+    assert_eq!((tasks[1].description.as_str(), tasks[1].tags.clone(), tasks[1].priority.as_ref()), ("Call", vec!["mom".to_string()], None));
+    assert_eq!((tasks[2].status.as_str(), tasks[2].priority.as_deref(), tasks[2].end.as_deref()), ("completed", Some("L"), Some("20240602T100000Z")));
+
+    let text = write_taskwarrior(&tasks).unwrap();
+    assert!(text.starts_with("[\n{") && text.lines().count() == 5);
+    assert_eq!(read_taskwarrior(&text).unwrap(), tasks);
+}
+
+#[tokio::test]
+async fn test_round_trip_through_taskwarrior() {
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&ListEntry { color: 5, ..test_list("school", "School") }).await.unwrap();
+    // Another list with the same name stays apart
+    a.new_list(&test_list("school2", "School")).await.unwrap();
+    let mut tasks = vec![
+        TaskEntry { importance: 4, ..dated_task("531902", "Essay #english", None) },
+        TaskEntry { importance: 3, ..dated_task("885127", "Outline", Some("531902")) },
+        TaskEntry { importance: 0, ..dated_task("274018", "Quiz\nChapter 4", None) },
+        TaskEntry { importance: 1, ..dated_task("660431", "Sources #library #Books", Some("885127")) }
+    ];
+    tasks[2].completed = true;
+    tasks[3].size = 4;
+    // Taskwarrior times are to the second, so keep these whole seconds
+    for task in &tasks {
+        a.save_synced_task("school".to_string(), task).await.unwrap();
+    }
+    a.save_synced_task("school2".to_string(), &dated_task("117350", "Lab report", None)).await.unwrap();
+    a.close().await;
+
+    switch_task_db(DEVICE_A).await.unwrap();
+    // Like the other exports, an unknown list is an error
+    assert!(export_taskwarrior(vec!["gone".to_string()], FILE.to_string()).await.unwrap_err().contains("no list"));
+    assert_eq!(export_taskwarrior(vec![], FILE.to_string()).await.unwrap(), 5);
+    let exported = get_saved_tasks("school").await.unwrap();
+
+    switch_task_db(DEVICE_B).await.unwrap();
+    let res = import_taskwarrior_file(FILE).await.unwrap();
+    assert_eq!((res.lists, res.tasks), (2, 5));
+    assert!(res.dropped.is_empty(), "{:?}", res.dropped);
+    let lists = get_saved_lists().await.unwrap();
+    let list = lists.iter().find(|l| l.uuid == "school").unwrap();
+    assert_eq!((list.name.as_str(), list.color), ("School", 5));
+    let other = lists.iter().find(|l| l.uuid == "school2").unwrap();
+    assert_eq!((other.name.as_str(), other.color), ("School", 2));
+    let imported = get_saved_tasks("school").await.unwrap();
+    for task in &exported {
+        let copy = imported.iter().find(|t| t.id == task.id).unwrap();
+        assert_eq!(
+            (&copy.name, copy.size, copy.importance, copy.due, copy.completed, &copy.parent, copy.created, copy.last_edited),
+            (&task.name, task.size, task.importance, task.due, task.completed, &task.parent, task.created, task.last_edited)
+        );
+    }
+    assert_eq!(get_saved_tasks("school2").await.unwrap()[0].name, "Lab report");
+
+    // Importing again updates the same lists and tasks
+    let mut file = read_taskwarrior(&fs::read_to_string(FILE).unwrap()).unwrap();
+    file.iter_mut().find(|t| t.tmid.as_deref() == Some("531902")).unwrap().description = "Final essay".to_string();
+    fs::write(FILE, write_taskwarrior(&file).unwrap()).unwrap();
+    let again = import_taskwarrior_file(FILE).await.unwrap();
+    assert_eq!((again.lists, again.tasks), (2, 5));
+    assert_eq!(get_saved_lists().await.unwrap().len(), 2);
+    let updated = get_saved_tasks("school").await.unwrap();
+    assert_eq!(updated.len(), 4);
+    assert_eq!(find_task(&updated, "Final essay #english").id, "531902");
+
+    // Without list ids, the tasks go in a new list, and ids taken by
+    // another list aren't reused
+    for task in &mut file {
+        task.tmlist = None;
+    }
+    fs::write(FILE, write_taskwarrior(&file).unwrap()).unwrap();
+    import_taskwarrior_file(FILE).await.unwrap();
+    let lists = get_saved_lists().await.unwrap();
+    assert_eq!(lists.len(), 3);
+    let copy = get_saved_tasks(&lists.iter().find(|l| !l.uuid.starts_with("school")).unwrap().uuid).await.unwrap();
+    assert!(copy.iter().all(|t| !imported.iter().any(|i| i.id == t.id)));
+    let essay = find_task(&copy, "Final essay #english");
+    assert_eq!(find_task(&copy, "Outline").parent.as_ref(), Some(&essay.id));
+
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+}
+
+#[tokio::test]
+async fn test_round_trip_from_taskwarrior() {
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+    fs::write(FILE, SAMPLE).unwrap();
+    switch_task_db(DEVICE_A).await.unwrap();
+    let res = import_taskwarrior_file(FILE).await.unwrap();
+    assert_eq!((res.lists, res.tasks), (4, 6));
+    export_taskwarrior(vec![], FILE.to_string()).await.unwrap();
+    let exported = read_taskwarrior(&fs::read_to_string(FILE).unwrap()).unwrap();
+
+    let original = read_taskwarrior(SAMPLE).unwrap();
+    let kept: Vec<&TwTask> = original.iter().filter(|t| t.status != "deleted" && t.status != "recurring").collect();
+    assert_eq!(exported.len(), kept.len());
+    for task in kept {
+        let copy = exported.iter().find(|t| t.uuid == task.uuid).unwrap();
+        assert!(copy.tmid.is_none());
+        // Task Manager needs a list and a due date
+        if task.project.is_none() || task.due.is_none() {
+            assert_eq!((copy.project.as_deref(), &copy.description), (Some("testTaskwarrior"), &task.description));
+            continue;
+        }
+        assert_eq!(tw_fields(copy), tw_fields(task));
+    }
+
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+}
@@ -0,0 +1,220 @@
+// The todo.txt parser and serializer, and importing/exporting with it.
+use std::fs;
+
+use proptest::prelude::*;
+
+use crate::task::{get_saved_lists, get_saved_tasks, switch_task_db, TaskEntry};
+use crate::testutils::{open_device, remove_files, test_list, test_task};
+use crate::utils::now;
+use crate::todotxt::{export_todotxt, import_todotxt_file, items_to_lists, parse, serialize, tasks_to_items, Date, TodoItem};
+
+const DEVICE_A: &str = "testTodoTxtA.db";
+const DEVICE_B: &str = "testTodoTxtB.db";
+const FILE: &str = "testTodoTxt.txt";
+
+fn date(text: &str) -> Date {
+    Date::parse(text).unwrap()
+}
+
+fn dated_task(id: &str, name: &str, parent: Option<&str>) -> TaskEntry {
+    TaskEntry {
+        last_edited: Some(date("2024-06-02").to_ms(0)),
+        created: Some(date("2024-06-01").to_ms(0)),
+        ..test_task(id, name, parent, date("2024-06-10").to_ms(0))
+    }
+}
+
+#[test]
+fn test_parse_line() {
+    let item = TodoItem::parse("x (A) 2016-05-20 2016-04-30 measure space for +chapelShelving @chapel due:2016-05-30").unwrap();
+    assert_eq!(item, TodoItem {
+        completed: true,
+        priority: Some('A'),
+        completion_date: Some(date("2016-05-20")),
+        creation_date: Some(date("2016-04-30")),
+        description: "measure space for +chapelShelving @chapel due:2016-05-30".to_string()
+    });
+    assert_eq!(item.projects(), vec!["chapelShelving"]);
+    assert_eq!(item.contexts(), vec!["chapel"]);
+    assert_eq!(item.tags(), vec![("due", "2016-05-30")]);
+
+    let item = TodoItem::parse("(B) 2024-01-31 Call Mom @phone +Family see https://example.com at 10:30").unwrap();
+    assert_eq!((item.priority, item.creation_date, item.completion_date), (Some('B'), Some(date("2024-01-31")), None));
+    assert!(item.tags().is_empty());
+
+    // Not a priority or date where they'd have to be
+    for line in ["Really gotta call Mom (A) @phone", "(a) lowercase", "xylophone lesson", "2024-13-01 not a date", "2024-02-30 nor this"] {
+        let item = TodoItem::parse(line).unwrap();
+        assert_eq!(item.description, line);
+        assert!(!item.completed && item.priority.is_none() && item.creation_date.is_none());
+    }
+    // One date on a completed task is when it was completed
+    let item = TodoItem::parse("x 2024-06-01 Done").unwrap();
+    assert_eq!((item.completion_date, item.creation_date), (Some(date("2024-06-01")), None));
+    assert_eq!(TodoItem::parse("   "), None);
+    assert_eq!(parse("a\n\n  \r\nb\r\n").len(), 2);
+}
+
+#[test]
+fn test_items_to_lists() {
+    let text = "(A) Essay @english +School due:2024-06-10 id:1
+Outline +School p:1 size:0
+x 2024-06-03 2024-06-01 Quiz +School pri:B
+Dishes @home
+(F) Stray p:9 +Other +School
+";
+    let lists = items_to_lists(&parse(text), "todo", 120);
+    let names: Vec<&str> = lists.iter().map(|l| l.list.name.as_str()).collect();
+    assert_eq!(names, vec!["School", "todo"]);
+    let school = &lists[0].tasks;
+    assert_eq!(school[0].name, "Essay #english");
+    assert_eq!(school[0].importance, 4);
+    assert_eq!(school[0].due, date("2024-06-10").to_ms(120));
+    assert_eq!(school[1].name, "Outline");
+    assert_eq!(school[1].size, 0);
+    assert_eq!(school[1].importance, 2);
+    assert_eq!(school[1].parent.as_ref(), Some(&school[0].id));
+    assert_eq!((school[2].name.as_str(), school[2].completed, school[2].importance), ("Quiz", true, 3));
+    assert_eq!(lists[1].tasks[0].name, "Dishes #home");
+    // The last project is the list
+    let stray = &school[3];
+    assert_eq!((stray.name.as_str(), stray.importance, stray.parent.as_ref()), ("Stray +Other", 0, None));
+}
+
+#[tokio::test]
+async fn test_todotxt_round_trip() {
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+    let mut a = open_device(DEVICE_A).await;
+    a.new_list(&test_list("school", "School Spring")).await.unwrap();
+    a.new_task("school".to_string(), &dated_task("essay", "Essay #english", None)).await.unwrap();
+    a.new_task("school".to_string(), &dated_task("outline", "Outline", Some("essay"))).await.unwrap();
+    let mut quiz = dated_task("quiz", "Quiz\nChapter 4", None);
+    quiz.completed = true;
+    quiz.size = 4;
+    quiz.importance = 0;
+    a.new_task("school".to_string(), &quiz).await.unwrap();
+    a.close().await;
+
+    switch_task_db(DEVICE_A).await.unwrap();
+    assert_eq!(export_todotxt(vec![], FILE.to_string(), 0).await.unwrap(), 3);
+    let text = fs::read_to_string(FILE).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3);
+    // Saving new tasks stamps them as created today
+    let today = Date::from_ms(now(), 0);
+    assert!(lines.contains(&format!("(B) {} Essay @english +School_Spring due:2024-06-10 id:1", today).as_str()), "{}", text);
+    assert!(lines.contains(&format!("(B) {} Outline +School_Spring due:2024-06-10 p:1", today).as_str()), "{}", text);
+    assert!(lines.contains(&format!("x (E) {} {} Quiz Chapter 4 +School_Spring due:2024-06-10 size:4", today, today).as_str()), "{}", text);
+
+    switch_task_db(DEVICE_B).await.unwrap();
+    let res = import_todotxt_file(FILE, 0).await.unwrap();
+    assert_eq!((res.lists, res.tasks), (1, 3));
+    let list = get_saved_lists().await.unwrap().remove(0);
+    assert_eq!(list.name, "School_Spring");
+    let tasks = get_saved_tasks(&list.uuid).await.unwrap();
+    let essay = tasks.iter().find(|t| t.name == "Essay #english").unwrap();
+    let outline = tasks.iter().find(|t| t.name == "Outline").unwrap();
+    assert_eq!(outline.parent.as_ref(), Some(&essay.id));
+    assert_eq!((essay.importance, essay.due), (3, date("2024-06-10").to_ms(0)));
+    let quiz = tasks.iter().find(|t| t.name == "Quiz Chapter 4").unwrap();
+    assert_eq!((quiz.completed, quiz.size, quiz.importance), (true, 4, 0));
+
+    remove_files(&[DEVICE_A, DEVICE_B, FILE]);
+}
+
+fn arb_date() -> impl Strategy<Value = Date> {
+    // 1900-01-01 to 2199-12-31
+    (-25567i64..84005).prop_map(Date)
+}
+
+fn arb_word() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-zA-Z0-9.,!?'()-]{1,8}",
+        "[+@][a-zA-Z0-9_]{1,6}",
+        "[a-z]{1,4}:[a-zA-Z0-9-]{1,10}",
+        Just("x".to_string()),
+        "\\([A-Z]\\)",
+        arb_date().prop_map(|d| d.to_string())
+    ]
+}
+
+/// Descriptions whose first word can't be read as part of the prefix
+fn arb_description() -> impl Strategy<Value = String> {
+    (prop::collection::vec(arb_word(), 0..8), "[a-zA-Z][a-zA-Z ]{0,6}").prop_map(|(words, first)| {
+        if words.is_empty() && first.len() % 2 == 0 {
+            return String::new();
+        }
+        std::iter::once(first.trim_end().to_string()).chain(words).collect::<Vec<_>>().join(" ")
+    }).prop_filter("prefix-like first word", |d| {
+        let first = d.split_whitespace().next().unwrap_or_default();
+        first != "x" && Date::parse(first).is_none()
+    })
+}
+
+fn arb_item() -> impl Strategy<Value = TodoItem> {
+    (any::<bool>(), prop::option::of(prop::char::range('A', 'Z')), prop::option::of(arb_date()), prop::option::of(arb_date()), arb_description())
+        .prop_map(|(completed, priority, done, created, description)| TodoItem {
+            completed,
+            priority,
+            completion_date: if completed { done } else { None },
+            // A completed item's creation date comes with a completion date
+            creation_date: if completed && done.is_none() { None } else { created },
+            description
+        })
+        .prop_filter("blank line", |i| !i.to_line().trim().is_empty())
+}
+
+proptest! {
+    #[test]
+    fn prop_item_round_trip(item in arb_item()) {
+        prop_assert_eq!(TodoItem::parse(&item.to_line()), Some(item));
+    }
+
+    #[test]
+    fn prop_file_round_trip(items in prop::collection::vec(arb_item(), 0..20)) {
+        prop_assert_eq!(parse(&serialize(&items)), items);
+    }
+
+    #[test]
+    fn prop_parse_is_stable(line in "[ x(A-Z)0-9:+@a-z-]{0,40}") {
+        // Whatever a line is read as, writing and reading it again agrees
+        if let Some(item) = TodoItem::parse(&line) {
+            let again = TodoItem::parse(&item.to_line());
+            prop_assert_eq!(again, Some(item));
+        }
+    }
+
+    #[test]
+    fn prop_dates(days in -25567i64..84005) {
+        let text = Date(days).to_string();
+        prop_assert_eq!(Date::parse(&text), Some(Date(days)));
+        prop_assert_eq!(Date::from_ms(Date(days).to_ms(-300), -300), Date(days));
+    }
+
+    #[test]
+    fn prop_tasks_survive_items(names in prop::collection::vec("[a-zA-Z#+ ]{1,20}", 1..10), importance in 0..5i32, size in 0..5i32) {
+        let list = test_list("l", "List");
+        let tasks: Vec<TaskEntry> = names.iter().enumerate().map(|(i, n)| {
+            let mut t = dated_task(&i.to_string(), n, if i > 0 { Some("0") } else { None });
+            t.importance = importance;
+            t.size = size;
+            t
+        }).collect();
+        let items = parse(&serialize(&tasks_to_items(&[(list, tasks.clone())], 60)));
+        let lists = items_to_lists(&items, "fallback", 60);
+        prop_assert_eq!(lists.len(), 1);
+        prop_assert_eq!(&lists[0].list.name, "List");
+        let read = &lists[0].tasks;
+        prop_assert_eq!(read.len(), tasks.len());
+        let mut read_names: Vec<&str> = read.iter().map(|t| t.name.as_str()).collect();
+        let mut tidied: Vec<String> = names.iter().map(|n| n.split_whitespace().collect::<Vec<_>>().join(" ")).collect();
+        read_names.sort();
+        tidied.sort();
+        prop_assert_eq!(read_names, tidied);
+        // Due dates keep their day, at local midnight
+        let due = Date::from_ms(tasks[0].due, 60).to_ms(60);
+        prop_assert!(read.iter().all(|t| t.importance == importance && t.size == size && t.due == due));
+        // Parents come first, so the first task is the only top-level one
+        prop_assert!(read[1..].iter().all(|t| t.parent.as_ref() == Some(&read[0].id)));
+    }
+}
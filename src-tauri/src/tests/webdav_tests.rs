@@ -0,0 +1,207 @@
+// WebDAV snapshots against a small in-process WebDAV server.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use axum::{body::Bytes, extract::State, http::{HeaderMap, Method, StatusCode as MockStatus, Uri}, response::{IntoResponse, Response}, Router};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::json;
+
+use crate::algorithm::switch_history_db;
+use crate::crypto::{seal_with, KdfParams};
+use crate::history::History;
+use crate::ics::parse_datetime;
+use crate::task::{get_saved_list, switch_task_db};
+use crate::restore::snapshots_to_keep;
+use crate::testutils::{open_device, test_list};
+use crate::webdav::{backup_to, restore_from, WebDavTarget};
+
+const DIR: &str = "testWebDav";
+const FOLDER: &str = "/dav/backups/";
+const PASSPHRASE: &str = "correct horse";
+const DAY: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Default)]
+struct MockWebDav {
+    folder: bool,
+    /// File name → contents
+    files: BTreeMap<String, Vec<u8>>
+}
+
+type Dav = Arc<Mutex<MockWebDav>>;
+
+async fn mock_webdav(State(dav): State<Dav>, method: Method, uri: Uri, headers: HeaderMap, body: Bytes) -> Response {
+    let auth = format!("Basic {}", STANDARD.encode("alice:secret"));
+    if headers.get("authorization").and_then(|a| a.to_str().ok()) != Some(&auth) {
+        return MockStatus::UNAUTHORIZED.into_response();
+    }
+    let mut dav = dav.lock().unwrap();
+    let name = match uri.path().strip_prefix(FOLDER) {
+        Some(name) => name.to_string(),
+        None if uri.path() == FOLDER.trim_end_matches('/') => String::new(),
+        None => return MockStatus::NOT_FOUND.into_response()
+    };
+    match (method.as_str(), name.is_empty()) {
+        ("MKCOL", true) if dav.folder => MockStatus::METHOD_NOT_ALLOWED.into_response(),
+        ("MKCOL", true) => {
+            dav.folder = true;
+            MockStatus::CREATED.into_response()
+        },
+        (_, _) if !dav.folder => MockStatus::NOT_FOUND.into_response(),
+        ("PROPFIND", true) => {
+            let mut body = format!(r#"<?xml version="1.0"?><D:multistatus xmlns:D="DAV:"><D:response><D:href>{}</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"#, FOLDER);
+            for file in dav.files.keys() {
+                body.push_str(&format!(r#"<D:response><D:href>{}{}</D:href><D:propstat><D:prop><D:resourcetype/></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"#, FOLDER, file));
+            }
+            body.push_str("</D:multistatus>");
+            (MockStatus::MULTI_STATUS, body).into_response()
+        },
+        ("PUT", false) => {
+            dav.files.insert(name, body.to_vec());
+            MockStatus::CREATED.into_response()
+        },
+        ("GET", false) => match dav.files.get(&name) {
+            Some(data) => (MockStatus::OK, data.clone()).into_response(),
+            None => MockStatus::NOT_FOUND.into_response()
+        },
+        ("DELETE", false) => match dav.files.remove(&name) {
+            Some(_) => MockStatus::NO_CONTENT.into_response(),
+            None => MockStatus::NOT_FOUND.into_response()
+        },
+        _ => MockStatus::METHOD_NOT_ALLOWED.into_response()
+    }
+}
+
+async fn start_mock() -> (Dav, String) {
+    let dav: Dav = Arc::new(Mutex::new(MockWebDav::default()));
+    let app = Router::new().fallback(mock_webdav).with_state(dav.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}{}", listener.local_addr().unwrap(), FOLDER);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (dav, url)
+}
+
+/// Fresh task and history databases in `DIR`, opened as the globals.
+async fn setup() {
+    let _ = fs::remove_dir_all(DIR);
+    fs::create_dir_all(DIR).unwrap();
+    let mut tasks = open_device(&format!("{}/tasks.db", DIR)).await;
+    tasks.new_list(&test_list("work", "Work")).await.unwrap();
+    tasks.close().await;
+    let mut history = History::new();
+    history.load(&format!("{}/history2.db", DIR)).await.unwrap();
+    history.close().await;
+    switch_task_db(&format!("{}/tasks.db", DIR)).await.unwrap();
+    switch_history_db(&format!("{}/history2.db", DIR)).await.unwrap();
+}
+
+async fn add_list(uuid: &str) {
+    let mut tasks = open_device(&format!("{}/tasks.db", DIR)).await;
+    tasks.new_list(&test_list(uuid, uuid)).await.unwrap();
+    tasks.close().await;
+}
+
+#[test]
+fn test_snapshot_retention() {
+    let start = parse_datetime("20240101T120000Z").unwrap(); // A Monday
+    let times: Vec<i64> = (0..21).map(|d| start + d * DAY).collect();
+    // The last 3 days, plus the newest of 2 weeks
+    let keep = snapshots_to_keep(&times, 3, 2);
+    let mut kept: Vec<i64> = keep.into_iter().map(|t| (t - start) / DAY).collect();
+    kept.sort();
+    assert_eq!(kept, vec![13, 18, 19, 20]);
+
+    // Several a day keep the newest of each
+    let times = vec![start, start + 1000, start + DAY, start + DAY + 1000];
+    let keep = snapshots_to_keep(&times, 7, 0);
+    assert_eq!(keep.len(), 2);
+    assert!(keep.contains(&(start + 1000)) && keep.contains(&(start + DAY + 1000)));
+    assert_eq!(snapshots_to_keep(&times, 0, 0).len(), 1);
+}
+
+#[tokio::test]
+async fn test_webdav_backup_and_restore() {
+    setup().await;
+    let (dav, url) = start_mock().await;
+    let target = WebDavTarget::new(&url, "alice", "secret").unwrap();
+
+    let backup = backup_to(&target, DIR, PASSPHRASE, 7, 4).await.unwrap();
+    assert_eq!(backup.removed, 0);
+    assert_eq!(target.list().await.unwrap().len(), 1);
+    // Sealed, not a readable database
+    let uploaded = dav.lock().unwrap().files[&backup.name].clone();
+    assert!(!String::from_utf8_lossy(&uploaded).contains("SQLite format"));
+
+    add_list("later").await;
+    assert!(restore_from(&target, &backup.name, "wrong passphrase", DIR).await.unwrap_err().contains("passphrase"));
+    assert!(get_saved_list("later").await.unwrap().is_some());
+    // Only snapshot names are fetched
+    for name in ["../tasks.db", "taskmgr-20240101T000000Z.snapshot/../../x", "notes.txt"] {
+        assert!(restore_from(&target, name, PASSPHRASE, DIR).await.unwrap_err().contains("no snapshot"), "{}", name);
+    }
+
+    restore_from(&target, &backup.name, PASSPHRASE, DIR).await.unwrap();
+    assert!(get_saved_list("work").await.unwrap().is_some());
+    assert!(get_saved_list("later").await.unwrap().is_none());
+    assert!(Path::new(&format!("{}/tasks.db.pre-restore", DIR)).exists());
+
+    let _ = fs::remove_dir_all(DIR);
+}
+
+#[tokio::test]
+async fn test_webdav_restore_rejects_damaged_snapshot() {
+    setup().await;
+    let (dav, url) = start_mock().await;
+    let target = WebDavTarget::new(&url, "alice", "secret").unwrap();
+    target.ensure_folder().await.unwrap();
+
+    // Opens fine, but the databases in it aren't databases
+    let mut payload = vec![];
+    for db in [b"not a database".to_vec(), vec![]] {
+        payload.extend((db.len() as u64).to_be_bytes());
+        payload.extend(db);
+    }
+    let kdf = KdfParams::generate();
+    let key = kdf.derive(PASSPHRASE).unwrap();
+    let file = json!({ "version": 1, "created": 0, "kdf": kdf, "data": seal_with(&key, b"taskmgr-snapshot-v1", &payload) });
+    let name = "taskmgr-20240101T000000Z.snapshot";
+    dav.lock().unwrap().files.insert(name.to_string(), serde_json::to_vec(&file).unwrap());
+
+    assert!(restore_from(&target, name, PASSPHRASE, DIR).await.unwrap_err().contains("damaged"));
+    // Nothing was replaced
+    assert!(get_saved_list("work").await.unwrap().is_some());
+    assert!(!Path::new(&format!("{}/tasks.db.pre-restore", DIR)).exists());
+    assert!(!Path::new(&format!("{}/tasks.db.restore", DIR)).exists());
+
+    let _ = fs::remove_dir_all(DIR);
+}
+
+#[tokio::test]
+async fn test_webdav_retention_and_auth() {
+    setup().await;
+    let (dav, url) = start_mock().await;
+    {
+        let mut dav = dav.lock().unwrap();
+        dav.folder = true;
+        for day in 1..=10 {
+            dav.files.insert(format!("taskmgr-202401{:02}T120000Z.snapshot", day), vec![]);
+        }
+        dav.files.insert("notes.txt".to_string(), vec![]);
+    }
+    let target = WebDavTarget::new(&url, "alice", "secret").unwrap();
+    let backup = backup_to(&target, DIR, PASSPHRASE, 2, 3).await.unwrap();
+    // Today's, the 10th, and the 7th (a Sunday) for the week before
+    assert_eq!(backup.removed, 8);
+    let names: Vec<String> = dav.lock().unwrap().files.keys().cloned().collect();
+    assert!(names.contains(&backup.name));
+    assert!(names.contains(&"taskmgr-20240110T120000Z.snapshot".to_string()));
+    assert!(names.contains(&"taskmgr-20240107T120000Z.snapshot".to_string()));
+    assert!(names.contains(&"notes.txt".to_string()));
+    assert_eq!(names.len(), 4);
+
+    let target = WebDavTarget::new(&url, "alice", "wrong").unwrap();
+    assert!(target.list().await.unwrap_err().contains("password"));
+
+    let _ = fs::remove_dir_all(DIR);
+}
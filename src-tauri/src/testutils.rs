@@ -1,6 +1,9 @@
-use std::{fs, time::{SystemTime, UNIX_EPOCH}};
+// Fixtures shared by the tests in `tests/`. The task and history databases,
+// the active profile and the session are globals, so .cargo/config.toml
+// runs the tests one at a time.
+use std::{collections::BTreeMap, fs, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
 
-use crate::algorithm::{DueEvent, DueEventType};
+use crate::{algorithm::{DueEvent, DueEventType}, backend::{AuthStatus, SyncBackend}, http::SyncData, ics::parse_datetime, storage::TaskDb, task::{ListEntry, TaskEntry}, utils::now};
 
 #[allow(dead_code)]
 pub fn get_due_event() -> DueEvent {
@@ -35,4 +38,201 @@ pub fn delete_test_db() {
         println!("{e}");
         Err(e)
     });
-}
\ No newline at end of file
+}
+
+/// A list without timestamps.
+#[allow(unused)]
+pub fn test_list(uuid: &str, name: &str) -> ListEntry {
+    ListEntry {
+        name: name.to_string(),
+        uuid: uuid.to_string(),
+        color: 2,
+        last_edited: None,
+        created: None,
+        sealed: None,
+        extra: Default::default()
+    }
+}
+
+/// A task without timestamps, due at `due`.
+#[allow(unused)]
+pub fn test_task(id: &str, name: &str, parent: Option<&str>, due: i64) -> TaskEntry {
+    TaskEntry {
+        name: name.to_string(),
+        size: 2,
+        importance: 3,
+        due,
+        completed: false,
+        id: id.to_string(),
+        parent: parent.map(|p| p.to_string()),
+        last_edited: None,
+        created: None,
+        sealed: None,
+        extra: Default::default()
+    }
+}
+
+/// Removes `paths`, with the -shm and -wal files of those that are databases.
+/// A task with sound, whole-second times, which file formats keep and the
+/// health check accepts.
+#[allow(unused)]
+pub fn dated_task(id: &str, name: &str, parent: Option<&str>) -> TaskEntry {
+    TaskEntry {
+        size: 1,
+        importance: 2,
+        last_edited: parse_datetime("20240602T100000Z"),
+        created: parse_datetime("20240601T090000Z"),
+        ..test_task(id, name, parent, parse_datetime("20240610T143000Z").unwrap())
+    }
+}
+
+#[allow(unused)]
+pub fn find_task<'a>(tasks: &'a [TaskEntry], name: &str) -> &'a TaskEntry {
+    tasks.iter().find(|t| t.name == name).unwrap_or_else(|| panic!("no task {}", name))
+}
+
+#[allow(unused)]
+pub async fn open_device(path: &str) -> TaskDb {
+    let mut tasks = TaskDb::new();
+    tasks.load(path).await.unwrap();
+    tasks
+}
+
+#[allow(unused)]
+pub fn remove_files(paths: &[&str]) {
+    for path in paths {
+        for suffix in ["", "-shm", "-wal"] {
+            let _ = fs::remove_file(path.to_string() + suffix);
+        }
+    }
+}
+
+#[allow(unused)]
+#[derive(Default)]
+struct MemoryStore {
+    signed_in: bool,
+    /// Bumped on every write; items are pulled in this order
+    seq: u64,
+    lists: BTreeMap<String, (u64, ListEntry)>,
+    tasks: BTreeMap<(String, String), (u64, TaskEntry)>,
+    /// When the last push was, for backends that can't page
+    last_sync: i64
+}
+
+/// A sync backend that keeps everything in memory. It behaves like the
+/// sync server: later edits win, and every accepted edit is pulled again
+/// after its cursor.
+#[allow(unused)]
+pub struct MemoryBackend {
+    name: String,
+    paged: bool,
+    store: Mutex<MemoryStore>
+}
+
+#[allow(unused)]
+impl MemoryBackend {
+    pub fn new(name: &str) -> MemoryBackend {
+        MemoryBackend {
+            name: name.to_string(),
+            paged: true,
+            store: Mutex::new(MemoryStore { signed_in: true, ..Default::default() })
+        }
+    }
+
+    /// Sends everything in every pull, like servers without paging.
+    pub fn unpaged(name: &str) -> MemoryBackend {
+        MemoryBackend { paged: false, ..MemoryBackend::new(name) }
+    }
+
+    pub fn set_signed_in(&self, signed_in: bool) {
+        self.store.lock().unwrap().signed_in = signed_in;
+    }
+
+    pub fn lists(&self) -> Vec<ListEntry> {
+        self.store.lock().unwrap().lists.values().map(|l| l.1.clone()).collect()
+    }
+
+    pub fn tasks(&self, list: &str) -> Vec<TaskEntry> {
+        self.store.lock().unwrap().tasks.iter()
+            .filter(|((l, _), _)| l == list)
+            .map(|(_, t)| t.1.clone())
+            .collect()
+    }
+}
+
+#[allow(unused)]
+enum Change<'a> {
+    List(&'a ListEntry),
+    Task(&'a str, &'a TaskEntry)
+}
+
+/// Whether an incoming edit replaces what's stored.
+#[allow(unused)]
+fn accepts(stored: Option<i64>, incoming: Option<i64>) -> bool {
+    incoming.unwrap_or(-1) >= stored.unwrap_or(-1)
+}
+
+impl SyncBackend for MemoryBackend {
+    fn id(&self) -> String {
+        format!("memory:{}", self.name)
+    }
+
+    fn auth_status(&self) -> AuthStatus {
+        match self.store.lock().unwrap().signed_in {
+            true => AuthStatus::SignedIn,
+            false => AuthStatus::SignedOut
+        }
+    }
+
+    async fn pull(&self, cursor: Option<&str>, limit: u32) -> Result<SyncData, String> {
+        let store = self.store.lock().unwrap();
+        let mut data = SyncData::new();
+        data.last_sync = store.last_sync;
+        let after: u64 = match self.paged {
+            true => cursor.unwrap_or("0").parse().or(Err("Invalid cursor.".to_string()))?,
+            false => 0
+        };
+        let mut changes: Vec<(u64, Change)> = store.lists.values()
+            .map(|(seq, l)| (*seq, Change::List(l)))
+            .chain(store.tasks.iter().map(|((list, _), (seq, t))| (*seq, Change::Task(list, t))))
+            .filter(|(seq, _)| *seq > after)
+            .collect();
+        changes.sort_by_key(|c| c.0);
+        let more = self.paged && changes.len() > limit as usize;
+        if self.paged {
+            changes.truncate(limit as usize);
+        }
+        for (_, change) in &changes {
+            match change {
+                Change::List(list) => data.lists.push((*list).clone()),
+                Change::Task(list, task) => data.tasks.entry(list.to_string()).or_default().push((*task).clone())
+            }
+        }
+        if self.paged {
+            data.cursor = Some(changes.last().map_or(after, |c| c.0).to_string());
+            data.has_more = Some(more);
+        }
+        Ok(data)
+    }
+
+    async fn push(&self, data: &SyncData) -> Result<(), String> {
+        let mut store = self.store.lock().unwrap();
+        for list in &data.lists {
+            if !accepts(store.lists.get(&list.uuid).and_then(|l| l.1.last_edited), list.last_edited) { continue; }
+            store.seq += 1;
+            let seq = store.seq;
+            store.lists.insert(list.uuid.clone(), (seq, list.clone()));
+        }
+        for (list, tasks) in &data.tasks {
+            for task in tasks {
+                let key = (list.clone(), task.id.clone());
+                if !accepts(store.tasks.get(&key).and_then(|t| t.1.last_edited), task.last_edited) { continue; }
+                store.seq += 1;
+                let seq = store.seq;
+                store.tasks.insert(key, (seq, task.clone()));
+            }
+        }
+        store.last_sync = now();
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+// The todo.txt format (https://github.com/todotxt/todo.txt), and importing
+// and exporting lists with it. Lists are +projects, tags are @contexts, and
+// subtasks use the common id:/p: extension.
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+use serde::Serialize;
+use serde_json::Map;
+use tauri::{AppHandle, Emitter, Runtime};
+
+use crate::{calendar::ImportedList, ics::{civil_from_days, days_from_civil}, task::{add_imported_list, fix_parents, lists_to_export, load_records, tasks_loaded, DEFAULT_COLOR, DEFAULT_IMPORTANCE, DEFAULT_SIZE, ListEntry, TaskEntry, TaskRecord}, utils::{now, DAY_MS}};
+
+/// Importance 4 is (A), 0 is (E) and anything lower is 0 too
+const PRIORITIES: [char; 5] = ['E', 'D', 'C', 'B', 'A'];
+const DUE_KEY: &str = "due";
+/// Task size has no todo.txt equivalent
+const SIZE_KEY: &str = "size";
+const ID_KEY: &str = "id";
+const PARENT_KEY: &str = "p";
+/// Where some clients keep the priority of completed tasks
+const DONE_PRIORITY_KEY: &str = "pri";
+
+/// A date, as days since 1970-01-01.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date(pub i64);
+
+impl Date {
+    /// A strict `YYYY-MM-DD`.
+    pub fn parse(text: &str) -> Option<Date> {
+        let bytes = text.as_bytes();
+        if bytes.len() != 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+            return None;
+        }
+        if !text.chars().enumerate().all(|(i, c)| i == 4 || i == 7 || c.is_ascii_digit()) {
+            return None;
+        }
+        let (y, m, d) = (text[..4].parse().ok()?, text[5..7].parse().ok()?, text[8..].parse().ok()?);
+        let days = days_from_civil(y, m, d);
+        (civil_from_days(days) == (y, m, d)).then_some(Date(days))
+    }
+
+    /// The local date of `ms`, at `utc_offset` minutes from UTC.
+    pub fn from_ms(ms: i64, utc_offset: i32) -> Date {
+        Date((ms + utc_offset as i64 * 60_000).div_euclid(DAY_MS))
+    }
+
+    /// Local midnight at the start of this date.
+    pub fn to_ms(self, utc_offset: i32) -> i64 {
+        self.0 * DAY_MS - utc_offset as i64 * 60_000
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (y, m, d) = civil_from_days(self.0);
+        write!(f, "{:04}-{:02}-{:02}", y, m, d)
+    }
+}
+
+/// One line of a todo.txt file:
+/// `[x ][(A) ][completion date ][creation date ]description`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TodoItem {
+    pub completed: bool,
+    /// `A` to `Z`
+    pub priority: Option<char>,
+    /// Only read on completed tasks
+    pub completion_date: Option<Date>,
+    pub creation_date: Option<Date>,
+    /// The rest, with projects, contexts and tags left in place
+    pub description: String
+}
+
+/// The first whitespace-separated word of `text` and what follows it.
+fn next_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim_start()),
+        None => (text, "")
+    }
+}
+
+fn parse_priority(word: &str) -> Option<char> {
+    match word.as_bytes() {
+        [b'(', p @ b'A'..=b'Z', b')'] => Some(*p as char),
+        _ => None
+    }
+}
+
+impl TodoItem {
+    /// Reads a line. Returns `None` for blank lines.
+    pub fn parse(line: &str) -> Option<TodoItem> {
+        let mut rest = line.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let mut item = TodoItem::default();
+        let (word, after) = next_word(rest);
+        if word == "x" {
+            item.completed = true;
+            rest = after;
+        }
+        let (word, after) = next_word(rest);
+        if let Some(p) = parse_priority(word) {
+            item.priority = Some(p);
+            rest = after;
+        }
+        let (word, after) = next_word(rest);
+        if let Some(date) = Date::parse(word) {
+            rest = after;
+            let (word, after) = next_word(rest);
+            match Date::parse(word) {
+                // A completed task's first date is when it was completed
+                Some(created) if item.completed => {
+                    item.completion_date = Some(date);
+                    item.creation_date = Some(created);
+                    rest = after;
+                },
+                _ if item.completed => item.completion_date = Some(date),
+                _ => item.creation_date = Some(date)
+            }
+        }
+        item.description = rest.to_string();
+        Some(item)
+    }
+
+    /// The line for this item. As in the format itself, a completed item
+    /// can only have a creation date alongside a completion date.
+    pub fn to_line(&self) -> String {
+        let mut words = vec![];
+        if self.completed {
+            words.push("x".to_string());
+        }
+        if let Some(p) = self.priority {
+            words.push(format!("({})", p));
+        }
+        if let (true, Some(done)) = (self.completed, self.completion_date) {
+            words.push(done.to_string());
+        }
+        if let Some(created) = self.creation_date {
+            words.push(created.to_string());
+        }
+        if !self.description.is_empty() {
+            words.push(self.description.clone());
+        }
+        words.join(" ")
+    }
+
+    fn words_with(&self, prefix: char) -> Vec<&str> {
+        self.description.split_whitespace()
+            .filter_map(|w| w.strip_prefix(prefix))
+            .filter(|w| !w.is_empty())
+            .collect()
+    }
+
+    /// `+project` names, without the `+`.
+    pub fn projects(&self) -> Vec<&str> {
+        self.words_with('+')
+    }
+
+    /// `@context` names, without the `@`.
+    pub fn contexts(&self) -> Vec<&str> {
+        self.words_with('@')
+    }
+
+    /// `key:value` pairs. Neither side can be empty or hold a colon, and
+    /// keys start with a letter, which leaves out URLs and times.
+    pub fn tags(&self) -> Vec<(&str, &str)> {
+        self.description.split_whitespace()
+            .filter_map(|w| w.split_once(':'))
+            .filter(|(k, v)| k.starts_with(|c: char| c.is_alphabetic()) && !v.is_empty() && !v.contains(':') && !v.starts_with('/'))
+            .collect()
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags().into_iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+}
+
+/// Reads every non-blank line of `text`.
+pub fn parse(text: &str) -> Vec<TodoItem> {
+    text.lines().filter_map(TodoItem::parse).collect()
+}
+
+/// One line per item.
+pub fn serialize(items: &[TodoItem]) -> String {
+    items.iter().map(|i| i.to_line() + "\n").collect()
+}
+
+/// `#tag` words as `@tag`, and the other way round.
+fn swap_tag_marks(text: &str, from: char, to: char) -> String {
+    text.split(' ')
+        .map(|w| match w.strip_prefix(from) {
+            Some(tag) if !tag.is_empty() => format!("{}{}", to, tag),
+            _ => w.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A list name as a project, which can't have spaces.
+pub fn project_name(list: &str) -> String {
+    list.split_whitespace().collect::<Vec<_>>().join("_")
+}
+
+/// Every task in `lists` as todo.txt items, parents first. Dates are local
+/// to `utc_offset`.
+pub fn tasks_to_items(lists: &[(ListEntry, Vec<TaskEntry>)], utc_offset: i32) -> Vec<TodoItem> {
+    fn walk<'a>(records: &'a [TaskRecord], parent: Option<&'a str>, out: &mut Vec<(&'a TaskRecord, Option<&'a str>)>) {
+        for r in records {
+            out.push((r, parent));
+            walk(&r.subtasks, Some(&r.id), out);
+        }
+    }
+    let mut items = vec![];
+    // Short ids for parents, unique across the file
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    for (list, tasks) in lists {
+        let entries: HashMap<&str, &TaskEntry> = tasks.iter().map(|t| (t.id.as_str(), t)).collect();
+        let records = load_records(tasks);
+        let mut rows = vec![];
+        walk(&records, None, &mut rows);
+        for (r, parent) in rows {
+            let entry = entries[r.id.as_str()];
+            // One line per task
+            let mut description = swap_tag_marks(&r.name.replace(['\r', '\n'], " "), '#', '@');
+            description += &format!(" +{}", project_name(&list.name));
+            description += &format!(" {}:{}", DUE_KEY, Date::from_ms(r.due, utc_offset));
+            if r.size != DEFAULT_SIZE {
+                description += &format!(" {}:{}", SIZE_KEY, r.size);
+            }
+            let next = ids.len() + 1;
+            if !r.subtasks.is_empty() {
+                description += &format!(" {}:{}", ID_KEY, ids.entry(format!("{}/{}", list.uuid, r.id)).or_insert(next));
+            }
+            if let Some(p) = parent {
+                description += &format!(" {}:{}", PARENT_KEY, ids[&format!("{}/{}", list.uuid, p)]);
+            }
+            let created = entry.created.map(|c| Date::from_ms(c, utc_offset));
+            let done = r.completed.then(|| Date::from_ms(entry.last_edited.unwrap_or_else(now), utc_offset));
+            items.push(TodoItem {
+                completed: r.completed,
+                priority: Some(PRIORITIES[r.importance.clamp(0, 4) as usize]),
+                completion_date: done,
+                // A completion date needs a creation date before it
+                creation_date: created.or(done),
+                description
+            });
+        }
+    }
+    items
+}
+
+/// Removes our `key:value` tags and the list's `+project`, turns
+/// `@contexts` into `#tags` and tidies the spaces left over.
+fn task_name(item: &TodoItem, project: Option<&str>) -> String {
+    let project = project.map(|p| format!("+{}", p));
+    let keys = [DUE_KEY, SIZE_KEY, ID_KEY, PARENT_KEY, DONE_PRIORITY_KEY];
+    let mut words: Vec<&str> = item.description.split_whitespace()
+        .filter(|w| !w.split_once(':').is_some_and(|(k, v)| keys.contains(&k) && !v.is_empty()))
+        .collect();
+    if let Some(i) = words.iter().rposition(|w| Some(*w) == project.as_deref()) {
+        words.remove(i);
+    }
+    swap_tag_marks(&words.join(" "), '@', '#')
+}
+
+struct ItemTask {
+    task: TaskEntry,
+    id: Option<String>,
+    parent: Option<String>
+}
+
+/// Tasks in `items`, in lists named after their last project, which is
+/// where exports put it. Items with no project go in a list named
+/// `fallback_list`.
+pub fn items_to_lists(items: &[TodoItem], fallback_list: &str, utc_offset: i32) -> Vec<ImportedList> {
+    let mut order: Vec<String> = vec![];
+    // Tasks with their id: and p: tags, by list
+    let mut by_list: HashMap<String, Vec<ItemTask>> = HashMap::new();
+    for item in items {
+        let project = item.projects().last().copied();
+        let list = project.unwrap_or(fallback_list).to_string();
+        let priority = item.priority.or_else(|| item.tag(DONE_PRIORITY_KEY).and_then(|p| p.chars().next()));
+        let importance = match priority {
+            Some(p) if p.is_ascii_uppercase() => 4 - (p as i32 - 'A' as i32).min(4),
+            _ => DEFAULT_IMPORTANCE
+        };
+        let task = TaskEntry {
+            name: task_name(item, project),
+            size: item.tag(SIZE_KEY).and_then(|s| s.parse().ok()).filter(|s| (0..=4).contains(s)).unwrap_or(DEFAULT_SIZE),
+            importance,
+            due: item.tag(DUE_KEY).and_then(Date::parse).map_or_else(now, |d| d.to_ms(utc_offset)),
+            completed: item.completed,
+            id: uuid::Uuid::new_v4().to_string(),
+            parent: None,
+            last_edited: None,
+            created: None,
+            sealed: None,
+            extra: Map::new()
+        };
+        if !by_list.contains_key(&list) {
+            order.push(list.clone());
+        }
+        let id = item.tag(ID_KEY).map(|s| s.to_string());
+        let parent = item.tag(PARENT_KEY).map(|s| s.to_string());
+        by_list.entry(list).or_default().push(ItemTask { task, id, parent });
+    }
+    order.into_iter().map(|name| {
+        let rows = by_list.remove(&name).unwrap();
+        let ids: HashMap<&str, &str> = rows.iter()
+            .filter_map(|r| r.id.as_deref().map(|id| (id, r.task.id.as_str())))
+            .collect();
+        let parents: Vec<Option<String>> = rows.iter()
+            .map(|r| r.parent.as_deref().and_then(|p| ids.get(p)).map(|id| id.to_string()))
+            .collect();
+        let mut tasks: Vec<TaskEntry> = rows.into_iter().zip(parents).map(|(r, parent)| TaskEntry { parent, ..r.task }).collect();
+        fix_parents(&mut tasks);
+        ImportedList {
+            list: ListEntry {
+                name,
+                uuid: uuid::Uuid::new_v4().to_string(),
+                color: DEFAULT_COLOR,
+                last_edited: None,
+                created: None,
+                sealed: None,
+                extra: Map::new()
+            },
+            tasks
+        }
+    }).collect()
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct TodoImport {
+    pub lists: usize,
+    pub tasks: usize
+}
+
+/// Writes `list_ids` (every list if empty) to a todo.txt file at `path`,
+/// with dates local to `utc_offset`. Returns how many tasks were written.
+#[tauri::command]
+pub async fn export_todotxt(list_ids: Vec<String>, path: String, utc_offset: i32) -> Result<usize, String> {
+    let with_tasks = lists_to_export(&list_ids).await?;
+    let items = tasks_to_items(&with_tasks, utc_offset);
+    fs::write(&path, serialize(&items))
+        .or_else(|e| Err(format!("Export Error: {}", e)))?;
+    Ok(items.len())
+}
+
+/// Makes new lists from the todo.txt file at `path`, then has the task list
+/// reload.
+#[tauri::command]
+pub async fn import_todotxt<R: Runtime>(app: AppHandle<R>, path: String, utc_offset: i32) -> Result<TodoImport, String> {
+    let result = import_todotxt_file(&path, utc_offset).await?;
+    let _ = app.emit("tasks-imported", result.tasks);
+    Ok(result)
+}
+
+pub async fn import_todotxt_file(path: &str, utc_offset: i32) -> Result<TodoImport, String> {
+    if !tasks_loaded() {
+        return Err("Tasks aren't loaded yet.".to_string());
+    }
+    let text = fs::read_to_string(path)
+        .or_else(|e| Err(format!("Import Error: {}", e)))?;
+    let name = Path::new(path).file_stem().map_or("Imported".to_string(), |s| s.to_string_lossy().to_string());
+    let mut result = TodoImport::default();
+    for imported in items_to_lists(&parse(&text), &name, utc_offset) {
+        let added = add_imported_list(&imported.list, &imported.tasks).await
+            .or_else(|e| Err(format!("Import Error: {}", e)))?;
+        if !added {
+            return Err(format!("Import Error: couldn't add list {}.", imported.list.name));
+        }
+        result.lists += 1;
+        result.tasks += imported.tasks.len();
+    }
+    Ok(result)
+}
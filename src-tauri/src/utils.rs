@@ -3,6 +3,8 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Deserializer, de, Deserialize};
 use serde_json::Value;
 
+pub const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
 pub fn now() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -0,0 +1,352 @@
+// Encrypted snapshots of the task and history databases on a WebDAV server.
+//
+// A snapshot is both databases copied with SQLite's backup API, sealed with a key
+// derived from a backup passphrase (so it can be restored on a new device),
+// and uploaded as `taskmgr-<time>.snapshot`. Older snapshots are thinned
+// out to the newest few days and weeks. Restoring checks both databases
+// before they replace the open ones.
+use std::fs::{self, read_to_string};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use reqwest::{header, Client, Method, RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_slice, from_str, to_string, to_vec};
+
+use crate::{algorithm::HISTORY_PATH, caldav::parse_multistatus, crypto::{open_with, seal_with, KdfParams}, http, ics::{format_utc, parse_datetime}, restore::{data_dir, replace_databases, snapshots_to_keep, BackupResult, RESTORE_SUFFIX}, storage::snapshot_db, task::TASKS_PATH, utils::{now, DAY_MS}};
+
+const WEBDAV_CONF_PATH: &str = "/webdav_backup.json";
+/// Credential store key prefixes
+const PASSWORD_PREFIX: &str = "webdav:";
+const PASSPHRASE_PREFIX: &str = "webdav-key:";
+const SNAPSHOT_PREFIX: &str = "taskmgr-";
+const SNAPSHOT_SUFFIX: &str = ".snapshot";
+const SNAPSHOT_VERSION: u32 = 1;
+const SNAPSHOT_AAD: &[u8] = b"taskmgr-snapshot-v1";
+const BACKUP_INTERVAL_MS: i64 = DAY_MS;
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+const DEFAULT_DAILY: usize = 7;
+const DEFAULT_WEEKLY: usize = 4;
+
+/// Bumped to stop the running backup schedule.
+static SCHEDULE: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WebDavBackupConf {
+    /// Folder the snapshots go in
+    pub url: String,
+    pub username: String,
+    /// Snapshots kept for the most recent days and weeks
+    pub daily: usize,
+    pub weekly: usize,
+    #[serde(default)]
+    pub last_backup: Option<i64>
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RemoteSnapshot {
+    pub name: String,
+    pub created: i64
+}
+
+/// What's uploaded. The payload is both databases, each prefixed with its
+/// length as a big-endian u64.
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    version: u32,
+    created: i64,
+    kdf: KdfParams,
+    data: String
+}
+
+pub struct WebDavTarget {
+    folder: Url,
+    username: String,
+    password: String,
+    client: Client
+}
+
+impl WebDavTarget {
+    pub fn new(url: &str, username: &str, password: &str) -> Result<WebDavTarget, String> {
+        let folder = http::validate_folder_url(url)?;
+        Ok(WebDavTarget {
+            folder,
+            username: username.to_string(),
+            password: password.to_string(),
+            client: Client::new()
+        })
+    }
+
+    fn request(&self, method: &str, name: &str) -> Result<RequestBuilder, String> {
+        let url = self.folder.join(name).or_else(|e| Err(format!("WebDAV Error: {}", e)))?;
+        Ok(self.client.request(Method::from_bytes(method.as_bytes()).unwrap(), url)
+            .basic_auth(&self.username, Some(&self.password)))
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, String> {
+        let response = request.send().await.or_else(|e| Err(format!("Couldn't reach the WebDAV server: {}", e)))?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED => Err("The WebDAV server rejected the username or password.".to_string()),
+            StatusCode::FORBIDDEN => Err("The WebDAV account can't write to that folder.".to_string()),
+            _ => Ok(response)
+        }
+    }
+
+    /// Creates the folder if it isn't there.
+    pub async fn ensure_folder(&self) -> Result<(), String> {
+        let status = self.send(self.request("MKCOL", "")?).await?.status();
+        // 405: it exists
+        if !status.is_success() && status != StatusCode::METHOD_NOT_ALLOWED {
+            return Err(format!("WebDAV Error: couldn't create the backup folder ({}).", status));
+        }
+        Ok(())
+    }
+
+    /// Snapshots in the folder, newest first.
+    pub async fn list(&self) -> Result<Vec<RemoteSnapshot>, String> {
+        let request = self.request("PROPFIND", "")?
+            .header("Depth", "1")
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/></d:prop></d:propfind>"#);
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+        if response.status() != StatusCode::MULTI_STATUS {
+            return Err(format!("WebDAV Error: the server returned {}.", response.status()));
+        }
+        let body = response.text().await.or_else(|e| Err(format!("WebDAV Error: {}", e)))?;
+        let mut snapshots: Vec<RemoteSnapshot> = parse_multistatus(&body)?.into_iter()
+            .filter(|r| !r.collection)
+            .filter_map(|r| {
+                let name = r.href.trim_end_matches('/').rsplit('/').next()?.to_string();
+                Some(RemoteSnapshot { created: snapshot_time(&name)?, name })
+            })
+            .collect();
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.created));
+        Ok(snapshots)
+    }
+
+    pub async fn upload(&self, name: &str, data: Vec<u8>) -> Result<(), String> {
+        let status = self.send(self.request("PUT", name)?
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .body(data)).await?.status();
+        if !status.is_success() {
+            return Err(format!("WebDAV Error: couldn't upload {} ({}).", name, status));
+        }
+        Ok(())
+    }
+
+    pub async fn download(&self, name: &str) -> Result<Vec<u8>, String> {
+        let response = self.send(self.request("GET", name)?).await?;
+        if !response.status().is_success() {
+            return Err(format!("WebDAV Error: couldn't download {} ({}).", name, response.status()));
+        }
+        Ok(response.bytes().await.or_else(|e| Err(format!("WebDAV Error: {}", e)))?.to_vec())
+    }
+
+    pub async fn delete(&self, name: &str) -> Result<(), String> {
+        let status = self.send(self.request("DELETE", name)?).await?.status();
+        if !status.is_success() && status != StatusCode::NOT_FOUND {
+            return Err(format!("WebDAV Error: couldn't delete {} ({}).", name, status));
+        }
+        Ok(())
+    }
+}
+
+fn snapshot_name(time: i64) -> String {
+    format!("{}{}{}", SNAPSHOT_PREFIX, format_utc(time), SNAPSHOT_SUFFIX)
+}
+
+/// When the snapshot called `name` was made, if that's a snapshot name.
+fn snapshot_time(name: &str) -> Option<i64> {
+    parse_datetime(name.strip_prefix(SNAPSHOT_PREFIX)?.strip_suffix(SNAPSHOT_SUFFIX)?).filter(|time| snapshot_name(*time) == name)
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).or_else(|e| Err(format!("Couldn't read {}: {}", path, e)))
+}
+
+/// Consistent copies of the databases in `dir`, sealed with `passphrase`.
+pub async fn create_snapshot(dir: &str, passphrase: &str, created: i64) -> Result<Vec<u8>, String> {
+    let mut payload = vec![];
+    for db in [TASKS_PATH, HISTORY_PATH] {
+        let path = dir.to_string() + db;
+        let copy = path.clone() + SNAPSHOT_SUFFIX;
+        let _ = fs::remove_file(&copy);
+        snapshot_db(&path, &copy).await.or_else(|e| Err(format!("Couldn't copy {}: {}", path, e)))?;
+        let bytes = read_file(&copy);
+        let _ = fs::remove_file(&copy);
+        let bytes = bytes?;
+        payload.extend((bytes.len() as u64).to_be_bytes());
+        payload.extend(bytes);
+    }
+    let kdf = KdfParams::generate();
+    let key = kdf.derive(passphrase)?;
+    let file = SnapshotFile { version: SNAPSHOT_VERSION, created, data: seal_with(&key, SNAPSHOT_AAD, &payload), kdf };
+    Ok(to_vec(&file).unwrap())
+}
+
+/// The task and history databases in a snapshot.
+pub fn open_snapshot(bytes: &[u8], passphrase: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let file: SnapshotFile = from_slice(bytes).or(Err("That isn't a task manager snapshot.".to_string()))?;
+    if file.version > SNAPSHOT_VERSION {
+        return Err("That snapshot is from a newer version. Update to restore it.".to_string());
+    }
+    let key = file.kdf.derive(passphrase)?;
+    let payload = open_with(&key, SNAPSHOT_AAD, &file.data).or(Err("Wrong backup passphrase.".to_string()))?;
+    let mut rest = payload.as_slice();
+    let mut take = || -> Result<Vec<u8>, String> {
+        if rest.len() < 8 {
+            return Err("The snapshot is damaged.".to_string());
+        }
+        let (len, tail) = rest.split_at(8);
+        let len = u64::from_be_bytes(len.try_into().unwrap()) as usize;
+        if tail.len() < len {
+            return Err("The snapshot is damaged.".to_string());
+        }
+        let (db, tail) = tail.split_at(len);
+        rest = tail;
+        Ok(db.to_vec())
+    };
+    Ok((take()?, take()?))
+}
+
+/// Uploads a snapshot of the databases in `dir`, then removes the ones
+/// retention no longer keeps.
+pub async fn backup_to(target: &WebDavTarget, dir: &str, passphrase: &str, daily: usize, weekly: usize) -> Result<BackupResult, String> {
+    target.ensure_folder().await?;
+    let created = now();
+    let name = snapshot_name(created);
+    target.upload(&name, create_snapshot(dir, passphrase, created).await?).await?;
+    let snapshots = target.list().await?;
+    let times: Vec<i64> = snapshots.iter().map(|s| s.created).collect();
+    let keep = snapshots_to_keep(&times, daily.max(1), weekly);
+    let mut removed = 0;
+    for snapshot in snapshots.iter().filter(|s| !keep.contains(&s.created) && s.name != name) {
+        target.delete(&snapshot.name).await?;
+        removed += 1;
+    }
+    Ok(BackupResult { name, removed })
+}
+
+/// Downloads a snapshot and checks both databases in it. Only then are the
+/// open databases in `dir` closed and replaced; the old files are kept with
+/// a `.pre-restore` suffix.
+pub async fn restore_from(target: &WebDavTarget, name: &str, passphrase: &str, dir: &str) -> Result<(), String> {
+    // Anything else could point outside the folder
+    if snapshot_time(name).is_none() {
+        return Err(format!("There's no snapshot called {}.", name));
+    }
+    let (tasks, history) = open_snapshot(&target.download(name).await?, passphrase)?;
+    for (db, bytes) in [(TASKS_PATH, tasks), (HISTORY_PATH, history)] {
+        fs::write(dir.to_string() + db + RESTORE_SUFFIX, bytes).or_else(|e| Err(format!("{}", e)))?;
+    }
+    replace_databases(dir, "snapshot").await
+}
+
+fn load_conf() -> Option<WebDavBackupConf> {
+    from_str(&read_to_string(http::app_conf_dir()? + WEBDAV_CONF_PATH).ok()?).ok()
+}
+
+fn save_conf(conf: &WebDavBackupConf) -> Result<(), String> {
+    let dir = http::app_conf_dir().ok_or("No app data directory.".to_string())?;
+    fs::write(dir + WEBDAV_CONF_PATH, to_string(conf).unwrap()).or_else(|e| Err(format!("{}", e)))
+}
+
+/// The configured target and backup passphrase.
+fn target() -> Result<(WebDavBackupConf, WebDavTarget, String), String> {
+    let conf = load_conf().ok_or("WebDAV backups aren't set up.".to_string())?;
+    let store = http::credential_store().ok_or("No credential store.".to_string())?;
+    let password = store.load(&(PASSWORD_PREFIX.to_string() + &conf.url))
+        .ok_or("The WebDAV password is missing. Set up backups again.".to_string())?;
+    let passphrase = store.load(&(PASSPHRASE_PREFIX.to_string() + &conf.url))
+        .ok_or("The backup passphrase is missing. Set up backups again.".to_string())?;
+    let target = WebDavTarget::new(&conf.url, &conf.username, &password)?;
+    Ok((conf, target, passphrase))
+}
+
+#[tauri::command]
+pub fn get_webdav_backup() -> Option<WebDavBackupConf> {
+    load_conf()
+}
+
+/// Checks the folder can be written to, then saves the target. The
+/// passphrase is needed again to restore, possibly on another device.
+#[tauri::command]
+pub async fn set_webdav_backup(url: String, username: String, password: String, passphrase: String, daily: Option<usize>, weekly: Option<usize>) -> Result<WebDavBackupConf, String> {
+    if passphrase.chars().count() < 8 {
+        return Err("Use a backup passphrase of at least 8 characters.".to_string());
+    }
+    let target = WebDavTarget::new(&url, username.trim(), &password)?;
+    target.ensure_folder().await?;
+    target.list().await?;
+    remove_webdav_backup();
+    let conf = WebDavBackupConf {
+        url: target.folder.to_string(),
+        username: target.username.clone(),
+        daily: daily.unwrap_or(DEFAULT_DAILY).max(1),
+        weekly: weekly.unwrap_or(DEFAULT_WEEKLY),
+        last_backup: None
+    };
+    let store = http::credential_store().ok_or("No credential store.".to_string())?;
+    store.save(&(PASSWORD_PREFIX.to_string() + &conf.url), &password)?;
+    store.save(&(PASSPHRASE_PREFIX.to_string() + &conf.url), &passphrase)?;
+    save_conf(&conf)?;
+    Ok(conf)
+}
+
+#[tauri::command]
+pub fn remove_webdav_backup() {
+    stop_backups();
+    if let (Some(conf), Some(store)) = (load_conf(), http::credential_store()) {
+        store.remove(&(PASSWORD_PREFIX.to_string() + &conf.url));
+        store.remove(&(PASSPHRASE_PREFIX.to_string() + &conf.url));
+    }
+    if let Some(dir) = http::app_conf_dir() {
+        let _ = fs::remove_file(dir + WEBDAV_CONF_PATH);
+    }
+}
+
+#[tauri::command]
+pub async fn backup_to_webdav() -> Result<BackupResult, String> {
+    let (mut conf, target, passphrase) = target()?;
+    let result = backup_to(&target, &data_dir()?, &passphrase, conf.daily, conf.weekly).await?;
+    conf.last_backup = snapshot_time(&result.name);
+    save_conf(&conf)?;
+    Ok(result)
+}
+
+#[tauri::command]
+pub async fn list_webdav_snapshots() -> Result<Vec<RemoteSnapshot>, String> {
+    target()?.1.list().await
+}
+
+/// Replaces the local databases with a snapshot. The passphrase is asked
+/// for again, since the snapshot may predate a change of passphrase.
+#[tauri::command]
+pub async fn restore_webdav_snapshot(name: String, passphrase: String) -> Result<(), String> {
+    let (_, target, _) = target()?;
+    restore_from(&target, &name, &passphrase, &data_dir()?).await
+}
+
+/// Backs up once a day while the app runs, if backups are set up.
+#[tauri::command]
+pub fn start_webdav_backups() {
+    let generation = SCHEDULE.fetch_add(1, Ordering::SeqCst) + 1;
+    tauri::async_runtime::spawn(async move {
+        while SCHEDULE.load(Ordering::SeqCst) == generation {
+            let due = load_conf().is_some_and(|c| c.last_backup.is_none_or(|t| now() - t >= BACKUP_INTERVAL_MS));
+            if due {
+                if let Err(e) = backup_to_webdav().await {
+                    println!("WebDAV backup failed: {}", e);
+                }
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+/// Stops the backup schedule, e.g. when switching profile.
+pub fn stop_backups() {
+    SCHEDULE.fetch_add(1, Ordering::SeqCst);
+}
@@ -0,0 +1,607 @@
+use argon2::{password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString}, Argon2};
+use sqlx::{migrate::MigrateDatabase, sqlite::SqlitePool, Error, Sqlite};
+
+use crate::{now, List, Task, ACCESS_TOKEN_TTL_SECS, LOGIN_WINDOW_SECS, MAX_FAILED_LOGINS, REFRESH_TOKEN_TTL_SECS, RESET_CODE_TTL_SECS, SESSION_MAX_AGE_SECS};
+
+/// Server-side storage. Every list and task row is owned by a user, and keeps
+/// both the client's `last_edited` (used to resolve conflicts) and the time
+/// the server received it (`updated_at`, used to compute deltas).
+///
+/// Each accepted change also takes the next value of a per-user counter
+/// (`seq`). Paged clients use the highest `seq` they've seen as their cursor,
+/// and `origin` (the pushing session) keeps a device's own pushes out of its
+/// next pull.
+#[derive(Clone)]
+pub struct ServerDb {
+    pool: SqlitePool,
+}
+
+pub struct Session {
+    pub username: String,
+    pub last_sync: i64,
+}
+
+/// A freshly issued access/refresh token pair for a session.
+pub struct Tokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// One page of changes after a cursor, in `seq` order.
+pub struct ChangePage {
+    pub lists: Vec<List>,
+    pub tasks: Vec<(String, Task)>,
+    pub cursor: i64,
+    pub has_more: bool,
+}
+
+impl ServerDb {
+    pub async fn open(path: &str) -> Result<ServerDb, Error> {
+        let url = "sqlite:".to_string() + path;
+        if !Sqlite::database_exists(&url).await.unwrap_or(false) {
+            Sqlite::create_database(&url).await?;
+        }
+        let pool = SqlitePool::connect(&url).await?;
+        let db = ServerDb { pool };
+        db.create_tables().await?;
+        Ok(db)
+    }
+
+    async fn create_tables(&self) -> Result<(), Error> {
+        for query in [
+            "CREATE TABLE IF NOT EXISTS Users ( \
+                username TEXT PRIMARY KEY, \
+                password TEXT NOT NULL \
+            )",
+            "CREATE TABLE IF NOT EXISTS Sessions ( \
+                token TEXT PRIMARY KEY, \
+                username TEXT NOT NULL, \
+                created BIGINT NOT NULL, \
+                last_sync BIGINT NOT NULL \
+            )",
+            "CREATE TABLE IF NOT EXISTS Lists ( \
+                owner TEXT, \
+                uuid TEXT, \
+                name TEXT, \
+                color INTEGER, \
+                created BIGINT, \
+                last_edited BIGINT, \
+                updated_at BIGINT NOT NULL, \
+                PRIMARY KEY(owner, uuid) \
+            )",
+            "CREATE TABLE IF NOT EXISTS Tasks ( \
+                owner TEXT, \
+                list TEXT, \
+                id TEXT, \
+                name TEXT, \
+                size INTEGER, \
+                importance INTEGER, \
+                due BIGINT, \
+                completed BOOLEAN, \
+                parent TEXT, \
+                created BIGINT, \
+                last_edited BIGINT, \
+                updated_at BIGINT NOT NULL, \
+                PRIMARY KEY(owner, list, id) \
+            )",
+            "CREATE TABLE IF NOT EXISTS Telemetry ( \
+                device_id TEXT, \
+                previous TEXT, \
+                time BIGINT \
+            )",
+            "CREATE TABLE IF NOT EXISTS Counters ( \
+                owner TEXT PRIMARY KEY, \
+                seq INTEGER NOT NULL \
+            )",
+            "CREATE TABLE IF NOT EXISTS FailedLogins ( \
+                username TEXT NOT NULL, \
+                time BIGINT NOT NULL \
+            )",
+            "CREATE TABLE IF NOT EXISTS PasswordResets ( \
+                username TEXT PRIMARY KEY, \
+                code TEXT NOT NULL, \
+                expires BIGINT NOT NULL \
+            )",
+            "CREATE TABLE IF NOT EXISTS Keyrings ( \
+                owner TEXT PRIMARY KEY, \
+                keyring TEXT NOT NULL, \
+                updated_at BIGINT NOT NULL \
+            )",
+        ] {
+            sqlx::query(query).execute(&self.pool).await?;
+        }
+        // Columns added for paged and encrypted sync and token auth; these
+        // fail harmlessly once they exist
+        for query in [
+            "ALTER TABLE Sessions ADD COLUMN access_token TEXT",
+            "ALTER TABLE Sessions ADD COLUMN access_expires BIGINT",
+            "ALTER TABLE Sessions ADD COLUMN refresh_token TEXT",
+            "ALTER TABLE Sessions ADD COLUMN refresh_expires BIGINT",
+            "ALTER TABLE Lists ADD COLUMN seq INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE Lists ADD COLUMN origin TEXT",
+            "ALTER TABLE Lists ADD COLUMN sealed TEXT",
+            "ALTER TABLE Tasks ADD COLUMN seq INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE Tasks ADD COLUMN origin TEXT",
+            "ALTER TABLE Tasks ADD COLUMN sealed TEXT",
+        ] {
+            let _ = sqlx::query(query).execute(&self.pool).await;
+        }
+        Ok(())
+    }
+
+    async fn next_seq(&self, owner: &str) -> Result<i64, Error> {
+        let row: (i64,) = sqlx::query_as(
+            "INSERT INTO Counters (owner, seq) VALUES (?, 1) \
+            ON CONFLICT(owner) DO UPDATE SET seq=seq+1 \
+            RETURNING seq"
+        )
+            .bind(owner)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.0)
+    }
+
+    pub async fn add_user(&self, username: &str, password: &str) -> Result<bool, Error> {
+        let result = sqlx::query("INSERT OR IGNORE INTO Users (username, password) VALUES (?, ?)")
+            .bind(username)
+            .bind(hash_secret(password)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Changes a user's password and signs out all of their sessions.
+    pub async fn set_password(&self, username: &str, password: &str) -> Result<(), Error> {
+        sqlx::query("UPDATE Users SET password=? WHERE username=?")
+            .bind(hash_secret(password)?)
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM Sessions WHERE username=?")
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn check_password(&self, username: &str, password: &str) -> Result<bool, Error> {
+        let hash: Option<(String,)> = sqlx::query_as("SELECT password FROM Users WHERE username=?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        let hash = match hash {
+            Some(h) => h.0,
+            None => return Ok(false),
+        };
+        Ok(verify_secret(&hash, password))
+    }
+
+    /// How many seconds `username` must wait before trying to sign in again,
+    /// if they've failed too often recently.
+    pub async fn login_retry_after(&self, username: &str) -> Result<Option<i64>, Error> {
+        let since = now() - LOGIN_WINDOW_SECS * 1000;
+        let row: (i64, Option<i64>) = sqlx::query_as("SELECT COUNT(*), MIN(time) FROM FailedLogins WHERE username=? AND time > ?")
+            .bind(username)
+            .bind(since)
+            .fetch_one(&self.pool)
+            .await?;
+        if row.0 < MAX_FAILED_LOGINS {
+            return Ok(None);
+        }
+        Ok(Some((row.1.unwrap_or(since) - since) / 1000 + 1))
+    }
+
+    pub async fn record_failed_login(&self, username: &str) -> Result<(), Error> {
+        sqlx::query("INSERT INTO FailedLogins (username, time) VALUES (?, ?)")
+            .bind(username)
+            .bind(now())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn clear_failed_logins(&self, username: &str) -> Result<(), Error> {
+        sqlx::query("DELETE FROM FailedLogins WHERE username=?")
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Creates a one-time password reset code, or `None` if there's no such
+    /// user. Only a hash of the code is stored.
+    pub async fn create_reset_code(&self, username: &str) -> Result<Option<String>, Error> {
+        let exists: Option<(String,)> = sqlx::query_as("SELECT username FROM Users WHERE username=?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+        let code = uuid::Uuid::new_v4().simple().to_string()[..10].to_uppercase();
+        sqlx::query("INSERT OR REPLACE INTO PasswordResets (username, code, expires) VALUES (?, ?, ?)")
+            .bind(username)
+            .bind(hash_secret(&code)?)
+            .bind(now() + RESET_CODE_TTL_SECS * 1000)
+            .execute(&self.pool)
+            .await?;
+        Ok(Some(code))
+    }
+
+    /// Checks and uses up a reset code.
+    pub async fn consume_reset_code(&self, username: &str, code: &str) -> Result<bool, Error> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT code FROM PasswordResets WHERE username=? AND expires > ?")
+            .bind(username)
+            .bind(now())
+            .fetch_optional(&self.pool)
+            .await?;
+        if !row.is_some_and(|r| verify_secret(&r.0, &code.trim().to_uppercase())) {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM PasswordResets WHERE username=?")
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+        Ok(true)
+    }
+
+    pub async fn new_session(&self, username: &str) -> Result<String, Error> {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        sqlx::query("INSERT INTO Sessions (token, username, created, last_sync) VALUES (?, ?, ?, 0)")
+            .bind(&token)
+            .bind(username)
+            .bind(now())
+            .execute(&self.pool)
+            .await?;
+        Ok(token)
+    }
+
+    /// Starts a session for a token client and issues its first tokens.
+    pub async fn new_token_session(&self, username: &str) -> Result<Tokens, Error> {
+        let session = self.new_session(username).await?;
+        self.issue_tokens(&session).await
+    }
+
+    /// Replaces the session's tokens, so each refresh token works only once.
+    async fn issue_tokens(&self, session: &str) -> Result<Tokens, Error> {
+        let tokens = Tokens { access_token: new_token(), refresh_token: new_token() };
+        sqlx::query("UPDATE Sessions SET access_token=?, access_expires=?, refresh_token=?, refresh_expires=? WHERE token=?")
+            .bind(&tokens.access_token)
+            .bind(now() + ACCESS_TOKEN_TTL_SECS * 1000)
+            .bind(&tokens.refresh_token)
+            .bind(now() + REFRESH_TOKEN_TTL_SECS * 1000)
+            .bind(session)
+            .execute(&self.pool)
+            .await?;
+        Ok(tokens)
+    }
+
+    /// New tokens for a valid refresh token, or `None`.
+    pub async fn refresh_tokens(&self, refresh_token: &str) -> Result<Option<Tokens>, Error> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT token FROM Sessions WHERE refresh_token=? AND refresh_expires > ?")
+            .bind(refresh_token)
+            .bind(now())
+            .fetch_optional(&self.pool)
+            .await?;
+        match row {
+            Some(r) => Ok(Some(self.issue_tokens(&r.0).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The session id and session for an unexpired access token.
+    pub async fn get_session_by_access_token(&self, access_token: &str) -> Result<Option<(String, Session)>, Error> {
+        let row: Option<(String, String, i64)> = sqlx::query_as(
+            "SELECT token, username, last_sync FROM Sessions WHERE access_token=? AND access_expires > ?"
+        )
+            .bind(access_token)
+            .bind(now())
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| (r.0, Session { username: r.1, last_sync: r.2 })))
+    }
+
+    /// The session for `token`, unless it was revoked or has expired.
+    pub async fn get_session(&self, token: &str) -> Result<Option<Session>, Error> {
+        let row: Option<(String, i64)> = sqlx::query_as("SELECT username, last_sync FROM Sessions WHERE token=? AND created > ?")
+            .bind(token)
+            .bind(now() - SESSION_MAX_AGE_SECS * 1000)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| Session { username: r.0, last_sync: r.1 }))
+    }
+
+    pub async fn delete_session(&self, token: &str) -> Result<(), Error> {
+        sqlx::query("DELETE FROM Sessions WHERE token=?")
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_last_sync(&self, token: &str, last_sync: i64) -> Result<(), Error> {
+        sqlx::query("UPDATE Sessions SET last_sync=? WHERE token=?")
+            .bind(last_sync)
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Lists received from any session after `since`.
+    pub async fn lists_since(&self, owner: &str, since: i64) -> Result<Vec<List>, Error> {
+        sqlx::query_as("SELECT name, uuid, color, last_edited, created, sealed FROM Lists WHERE owner=? AND updated_at > ?")
+            .bind(owner)
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Tasks received from any session after `since`, as (list, task) pairs.
+    pub async fn tasks_since(&self, owner: &str, since: i64) -> Result<Vec<(String, Task)>, Error> {
+        let rows: Vec<TaskRow> = sqlx::query_as(
+            "SELECT list, name, size, importance, due, completed, id, parent, last_edited, created, sealed \
+            FROM Tasks WHERE owner=? AND updated_at > ?"
+        )
+            .bind(owner)
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.list.clone(), r.into_task())).collect())
+    }
+
+    /// Up to `limit` lists and tasks changed after `cursor`, skipping ones
+    /// last pushed by the `exclude_origin` session.
+    pub async fn changes_after(&self, owner: &str, cursor: i64, limit: i64, exclude_origin: &str) -> Result<ChangePage, Error> {
+        let lists: Vec<(i64, List)> = sqlx::query_as::<_, ListRow>(
+            "SELECT seq, name, uuid, color, last_edited, created, sealed FROM Lists \
+            WHERE owner=? AND seq > ? AND IFNULL(origin, '') != ? \
+            ORDER BY seq LIMIT ?"
+        )
+            .bind(owner)
+            .bind(cursor)
+            .bind(exclude_origin)
+            .bind(limit + 1)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|r| r.into_list())
+            .collect();
+        let tasks: Vec<(i64, String, Task)> = sqlx::query_as::<_, TaskRow>(
+            "SELECT seq, list, name, size, importance, due, completed, id, parent, last_edited, created, sealed \
+            FROM Tasks WHERE owner=? AND seq > ? AND IFNULL(origin, '') != ? \
+            ORDER BY seq LIMIT ?"
+        )
+            .bind(owner)
+            .bind(cursor)
+            .bind(exclude_origin)
+            .bind(limit + 1)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|r| (r.seq, r.list.clone(), r.into_task()))
+            .collect();
+        // Merge the two seq-ordered streams and keep the first `limit`
+        let mut page = ChangePage { lists: Vec::new(), tasks: Vec::new(), cursor, has_more: false };
+        let (mut l, mut t) = (lists.into_iter().peekable(), tasks.into_iter().peekable());
+        loop {
+            let take_list = match (l.peek(), t.peek()) {
+                (None, None) => break,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(a), Some(b)) => a.0 < b.0,
+            };
+            if (page.lists.len() + page.tasks.len()) as i64 >= limit {
+                page.has_more = true;
+                break;
+            }
+            if take_list {
+                let (seq, list) = l.next().unwrap();
+                page.cursor = seq;
+                page.lists.push(list);
+            } else {
+                let (seq, list, task) = t.next().unwrap();
+                page.cursor = seq;
+                page.tasks.push((list, task));
+            }
+        }
+        Ok(page)
+    }
+
+    /// Stores a list unless the server already has a newer (or the same) edit
+    /// of it. The same edit is taken again if it was re-encrypted.
+    pub async fn upsert_list(&self, owner: &str, list: &List, received: i64, origin: Option<&str>) -> Result<bool, Error> {
+        let seq = self.next_seq(owner).await?;
+        let result = sqlx::query(
+            "INSERT INTO Lists (owner, uuid, name, color, created, last_edited, updated_at, seq, origin, sealed) \
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+            ON CONFLICT(owner, uuid) DO UPDATE SET \
+                name=excluded.name, \
+                color=excluded.color, \
+                last_edited=excluded.last_edited, \
+                updated_at=excluded.updated_at, \
+                seq=excluded.seq, \
+                origin=excluded.origin, \
+                sealed=excluded.sealed \
+            WHERE IFNULL(Lists.last_edited, -1) < IFNULL(excluded.last_edited, 0) \
+                OR (Lists.last_edited = excluded.last_edited AND Lists.sealed IS NOT excluded.sealed)"
+        )
+            .bind(owner)
+            .bind(&list.uuid)
+            .bind(&list.name)
+            .bind(list.color)
+            .bind(list.created)
+            .bind(list.last_edited)
+            .bind(received)
+            .bind(seq)
+            .bind(origin)
+            .bind(&list.sealed)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_list(&self, owner: &str, uuid: &str) -> Result<bool, Error> {
+        sqlx::query("DELETE FROM Tasks WHERE owner=? AND list=?")
+            .bind(owner)
+            .bind(uuid)
+            .execute(&self.pool)
+            .await?;
+        let result = sqlx::query("DELETE FROM Lists WHERE owner=? AND uuid=?")
+            .bind(owner)
+            .bind(uuid)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Stores a task unless the server already has a newer (or the same) edit
+    /// of it. The same edit is taken again if it was re-encrypted.
+    pub async fn upsert_task(&self, owner: &str, list: &str, task: &Task, received: i64, origin: Option<&str>) -> Result<bool, Error> {
+        let seq = self.next_seq(owner).await?;
+        let result = sqlx::query(
+            "INSERT INTO Tasks \
+            (owner, list, id, name, size, importance, due, completed, parent, created, last_edited, updated_at, seq, origin, sealed) \
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+            ON CONFLICT(owner, list, id) DO UPDATE SET \
+                name=excluded.name, \
+                size=excluded.size, \
+                importance=excluded.importance, \
+                due=excluded.due, \
+                completed=excluded.completed, \
+                parent=excluded.parent, \
+                last_edited=excluded.last_edited, \
+                updated_at=excluded.updated_at, \
+                seq=excluded.seq, \
+                origin=excluded.origin, \
+                sealed=excluded.sealed \
+            WHERE IFNULL(Tasks.last_edited, -1) < IFNULL(excluded.last_edited, 0) \
+                OR (Tasks.last_edited = excluded.last_edited AND Tasks.sealed IS NOT excluded.sealed)"
+        )
+            .bind(owner)
+            .bind(list)
+            .bind(&task.id)
+            .bind(&task.name)
+            .bind(task.size)
+            .bind(task.importance)
+            .bind(task.due)
+            .bind(task.completed)
+            .bind(&task.parent)
+            .bind(task.created)
+            .bind(task.last_edited)
+            .bind(received)
+            .bind(seq)
+            .bind(origin)
+            .bind(&task.sealed)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The account's sealed keyring (opaque JSON), if encrypted sync is set up.
+    pub async fn get_keyring(&self, owner: &str) -> Result<Option<String>, Error> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT keyring FROM Keyrings WHERE owner=?")
+            .bind(owner)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| r.0))
+    }
+
+    pub async fn set_keyring(&self, owner: &str, keyring: &str) -> Result<(), Error> {
+        sqlx::query("INSERT OR REPLACE INTO Keyrings (owner, keyring, updated_at) VALUES (?, ?, ?)")
+            .bind(owner)
+            .bind(keyring)
+            .bind(now())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn record_telemetry(&self, device_id: &str, previous: Option<&str>) -> Result<(), Error> {
+        sqlx::query("INSERT INTO Telemetry (device_id, previous, time) VALUES (?, ?, ?)")
+            .bind(device_id)
+            .bind(previous)
+            .bind(now())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+fn new_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+fn hash_secret(secret: &str) -> Result<String, Error> {
+    let salt = SaltString::encode_b64(uuid::Uuid::new_v4().as_bytes())
+        .map_err(|e| Error::Protocol(format!("{}", e)))?;
+    Ok(Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)
+        .map_err(|e| Error::Protocol(format!("{}", e)))?
+        .to_string())
+}
+
+fn verify_secret(hash: &str, secret: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default().verify_password(secret.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ListRow {
+    seq: i64,
+    name: String,
+    uuid: String,
+    color: i32,
+    last_edited: Option<i64>,
+    created: Option<i64>,
+    sealed: Option<String>,
+}
+
+impl ListRow {
+    fn into_list(self) -> (i64, List) {
+        (self.seq, List {
+            name: self.name,
+            uuid: self.uuid,
+            color: self.color,
+            last_edited: self.last_edited,
+            created: self.created,
+            sealed: self.sealed,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct TaskRow {
+    #[sqlx(default)]
+    seq: i64,
+    list: String,
+    name: String,
+    size: i32,
+    importance: i32,
+    due: i64,
+    completed: bool,
+    id: String,
+    parent: Option<String>,
+    last_edited: Option<i64>,
+    created: Option<i64>,
+    sealed: Option<String>,
+}
+
+impl TaskRow {
+    fn into_task(self) -> Task {
+        Task {
+            name: self.name,
+            size: self.size,
+            importance: self.importance,
+            due: self.due,
+            completed: self.completed,
+            id: self.id,
+            parent: self.parent,
+            last_edited: self.last_edited,
+            created: self.created,
+            sealed: self.sealed,
+        }
+    }
+}
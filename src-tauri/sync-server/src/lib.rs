@@ -0,0 +1,557 @@
+//! Reference implementation of the Task Manager sync server.
+//!
+//! Implements the endpoints `src-tauri/src/http.rs` talks to. See README.md
+//! in this crate for the wire protocol.
+
+use std::{collections::HashMap, time::{SystemTime, UNIX_EPOCH}};
+
+use axum::{
+    extract::{Path, Query, RawQuery, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, patch, post},
+    Form, Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+
+mod db;
+#[cfg(test)]
+mod tests;
+
+pub use db::ServerDb;
+
+pub const SESSION_COOKIE: &str = "session";
+/// Sessions expire this long after sign-in.
+pub const SESSION_MAX_AGE_SECS: i64 = 30 * 24 * 60 * 60;
+/// Lifetime of a bearer access token from `/auth/token`.
+pub const ACCESS_TOKEN_TTL_SECS: i64 = 15 * 60;
+/// Lifetime of a refresh token. Each refresh issues a new one.
+pub const REFRESH_TOKEN_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+/// Failed sign-ins allowed per user within `LOGIN_WINDOW_SECS` before the
+/// server answers `429`.
+pub const MAX_FAILED_LOGINS: i64 = 5;
+pub const LOGIN_WINDOW_SECS: i64 = 60;
+pub const RESET_CODE_TTL_SECS: i64 = 60 * 60;
+pub const MIN_PASSWORD_LEN: usize = 8;
+/// Newest `/sync` protocol version this server speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const CAPABILITIES: &[&str] = &["json-body", "paged", "sealed"];
+/// Largest page a client may ask for with `?limit=`.
+pub const MAX_PAGE_SIZE: i64 = 1000;
+pub const DEFAULT_PAGE_SIZE: i64 = 500;
+pub const PROTOCOL_HEADER: &str = "x-sync-protocol";
+pub const CAPABILITIES_HEADER: &str = "x-sync-capabilities";
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Timestamp issue in Rust (TS is before epoch)")
+        .as_millis() as i64
+}
+
+fn de_float_guard<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(num) => {
+            if num.is_f64() {
+                Some(num.as_f64().ok_or(de::Error::custom(format!("Invalid number {}", num)))? as i64)
+            } else {
+                Some(num.as_i64().ok_or(de::Error::custom(format!("Invalid number {}", num)))?)
+            }
+        },
+        Value::Null => None,
+        _ => return Err(de::Error::custom("wrong type"))
+    })
+}
+
+/// Same JSON shape as the app's `ListEntry`.
+#[derive(sqlx::FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct List {
+    pub name: String,
+    pub uuid: String,
+    pub color: i32,
+    #[serde(deserialize_with = "de_float_guard")]
+    pub last_edited: Option<i64>,
+    #[serde(deserialize_with = "de_float_guard")]
+    pub created: Option<i64>,
+    /// Encrypted content; stored as-is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<String>
+}
+
+/// Same JSON shape as the app's `TaskEntry`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Task {
+    pub name: String,
+    pub size: i32,
+    pub importance: i32,
+    pub due: i64,
+    pub completed: bool,
+    pub id: String,
+    pub parent: Option<String>,
+    #[serde(deserialize_with = "de_float_guard")]
+    pub last_edited: Option<i64>,
+    #[serde(deserialize_with = "de_float_guard")]
+    pub created: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<String>
+}
+
+/// Same JSON shape as the app's `SyncData`. Tasks are keyed by list UUID.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SyncData {
+    #[serde(default)]
+    pub protocol_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_protocol_version: Option<u32>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    pub last_sync: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_more: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<bool>,
+    pub lists: Vec<List>,
+    pub tasks: HashMap<String, Vec<Task>>
+}
+
+pub enum ApiError {
+    Unauthorized,
+    Forbidden,
+    BadRequest(String),
+    NotFound,
+    Conflict(String),
+    /// Too many failed sign-ins; retry after this many seconds
+    TooManyRequests(i64),
+    /// Unknown, expired or already used refresh token
+    InvalidGrant,
+    Db(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        ApiError::Db(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"Task Manager\"")],
+                "Not logged in."
+            ).into_response(),
+            ApiError::Forbidden => (StatusCode::FORBIDDEN, "Invalid credentials.").into_response(),
+            ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg).into_response(),
+            ApiError::NotFound => StatusCode::NOT_FOUND.into_response(),
+            ApiError::Conflict(msg) => (StatusCode::CONFLICT, msg).into_response(),
+            ApiError::TooManyRequests(secs) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, secs.to_string())],
+                "Too many attempts. Try again later."
+            ).into_response(),
+            ApiError::InvalidGrant => (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({ "error": "invalid_grant" }))
+            ).into_response(),
+            ApiError::Db(e) => {
+                println!("Database error {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Database error.").into_response()
+            }
+        }
+    }
+}
+
+pub fn router(db: ServerDb) -> Router {
+    Router::new()
+        .route("/login", get(login))
+        .route("/logout", post(logout))
+        .route("/auth/token", post(token))
+        .route("/auth/register", post(register))
+        .route("/auth/reset", post(request_reset))
+        .route("/auth/reset/confirm", post(confirm_reset))
+        .route("/sync", get(pull).post(push))
+        .route("/lists", post(create_list))
+        .route("/lists/:uuid", patch(update_list).delete(remove_list))
+        .route("/keyring", get(get_keyring).put(put_keyring))
+        .route("/telemetry", post(telemetry))
+        .with_state(db)
+}
+
+pub async fn serve(listener: tokio::net::TcpListener, db: ServerDb) -> std::io::Result<()> {
+    axum::serve(listener, router(db)).await
+}
+
+/// Starts a server on an ephemeral loopback port in the background and
+/// returns its base URL (e.g. `http://127.0.0.1:53211`).
+pub async fn spawn_local(db: ServerDb) -> std::io::Result<String> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(serve(listener, db));
+    Ok(format!("http://{}", addr))
+}
+
+/// Finds the session token in the `Cookie` header. Clients may echo back
+/// the whole `Set-Cookie` value, attributes included, so those are skipped.
+pub fn session_token(headers: &HeaderMap) -> Option<String> {
+    for value in headers.get_all(header::COOKIE) {
+        let value = match value.to_str() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        for pair in value.split(';') {
+            let pair = pair.trim();
+            if let Some(token) = pair.strip_prefix(&format!("{}=", SESSION_COOKIE)) {
+                if !token.is_empty() {
+                    return Some(token.to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// The token from an `Authorization: Bearer` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+    if token.is_empty() {
+        return None;
+    }
+    Some(token.to_string())
+}
+
+async fn authenticate(db: &ServerDb, headers: &HeaderMap) -> Result<(String, db::Session), ApiError> {
+    if let Some(access_token) = bearer_token(headers) {
+        return db.get_session_by_access_token(&access_token).await?.ok_or(ApiError::Unauthorized);
+    }
+    let token = session_token(headers).ok_or(ApiError::Unauthorized)?;
+    let session = db.get_session(&token).await?.ok_or(ApiError::Unauthorized)?;
+    Ok((token, session))
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+fn session_cookie(token: &str) -> String {
+    format!("{}={}; Max-Age={}; HttpOnly; Path=/; SameSite=Strict", SESSION_COOKIE, token, SESSION_MAX_AGE_SECS)
+}
+
+async fn logout(State(db): State<ServerDb>, headers: HeaderMap) -> Result<Response, ApiError> {
+    let (token, _) = authenticate(&db, &headers).await?;
+    db.delete_session(&token).await?;
+    let cleared = format!("{}=; Max-Age=0; HttpOnly; Path=/; SameSite=Strict", SESSION_COOKIE);
+    Ok((StatusCode::OK, [(header::SET_COOKIE, cleared)], "OK").into_response())
+}
+
+/// Checks a password, counting failures towards the user's rate limit.
+async fn check_login(db: &ServerDb, username: &str, password: &str) -> Result<bool, ApiError> {
+    if let Some(secs) = db.login_retry_after(username).await? {
+        return Err(ApiError::TooManyRequests(secs));
+    }
+    if !db.check_password(username, password).await? {
+        db.record_failed_login(username).await?;
+        return Ok(false);
+    }
+    db.clear_failed_logins(username).await?;
+    Ok(true)
+}
+
+async fn login(State(db): State<ServerDb>, headers: HeaderMap) -> Result<Response, ApiError> {
+    let (username, password) = basic_credentials(&headers).ok_or(ApiError::Unauthorized)?;
+    if !check_login(&db, &username, &password).await? {
+        return Err(ApiError::Forbidden);
+    }
+    let token = db.new_session(&username).await?;
+    Ok((StatusCode::OK, [(header::SET_COOKIE, session_cookie(&token))], "OK").into_response())
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "grant_type", rename_all = "snake_case")]
+enum TokenRequest {
+    Password { username: String, password: String },
+    RefreshToken { refresh_token: String },
+}
+
+#[derive(Serialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: i64,
+    refresh_token: String,
+    refresh_expires_in: i64,
+}
+
+impl From<db::Tokens> for TokenResponse {
+    fn from(tokens: db::Tokens) -> Self {
+        TokenResponse {
+            access_token: tokens.access_token,
+            token_type: "Bearer",
+            expires_in: ACCESS_TOKEN_TTL_SECS,
+            refresh_token: tokens.refresh_token,
+            refresh_expires_in: REFRESH_TOKEN_TTL_SECS,
+        }
+    }
+}
+
+async fn token(State(db): State<ServerDb>, Form(request): Form<TokenRequest>) -> Result<Json<TokenResponse>, ApiError> {
+    let tokens = match request {
+        TokenRequest::Password { username, password } => {
+            if !check_login(&db, &username, &password).await? {
+                return Err(ApiError::Forbidden);
+            }
+            db.new_token_session(&username).await?
+        },
+        TokenRequest::RefreshToken { refresh_token } => {
+            db.refresh_tokens(&refresh_token).await?.ok_or(ApiError::InvalidGrant)?
+        },
+    };
+    Ok(Json(tokens.into()))
+}
+
+#[derive(Deserialize)]
+struct Registration {
+    username: String,
+    password: String,
+}
+
+/// Usernames are 1-64 letters, digits, `.`, `-` or `_`.
+pub fn valid_username(username: &str) -> bool {
+    !username.is_empty() && username.len() <= 64
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+fn check_new_password(password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ApiError::BadRequest(format!("Password must be at least {} characters.", MIN_PASSWORD_LEN)));
+    }
+    Ok(())
+}
+
+async fn register(State(db): State<ServerDb>, Json(registration): Json<Registration>) -> Result<Response, ApiError> {
+    if !valid_username(&registration.username) {
+        return Err(ApiError::BadRequest("Invalid username.".to_string()));
+    }
+    check_new_password(&registration.password)?;
+    if !db.add_user(&registration.username, &registration.password).await? {
+        return Err(ApiError::Conflict("Username is taken.".to_string()));
+    }
+    Ok((StatusCode::CREATED, "Created").into_response())
+}
+
+#[derive(Deserialize)]
+struct ResetRequest {
+    username: String,
+}
+
+/// Starts a password reset. Always answers `202`, so it can't be used to
+/// find out which accounts exist. The reference server has no mailer, so the
+/// code is printed for the operator to pass on.
+async fn request_reset(State(db): State<ServerDb>, Json(request): Json<ResetRequest>) -> Result<Response, ApiError> {
+    if let Some(code) = db.create_reset_code(&request.username).await? {
+        println!("Password reset code for {}: {}", request.username, code);
+    }
+    Ok((StatusCode::ACCEPTED, "Accepted").into_response())
+}
+
+#[derive(Deserialize)]
+struct ResetConfirm {
+    username: String,
+    code: String,
+    new_password: String,
+}
+
+async fn confirm_reset(State(db): State<ServerDb>, Json(confirm): Json<ResetConfirm>) -> Result<Response, ApiError> {
+    check_new_password(&confirm.new_password)?;
+    if let Some(secs) = db.login_retry_after(&confirm.username).await? {
+        return Err(ApiError::TooManyRequests(secs));
+    }
+    if !db.consume_reset_code(&confirm.username, &confirm.code).await? {
+        db.record_failed_login(&confirm.username).await?;
+        return Err(ApiError::Forbidden);
+    }
+    db.set_password(&confirm.username, &confirm.new_password).await?;
+    db.clear_failed_logins(&confirm.username).await?;
+    Ok((StatusCode::OK, "OK").into_response())
+}
+
+/// The client's protocol version and capabilities from the GET /sync
+/// headers. Clients that predate versioning send neither (version 0).
+pub fn client_protocol(headers: &HeaderMap) -> (u32, Vec<String>) {
+    let version = headers
+        .get(PROTOCOL_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0);
+    let capabilities = headers
+        .get(CAPABILITIES_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect())
+        .unwrap_or_default();
+    (version, capabilities)
+}
+
+#[derive(Deserialize)]
+struct PullParams {
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Cursors are the last change sequence number the client has applied.
+/// An empty cursor starts from the beginning.
+pub fn parse_cursor(cursor: &str) -> Result<i64, ApiError> {
+    if cursor.is_empty() {
+        return Ok(0);
+    }
+    cursor.parse().map_err(|_| ApiError::BadRequest("Invalid cursor.".to_string()))
+}
+
+async fn pull(State(db): State<ServerDb>, headers: HeaderMap, Query(params): Query<PullParams>) -> Result<Json<SyncData>, ApiError> {
+    let (token, session) = authenticate(&db, &headers).await?;
+    let (client_version, _) = client_protocol(&headers);
+    let mut data = SyncData {
+        // Answer in the newest version both sides speak
+        protocol_version: client_version.min(PROTOCOL_VERSION),
+        min_protocol_version: None,
+        capabilities: if client_version == 0 {
+            Vec::new()
+        } else {
+            CAPABILITIES.iter().map(|c| c.to_string()).collect()
+        },
+        last_sync: session.last_sync,
+        cursor: None,
+        has_more: None,
+        encrypted: None,
+        lists: Vec::new(),
+        tasks: HashMap::new()
+    };
+    if client_version > 0 && db.get_keyring(&session.username).await?.is_some() {
+        data.encrypted = Some(true);
+    }
+    if let Some(cursor) = params.cursor.filter(|_| client_version > 0) {
+        // Paged: one page of changes after the cursor
+        let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let page = db.changes_after(&session.username, parse_cursor(&cursor)?, limit, &token).await?;
+        data.lists = page.lists;
+        for (list, task) in page.tasks {
+            data.tasks.entry(list).or_default().push(task);
+        }
+        data.cursor = Some(page.cursor.to_string());
+        data.has_more = Some(page.has_more);
+        return Ok(Json(data));
+    }
+    data.lists = db.lists_since(&session.username, session.last_sync).await?;
+    for (list, task) in db.tasks_since(&session.username, session.last_sync).await? {
+        data.tasks.entry(list).or_default().push(task);
+    }
+    Ok(Json(data))
+}
+
+/// Parses a POST /sync body. Older clients send the `SyncData` JSON wrapped
+/// in a JSON string, so one level of string encoding is unwrapped.
+pub fn parse_sync_body(body: &str) -> Result<SyncData, String> {
+    let mut value: Value = serde_json::from_str(body).map_err(|e| format!("Invalid JSON: {}", e))?;
+    if let Value::String(inner) = &value {
+        value = serde_json::from_str(inner).map_err(|e| format!("Invalid JSON: {}", e))?;
+    }
+    serde_json::from_value(value).map_err(|e| format!("Invalid sync data: {}", e))
+}
+
+async fn push(State(db): State<ServerDb>, headers: HeaderMap, body: String) -> Result<Json<Value>, ApiError> {
+    let (token, session) = authenticate(&db, &headers).await?;
+    let data = parse_sync_body(&body).map_err(ApiError::BadRequest)?;
+    let received = now();
+    let mut accepted = 0;
+    for list in &data.lists {
+        if db.upsert_list(&session.username, list, received, Some(&token)).await? {
+            accepted += 1;
+        }
+    }
+    for (list, tasks) in &data.tasks {
+        for task in tasks {
+            if db.upsert_task(&session.username, list, task, received, Some(&token)).await? {
+                accepted += 1;
+            }
+        }
+    }
+    db.set_last_sync(&token, received).await?;
+    Ok(Json(json!({ "accepted": accepted, "last_sync": received })))
+}
+
+async fn create_list(State(db): State<ServerDb>, headers: HeaderMap, Json(list): Json<List>) -> Result<StatusCode, ApiError> {
+    let (_, session) = authenticate(&db, &headers).await?;
+    db.upsert_list(&session.username, &list, now(), None).await?;
+    Ok(StatusCode::CREATED)
+}
+
+async fn update_list(
+    State(db): State<ServerDb>,
+    headers: HeaderMap,
+    Path(uuid): Path<String>,
+    Json(list): Json<List>
+) -> Result<StatusCode, ApiError> {
+    let (_, session) = authenticate(&db, &headers).await?;
+    if list.uuid != uuid {
+        return Err(ApiError::BadRequest("List UUID does not match the URL.".to_string()));
+    }
+    db.upsert_list(&session.username, &list, now(), None).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn remove_list(State(db): State<ServerDb>, headers: HeaderMap, Path(uuid): Path<String>) -> Result<StatusCode, ApiError> {
+    let (_, session) = authenticate(&db, &headers).await?;
+    if !db.delete_list(&session.username, &uuid).await? {
+        return Err(ApiError::NotFound);
+    }
+    Ok(StatusCode::OK)
+}
+
+async fn get_keyring(State(db): State<ServerDb>, headers: HeaderMap) -> Result<Response, ApiError> {
+    let (_, session) = authenticate(&db, &headers).await?;
+    match db.get_keyring(&session.username).await? {
+        Some(keyring) => Ok(([(header::CONTENT_TYPE, "application/json")], keyring).into_response()),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+/// Stores the account's keyring. The server can't read it; it only checks
+/// that it's a JSON object.
+async fn put_keyring(State(db): State<ServerDb>, headers: HeaderMap, Json(keyring): Json<Value>) -> Result<StatusCode, ApiError> {
+    let (_, session) = authenticate(&db, &headers).await?;
+    if !keyring.is_object() {
+        return Err(ApiError::BadRequest("Keyring must be a JSON object.".to_string()));
+    }
+    db.set_keyring(&session.username, &keyring.to_string()).await?;
+    Ok(StatusCode::OK)
+}
+
+/// Splits a telemetry query. The app has always sent
+/// `device_id=X?previous=Y`, so `?` is accepted as a separator as well as `&`.
+pub fn parse_telemetry_query(query: &str) -> (Option<String>, Option<String>) {
+    let mut device_id = None;
+    let mut previous = None;
+    for pair in query.split(['&', '?']) {
+        match pair.split_once('=') {
+            Some(("device_id", v)) => device_id = Some(v.to_string()),
+            Some(("previous", v)) => previous = Some(v.to_string()),
+            _ => {}
+        }
+    }
+    (device_id, previous)
+}
+
+async fn telemetry(State(db): State<ServerDb>, RawQuery(query): RawQuery) -> Result<StatusCode, ApiError> {
+    let (device_id, previous) = parse_telemetry_query(&query.unwrap_or_default());
+    let device_id = device_id.ok_or(ApiError::BadRequest("Missing device_id.".to_string()))?;
+    db.record_telemetry(&device_id, previous.as_deref()).await?;
+    Ok(StatusCode::OK)
+}
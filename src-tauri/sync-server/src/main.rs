@@ -0,0 +1,40 @@
+use std::env;
+
+use sync_server::{serve, ServerDb};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:5000";
+const DEFAULT_DB: &str = "sync-server.db";
+
+fn usage() {
+    println!("Usage:");
+    println!("  sync-server                       Run the server");
+    println!("  sync-server add-user NAME PASS    Create an account");
+    println!();
+    println!("Environment:");
+    println!("  TASKMGR_ADDR  Address to listen on (default {})", DEFAULT_ADDR);
+    println!("  TASKMGR_DB    SQLite database path (default {})", DEFAULT_DB);
+}
+
+#[tokio::main]
+async fn main() {
+    let addr = env::var("TASKMGR_ADDR").unwrap_or(DEFAULT_ADDR.to_string());
+    let db_path = env::var("TASKMGR_DB").unwrap_or(DEFAULT_DB.to_string());
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let db = ServerDb::open(&db_path).await.expect("Issue opening server database.");
+    match args.iter().map(|a| a.as_str()).collect::<Vec<&str>>().as_slice() {
+        [] => {
+            let listener = tokio::net::TcpListener::bind(&addr).await.expect("Issue binding address.");
+            println!("Listening on http://{}", addr);
+            serve(listener, db).await.expect("Server error.");
+        },
+        ["add-user", username, password] => {
+            if db.add_user(username, password).await.expect("Issue adding user.") {
+                println!("Added {}", username);
+            } else {
+                println!("{} already exists", username);
+            }
+        },
+        _ => usage(),
+    }
+}
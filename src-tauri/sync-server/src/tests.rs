@@ -0,0 +1,95 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+
+use crate::*;
+
+#[test]
+fn test_session_token_ignores_attributes() {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::COOKIE, HeaderValue::from_static("session=abc123; HttpOnly; Path=/; SameSite=Strict"));
+    assert_eq!(session_token(&headers), Some("abc123".to_string()));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::COOKIE, HeaderValue::from_static("theme=dark; session=xyz"));
+    assert_eq!(session_token(&headers), Some("xyz".to_string()));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::COOKIE, HeaderValue::from_static("session="));
+    assert_eq!(session_token(&headers), None);
+    assert_eq!(session_token(&HeaderMap::new()), None);
+}
+
+#[test]
+fn test_parse_sync_body_accepts_string_wrapped() {
+    let body = r#"{"last_sync":5,"lists":[],"tasks":{"l1":[{"name":"t","size":1,"importance":2,"due":3,"completed":false,"id":"a","parent":null,"last_edited":4.0,"created":null}]}}"#;
+    let direct = parse_sync_body(body).unwrap();
+    assert_eq!(direct.tasks.get("l1").unwrap()[0].last_edited, Some(4));
+
+    let wrapped = serde_json::to_string(&body).unwrap();
+    let wrapped = parse_sync_body(&wrapped).unwrap();
+    assert_eq!(wrapped.last_sync, 5);
+    assert_eq!(wrapped.tasks.get("l1").unwrap().len(), 1);
+
+    assert!(parse_sync_body("{}").is_err());
+}
+
+#[test]
+fn test_parse_telemetry_query() {
+    assert_eq!(parse_telemetry_query("device_id=abc"), (Some("abc".to_string()), None));
+    assert_eq!(
+        parse_telemetry_query("device_id=abc?previous=0.3.0"),
+        (Some("abc".to_string()), Some("0.3.0".to_string()))
+    );
+    assert_eq!(
+        parse_telemetry_query("previous=0.3.0&device_id=abc"),
+        (Some("abc".to_string()), Some("0.3.0".to_string()))
+    );
+    assert_eq!(parse_telemetry_query(""), (None, None));
+}
+
+#[test]
+fn test_client_protocol() {
+    assert_eq!(client_protocol(&HeaderMap::new()), (0, vec![]));
+    let mut headers = HeaderMap::new();
+    headers.insert(PROTOCOL_HEADER, HeaderValue::from_static("1"));
+    headers.insert(CAPABILITIES_HEADER, HeaderValue::from_static("json-body, future-thing"));
+    assert_eq!(client_protocol(&headers), (1, vec!["json-body".to_string(), "future-thing".to_string()]));
+}
+
+#[test]
+fn test_parse_cursor() {
+    assert_eq!(parse_cursor("").ok(), Some(0));
+    assert_eq!(parse_cursor("42").ok(), Some(42));
+    assert!(parse_cursor("abc").is_err());
+    assert!(parse_cursor("1; DROP TABLE Tasks").is_err());
+}
+
+#[test]
+fn test_session_cookie_expires() {
+    let cookie = session_cookie("abc");
+    assert!(cookie.starts_with("session=abc;"));
+    assert!(cookie.contains(&format!("Max-Age={}", SESSION_MAX_AGE_SECS)));
+}
+
+#[test]
+fn test_bearer_token() {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer abc123"));
+    assert_eq!(bearer_token(&headers), Some("abc123".to_string()));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Basic YWxpY2U6aHVudGVyMg=="));
+    assert_eq!(bearer_token(&headers), None);
+    let mut headers = HeaderMap::new();
+    headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer "));
+    assert_eq!(bearer_token(&headers), None);
+}
+
+#[test]
+fn test_valid_username() {
+    assert!(valid_username("alice"));
+    assert!(valid_username("a.l-i_c3"));
+    assert!(!valid_username(""));
+    assert!(!valid_username("alice smith"));
+    assert!(!valid_username("alice:bob"));
+    assert!(!valid_username(&"a".repeat(65)));
+}
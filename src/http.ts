@@ -1,5 +1,6 @@
 import { invoke } from "@tauri-apps/api/core";
 import { getElement } from "./utils";
+import { TaskRecord } from "./task";
 
 var isAuthed: boolean = await invoke("is_logged_in")
 
@@ -12,6 +13,37 @@ export async function signIn(username: string, password: string) {
     
 }
 
+/** Creates an account on the current server and signs in to it. */
+export async function register(username: string, password: string) {
+    await invoke("register_account", {username: username, password: password})
+    isAuthed = true
+}
+
+export async function requestPasswordReset(username: string) {
+    await invoke("request_password_reset", {username: username})
+}
+
+export async function resetPassword(username: string, code: string, newPassword: string) {
+    await invoke("reset_password", {username: username, code: code, newPassword: newPassword})
+}
+
+export type DeviceLogin = {user_code: string, verification_uri: string, verification_uri_complete: string | null, expires_in: number, interval: number}
+
+/** Starts signing in with a code the user approves on another device. */
+export async function startDeviceLogin(): Promise<DeviceLogin> {
+    return await invoke("start_device_login")
+}
+
+/** Resolves once the code is approved; rejects if denied, expired or cancelled. */
+export async function pollDeviceLogin() {
+    await invoke("poll_device_login")
+    isAuthed = true
+}
+
+export async function cancelDeviceLogin() {
+    await invoke("cancel_device_login")
+}
+
 export async function logOut() {
     await invoke("log_out")
 }
@@ -32,6 +64,366 @@ export async function doSync(): Promise<boolean> {
     }
 }
 
+type ItemRef = {list: string, id: string, name: string}
+type ChangeSet = {created: ItemRef[], updated: ItemRef[], overwritten: ItemRef[]}
+export type SideChanges = {lists: ChangeSet, tasks: ChangeSet}
+export type SyncPreview = {local: SideChanges, remote: SideChanges}
+
+/** What a sync would do, without changing anything on either side. */
+export async function previewSync(): Promise<SyncPreview> {
+    return await invoke("preview_sync")
+}
+
+export async function getServerEndpoint(): Promise<string> {
+    return await invoke("get_server_endpoint")
+}
+
+/**
+ * Saves a new sync server (or resets to the default with `undefined`).
+ * Rejects with a message if the URL is invalid.
+ */
+export async function setServerEndpoint(endpoint: string | undefined): Promise<string> {
+    const root: string = await invoke("set_server_endpoint", {endpoint: endpoint})
+    isAuthed = await invoke("is_logged_in")
+    return root
+}
+
+export async function testConnection(endpoint: string | undefined): Promise<{endpoint: string, status: number, latency_ms: number, logged_in: boolean}> {
+    return await invoke("test_connection", {endpoint: endpoint})
+}
+
+export type ProfileInfo = {id: string, name: string, endpoint: string, active: boolean}
+
+export async function listProfiles(): Promise<ProfileInfo[]> {
+    return await invoke("list_profiles")
+}
+
+/** Creates a profile with its own tasks, server and sign-in. */
+export async function addProfile(name: string, endpoint: string | undefined): Promise<ProfileInfo> {
+    return await invoke("add_profile", {name: name, endpoint: endpoint})
+}
+
+/** Switches profile and reloads the window with its tasks. */
+export async function switchProfile(id: string) {
+    await invoke("switch_profile", {id: id})
+    window.location.reload()
+}
+
+export async function removeProfile(id: string) {
+    await invoke("remove_profile", {id: id})
+}
+
+export type FolderSyncResult = {imported: number, exported: number}
+
+export async function getSyncFolder(): Promise<string | null> {
+    return await invoke("get_sync_folder")
+}
+
+/** Sets the shared folder (or stops using one with `null`) and merges it. */
+export async function setSyncFolder(path: string | null): Promise<FolderSyncResult> {
+    return await invoke("set_sync_folder", {path: path})
+}
+
+/** Merges the shared folder and watches it for other devices' changes. */
+export async function startFolderSync(): Promise<FolderSyncResult> {
+    return await invoke("start_folder_sync")
+}
+
+/** Writes this device's latest changes to the shared folder. */
+export async function syncFolder(): Promise<FolderSyncResult> {
+    return await invoke("sync_folder")
+}
+
+export type CalDavAccount = {url: string, username: string}
+export type CalDavSyncResult = {pulled: number, pushed: number, deleted: number}
+
+export async function getCalDavAccount(): Promise<CalDavAccount | null> {
+    return await invoke("get_caldav_account")
+}
+
+/** Checks the account can reach its calendars, then saves it. */
+export async function setCalDavAccount(url: string, username: string, password: string): Promise<CalDavAccount> {
+    return await invoke("set_caldav_account", {url: url, username: username, password: password})
+}
+
+export async function removeCalDavAccount() {
+    await invoke("remove_caldav_account")
+}
+
+/** Two-way sync with the CalDAV account, if there is one. */
+export async function calDavSync(): Promise<CalDavSyncResult> {
+    return await invoke("caldav_sync")
+}
+
+export type LocalBackup = {name: string, created: number, size: number}
+
+/** Copies and checks both databases, then removes old backups. */
+export async function backupNow(): Promise<BackupResult> {
+    return await invoke("backup_now")
+}
+
+/** Backups on this device, newest first. */
+export async function listBackups(): Promise<LocalBackup[]> {
+    return await invoke("list_backups")
+}
+
+/** Replaces the local databases with a backup and reloads the window. */
+export async function restoreBackup(name: string) {
+    await invoke("restore_backup", {name: name})
+    window.location.reload()
+}
+
+/** Backs up now and then once a day while the app runs. */
+export async function startLocalBackups() {
+    await invoke("start_local_backups")
+}
+
+export type DbIssue = {kind: string, list: string, task: string | null, problem: string, fix: string}
+
+/** Problems in the task database and what a repair would do about each. */
+export async function checkDatabase(): Promise<DbIssue[]> {
+    return await invoke("check_database")
+}
+
+/** Fixes what `checkDatabase` finds and returns what was changed. */
+export async function repairDatabase(): Promise<DbIssue[]> {
+    return await invoke("repair_database")
+}
+
+export type WebDavBackup = {url: string, username: string, daily: number, weekly: number, last_backup: number | null}
+export type RemoteSnapshot = {name: string, created: number}
+export type BackupResult = {name: string, removed: number}
+
+export async function getWebDavBackup(): Promise<WebDavBackup | null> {
+    return await invoke("get_webdav_backup")
+}
+
+/** Checks the folder can be written to, then saves it as the backup target. */
+export async function setWebDavBackup(url: string, username: string, password: string, passphrase: string, daily: number, weekly: number): Promise<WebDavBackup> {
+    return await invoke("set_webdav_backup", {url: url, username: username, password: password, passphrase: passphrase, daily: daily, weekly: weekly})
+}
+
+export async function removeWebDavBackup() {
+    await invoke("remove_webdav_backup")
+}
+
+export async function backupToWebDav(): Promise<BackupResult> {
+    return await invoke("backup_to_webdav")
+}
+
+/** Snapshots on the server, newest first. */
+export async function listWebDavSnapshots(): Promise<RemoteSnapshot[]> {
+    return await invoke("list_webdav_snapshots")
+}
+
+/** Replaces the local databases with a snapshot and reloads the window. */
+export async function restoreWebDavSnapshot(name: string, passphrase: string) {
+    await invoke("restore_webdav_snapshot", {name: name, passphrase: passphrase})
+    window.location.reload()
+}
+
+/** Backs up once a day while the app runs, if backups are set up. */
+export async function startWebDavBackups() {
+    await invoke("start_webdav_backups")
+}
+
+export type IcsImport = {lists: number, tasks: number, unsupported: {[name: string]: number}}
+
+/** Writes lists (all of them if `listIds` is empty) to an .ics file. Returns how many tasks were written. */
+export async function exportIcs(listIds: string[], path: string): Promise<number> {
+    return await invoke("export_ics", {listIds: listIds, path: path})
+}
+
+/** Adds the tasks in an .ics file as new lists. */
+export async function importIcs(path: string): Promise<IcsImport> {
+    return await invoke("import_ics", {path: path})
+}
+
+/** A list as a nested `- [ ] task (due: ...)` checklist. */
+export async function exportMarkdown(listId: string): Promise<string> {
+    return await invoke("export_markdown", {listId: listId, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+/** Adds the items in a Markdown checklist as new lists, one per heading. */
+export async function importMarkdown(text: string, name: string): Promise<{lists: number, tasks: number}> {
+    return await invoke("import_markdown", {text: text, name: name, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+/** Writes `listIds` (every list if empty) to a todo.txt file. Returns how many tasks were written. */
+export async function exportTodoTxt(listIds: string[], path: string): Promise<number> {
+    return await invoke("export_todotxt", {listIds: listIds, path: path, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+/** Adds the tasks in a todo.txt file as new lists, one per +project. */
+export async function importTodoTxt(path: string): Promise<{lists: number, tasks: number}> {
+    return await invoke("import_todotxt", {path: path, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+export type MigrationImport = {lists: number, tasks: number, dropped: {[what: string]: number}}
+
+/** Adds Todoist projects as new lists, from CSV project exports or a JSON backup. */
+export async function importTodoist(paths: string[]): Promise<MigrationImport> {
+    return await invoke("import_todoist", {paths: paths, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+/** Adds the lists in a Microsoft To Do JSON export as new lists. */
+export async function importMsTodo(path: string): Promise<MigrationImport> {
+    return await invoke("import_mstodo", {path: path, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+/** Writes `listIds` (every list if empty) to a Taskwarrior JSON file. Returns how many tasks were written. */
+export async function exportTaskwarrior(listIds: string[], path: string): Promise<number> {
+    return await invoke("export_taskwarrior", {listIds: listIds, path: path})
+}
+
+/** Adds the tasks in a Taskwarrior JSON export as lists, one per project, updating the lists it was exported from. */
+export async function importTaskwarrior(path: string): Promise<{lists: number, tasks: number, dropped: {[what: string]: number}}> {
+    return await invoke("import_taskwarrior", {path: path})
+}
+
+/** Writes `listIds` (every list if empty) to a CSV file. Returns how many tasks were written. */
+export async function exportCsv(listIds: string[], path: string): Promise<number> {
+    return await invoke("export_csv", {listIds: listIds, path: path, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+/** Header name of each field's column; "" leaves it out, and missing ones are guessed. */
+export type ColumnMapping = {
+    name?: string,
+    list?: string,
+    path?: string,
+    indent?: string,
+    size?: string,
+    importance?: string,
+    due?: string,
+    completed?: string,
+    date_order?: "mdy" | "dmy",
+    priorities?: {[text: string]: number}
+}
+export type CsvPreview = {
+    headers: string[],
+    mapping: ColumnMapping,
+    lists: {name: string, tasks: number, sample: TaskRecord[]}[],
+    problems: string[]
+}
+
+/** What importing a CSV file would make, without saving anything. */
+export async function previewCsvImport(path: string, mapping: ColumnMapping): Promise<CsvPreview> {
+    return await invoke("preview_csv_import", {path: path, mapping: mapping, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+export async function importCsv(path: string, mapping: ColumnMapping): Promise<{lists: number, tasks: number, problems: string[]}> {
+    return await invoke("import_csv", {path: path, mapping: mapping, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+/** Writes every list, task and due event, plus `settings`, to a JSON archive. */
+export async function exportAll(path: string, settings: {[key: string]: unknown}) {
+    await invoke("export_all", {path: path, settings: settings})
+}
+
+export type ImportMode = "merge" | "replace"
+export type ItemCounts = {added: number, updated: number, kept: number, removed: number}
+export type ImportReport = {
+    mode: ImportMode,
+    lists: ItemCounts,
+    tasks: ItemCounts,
+    due_events: ItemCounts,
+    settings: {[key: string]: unknown} | null,
+    skipped: string[]
+}
+
+/** Imports a JSON archive, checking it fully before changing anything. */
+export async function importAll(path: string, mode: ImportMode): Promise<ImportReport> {
+    return await invoke("import_all", {path: path, mode: mode})
+}
+
+export type FeedInfo = {url: string, lan: boolean}
+
+export async function getIcsFeed(): Promise<FeedInfo | null> {
+    return await invoke("get_ics_feed")
+}
+
+/** Serves the due date feed, on this device only unless `lan` is set. */
+export async function startIcsFeed(lan: boolean): Promise<FeedInfo> {
+    return await invoke("start_ics_feed", {lan: lan, utcOffset: -new Date().getTimezoneOffset()})
+}
+
+/** Starts the feed if it was on last time. */
+export async function resumeIcsFeed(): Promise<FeedInfo | null> {
+    return await invoke("resume_ics_feed")
+}
+
+export async function stopIcsFeed() {
+    await invoke("stop_ics_feed")
+}
+
+/** Makes a new feed link; subscriptions to the old one stop working. */
+export async function resetIcsFeedToken(): Promise<FeedInfo | null> {
+    return await invoke("reset_ics_feed_token")
+}
+
+export type LanPeer = {id: string, name: string, public_key: string}
+export type LanDevice = {id: string, name: string, address: string, paired: boolean}
+
+/** Starts accepting syncs from paired devices on this network. */
+export async function startLanSync(): Promise<number> {
+    return await invoke("start_lan_sync")
+}
+
+export async function stopLanSync() {
+    await invoke("stop_lan_sync")
+}
+
+/** Opens a two minute window for one device to pair. Returns the PIN to show. */
+export async function startLanPairing(): Promise<string> {
+    return await invoke("start_lan_pairing")
+}
+
+export async function discoverLanDevices(): Promise<LanDevice[]> {
+    return await invoke("discover_lan_devices")
+}
+
+export async function pairLanDevice(address: string, pin: string): Promise<LanPeer> {
+    return await invoke("pair_lan_device", {address: address, pin: pin})
+}
+
+export async function syncLanDevice(address: string): Promise<LanPeer> {
+    return await invoke("sync_lan_device", {address: address})
+}
+
+export async function forgetLanPeer(id: string) {
+    await invoke("forget_lan_peer", {id: id})
+}
+
+export type EncryptionStatus = {enabled: boolean, unlocked: boolean, key_id: number | null}
+
+export async function getEncryptionStatus(): Promise<EncryptionStatus> {
+    return await invoke("get_encryption_status")
+}
+
+/**
+ * Turns on end-to-end encrypted sync for this account. Resolves to the
+ * recovery phrase, which the user must write down.
+ */
+export async function enableEncryption(passphrase: string): Promise<string> {
+    return await invoke("enable_encryption", {passphrase: passphrase})
+}
+
+export async function unlockEncryption(passphrase: string) {
+    await invoke("unlock_encryption", {passphrase: passphrase})
+}
+
+export async function rotateEncryptionKey(passphrase: string): Promise<number> {
+    return await invoke("rotate_encryption_key", {passphrase: passphrase})
+}
+
+export async function exportRecoveryPhrase(passphrase: string): Promise<string> {
+    return await invoke("export_recovery_phrase", {passphrase: passphrase})
+}
+
+export async function recoverEncryption(phrase: string, newPassphrase: string) {
+    await invoke("recover_encryption", {phrase: phrase, newPassphrase: newPassphrase})
+}
+
 export async function sendMetadata(deviceId: string, previousVersion: string) {
     await invoke("send_telemetry", {
         deviceId: deviceId,
@@ -1,13 +1,15 @@
 import { Store } from "@tauri-apps/plugin-store";
 import { CheckInHandler } from "./notifications";
 import { Weekdays, getElement, onWindowFocused, padWithLeftZeroes, registerShowHideButton } from "./utils";
-import { SETTINGS_PATH } from "./storage";
+import { SETTINGS_PATH, loadTasks } from "./storage";
+import { TaskRecord } from "./task";
 import { getVersion } from "@tauri-apps/api/app";
-import { isAuthenticated, logOut, sendMetadata as sendTelemetry, signIn } from "./http";
+import { addProfile, backupNow, backupToWebDav, calDavSync, cancelDeviceLogin, checkDatabase, discoverLanDevices, enableEncryption, exportAll, exportCsv, exportIcs, exportMarkdown, exportRecoveryPhrase, exportTaskwarrior, exportTodoTxt, forgetLanPeer, getCalDavAccount, getEncryptionStatus, getIcsFeed, getServerEndpoint, getWebDavBackup, getSyncFolder, importAll, importCsv, importIcs, importMarkdown, importMsTodo, importTaskwarrior, importTodoist, importTodoTxt, isAuthenticated, listBackups, listProfiles, listWebDavSnapshots, logOut, pollDeviceLogin, previewCsvImport, previewSync, recoverEncryption, register, removeCalDavAccount, removeProfile, removeWebDavBackup, repairDatabase, restoreBackup, restoreWebDavSnapshot, requestPasswordReset, startDeviceLogin, resetIcsFeedToken, resetPassword, rotateEncryptionKey, pairLanDevice, sendMetadata as sendTelemetry, setCalDavAccount, setServerEndpoint, setWebDavBackup, setSyncFolder, signIn, startIcsFeed, startLanPairing, startLanSync, stopIcsFeed, stopLanSync, switchProfile, syncLanDevice, testConnection, unlockEncryption, ColumnMapping, DbIssue, ImportMode, ItemCounts, MigrationImport, SideChanges } from "./http";
 import { Update, check } from "@tauri-apps/plugin-updater";
 import { loadBugReport } from "./feedback";
 import { getCurrentWindow } from "@tauri-apps/api/window";
 import { UnlistenFn } from "@tauri-apps/api/event";
+import { ask, open, save } from "@tauri-apps/plugin-dialog";
 
 const VERSION = await getVersion()
 
@@ -207,6 +209,7 @@ export class SettingsView {
         // @ts-ignore
         syncCheckbox.checked = this.settings.syncEnabled
         
+        getElement("syncpreviewbutton").addEventListener("click", _ => this.syncPreview())
         getElement("logoutbutton").addEventListener(
             "click",
             _ => {
@@ -222,7 +225,150 @@ export class SettingsView {
             "submit",
             e => {
                 e.preventDefault()
-                this.syncSignInFormSubmit()
+                const button = (e as SubmitEvent).submitter
+                this.syncSignInFormSubmit(button?.id == "syncregisterbutton")
+            }
+        )
+        getElement("syncdevicebutton").addEventListener("click", _ => this.syncDeviceLogin())
+        getElement("syncdevicecancelbutton").addEventListener("click", _ => cancelDeviceLogin())
+        getElement("syncresetcodebutton").addEventListener("click", _ => this.syncResetCode())
+        getElement("syncresetform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                this.syncResetFormSubmit()
+            }
+        )
+
+        getServerEndpoint().then(root => {
+            (getElement("syncserverinput") as HTMLInputElement).value = root
+        })
+        getElement("syncserverform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                this.syncServerFormSubmit()
+            }
+        )
+        getElement("syncservertestbutton").addEventListener(
+            "click",
+            _ => this.syncServerTest()
+        )
+
+        this.showProfiles().then()
+        getElement("profileswitchbutton").addEventListener("click", _ => this.profileAction("switch"))
+        getElement("profileremovebutton").addEventListener("click", _ => this.profileAction("remove"))
+        getElement("profileaddform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                this.profileAddFormSubmit()
+            }
+        )
+
+        this.showSyncFolder().then()
+        getElement("syncfolderbutton").addEventListener("click", _ => this.syncFolderChoose())
+        getElement("syncfolderclearbutton").addEventListener("click", _ => this.syncFolderSet(null))
+
+        this.showCalDavAccount().then()
+        getElement("caldavsyncbutton").addEventListener("click", _ => this.calDavSyncNow())
+        getElement("caldavremovebutton").addEventListener("click", _ => this.calDavRemove())
+        getElement("caldavform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                this.calDavFormSubmit()
+            }
+        )
+
+        this.showLocalBackups().then()
+        getElement("localbackupbutton").addEventListener("click", _ => this.localBackupNow())
+        getElement("localrestoreform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                this.localRestoreSubmit()
+            }
+        )
+
+        getElement("dbcheckbutton").addEventListener("click", _ => this.databaseCheck())
+        getElement("dbrepairbutton").addEventListener("click", _ => this.databaseRepair())
+
+        this.showWebDavBackup().then()
+        getElement("webdavbackupbutton").addEventListener("click", _ => this.webDavBackupNow())
+        getElement("webdavremovebutton").addEventListener("click", _ => this.webDavRemove())
+        getElement("webdavform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                this.webDavFormSubmit()
+            }
+        )
+        getElement("webdavrestoreform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                this.webDavRestoreSubmit()
+            }
+        )
+
+        getElement("icsexportbutton").addEventListener("click", _ => this.icsExport())
+        getElement("icsimportbutton").addEventListener("click", _ => this.icsImport())
+        getElement("markdownlist").addEventListener("focus", _ => this.showMarkdownLists())
+        this.showMarkdownLists().then()
+        getElement("markdowncopybutton").addEventListener("click", _ => this.markdownCopy())
+        getElement("markdownimportbutton").addEventListener("click", _ => this.markdownImport())
+        getElement("todotxtexportbutton").addEventListener("click", _ => this.todoTxtExport())
+        getElement("todotxtimportbutton").addEventListener("click", _ => this.todoTxtImport())
+        getElement("taskwarriorexportbutton").addEventListener("click", _ => this.taskwarriorExport())
+        getElement("taskwarriorimportbutton").addEventListener("click", _ => this.taskwarriorImport())
+        getElement("todoistimportbutton").addEventListener("click", _ => this.todoistImport())
+        getElement("mstodoimportbutton").addEventListener("click", _ => this.msTodoImport())
+        getElement("csvexportbutton").addEventListener("click", _ => this.csvExport())
+        getElement("csvimportbutton").addEventListener("click", _ => this.csvImport())
+        getElement("csvpreviewbutton").addEventListener("click", _ => this.csvPreview())
+        getElement("csvcancelbutton").addEventListener("click", _ => this.csvCancel())
+        getElement("csvmappingform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                this.csvImportSubmit()
+            }
+        )
+        getElement("archiveexportbutton").addEventListener("click", _ => this.archiveExport())
+        getElement("archivemergebutton").addEventListener("click", _ => this.archiveImport("merge"))
+        getElement("archivereplacebutton").addEventListener("click", _ => this.archiveImport("replace"))
+
+        this.showIcsFeed().then()
+        getElement("feedenabled").addEventListener("change", _ => this.icsFeedChange())
+        getElement("feedlan").addEventListener("change", _ => this.icsFeedChange())
+        for (const id of ["feedlist", "feedtag", "feedallday", "feedalarm"]) {
+            getElement(id).addEventListener("input", _ => this.showIcsFeedUrl())
+        }
+        getElement("feedresetbutton").addEventListener("click", _ => this.icsFeedReset())
+
+        getElement("lanenabled").addEventListener(
+            "change",
+            e => this.lanSettingsChange((e.target as HTMLInputElement).checked)
+        )
+        getElement("lanpairbutton").addEventListener("click", _ => this.lanPair())
+        getElement("landiscoverbutton").addEventListener("click", _ => this.lanDiscover())
+
+        getElement("encryptionform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                const button = (e as SubmitEvent).submitter
+                this.encryptionAction(button?.id == "encryptionenablebutton" ? "enable" : "unlock")
+            }
+        )
+        getElement("encryptionrotatebutton").addEventListener("click", _ => this.encryptionAction("rotate"))
+        getElement("encryptionphrasebutton").addEventListener("click", _ => this.encryptionAction("phrase"))
+        getElement("encryptionrecoverform").addEventListener(
+            "submit",
+            e => {
+                e.preventDefault()
+                this.encryptionRecoverFormSubmit()
             }
         )
 
@@ -469,6 +615,7 @@ export class SettingsView {
         getElement("syncinfo").style.display = "none"
         getElement("syncsigninbox").style.display = "none"
         getElement("syncbuttonbox").style.display = "block"
+        this.showEncryptionStatus().then()
     }
 
     private syncShowSignIn() {
@@ -477,9 +624,9 @@ export class SettingsView {
         getElement("syncbuttonbox").style.display = "none"
     }
     
-    private async syncSignInFormSubmit() {
+    private async syncSignInFormSubmit(createAccount: boolean) {
         getElement("syncinfo").style.display = "block"
-        getElement("syncinfo").innerHTML = "Signing in..."
+        getElement("syncinfo").innerHTML = createAccount ? "Creating your account..." : "Signing in..."
         getElement("syncsigninbox").style.display = "none"
 
         // @ts-ignore
@@ -490,15 +637,919 @@ export class SettingsView {
         form.reset()
 
         try {
-            await signIn(uname, passwd)
+            if (createAccount) {
+                await register(uname, passwd)
+            } else {
+                await signIn(uname, passwd)
+            }
             this.syncSignedIn()
         } catch (error) {
-            getElement("syncinfo").innerHTML = "<element style='color: red;'>Username or password incorrect. Please try again.</element>"
+            getElement("syncinfo").innerHTML = ""
+            const message = document.createElement("element")
+            message.style.color = "red"
+            message.innerText = `${error}`
+            getElement("syncinfo").appendChild(message)
             getElement("syncsigninbox").style.display = "block"
         }
     }
 
+    private async syncDeviceLogin() {
+        const box = getElement("syncdevicebox")
+        try {
+            const login = await startDeviceLogin()
+            const link = getElement("syncdevicelink") as HTMLAnchorElement
+            link.href = login.verification_uri_complete ?? login.verification_uri
+            link.innerText = login.verification_uri
+            getElement("syncdevicecode").innerText = login.user_code
+            box.style.display = "block"
+            await pollDeviceLogin()
+            box.style.display = "none"
+            this.syncSignedIn()
+        } catch (error) {
+            box.style.display = "none"
+            getElement("syncinfo").style.display = "block"
+            getElement("syncinfo").innerHTML = ""
+            const message = document.createElement("element")
+            message.style.color = "red"
+            message.innerText = `${error}`
+            getElement("syncinfo").appendChild(message)
+        }
+    }
+
+    private async syncResetCode() {
+        const form = getElement("syncresetform") as HTMLFormElement
+        const info = getElement("syncresetinfo")
+        try {
+            await requestPasswordReset(form.username.value)
+            info.style.color = ""
+            info.innerText = "If that account exists, a reset code is on its way."
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async syncResetFormSubmit() {
+        const form = getElement("syncresetform") as HTMLFormElement
+        const info = getElement("syncresetinfo")
+        try {
+            await resetPassword(form.username.value, form.code.value, form.password.value)
+            form.reset()
+            info.style.color = "green"
+            info.innerText = "✅ Password changed. Sign in with your new password."
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async showEncryptionStatus() {
+        const status = await getEncryptionStatus()
+        getElement("encryptionstatus").innerHTML = !status.enabled
+            ? "Your tasks are sent to the server as-is. Turn on encryption to keep their contents private."
+            : status.unlocked
+                ? `🔒 Encrypted (key ${status.key_id}).`
+                : "🔒 Encrypted, but locked on this device. Enter your passphrase to sync."
+        getElement("encryptionenablebutton").style.display = status.enabled ? "none" : ""
+        getElement("encryptionunlockbutton").style.display = status.enabled && !status.unlocked ? "" : "none"
+        getElement("encryptionrotatebutton").style.display = status.unlocked ? "" : "none"
+        getElement("encryptionphrasebutton").style.display = status.enabled ? "" : "none"
+        getElement("encryptionrecoverform").style.display = status.enabled && !status.unlocked ? "" : "none"
+    }
+
+    private async encryptionAction(action: "enable" | "unlock" | "rotate" | "phrase") {
+        const input = getElement("encryptionpassphrase") as HTMLInputElement
+        const passphrase = input.value
+        const info = getElement("encryptioninfo")
+        info.style.color = ""
+        try {
+            switch (action) {
+                case "enable":
+                    info.innerText = `Write down your recovery phrase and keep it somewhere safe. It's the only way back in if you forget your passphrase:\n${await enableEncryption(passphrase)}`
+                    break;
+                case "unlock":
+                    await unlockEncryption(passphrase)
+                    info.innerText = ""
+                    break;
+                case "rotate":
+                    info.innerText = `Now using key ${await rotateEncryptionKey(passphrase)}. Your tasks will be re-encrypted on the next sync.`
+                    break;
+                case "phrase":
+                    info.innerText = await exportRecoveryPhrase(passphrase)
+                    break;
+            }
+            input.value = ""
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+        await this.showEncryptionStatus()
+    }
+
+    private async encryptionRecoverFormSubmit() {
+        const form = getElement("encryptionrecoverform") as HTMLFormElement
+        const info = getElement("encryptioninfo")
+        try {
+            await recoverEncryption(form.phrase.value, form.passphrase.value)
+            form.reset()
+            info.style.color = "green"
+            info.innerText = "✅ Unlocked with your new passphrase."
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+        await this.showEncryptionStatus()
+    }
+
+    private serverInputValue(): string | undefined {
+        const value = (getElement("syncserverinput") as HTMLInputElement).value.trim()
+        return value == "" ? undefined : value
+    }
+
+    private async syncServerTest() {
+        const info = getElement("syncserverinfo")
+        info.style.color = ""
+        info.innerHTML = "Testing connection..."
+        try {
+            const status = await testConnection(this.serverInputValue())
+            info.style.color = "green"
+            info.innerHTML = `✅ Reached ${status.endpoint} (${status.latency_ms} ms)`
+        } catch (e) {
+            info.style.color = "red"
+            info.innerHTML = `⚠️ ${e}`
+        }
+    }
+
+    private async syncPreview() {
+        const info = getElement("syncdebuginfo")
+        const describe = (side: SideChanges) => [
+            `${side.lists.created.length} new, ${side.lists.updated.length} updated, ${side.lists.overwritten.length} overwritten lists`,
+            `${side.tasks.created.length} new, ${side.tasks.updated.length} updated, ${side.tasks.overwritten.length} overwritten tasks`,
+            ...side.lists.overwritten.concat(side.tasks.overwritten).map(i => `  ⚠️ ${i.name} would be overwritten`)
+        ].join("\n")
+        try {
+            const preview = await previewSync()
+            info.style.color = ""
+            info.innerText = `This device: ${describe(preview.local)}\nServer: ${describe(preview.remote)}`
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async showProfiles() {
+        const select = getElement("profileselect") as HTMLSelectElement
+        select.innerHTML = ""
+        for (const profile of await listProfiles()) {
+            const option = document.createElement("option")
+            option.value = profile.id
+            option.innerText = `${profile.name} (${profile.endpoint})`
+            option.selected = profile.active
+            select.appendChild(option)
+        }
+    }
+
+    private async profileAction(action: "switch" | "remove") {
+        const id = (getElement("profileselect") as HTMLSelectElement).value
+        const info = getElement("profileinfo")
+        try {
+            if (action == "switch") {
+                await switchProfile(id)
+            } else {
+                await removeProfile(id)
+                info.style.color = "green"
+                info.innerText = "✅ Profile removed."
+                await this.showProfiles()
+            }
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async profileAddFormSubmit() {
+        const form = getElement("profileaddform") as HTMLFormElement
+        const info = getElement("profileinfo")
+        const endpoint = form.endpoint.value.trim()
+        try {
+            await addProfile((form.elements.namedItem("name") as HTMLInputElement).value, endpoint == "" ? undefined : endpoint)
+            form.reset()
+            info.style.color = "green"
+            info.innerText = "✅ Profile added. Switch to it to sign in."
+            await this.showProfiles()
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async showSyncFolder() {
+        const folder = await getSyncFolder()
+        getElement("syncfolderlabel").innerText = folder ?? "None"
+        getElement("syncfolderclearbutton").style.display = folder == null ? "none" : ""
+    }
+
+    private async syncFolderChoose() {
+        const folder = await open({directory: true, title: "Choose a shared folder"})
+        if (typeof folder == "string") {
+            await this.syncFolderSet(folder)
+        }
+    }
+
+    private async syncFolderSet(folder: string | null) {
+        const info = getElement("syncfolderinfo")
+        try {
+            const res = await setSyncFolder(folder)
+            info.style.color = "green"
+            info.innerText = folder == null ? "✅ Stopped using the folder." : `✅ Merged ${res.imported} and shared ${res.exported} changes.`
+            await this.showSyncFolder()
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async showCalDavAccount() {
+        const account = await getCalDavAccount()
+        getElement("caldavlabel").innerText = account == null ? "None" : `${account.username} at ${account.url}`
+        getElement("caldavsyncbutton").style.display = account == null ? "none" : ""
+        getElement("caldavremovebutton").style.display = account == null ? "none" : ""
+    }
+
+    private async calDavFormSubmit() {
+        const form = getElement("caldavform") as HTMLFormElement
+        const info = getElement("caldavinfo")
+        const field = (name: string) => (form.elements.namedItem(name) as HTMLInputElement).value
+        try {
+            await setCalDavAccount(field("url"), field("username"), field("password"))
+            form.reset()
+            await this.showCalDavAccount()
+            await this.calDavSyncNow()
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async calDavSyncNow() {
+        const info = getElement("caldavinfo")
+        try {
+            const res = await calDavSync()
+            info.style.color = "green"
+            info.innerText = `✅ Got ${res.pulled}, sent ${res.pushed} and deleted ${res.deleted} items.`
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async calDavRemove() {
+        await removeCalDavAccount()
+        getElement("caldavinfo").innerText = ""
+        await this.showCalDavAccount()
+    }
+
+    private async showLocalBackups() {
+        const restore = getElement("localrestoreform") as HTMLFormElement
+        try {
+            const backups = await listBackups()
+            const select = restore.elements.namedItem("backup") as HTMLSelectElement
+            select.innerHTML = ""
+            backups.forEach(b => {
+                const option = document.createElement("option")
+                option.value = b.name
+                option.innerText = `${new Date(b.created).toLocaleString()} (${Math.ceil(b.size / 1024)} KB)`
+                select.appendChild(option)
+            })
+            restore.style.display = backups.length > 0 ? "" : "none"
+        } catch (e) {
+            const info = getElement("localbackupinfo")
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async localBackupNow() {
+        const info = getElement("localbackupinfo")
+        info.style.color = ""
+        info.innerText = "Backing up..."
+        try {
+            const res = await backupNow()
+            info.style.color = "green"
+            info.innerText = `✅ Saved and checked ${res.name}` + (res.removed > 0 ? ` and removed ${res.removed} old backups.` : ".")
+            await this.showLocalBackups()
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async localRestoreSubmit() {
+        const form = getElement("localrestoreform") as HTMLFormElement
+        const name = (form.elements.namedItem("backup") as HTMLSelectElement).value
+        if (!await ask("Replace your tasks and history with this backup? The current ones are kept alongside as .pre-restore files.")) {
+            return
+        }
+        try {
+            await restoreBackup(name)
+        } catch (e) {
+            const info = getElement("localbackupinfo")
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private showDatabaseIssues(issues: DbIssue[], repaired: boolean) {
+        const list = getElement("dbissues")
+        list.innerHTML = ""
+        issues.forEach(i => {
+            const item = document.createElement("li")
+            item.innerText = `${i.task == null ? `List ${i.list}` : `Task ${i.task} in list ${i.list}`}: ${i.problem}. ${i.fix}.`
+            list.appendChild(item)
+        })
+        getElement("dbrepairbutton").style.display = issues.length > 0 && !repaired ? "" : "none"
+    }
+
+    private async databaseCheck() {
+        const info = getElement("dbinfo")
+        try {
+            const issues = await checkDatabase()
+            info.style.color = issues.length > 0 ? "red" : "green"
+            info.innerText = issues.length > 0 ? `⚠️ Found ${issues.length} problems.` : "✅ No problems found."
+            this.showDatabaseIssues(issues, false)
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async databaseRepair() {
+        const info = getElement("dbinfo")
+        if (!await ask("Repair the task database? Back up first if you want to be able to undo it.")) {
+            return
+        }
+        try {
+            const changes = await repairDatabase()
+            info.style.color = "green"
+            info.innerText = `✅ Fixed ${changes.length} problems. Reload to see the changes.`
+            this.showDatabaseIssues(changes, true)
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async showWebDavBackup() {
+        const backup = await getWebDavBackup()
+        const last = backup?.last_backup == null ? "" : ` (last backup ${new Date(backup.last_backup).toLocaleString()})`
+        getElement("webdavlabel").innerText = backup == null ? "None" : `${backup.url}${last}`
+        getElement("webdavbackupbutton").style.display = backup == null ? "none" : ""
+        getElement("webdavremovebutton").style.display = backup == null ? "none" : ""
+        const restore = getElement("webdavrestoreform") as HTMLFormElement
+        restore.style.display = "none"
+        if (backup == null) {
+            return
+        }
+        try {
+            const snapshots = await listWebDavSnapshots()
+            const select = restore.elements.namedItem("snapshot") as HTMLSelectElement
+            select.innerHTML = ""
+            snapshots.forEach(s => {
+                const option = document.createElement("option")
+                option.value = s.name
+                option.innerText = new Date(s.created).toLocaleString()
+                select.appendChild(option)
+            })
+            restore.style.display = snapshots.length > 0 ? "" : "none"
+        } catch (e) {
+            const info = getElement("webdavinfo")
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async webDavFormSubmit() {
+        const form = getElement("webdavform") as HTMLFormElement
+        const info = getElement("webdavinfo")
+        const field = (name: string) => (form.elements.namedItem(name) as HTMLInputElement).value
+        try {
+            await setWebDavBackup(field("url"), field("username"), field("password"), field("passphrase"), Number(field("daily")), Number(field("weekly")))
+            form.reset()
+            info.style.color = "green"
+            info.innerText = "✅ Saved. Keep the passphrase somewhere safe: restoring needs it."
+            await this.showWebDavBackup()
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async webDavBackupNow() {
+        const info = getElement("webdavinfo")
+        info.style.color = ""
+        info.innerText = "Backing up..."
+        try {
+            const res = await backupToWebDav()
+            info.style.color = "green"
+            info.innerText = `✅ Uploaded ${res.name}` + (res.removed > 0 ? ` and removed ${res.removed} old snapshots.` : ".")
+            await this.showWebDavBackup()
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async webDavRestoreSubmit() {
+        const form = getElement("webdavrestoreform") as HTMLFormElement
+        const info = getElement("webdavinfo")
+        const name = (form.elements.namedItem("snapshot") as HTMLSelectElement).value
+        if (!await ask("Replace your tasks and history with this snapshot? The current ones are kept alongside as .pre-restore files.")) {
+            return
+        }
+        try {
+            await restoreWebDavSnapshot(name, (form.elements.namedItem("passphrase") as HTMLInputElement).value)
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async webDavRemove() {
+        await removeWebDavBackup()
+        getElement("webdavinfo").innerText = ""
+        await this.showWebDavBackup()
+    }
+
+    private async icsExport() {
+        const info = getElement("importinfo")
+        const path = await save({title: "Export tasks", defaultPath: "tasks.ics", filters: [{name: "iCalendar", extensions: ["ics"]}]})
+        if (path == null) {
+            return
+        }
+        try {
+            const count = await exportIcs([], path)
+            info.style.color = "green"
+            info.innerText = `✅ Exported ${count} tasks.`
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async icsImport() {
+        const info = getElement("importinfo")
+        const path = await open({title: "Import tasks", filters: [{name: "iCalendar", extensions: ["ics"]}]})
+        if (typeof path != "string") {
+            return
+        }
+        try {
+            const res = await importIcs(path)
+            const dropped = Object.entries(res.unsupported).map(([name, count]) => `${name} (${count})`)
+            info.style.color = "green"
+            info.innerText = `✅ Imported ${res.tasks} tasks into ${res.lists} lists.`
+                + (dropped.length > 0 ? `\nNot imported: ${dropped.join(", ")}` : "")
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async showMarkdownLists() {
+        const select = getElement("markdownlist") as HTMLSelectElement
+        const selected = select.value
+        select.innerHTML = ""
+        for (const list of await loadTasks()) {
+            select.add(new Option(list.name, list.uuid))
+        }
+        if (selected != "") {
+            select.value = selected
+        }
+    }
+
+    private async markdownCopy() {
+        const info = getElement("importinfo")
+        const list = (getElement("markdownlist") as HTMLSelectElement).value
+        if (list == "") {
+            return
+        }
+        try {
+            await navigator.clipboard.writeText(await exportMarkdown(list))
+            info.style.color = "green"
+            info.innerText = "✅ Copied."
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async markdownImport() {
+        const info = getElement("importinfo")
+        const text = getElement("markdowntext") as HTMLTextAreaElement
+        try {
+            const res = await importMarkdown(text.value, "Pasted checklist")
+            text.value = ""
+            info.style.color = "green"
+            info.innerText = `✅ Imported ${res.tasks} tasks into ${res.lists} lists.`
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async todoTxtExport() {
+        const info = getElement("importinfo")
+        const path = await save({title: "Export tasks", defaultPath: "todo.txt", filters: [{name: "todo.txt", extensions: ["txt"]}]})
+        if (path == null) {
+            return
+        }
+        try {
+            const count = await exportTodoTxt([], path)
+            info.style.color = "green"
+            info.innerText = `✅ Exported ${count} tasks.`
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async todoTxtImport() {
+        const info = getElement("importinfo")
+        const path = await open({title: "Import tasks", filters: [{name: "todo.txt", extensions: ["txt"]}]})
+        if (typeof path != "string") {
+            return
+        }
+        try {
+            const res = await importTodoTxt(path)
+            info.style.color = "green"
+            info.innerText = `✅ Imported ${res.tasks} tasks into ${res.lists} lists.`
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async taskwarriorExport() {
+        const info = getElement("importinfo")
+        const path = await save({title: "Export tasks", defaultPath: "tasks.json", filters: [{name: "Taskwarrior", extensions: ["json"]}]})
+        if (path == null) {
+            return
+        }
+        try {
+            const count = await exportTaskwarrior([], path)
+            info.style.color = "green"
+            info.innerText = `✅ Exported ${count} tasks.`
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async taskwarriorImport() {
+        const info = getElement("importinfo")
+        const path = await open({title: "Import tasks", filters: [{name: "Taskwarrior", extensions: ["json"]}]})
+        if (typeof path != "string") {
+            return
+        }
+        try {
+            this.showMigration(await importTaskwarrior(path))
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private showMigration(res: MigrationImport) {
+        const info = getElement("importinfo")
+        const dropped = Object.entries(res.dropped).map(([what, count]) => `${what} (${count})`)
+        info.style.color = "green"
+        info.innerText = `✅ Imported ${res.tasks} tasks into ${res.lists} lists.`
+            + (dropped.length > 0 ? `\nNot imported: ${dropped.join(", ")}` : "")
+    }
+
+    private async todoistImport() {
+        const info = getElement("importinfo")
+        const paths = await open({title: "Import from Todoist", multiple: true, filters: [{name: "Todoist backup", extensions: ["csv", "json"]}]})
+        if (!Array.isArray(paths) || paths.length == 0) {
+            return
+        }
+        try {
+            this.showMigration(await importTodoist(paths))
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async msTodoImport() {
+        const info = getElement("importinfo")
+        const path = await open({title: "Import from Microsoft To Do", filters: [{name: "JSON", extensions: ["json"]}]})
+        if (typeof path != "string") {
+            return
+        }
+        try {
+            this.showMigration(await importMsTodo(path))
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async csvExport() {
+        const info = getElement("importinfo")
+        const path = await save({title: "Export tasks", defaultPath: "tasks.csv", filters: [{name: "CSV", extensions: ["csv"]}]})
+        if (path == null) {
+            return
+        }
+        try {
+            const count = await exportCsv([], path)
+            info.style.color = "green"
+            info.innerText = `✅ Exported ${count} tasks.`
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private csvPath: string | null = null
+    private static CSV_FIELDS = ["name", "list", "path", "indent", "size", "importance", "due", "completed"] as const
+
+    private async csvImport() {
+        const path = await open({title: "Import tasks", filters: [{name: "CSV", extensions: ["csv"]}]})
+        if (typeof path != "string") {
+            return
+        }
+        this.csvPath = path
+        await this.csvPreview({})
+    }
+
+    /** The mapping picked in the form. */
+    private csvMapping(): ColumnMapping {
+        const form = getElement("csvmappingform") as HTMLFormElement
+        const field = (name: string) => (form.elements.namedItem(name) as HTMLSelectElement).value
+        const mapping: ColumnMapping = {date_order: field("date_order") as "mdy" | "dmy"}
+        SettingsView.CSV_FIELDS.forEach(f => mapping[f] = field(f))
+        return mapping
+    }
+
+    /** Shows what importing with `mapping` (the form's if not given) would make. */
+    private async csvPreview(mapping: ColumnMapping = this.csvMapping()) {
+        const info = getElement("importinfo")
+        const form = getElement("csvmappingform") as HTMLFormElement
+        if (this.csvPath == null) {
+            return
+        }
+        try {
+            const preview = await previewCsvImport(this.csvPath, mapping)
+            for (const f of SettingsView.CSV_FIELDS) {
+                const select = form.elements.namedItem(f) as HTMLSelectElement
+                select.innerHTML = ""
+                select.add(new Option("(none)", ""))
+                preview.headers.forEach(h => select.add(new Option(h, h)))
+                select.value = preview.mapping[f] ?? ""
+            }
+            (form.elements.namedItem("date_order") as HTMLSelectElement).value = preview.mapping.date_order ?? "mdy"
+            form.style.display = ""
+            const lines = (tasks: TaskRecord[], depth: number): string[] => tasks.flatMap(t =>
+                [`${"  ".repeat(depth)}${t.completed ? "☑" : "☐"} ${t.name} (due ${new Date(t.due).toLocaleString()})`, ...lines(t.subtasks, depth + 1)])
+            info.style.color = ""
+            info.innerText = preview.lists.map(l => `${l.name}: ${l.tasks} tasks\n` + lines(l.sample, 1).join("\n")).join("\n")
+                + (preview.problems.length > 0 ? `\n⚠️ ${preview.problems.join("\n⚠️ ")}` : "")
+        } catch (e) {
+            form.style.display = "none"
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private csvCancel() {
+        this.csvPath = null
+        getElement("csvmappingform").style.display = "none"
+        getElement("importinfo").innerText = ""
+    }
+
+    private async csvImportSubmit() {
+        const info = getElement("importinfo")
+        if (this.csvPath == null) {
+            return
+        }
+        try {
+            const res = await importCsv(this.csvPath, this.csvMapping())
+            this.csvCancel()
+            info.style.color = "green"
+            info.innerText = `✅ Imported ${res.tasks} tasks into ${res.lists} lists.`
+                + (res.problems.length > 0 ? `\n⚠️ ${res.problems.join("\n⚠️ ")}` : "")
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async archiveExport() {
+        const info = getElement("importinfo")
+        const path = await save({title: "Export everything", defaultPath: "taskmanager-archive.json", filters: [{name: "JSON", extensions: ["json"]}]})
+        if (path == null) {
+            return
+        }
+        try {
+            await exportAll(path, this.settings.exportable())
+            info.style.color = "green"
+            info.innerText = "✅ Exported all lists, tasks, history and settings."
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async archiveImport(mode: ImportMode) {
+        const info = getElement("importinfo")
+        const path = await open({title: "Import archive", filters: [{name: "JSON", extensions: ["json"]}]})
+        if (typeof path != "string") {
+            return
+        }
+        if (mode == "replace" && !await ask("Replace all your lists, tasks, history and settings with this archive? Anything not in it is deleted.")) {
+            return
+        }
+        try {
+            const report = await importAll(path, mode)
+            if (report.settings != null) {
+                this.settings.importAll(report.settings)
+            }
+            const counts = (name: string, c: ItemCounts) => `${name}: ${c.added} added, ${c.updated} updated, ${c.kept} kept, ${c.removed} removed`
+            info.style.color = "green"
+            info.innerText = "✅ Imported archive.\n" + [
+                counts("Lists", report.lists),
+                counts("Tasks", report.tasks),
+                counts("Due date history", report.due_events),
+                report.settings != null ? "Settings restored." : "No settings in archive.",
+                ...report.skipped.map(s => `Skipped ${s}`)
+            ].join("\n")
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private feedBase: string | null = null
+
+    private async showIcsFeed() {
+        const feed = await getIcsFeed()
+        this.feedBase = feed?.url ?? null;
+        (getElement("feedenabled") as HTMLInputElement).checked = feed != null;
+        (getElement("feedlan") as HTMLInputElement).checked = feed?.lan ?? false
+        getElement("feedbox").style.display = feed == null ? "none" : "block"
+        const select = getElement("feedlist") as HTMLSelectElement
+        if (feed != null && select.options.length == 1) {
+            for (const list of await loadTasks()) {
+                select.add(new Option(list.name, list.uuid))
+            }
+        }
+        this.showIcsFeedUrl()
+    }
+
+    /** The feed link with the chosen filters. */
+    private showIcsFeedUrl() {
+        if (this.feedBase == null) {
+            return
+        }
+        const params = new URLSearchParams()
+        const list = (getElement("feedlist") as HTMLSelectElement).value
+        const tag = (getElement("feedtag") as HTMLInputElement).value.trim()
+        const alarm = (getElement("feedalarm") as HTMLInputElement).value
+        if (list != "") params.append("list", list)
+        if (tag != "") params.append("tag", tag)
+        if ((getElement("feedallday") as HTMLInputElement).checked) params.append("allday", "1")
+        if (alarm != "") params.append("alarm", alarm)
+        const query = params.toString();
+        (getElement("feedurl") as HTMLInputElement).value = this.feedBase + (query == "" ? "" : `?${query}`)
+    }
+
+    private async icsFeedChange() {
+        const info = getElement("feedinfo")
+        info.innerText = ""
+        try {
+            if ((getElement("feedenabled") as HTMLInputElement).checked) {
+                await startIcsFeed((getElement("feedlan") as HTMLInputElement).checked)
+            } else {
+                await stopIcsFeed()
+            }
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+        await this.showIcsFeed()
+    }
+
+    private async icsFeedReset() {
+        if (!await ask("Make a new feed link? Calendars subscribed to the current one will stop updating.")) {
+            return
+        }
+        await resetIcsFeedToken()
+        await this.showIcsFeed()
+    }
+
+    private async lanSettingsChange(enabled: boolean) {
+        const info = getElement("laninfo")
+        info.innerText = ""
+        try {
+            if (enabled) {
+                await startLanSync()
+            } else {
+                await stopLanSync()
+                getElement("lanpinbox").style.display = "none"
+                getElement("landevicelist").innerHTML = ""
+            }
+            getElement("lanbox").style.display = enabled ? "block" : "none"
+        } catch (e) {
+            (getElement("lanenabled") as HTMLInputElement).checked = false
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async lanPair() {
+        try {
+            getElement("lanpin").innerText = await startLanPairing()
+            getElement("lanpinbox").style.display = "block"
+        } catch (e) {
+            const info = getElement("laninfo")
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async lanDiscover() {
+        const info = getElement("laninfo")
+        const list = getElement("landevicelist")
+        info.style.color = ""
+        info.innerText = "Looking for devices..."
+        list.innerHTML = ""
+        try {
+            const devices = await discoverLanDevices()
+            info.innerText = devices.length == 0 ? "No devices found. Is nearby sync on there too?" : ""
+            for (const device of devices) {
+                const item = document.createElement("li")
+                item.innerText = `${device.name} `
+                const sync = document.createElement("button")
+                sync.className = "settingsbutton"
+                sync.innerText = device.paired ? "Sync" : "Pair"
+                sync.addEventListener("click", _ => this.lanDeviceAction(device.address, device.paired))
+                item.appendChild(sync)
+                if (device.paired) {
+                    const forget = document.createElement("button")
+                    forget.className = "settingsbutton"
+                    forget.innerText = "Forget"
+                    forget.addEventListener("click", async _ => {
+                        await forgetLanPeer(device.id)
+                        await this.lanDiscover()
+                    })
+                    item.appendChild(forget)
+                }
+                list.appendChild(item)
+            }
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async lanDeviceAction(address: string, paired: boolean) {
+        const info = getElement("laninfo")
+        try {
+            if (paired) {
+                const peer = await syncLanDevice(address)
+                info.style.color = "green"
+                info.innerText = `✅ Synced with ${peer.name}.`
+                window.location.reload()
+            } else {
+                const input = getElement("lanpininput") as HTMLInputElement
+                const peer = await pairLanDevice(address, input.value)
+                input.value = ""
+                info.style.color = "green"
+                info.innerText = `✅ Paired with ${peer.name}. You can sync now.`
+                await this.lanDiscover()
+            }
+        } catch (e) {
+            info.style.color = "red"
+            info.innerText = `⚠️ ${e}`
+        }
+    }
+
+    private async syncServerFormSubmit() {
+        const info = getElement("syncserverinfo")
+        try {
+            const root = await setServerEndpoint(this.serverInputValue());
+            (getElement("syncserverinput") as HTMLInputElement).value = root
+            info.style.color = "green"
+            info.innerHTML = "✅ Server saved."
+            await this.syncSettingsChange(true)
+        } catch (e) {
+            info.style.color = "red"
+            info.innerHTML = `⚠️ ${e}`
+        }
+    }
+
     private async syncSettingsChange(newStatus: boolean) {
+        getElement("syncserverbox").style.display = newStatus ? "block" : "none"
         if (newStatus) {
             getElement("syncinfo").innerHTML = "Checking your sync status..."
             getElement("syncinfo").style.display = "block"
@@ -660,6 +1711,23 @@ export class Settings {
         return default_
     }
 
+    /** Settings tied to this install, which archives leave out. */
+    private static LOCAL_KEYS = ["deviceId", "lastVersion"]
+
+    /** Every setting that makes sense on another device, for archives. */
+    exportable(): {[key: string]: unknown} {
+        return Object.fromEntries(this.entries.filter(e => !Settings.LOCAL_KEYS.includes(e[0])))
+    }
+
+    /** Applies settings from an archive, as if each was changed here. */
+    importAll(values: {[key: string]: unknown}) {
+        for (const [key, value] of Object.entries(values)) {
+            if (!Settings.LOCAL_KEYS.includes(key)) {
+                this.setKey(key, value)
+            }
+        }
+    }
+
     /**
      * The last Theme selected by the user. (default: light)
      */
@@ -8,7 +8,8 @@ import { loadTasks } from "./storage";
 import { TaskPlanner } from "./taskplan";
 import { Task, List, colorStrToEnum, TaskColor, ListEvent, TaskEventType, onTaskEvent, onListEdit } from "./task";
 import { getElement, onWindowFocused } from "./utils";
-import { doSync, isAuthenticated } from "./http";
+import { calDavSync, doSync, isAuthenticated, resumeIcsFeed, startFolderSync, startLocalBackups, startWebDavBackups, syncFolder } from "./http";
+import { listen } from "@tauri-apps/api/event";
 
 const MIN_SYNC_SPACING = 5 * 60 * 1000
 
@@ -66,6 +67,22 @@ export class TaskManager {
         onWindowFocused(() => this.sync().then())
         onTaskEvent(_ => this.sync(true).then(), true, true)
         onListEdit(_ => this.sync(true).then())
+        onTaskEvent(_ => syncFolder().then(), true, true)
+        onListEdit(_ => syncFolder().then())
+        listen("folder-synced", _ => this.reloadTasks())
+        listen("tasks-imported", _ => this.reloadTasks())
+        startFolderSync().then(res => {
+            if (res.imported > 0) {
+                this.reloadTasks()
+            }
+        })
+        onTaskEvent(_ => this.calDavSync().then(), true, true)
+        onListEdit(_ => this.calDavSync().then())
+        onWindowFocused(() => this.calDavSync().then())
+        this.calDavSync().then()
+        startLocalBackups().then()
+        startWebDavBackups().then()
+        resumeIcsFeed().then()
         getElement("syncnowbutton").addEventListener(
             "click",
             _ => this.sync(true).then()
@@ -81,6 +98,23 @@ export class TaskManager {
         this.render();
     }
 
+    private async calDavSync() {
+        try {
+            const res = await calDavSync()
+            if (res.pulled > 0 || res.deleted > 0) {
+                await this.reloadTasks()
+            }
+        } catch (e) {
+            console.log(`CalDAV sync failed: ${e}`)
+        }
+    }
+
+    /** Picks up changes merged in from elsewhere. */
+    private async reloadTasks() {
+        this._lists = []
+        await this.loadTasks()
+    }
+
     private render() {
         this.taskList.render();
         this.planner.render();